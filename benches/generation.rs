@@ -0,0 +1,106 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use dungeon_3d_generator::delaunary_3d::Delaunay3D;
+use dungeon_3d_generator::generate_drd::{generate_dungeon_3d, Dungeon3DGeneratorConfig};
+use dungeon_3d_generator::passage::Passage;
+use dungeon_3d_generator::pipeline::{DefaultPlaceRooms, GenerationState, PlaceRooms};
+use dungeon_3d_generator::room::{Room, RoomId};
+use dungeon_3d_generator::voxel_map::VoxelMap;
+use nalgebra::Vector3;
+use rand::{Rng, SeedableRng};
+use std::collections::BTreeMap;
+
+const SIZES: [u32; 3] = [32, 64, 128];
+
+fn config(size: u32) -> Dungeon3DGeneratorConfig {
+    Dungeon3DGeneratorConfig {
+        width: size,
+        height: size,
+        depth: size,
+        seed: Some(0),
+        ..Default::default()
+    }
+}
+
+fn bench_generate(c: &mut Criterion) {
+    let mut group = c.benchmark_group("generate");
+    group.sample_size(10);
+    for size in SIZES {
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, size| {
+            b.iter(|| generate_dungeon_3d(config(*size)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn bench_place_rooms(c: &mut Criterion) {
+    let mut group = c.benchmark_group("place_rooms");
+    for size in SIZES {
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, size| {
+            b.iter(|| {
+                let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+                let mut state = GenerationState::new(config(*size), &mut rng);
+                DefaultPlaceRooms.place_rooms(&mut state).unwrap();
+                state.rooms
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_delaunay(c: &mut Criterion) {
+    let mut group = c.benchmark_group("delaunay");
+    for size in SIZES {
+        // 区画ごとに1部屋置いた場合と同程度の頂点数
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let vertices = (0..size / 2)
+            .map(|i| {
+                (
+                    i,
+                    Vector3::new(
+                        rng.gen_range(0.0..size as f32),
+                        rng.gen_range(0.0..size as f32),
+                        rng.gen_range(0.0..size as f32),
+                    ),
+                )
+            })
+            .collect::<Vec<_>>();
+        group.bench_with_input(
+            BenchmarkId::from_parameter(size),
+            &vertices,
+            |b, vertices| b.iter(|| Delaunay3D::new(vertices.clone())),
+        );
+    }
+    group.finish();
+}
+
+fn bench_passage(c: &mut Criterion) {
+    let mut group = c.benchmark_group("passage");
+    group.sample_size(10);
+    for size in SIZES {
+        // ダンジョンの中央まで対角に伸びる、階段を含む通路
+        let mut room_id = RoomId::first();
+        let room0 = Room::new(room_id.gen_id(), 5, 2, 5, (1, 1, 1));
+        let room1 = Room::new(room_id.gen_id(), 5, 2, 5, (size / 2, 4, size / 2));
+        let mut voxel_map = VoxelMap::new(0, 0, 0, size as i32, size as i32, size as i32);
+        voxel_map.add_room(&room0).unwrap();
+        voxel_map.add_room(&room1).unwrap();
+        let passage = Passage::new(&room0, &room1, 2);
+        let rooms = BTreeMap::from([(room0.id, room0), (room1.id, room1)]);
+        group.bench_function(BenchmarkId::from_parameter(size), |b| {
+            b.iter(|| {
+                let mut voxel_map = voxel_map.clone();
+                voxel_map.add_passage(&passage, &rooms).unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_generate,
+    bench_place_rooms,
+    bench_delaunay,
+    bench_passage
+);
+criterion_main!(benches);
@@ -59,11 +59,23 @@ fn main() {
                     key.z as f32 + 0.5,
                 ));
             }
+            VoxelType::CaveSpace => {}
+            VoxelType::CaveFloor => {}
+            VoxelType::Custom(_) => {}
+            VoxelType::Doorway(_) => {
+                let mut c = window.add_cube(1.0, 1.0, 1.0);
+                c.set_color(0.5, 0.5, 1.0);
+                c.set_local_translation(Translation3::new(
+                    key.x as f32 + 0.5,
+                    key.y as f32 + 0.5,
+                    key.z as f32 + 0.5,
+                ));
+            }
         }
     }
 
     while window.render() {
-        for passage in dungeon.passages.iter() {
+        for passage in dungeon.passages.values() {
             let room0 = dungeon.rooms.get(&passage.start_room_id).unwrap();
             let room1 = dungeon.rooms.get(&passage.end_room_id).unwrap();
             let room0_center = room0.center();
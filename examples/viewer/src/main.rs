@@ -0,0 +1,270 @@
+use dungeon_3d_generator::constants::VoxelType;
+use dungeon_3d_generator::generate_drd::Dungeon3DGeneratorConfig;
+use dungeon_3d_generator::regenerate::Regenerator;
+use eframe::egui;
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+fn main() -> eframe::Result<()> {
+    eframe::run_native(
+        "Dungeon 3D Generator",
+        eframe::NativeOptions::default(),
+        Box::new(|_| Ok(Box::new(Viewer::new()))),
+    )
+}
+
+// 表示を切り替えられるボクセルの種類
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+enum Layer {
+    RoomFloor,
+    RoomSpace,
+    PassageFloor,
+    PassageSpace,
+    Stair,
+    Doorway,
+    Cave,
+}
+
+impl Layer {
+    fn of(voxel_type: &VoxelType) -> Option<Self> {
+        match voxel_type {
+            VoxelType::RoomFloor(_) | VoxelType::RoomWall(_) => Some(Layer::RoomFloor),
+            VoxelType::RoomSpace(_) | VoxelType::RoomBottomSpace(_) => Some(Layer::RoomSpace),
+            VoxelType::PassageFloor => Some(Layer::PassageFloor),
+            VoxelType::PassageSpace => Some(Layer::PassageSpace),
+            VoxelType::PassageStair(_) => Some(Layer::Stair),
+            VoxelType::Doorway(_) => Some(Layer::Doorway),
+            VoxelType::CaveSpace | VoxelType::CaveFloor => Some(Layer::Cave),
+            VoxelType::Wall | VoxelType::Custom(_) => None,
+        }
+    }
+
+    fn color(&self) -> egui::Color32 {
+        match self {
+            Layer::RoomFloor => egui::Color32::from_rgb(200, 60, 60),
+            Layer::RoomSpace => egui::Color32::from_rgb(240, 120, 120),
+            Layer::PassageFloor => egui::Color32::from_rgb(120, 120, 200),
+            Layer::PassageSpace => egui::Color32::from_rgb(200, 200, 240),
+            Layer::Stair => egui::Color32::from_rgb(250, 200, 120),
+            Layer::Doorway => egui::Color32::from_rgb(80, 200, 80),
+            Layer::Cave => egui::Color32::from_rgb(160, 140, 100),
+        }
+    }
+}
+
+const LAYERS: [Layer; 7] = [
+    Layer::RoomFloor,
+    Layer::RoomSpace,
+    Layer::PassageFloor,
+    Layer::PassageSpace,
+    Layer::Stair,
+    Layer::Doorway,
+    Layer::Cave,
+];
+
+struct Viewer {
+    config: Dungeon3DGeneratorConfig,
+    regenerator: Regenerator,
+    visible: BTreeMap<Layer, bool>,
+    max_y: i32, // Voxels above this height are hidden so lower floors can be inspected
+}
+
+impl Viewer {
+    fn new() -> Self {
+        let config = Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        };
+        let mut regenerator = Regenerator::default();
+        regenerator.regenerate(&config);
+        Viewer {
+            max_y: config.height as i32,
+            config,
+            regenerator,
+            visible: LAYERS.iter().map(|layer| (*layer, true)).collect(),
+        }
+    }
+
+    // 設定の各項目のスライダー。変更があれば true を返す
+    fn config_controls(&mut self, ui: &mut egui::Ui) -> bool {
+        let config = &mut self.config;
+        let mut changed = false;
+        let mut slider =
+            |ui: &mut egui::Ui, label: &str, value: &mut u32, range: RangeInclusive<u32>| {
+                changed |= ui
+                    .add(egui::Slider::new(value, range).text(label))
+                    .changed();
+            };
+        slider(ui, "width", &mut config.width, 8..=96);
+        slider(ui, "height", &mut config.height, 4..=32);
+        slider(ui, "depth", &mut config.depth, 8..=96);
+        slider(ui, "room_hierarchy", &mut config.room_hierarchy, 1..=6);
+        slider(ui, "room_margin_x", &mut config.room_margin_x, 1..=8);
+        slider(ui, "room_margin_y", &mut config.room_margin_y, 1..=4);
+        slider(ui, "room_margin_z", &mut config.room_margin_z, 1..=8);
+        slider(ui, "passage_height", &mut config.passage_height, 1..=4);
+        slider(
+            ui,
+            "margin_for_bounds",
+            &mut config.margin_for_bounds,
+            0..=8,
+        );
+        slider(ui, "boundary_margin", &mut config.boundary_margin, 0..=4);
+        slider(
+            ui,
+            "min_inter_hierarchy_connections",
+            &mut config.min_inter_hierarchy_connections,
+            0..=4,
+        );
+        slider(ui, "min_room_degree", &mut config.min_room_degree, 0..=4);
+        slider(ui, "hub_min_degree", &mut config.hub_min_degree, 0..=6);
+        slider(ui, "hub_growth", &mut config.hub_growth, 0..=4);
+        slider(
+            ui,
+            "foreign_room_penalty",
+            &mut config.foreign_room_penalty,
+            0..=16,
+        );
+        slider(ui, "stair_run", &mut config.stair_run, 1..=4);
+
+        for (label, range, max) in [
+            ("room_width_range", &mut config.room_width_range, 16),
+            ("room_height_range", &mut config.room_height_range, 8),
+            ("room_depth_range", &mut config.room_depth_range, 16),
+        ] {
+            let (mut start, mut end) = (*range.start(), *range.end());
+            slider(ui, &format!("{} start", label), &mut start, 1..=max);
+            slider(ui, &format!("{} end", label), &mut end, start..=max);
+            *range = start..=end.max(start);
+        }
+
+        let mut ratio =
+            |ui: &mut egui::Ui, label: &str, value: &mut f64, range: RangeInclusive<f64>| {
+                changed |= ui
+                    .add(egui::Slider::new(value, range).text(label))
+                    .changed();
+            };
+        ratio(
+            ui,
+            "loop_probability",
+            &mut config.loop_probability,
+            0.0..=1.0,
+        );
+        ratio(
+            ui,
+            "passage_wall_noise",
+            &mut config.passage_wall_noise,
+            0.0..=1.0,
+        );
+        changed |= ui
+            .add(egui::Slider::new(&mut config.room_density, 0.0..=1.0).text("room_density"))
+            .changed();
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut config.block_split_jitter, 0.0..=0.5)
+                    .text("block_split_jitter"),
+            )
+            .changed();
+
+        for (label, value) in [
+            ("direct_doors", &mut config.direct_doors),
+            ("merge_adjacent_rooms", &mut config.merge_adjacent_rooms),
+            ("trim_dead_ends", &mut config.trim_dead_ends),
+            ("split_long_connections", &mut config.split_long_connections),
+            ("avoid_foreign_rooms", &mut config.avoid_foreign_rooms),
+            ("guided_route_search", &mut config.guided_route_search),
+            ("skip_connected_rooms", &mut config.skip_connected_rooms),
+        ] {
+            changed |= ui.checkbox(value, label).changed();
+        }
+        changed
+    }
+
+    // 見えている中で最も高いボクセルを真上から描く
+    fn draw_map(&self, ui: &mut egui::Ui) {
+        let Some(Ok(result)) = self.regenerator.result() else {
+            return;
+        };
+        let mut top: BTreeMap<(i32, i32), (i32, Layer)> = BTreeMap::new();
+        for (p, voxel_type) in result.voxel_map.map.iter() {
+            let Some(layer) = Layer::of(voxel_type) else {
+                continue;
+            };
+            if p.y > self.max_y || !self.visible[&layer] {
+                continue;
+            }
+            let entry = top.entry((p.x, p.z)).or_insert((p.y, layer));
+            if p.y > entry.0 {
+                *entry = (p.y, layer);
+            }
+        }
+
+        let (start, end) = result.voxel_map.bounds();
+        let size = ui.available_size();
+        let cell = (size.x / (end.x - start.x) as f32).min(size.y / (end.z - start.z) as f32);
+        let (response, painter) = ui.allocate_painter(size, egui::Sense::hover());
+        let origin = response.rect.min;
+        for ((x, z), (_, layer)) in top {
+            let min = origin + egui::vec2((x - start.x) as f32 * cell, (z - start.z) as f32 * cell);
+            painter.rect_filled(
+                egui::Rect::from_min_size(min, egui::vec2(cell, cell)),
+                0.0,
+                layer.color(),
+            );
+        }
+    }
+}
+
+impl eframe::App for Viewer {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        egui::SidePanel::left("config").show(ctx, |ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label(format!("seed: {:?}", self.config.seed));
+                    if ui.button("Reseed").clicked() {
+                        self.regenerator.reseed(&mut self.config);
+                    }
+                });
+                if self.config_controls(ui) {
+                    self.regenerator.regenerate(&self.config);
+                }
+
+                ui.separator();
+                ui.add(
+                    egui::Slider::new(&mut self.max_y, -1..=self.config.height as i32)
+                        .text("max y"),
+                );
+                for layer in LAYERS {
+                    let visible = self.visible.get_mut(&layer).unwrap();
+                    ui.checkbox(visible, format!("{:?}", layer));
+                }
+
+                ui.separator();
+                ui.label(format!("generated in {:?}", self.regenerator.elapsed()));
+                match self.regenerator.result() {
+                    Some(Ok(result)) => {
+                        ui.label(format!("rooms: {}", result.rooms.len()));
+                        ui.label(format!("passages: {}", result.passages.len()));
+                        ui.label(format!("repairs: {}", result.repairs.len()));
+                        ui.label(format!("dead ends: {}", result.dead_ends.len()));
+                        if let Some(report) = result.report.as_ref() {
+                            ui.label(format!("failed passages: {}", report.failed_passages));
+                            ui.label(format!(
+                                "explored route nodes: {}",
+                                report.explored_route_nodes
+                            ));
+                            for (phase, duration) in report.phase_timings.iter() {
+                                ui.label(format!("{:?}: {:?}", phase, duration));
+                            }
+                        }
+                    }
+                    Some(Err(error)) => {
+                        ui.colored_label(egui::Color32::RED, error.to_string());
+                    }
+                    None => {}
+                }
+            });
+        });
+        egui::CentralPanel::default().show(ctx, |ui| self.draw_map(ui));
+    }
+}
@@ -0,0 +1,12 @@
+#![no_main]
+
+use dungeon_3d_generator::generate_drd::{generate_dungeon_3d, Dungeon3DGeneratorConfig};
+use libfuzzer_sys::arbitrary::Unstructured;
+use libfuzzer_sys::fuzz_target;
+
+// エラーは想定内の結果とし、panic だけを見つける。見つかった入力は checked::replay_input で再現できる
+fuzz_target!(|data: &[u8]| {
+    if let Ok(config) = Unstructured::new(data).arbitrary::<Dungeon3DGeneratorConfig>() {
+        let _ = generate_dungeon_3d(config);
+    }
+});
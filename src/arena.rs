@@ -0,0 +1,257 @@
+use crate::constants::Axis;
+use crate::generate_drd::{
+    layout_connections, sample_room_size, ConfigViolation, Dungeon3DGeneratorConfig,
+    Dungeon3DGeneratorError, Dungeon3DGeneratorResult,
+};
+use crate::generation_report::GenerationPhase;
+use crate::pipeline::{GenerationPipeline, GenerationState, PlaceRooms, SelectEdges};
+use crate::room::{Room, RoomId};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::cmp::Ordering;
+use std::time::Instant;
+
+// 中央に大きな部屋を置き、周りを3x3に区切った残りの8区画に小さな部屋を置く
+// room_hierarchy、room_anchors、room_placement_score と地表の高さは考慮しない
+pub struct ArenaPlaceRooms {
+    pub central_width: u32,  // Width of the central chamber (x-axis)
+    pub central_height: u32, // Height of the central chamber (y-axis)
+    pub central_depth: u32,  // Depth of the central chamber (z-axis)
+    pub satellites: u32, // Rooms placed around the chamber; at most 8, fewer when the blocks are too small
+}
+
+impl Default for ArenaPlaceRooms {
+    fn default() -> Self {
+        ArenaPlaceRooms {
+            central_width: 12,
+            central_height: 4,
+            central_depth: 12,
+            satellites: 6,
+        }
+    }
+}
+
+impl PlaceRooms for ArenaPlaceRooms {
+    fn place_rooms(&self, state: &mut GenerationState<'_>) -> Result<(), Dungeon3DGeneratorError> {
+        let GenerationState {
+            config,
+            rng,
+            report,
+            rooms,
+            voxel_map,
+            central_room,
+            ..
+        } = state;
+        config.room_margin_x = config.room_margin_x.max(1);
+        config.room_margin_z = config.room_margin_z.max(1);
+        let violations = [
+            ("width", config.width, self.central_width),
+            ("height", config.height, self.central_height),
+            ("depth", config.depth, self.central_depth),
+        ]
+        .into_iter()
+        .filter_map(|(field, value, central_size)| {
+            let min = central_size + 2 * config.boundary_margin;
+            (value < min).then(|| ConfigViolation::new(field, value, format!(">= {}", min)))
+        })
+        .collect::<Vec<_>>();
+        if !violations.is_empty() {
+            return Err(Dungeon3DGeneratorError::ConfigValidation(violations));
+        }
+
+        let phase_start = Instant::now();
+        let mut room_id = RoomId::first();
+        let central_x = (config.width - self.central_width) / 2;
+        let central_z = (config.depth - self.central_depth) / 2;
+        let floor = config.boundary_margin;
+        let id = room_id.gen_id();
+        rooms.insert(
+            id,
+            Room::new(
+                id,
+                self.central_width,
+                self.central_height,
+                self.central_depth,
+                (central_x, floor, central_z),
+            ),
+        );
+        *central_room = Some(id);
+
+        // 中央の部屋の外側の列と行。中央の列と行は部屋と同じ幅になる
+        let x_spans = three_spans(
+            central_x,
+            self.central_width,
+            config.width,
+            config.room_margin_x,
+            config.boundary_margin,
+        );
+        let z_spans = three_spans(
+            central_z,
+            self.central_depth,
+            config.depth,
+            config.room_margin_z,
+            config.boundary_margin,
+        );
+        let mut blocks = (0..3)
+            .flat_map(|ix| (0..3).map(move |iz| (ix, iz)))
+            .filter(|(ix, iz)| {
+                (*ix, *iz) != (1, 1)
+                    && x_spans[*ix].1 >= *config.room_width_range.start()
+                    && z_spans[*iz].1 >= *config.room_depth_range.start()
+            })
+            .collect::<Vec<_>>();
+        blocks.shuffle(rng);
+        blocks.truncate(self.satellites as usize);
+        blocks.sort();
+        for (ix, iz) in blocks {
+            let block = (ix as u32, 0, iz as u32);
+            let (x_start, x_available) = x_spans[ix];
+            let (z_start, z_available) = z_spans[iz];
+            let width =
+                sample_room_size(rng, &config.room_width_range, x_available, Axis::X, block)?;
+            let height = sample_room_size(
+                rng,
+                &config.room_height_range,
+                self.central_height,
+                Axis::Y,
+                block,
+            )?;
+            let depth =
+                sample_room_size(rng, &config.room_depth_range, z_available, Axis::Z, block)?;
+            let x = rng.gen_range(x_start..=x_start + x_available - width);
+            let z = rng.gen_range(z_start..=z_start + z_available - depth);
+            let id = room_id.gen_id();
+            rooms.insert(id, Room::new(id, width, height, depth, (x, floor, z)));
+        }
+        report.record(GenerationPhase::PlaceRooms, phase_start);
+
+        let phase_start = Instant::now();
+        for room in rooms.values() {
+            voxel_map
+                .add_room(room)
+                .map_err(Dungeon3DGeneratorError::VoxelMapError)?;
+        }
+        report.record(GenerationPhase::VoxelizeRooms, phase_start);
+        Ok(())
+    }
+}
+
+// 中央の部屋の手前、中央、奥の (開始位置, 長さ)。部屋同士の余白は中央の部屋の側に取る
+fn three_spans(
+    central_start: u32,
+    central_size: u32,
+    total: u32,
+    margin: u32,
+    boundary_margin: u32,
+) -> [(u32, u32); 3] {
+    let far_start = central_start + central_size + margin;
+    [
+        (
+            boundary_margin,
+            central_start.saturating_sub(margin + boundary_margin),
+        ),
+        (central_start, central_size),
+        (
+            far_start,
+            total
+                .saturating_sub(boundary_margin)
+                .saturating_sub(far_start),
+        ),
+    ]
+}
+
+// 全ての部屋を中央の部屋とつなぎ、ring_corridors があれば周りの部屋を一周する通路も加える
+pub struct ArenaSelectEdges {
+    pub ring_corridors: bool, // Join neighboring satellites around the chamber; these passages are optional
+}
+
+impl Default for ArenaSelectEdges {
+    fn default() -> Self {
+        ArenaSelectEdges {
+            ring_corridors: true,
+        }
+    }
+}
+
+impl SelectEdges for ArenaSelectEdges {
+    fn select_edges(&self, state: &mut GenerationState<'_>) -> Result<(), Dungeon3DGeneratorError> {
+        let phase_start = Instant::now();
+        let Some(central_room_id) = state.central_room else {
+            return Ok(());
+        };
+        let central = state.rooms[&central_room_id].center();
+        // 中央の部屋から見た角度の順に並べる
+        let mut satellites = state
+            .rooms
+            .values()
+            .filter(|room| room.id != central_room_id)
+            .map(|room| {
+                let center = room.center();
+                let angle = (center.2 - central.2).atan2(center.0 - central.0);
+                (angle, room.id)
+            })
+            .collect::<Vec<_>>();
+        satellites.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+        let mut selected = satellites
+            .iter()
+            .map(|(_, room_id)| (central_room_id, *room_id, true))
+            .collect::<Vec<_>>();
+        if self.ring_corridors && satellites.len() > 2 {
+            for (index, (_, room_id)) in satellites.iter().enumerate() {
+                let (_, next_id) = satellites[(index + 1) % satellites.len()];
+                selected.push((*room_id, next_id, false));
+            }
+        }
+        state.connections = layout_connections(&state.rooms, selected, state.config.passage_height);
+        state
+            .report
+            .record(GenerationPhase::SelectEdges, phase_start);
+        Ok(())
+    }
+}
+
+impl GenerationPipeline {
+    // 中央の大部屋を囲むダンジョンを生成する段階の組み合わせ
+    pub fn arena() -> Self {
+        GenerationPipeline {
+            place_rooms: Box::new(ArenaPlaceRooms::default()),
+            select_edges: Box::new(ArenaSelectEdges::default()),
+            ..Default::default()
+        }
+    }
+}
+
+pub fn generate_arena(
+    config: Dungeon3DGeneratorConfig,
+) -> Result<Dungeon3DGeneratorResult, Dungeon3DGeneratorError> {
+    GenerationPipeline::arena().generate(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::arena::generate_arena;
+    use crate::generate_drd::Dungeon3DGeneratorConfig;
+    use crate::validation::validate;
+
+    #[test]
+    fn test_generate_arena() {
+        let result = generate_arena(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        let central_room_id = result.central_room.unwrap();
+        let central = &result.rooms[&central_room_id];
+        assert_eq!(result.rooms.len(), 7);
+        assert!(result.rooms.values().all(|room| room.id == central_room_id
+            || room.width * room.depth < central.width * central.depth));
+        // 周りの部屋は全て中央の部屋と直接つながる
+        for room_id in result.rooms.keys().filter(|id| **id != central_room_id) {
+            assert!(result.passages.values().any(|passage| {
+                let ids = [passage.start_room_id, passage.end_room_id];
+                ids.contains(room_id) && ids.contains(&central_room_id)
+            }));
+        }
+        assert!(validate(&result).is_empty());
+    }
+}
@@ -0,0 +1,100 @@
+#[cfg(feature = "events")]
+use crate::events::{record_edges, record_passages, record_rooms, record_voxel_changes};
+use crate::generate_drd::{
+    build_graph, place_rooms, post_process, select_edges, Dungeon3DGeneratorConfig,
+    Dungeon3DGeneratorError, Dungeon3DGeneratorResult, PassageCarver,
+};
+use crate::pipeline::{seeded_rng, GenerationState};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+// 1度だけ Pending を返して実行側に処理を譲る
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+// generate_dungeon_3d と同じ結果を、段階の間と通路を1本掘るごとに処理を譲りながら生成する
+// 非同期の読み込み処理からスレッドを塞がずに待てる。乱数を借りたまま待つため Future は Send ではない
+pub async fn generate_dungeon_3d_async(
+    config: Dungeon3DGeneratorConfig,
+) -> Result<Dungeon3DGeneratorResult, Dungeon3DGeneratorError> {
+    let mut rng = seeded_rng(config.seed);
+    let mut state = GenerationState::new(config, &mut rng);
+    place_rooms(&mut state)?;
+    #[cfg(feature = "events")]
+    record_rooms(&mut state);
+    YieldNow(false).await;
+    build_graph(&mut state)?;
+    YieldNow(false).await;
+    select_edges(&mut state)?;
+    #[cfg(feature = "events")]
+    {
+        record_rooms(&mut state);
+        record_edges(&mut state);
+    }
+    YieldNow(false).await;
+
+    let mut carver = PassageCarver::new(&state);
+    while carver.carve_next(&mut state) {
+        YieldNow(false).await;
+    }
+    carver.finish(&mut state)?;
+    #[cfg(feature = "events")]
+    record_passages(&mut state);
+    YieldNow(false).await;
+    #[cfg(feature = "events")]
+    let before = state.voxel_map.map.clone();
+    post_process(&mut state)?;
+    #[cfg(feature = "events")]
+    record_voxel_changes(&mut state, &before);
+    Ok(state.into_result())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::async_generation::generate_dungeon_3d_async;
+    use crate::generate_drd::{generate_dungeon_3d, Dungeon3DGeneratorConfig};
+    use std::future::Future;
+    use std::pin::pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+
+    struct NoopWaker;
+
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    #[test]
+    fn test_generate_dungeon_3d_async() {
+        let config = Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        };
+        let waker = Waker::from(Arc::new(NoopWaker));
+        let mut context = Context::from_waker(&waker);
+        let mut future = pin!(generate_dungeon_3d_async(config.clone()));
+        let mut polls = 1;
+        let result = loop {
+            match future.as_mut().poll(&mut context) {
+                Poll::Ready(result) => break result.unwrap(),
+                Poll::Pending => polls += 1,
+            }
+        };
+        let expected = generate_dungeon_3d(config).unwrap();
+        assert!(polls > expected.room_connections.len());
+        assert_eq!(result, expected);
+    }
+}
@@ -3,6 +3,8 @@ use std::collections::btree_map::{Iter, IterMut, Range, RangeMut};
 use std::collections::{BTreeMap, VecDeque};
 use std::ops::RangeBounds;
 
+// キーの順に並び、同じキーの値は追加した順に並ぶ複数値のマップ
+// キーを優先度とした優先度付きキューとして使える。同じ優先度の値は先に追加したものから取り出す
 #[derive(Debug, Clone)]
 pub struct BTreeKeyValues<K, V>
 where
@@ -26,78 +28,66 @@ impl<K, V> BTreeKeyValues<K, V>
 where
     K: Ord,
 {
-    pub fn push_back(&mut self, key: K, value: V)
-    where
-        K: Ord + Copy,
-    {
+    // キーの値の列の末尾に追加する
+    pub fn push_back(&mut self, key: K, value: V) {
         self.btree.entry(key).or_default().push_back(value);
     }
 
-    pub fn push_front(&mut self, key: K, value: V)
-    where
-        K: Ord + Copy,
-    {
+    // キーの値の列の先頭に追加する。次に取り出されるのはこの値になる
+    pub fn push_front(&mut self, key: K, value: V) {
         self.btree.entry(key).or_default().push_front(value);
     }
 
-    pub fn range<R>(&self, range: R) -> Range<'_, K, VecDeque<V>>
+    pub fn range<Q, R>(&self, range: R) -> Range<'_, K, VecDeque<V>>
     where
-        V: Ord,
-        K: Borrow<V>,
-        R: RangeBounds<V>,
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
     {
         self.btree.range(range)
     }
 
-    pub fn range_mut<R>(&mut self, range: R) -> RangeMut<'_, K, VecDeque<V>>
+    pub fn range_mut<Q, R>(&mut self, range: R) -> RangeMut<'_, K, VecDeque<V>>
     where
-        V: Ord,
-        K: Borrow<V>,
-        R: RangeBounds<V>,
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
     {
         self.btree.range_mut(range)
     }
 
-    pub fn pop_first_back(&mut self) -> Option<V>
-    where
-        K: Ord,
-    {
-        loop {
-            return match self.btree.pop_first() {
-                None => None,
-                Some((key, mut queue)) => {
-                    let ret = match queue.pop_front() {
-                        None => continue,
-                        Some(x) => x,
-                    };
-                    if !queue.is_empty() {
-                        self.btree.insert(key, queue);
-                    }
-                    Some(ret)
-                }
+    // 最小のキーの値のうち最も先に追加された値を取り出す
+    pub fn pop_first(&mut self) -> Option<V> {
+        while let Some(mut entry) = self.btree.first_entry() {
+            // iter_mut などで空にされた列は読み飛ばす
+            let Some(value) = entry.get_mut().pop_front() else {
+                entry.remove();
+                continue;
             };
+            if entry.get().is_empty() {
+                entry.remove();
+            }
+            return Some(value);
         }
+        None
     }
 
-    pub fn pop_last_back(&mut self) -> Option<V>
-    where
-        K: Ord,
-    {
-        match self.btree.pop_last() {
-            None => None,
-            Some((key, mut queue)) => {
-                let ret = match queue.pop_front() {
-                    None => panic!("Illegal state"),
-                    Some(x) => x,
-                };
-                if !queue.is_empty() {
-                    self.btree.insert(key, queue);
-                }
-                Some(ret)
+    // 最大のキーの値のうち最も先に追加された値を取り出す
+    pub fn pop_last(&mut self) -> Option<V> {
+        while let Some(mut entry) = self.btree.last_entry() {
+            let Some(value) = entry.get_mut().pop_front() else {
+                entry.remove();
+                continue;
+            };
+            if entry.get().is_empty() {
+                entry.remove();
             }
+            return Some(value);
         }
+        None
     }
 
+    // キーの値の列から最初に一致した値を取り除く
     pub fn remove(&mut self, key: &K, value: &V) -> Option<V>
     where
         V: Eq,
@@ -111,20 +101,20 @@ where
         ret
     }
 
+    // 次に pop_first で取り出される値
     pub fn first_key_value(&self) -> Option<(&K, &V)> {
         self.btree
-            .first_key_value()
-            .and_then(|(key, values)| values.front().map(|value| (key, value)))
+            .iter()
+            .find_map(|(key, values)| values.front().map(|value| (key, value)))
     }
 
-    #[inline]
+    // キーの数ではなく値の数。キーごとの列を数えるためキーの数に比例する時間がかかる
     pub fn len(&self) -> usize {
-        self.btree.len()
+        self.btree.values().map(VecDeque::len).sum()
     }
 
-    #[inline]
     pub fn is_empty(&self) -> bool {
-        self.btree.is_empty()
+        self.btree.values().all(VecDeque::is_empty)
     }
 
     #[inline]
@@ -143,15 +133,83 @@ where
     }
 }
 
+// pop_first で取り出す順に並べる
 impl<K, V> From<BTreeKeyValues<K, V>> for Vec<V>
 where
     K: Ord,
 {
     fn from(mut map: BTreeKeyValues<K, V>) -> Self {
         let mut ret = vec![];
-        while let Some(x) = map.pop_first_back() {
+        while let Some(x) = map.pop_first() {
             ret.push(x);
         }
         ret
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::btree_key_values::BTreeKeyValues;
+
+    #[test]
+    fn test_priority_order() {
+        let mut queue = BTreeKeyValues::default();
+        queue.push_back(2, "c");
+        queue.push_back(1, "a");
+        queue.push_back(2, "d");
+        queue.push_back(1, "b");
+        queue.push_front(2, "e");
+        assert_eq!(queue.len(), 5);
+        assert_eq!(queue.first_key_value(), Some((&1, &"a")));
+        assert_eq!(queue.pop_last(), Some("e"));
+        assert_eq!(Vec::from(queue), vec!["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn test_len_and_remove() {
+        let mut map = BTreeKeyValues::default();
+        assert!(map.is_empty());
+        map.push_back(1, 10);
+        map.push_back(1, 11);
+        map.push_back(3, 30);
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.remove(&1, &11), Some(11));
+        assert_eq!(map.remove(&1, &11), None);
+        assert_eq!(map.remove(&2, &20), None);
+        assert_eq!(map.remove(&3, &30), Some(30));
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.range(2..).count(), 0);
+        assert_eq!(
+            map.range(..2)
+                .map(|(key, values)| (*key, values.len()))
+                .collect::<Vec<_>>(),
+            vec![(1, 1)]
+        );
+        map.clear();
+        assert!(map.is_empty());
+        assert_eq!(map.pop_first(), None);
+        assert_eq!(map.pop_last(), None);
+    }
+
+    #[test]
+    fn test_emptied_values() {
+        let mut map = BTreeKeyValues::default();
+        map.push_back(1, 10);
+        map.push_back(2, 20);
+        map.push_back(3, 30);
+        for (_, values) in map.range_mut(1..=1) {
+            values.clear();
+        }
+        for (key, values) in map.iter_mut() {
+            if *key == 3 {
+                values.clear();
+            }
+        }
+        assert_eq!(map.len(), 1);
+        assert!(!map.is_empty());
+        assert_eq!(map.first_key_value(), Some((&2, &20)));
+        assert_eq!(map.pop_last(), Some(20));
+        assert!(map.is_empty());
+        assert_eq!(map.pop_first(), None);
+    }
+}
@@ -0,0 +1,204 @@
+use crate::constants::VoxelType;
+use crate::voxel_map::VoxelMap;
+use nalgebra::Vector3;
+use rand::{Rng, SeedableRng};
+use std::collections::HashSet;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaveConfig {
+    pub width: u32,              // Width of entire cave (x-axis)
+    pub height: u32,             // Height of entire cave (y-axis)
+    pub depth: u32,              // Depth of entire cave (z-axis)
+    pub seed: Option<u64>,       // Seed value for random cave generation
+    pub open_probability: f64,   // Probability that a cell starts as open space
+    pub iterations: u32,         // Number of cellular automata steps
+    pub wall_threshold: u32,     // A cell becomes solid when more of its 26 neighbors are solid
+    pub keep_largest_only: bool, // Fill every open region except the largest one
+}
+
+impl Default for CaveConfig {
+    fn default() -> Self {
+        CaveConfig {
+            width: 32,
+            height: 10,
+            depth: 32,
+            seed: None,
+            open_probability: 0.55,
+            iterations: 4,
+            wall_threshold: 13,
+            keep_largest_only: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CaveResult {
+    pub voxel_map: VoxelMap,
+    pub open_cells: usize,
+}
+
+#[derive(Debug)]
+pub enum CaveError {
+    InvalidOpenProbability,
+    NoOpenSpace,
+}
+
+impl fmt::Display for CaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaveError::InvalidOpenProbability => {
+                write!(f, "open probability must be between 0 and 1")
+            }
+            CaveError::NoOpenSpace => write!(f, "no open space was left after smoothing"),
+        }
+    }
+}
+
+impl std::error::Error for CaveError {}
+
+pub fn generate_cave(config: CaveConfig) -> Result<CaveResult, CaveError> {
+    if !(0.0..=1.0).contains(&config.open_probability) {
+        return Err(CaveError::InvalidOpenProbability);
+    }
+
+    let mut rng: rand::rngs::StdRng = config
+        .seed
+        .map(SeedableRng::seed_from_u64)
+        .unwrap_or_else(rand::rngs::StdRng::from_entropy);
+
+    let (width, height, depth) = (
+        config.width as i32,
+        config.height as i32,
+        config.depth as i32,
+    );
+    let is_border = |p: &Vector3<i32>| {
+        p.x == 0
+            || p.y == 0
+            || p.z == 0
+            || p.x == width - 1
+            || p.y == height - 1
+            || p.z == depth - 1
+    };
+    let cells = (0..height)
+        .flat_map(|y| (0..depth).flat_map(move |z| (0..width).map(move |x| Vector3::new(x, y, z))))
+        .collect::<Vec<_>>();
+
+    let mut open = HashSet::new();
+    for p in cells.iter() {
+        if !is_border(p) && rng.gen_bool(config.open_probability) {
+            open.insert(*p);
+        }
+    }
+
+    for _ in 0..config.iterations {
+        let mut next = HashSet::new();
+        for p in cells.iter().filter(|p| !is_border(p)) {
+            let mut walls = 0;
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    for x in -1..=1 {
+                        if (x, y, z) != (0, 0, 0) && !open.contains(&(p + Vector3::new(x, y, z))) {
+                            walls += 1;
+                        }
+                    }
+                }
+            }
+            if walls <= config.wall_threshold {
+                next.insert(*p);
+            }
+        }
+        open = next;
+    }
+
+    if config.keep_largest_only {
+        open = largest_region(&open, &cells);
+    }
+    if open.is_empty() {
+        return Err(CaveError::NoOpenSpace);
+    }
+
+    let mut voxel_map = VoxelMap::new(0, 0, 0, width, height, depth);
+    for p in open.iter() {
+        voxel_map.map.insert(*p, VoxelType::CaveSpace);
+        let ground = p + Vector3::new(0, -1, 0);
+        if !open.contains(&ground) {
+            voxel_map.map.insert(ground, VoxelType::CaveFloor);
+        }
+    }
+
+    Ok(CaveResult {
+        voxel_map,
+        open_cells: open.len(),
+    })
+}
+
+impl CaveResult {
+    // 既存の部屋や通路を上書きせずに洞窟を合成する
+    pub fn merge_into(&self, voxel_map: &mut VoxelMap, offset: Vector3<i32>) {
+        for (p, voxel_type) in self.voxel_map.map.iter() {
+            let p = p + offset;
+            if voxel_map.contains(&p) {
+                voxel_map.map.entry(p).or_insert(*voxel_type);
+            }
+        }
+    }
+}
+
+fn largest_region(open: &HashSet<Vector3<i32>>, cells: &[Vector3<i32>]) -> HashSet<Vector3<i32>> {
+    let mut visited = HashSet::new();
+    let mut largest = HashSet::new();
+    for start in cells.iter().filter(|p| open.contains(p)) {
+        if visited.contains(start) {
+            continue;
+        }
+        let mut region = HashSet::new();
+        let mut stack = vec![*start];
+        while let Some(p) = stack.pop() {
+            if !visited.insert(p) {
+                continue;
+            }
+            region.insert(p);
+            for d in [
+                Vector3::new(-1, 0, 0),
+                Vector3::new(1, 0, 0),
+                Vector3::new(0, -1, 0),
+                Vector3::new(0, 1, 0),
+                Vector3::new(0, 0, -1),
+                Vector3::new(0, 0, 1),
+            ] {
+                let next = p + d;
+                if open.contains(&next) && !visited.contains(&next) {
+                    stack.push(next);
+                }
+            }
+        }
+        if region.len() > largest.len() {
+            largest = region;
+        }
+    }
+    largest
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cave_dungeon::{generate_cave, CaveConfig};
+    use crate::constants::VoxelType;
+
+    #[test]
+    fn test_generate_cave() {
+        let result = generate_cave(CaveConfig {
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        let spaces = result
+            .voxel_map
+            .map
+            .values()
+            .filter(|voxel_type| **voxel_type == VoxelType::CaveSpace)
+            .count();
+        assert_eq!(spaces, result.open_cells);
+        assert!(result.open_cells > 0);
+    }
+}
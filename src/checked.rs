@@ -0,0 +1,140 @@
+use crate::generate_drd::{
+    generate_dungeon_3d, Dungeon3DGeneratorConfig, Dungeon3DGeneratorError,
+    Dungeon3DGeneratorResult,
+};
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+
+// 生成中の panic を捕まえて Dungeon3DGeneratorError::Panicked に変換する
+// 利用者から受け取った設定で生成する場合や、ファズテストで見つかった入力を再現する場合に使う
+pub fn generate_dungeon_3d_checked(
+    config: Dungeon3DGeneratorConfig,
+) -> Result<Dungeon3DGeneratorResult, Dungeon3DGeneratorError> {
+    panic::catch_unwind(AssertUnwindSafe(|| generate_dungeon_3d(config))).unwrap_or_else(
+        |payload| {
+            Err(Dungeon3DGeneratorError::Panicked(panic_message(
+                payload.as_ref(),
+            )))
+        },
+    )
+}
+
+// シードだけを変えて生成をやり直し、失敗したシードとそのエラーを返す。過去に失敗したシードの回帰テストに使う
+pub fn replay_seeds<I: IntoIterator<Item = u64>>(
+    config: &Dungeon3DGeneratorConfig,
+    seeds: I,
+) -> Vec<(u64, Dungeon3DGeneratorError)> {
+    seeds
+        .into_iter()
+        .filter_map(|seed| {
+            generate_dungeon_3d_checked(Dungeon3DGeneratorConfig {
+                seed: Some(seed),
+                ..config.clone()
+            })
+            .err()
+            .map(|error| (seed, error))
+        })
+        .collect()
+}
+
+// ファズテストの入力をそのまま設定に変換して生成する。fuzz/artifacts に残った入力の再現に使う
+#[cfg(feature = "fuzz")]
+pub fn replay_input(
+    data: &[u8],
+) -> Option<Result<Dungeon3DGeneratorResult, Dungeon3DGeneratorError>> {
+    let config = arbitrary::Unstructured::new(data).arbitrary().ok()?;
+    Some(generate_dungeon_3d_checked(config))
+}
+
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+// 生成が数ミリ秒で終わる大きさに抑えた設定。範囲の逆転や 0 の大きさなど、不正な値もそのまま作る
+// 地表の高さや評価関数などの関数と、部屋の番号に依存する設定は既定値のまま
+#[cfg(feature = "fuzz")]
+impl<'a> arbitrary::Arbitrary<'a> for Dungeon3DGeneratorConfig {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let mut range = |max: u32| -> arbitrary::Result<std::ops::RangeInclusive<u32>> {
+            Ok(u.int_in_range(0..=max)?..=u.int_in_range(0..=max)?)
+        };
+        let room_width_range = range(12)?;
+        let room_height_range = range(6)?;
+        let room_depth_range = range(12)?;
+        Ok(Dungeon3DGeneratorConfig {
+            width: u.int_in_range(0..=48)?,
+            height: u.int_in_range(0..=16)?,
+            depth: u.int_in_range(0..=48)?,
+            seed: Some(u.arbitrary()?),
+            room_hierarchy: u.int_in_range(0..=4)?,
+            room_width_range,
+            room_height_range,
+            room_depth_range,
+            room_margin_x: u.int_in_range(0..=4)?,
+            room_margin_y: u.int_in_range(0..=2)?,
+            room_margin_z: u.int_in_range(0..=4)?,
+            room_density: u.int_in_range(0..=10)? as f32 / 10.0,
+            block_split_jitter: u.int_in_range(0..=5)? as f32 / 10.0,
+            room_aspect_ratio_range: u
+                .arbitrary::<Option<u8>>()?
+                .map(|ratio| 1.0..=1.0 + ratio as f32 / 64.0),
+            passage_height: u.int_in_range(0..=3)?,
+            margin_for_bounds: u.int_in_range(0..=4)?,
+            boundary_margin: u.int_in_range(0..=3)?,
+            surface_opening: u.arbitrary()?,
+            passage_wall_noise: u.int_in_range(0..=10)? as f64 / 10.0,
+            direct_doors: u.arbitrary()?,
+            merge_adjacent_rooms: u.arbitrary()?,
+            wide_doorway_room_size: u.arbitrary::<Option<u8>>()?.map(|size| size as u32 % 12),
+            trim_dead_ends: u.arbitrary()?,
+            min_inter_hierarchy_connections: u.int_in_range(0..=3)?,
+            min_room_degree: u.int_in_range(0..=3)?,
+            hub_min_degree: u.int_in_range(0..=4)?,
+            hub_growth: u.int_in_range(0..=3)?,
+            loop_probability: u.int_in_range(0..=10)? as f64 / 10.0,
+            max_connection_length: u.arbitrary::<Option<u8>>()?.map(u32::from),
+            split_long_connections: u.arbitrary()?,
+            max_connection_rise: u.arbitrary::<Option<u8>>()?.map(|rise| rise as u32 % 8),
+            avoid_foreign_rooms: u.arbitrary()?,
+            foreign_room_penalty: u.int_in_range(0..=8)?,
+            skip_connected_rooms: u.arbitrary()?,
+            max_route_nodes: u.arbitrary::<Option<u16>>()?.map(u32::from),
+            guided_route_search: u.arbitrary()?,
+            stair_run: u.int_in_range(0..=3)?,
+            stair_clearance: u
+                .arbitrary::<Option<u8>>()?
+                .map(|clearance| clearance as u32 % 5),
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::checked::{generate_dungeon_3d_checked, replay_seeds};
+    use crate::generate_drd::{Dungeon3DGeneratorConfig, Dungeon3DGeneratorError};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_generate_dungeon_3d_checked() {
+        // 利用者の関数の panic もエラーになる
+        let result = generate_dungeon_3d_checked(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            room_placement_score: Some(Arc::new(|_| panic!("score"))),
+            ..Default::default()
+        });
+        assert!(matches!(result, Err(Dungeon3DGeneratorError::Panicked(_))));
+
+        let config = Dungeon3DGeneratorConfig::default();
+        assert!(replay_seeds(&config, 0..4).is_empty());
+        let failures = replay_seeds(&Dungeon3DGeneratorConfig { width: 4, ..config }, [1, 2]);
+        assert_eq!(
+            failures.iter().map(|(seed, _)| *seed).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+    }
+}
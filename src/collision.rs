@@ -0,0 +1,129 @@
+use crate::constants::{Direction4, VoxelType};
+use crate::voxel_map::VoxelMap;
+use nalgebra::Vector3;
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CollisionBox {
+    pub min: (i32, i32, i32),      // Inclusive voxel coordinates
+    pub max: (i32, i32, i32),      // Exclusive voxel coordinates
+    pub stair: Option<Direction4>, // Single stair voxel; replace the box with a slope rising this way
+}
+
+impl CollisionBox {
+    pub fn volume(&self) -> usize {
+        ((self.max.0 - self.min.0) * (self.max.1 - self.min.1) * (self.max.2 - self.min.2)) as usize
+    }
+}
+
+// 物理エンジン向けに、固いボクセルを貪欲法でなるべく大きな直方体にまとめる
+// x、z、y の順に広げられるだけ広げる。階段は斜面に置き換えられるよう1つずつ別に出す
+pub fn export_collision_boxes(voxel_map: &VoxelMap) -> Vec<CollisionBox> {
+    let mut boxes = Vec::new();
+    let mut solids = HashSet::new();
+    for (p, voxel_type) in voxel_map.map.iter() {
+        match voxel_type {
+            VoxelType::PassageStair(direction) => boxes.push(CollisionBox {
+                min: (p.x, p.y, p.z),
+                max: (p.x + 1, p.y + 1, p.z + 1),
+                stair: Some(*direction),
+            }),
+            voxel_type if !voxel_type.is_passable() => {
+                solids.insert(*p);
+            }
+            _ => {}
+        }
+    }
+    boxes.sort_by_key(|collision_box| collision_box.min);
+
+    let mut points = solids.iter().copied().collect::<Vec<_>>();
+    points.sort_by_key(|p| (p.y, p.z, p.x));
+    for start in points {
+        if !solids.contains(&start) {
+            continue;
+        }
+        let mut end = start + Vector3::new(1, 1, 1);
+        for axis in [Vector3::x(), Vector3::z(), Vector3::y()] {
+            // 広げる方向の次の一層が全て固いボクセルなら広げる
+            while contains_all(
+                &solids,
+                start + (end - start).component_mul(&axis),
+                end + axis,
+            ) {
+                end += axis;
+            }
+        }
+        for y in start.y..end.y {
+            for z in start.z..end.z {
+                for x in start.x..end.x {
+                    solids.remove(&Vector3::new(x, y, z));
+                }
+            }
+        }
+        boxes.push(CollisionBox {
+            min: (start.x, start.y, start.z),
+            max: (end.x, end.y, end.z),
+            stair: None,
+        });
+    }
+    boxes
+}
+
+fn contains_all(solids: &HashSet<Vector3<i32>>, min: Vector3<i32>, max: Vector3<i32>) -> bool {
+    (min.y..max.y).all(|y| {
+        (min.z..max.z).all(|z| (min.x..max.x).all(|x| solids.contains(&Vector3::new(x, y, z))))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collision::export_collision_boxes;
+    use crate::generate_drd::{generate_dungeon_3d, Dungeon3DGeneratorConfig};
+    use nalgebra::Vector3;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_export_collision_boxes() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        let boxes = export_collision_boxes(&result.voxel_map);
+        let solids = result
+            .voxel_map
+            .map
+            .iter()
+            .filter(|(_, voxel_type)| !voxel_type.is_passable())
+            .count();
+        let solid_boxes = boxes
+            .iter()
+            .filter(|collision_box| collision_box.stair.is_none())
+            .collect::<Vec<_>>();
+        assert!(solid_boxes.len() * 4 < solids);
+
+        // 箱は重ならず、固いボクセルをちょうど覆う
+        let mut covered = HashSet::new();
+        for collision_box in solid_boxes.iter() {
+            let (x0, y0, z0) = collision_box.min;
+            let (x1, y1, z1) = collision_box.max;
+            for y in y0..y1 {
+                for z in z0..z1 {
+                    for x in x0..x1 {
+                        let p = Vector3::new(x, y, z);
+                        assert!(!result.voxel_map.get(&p).is_passable());
+                        assert!(covered.insert(p));
+                    }
+                }
+            }
+        }
+        assert_eq!(covered.len(), solids);
+        assert_eq!(
+            solid_boxes
+                .iter()
+                .map(|collision_box| collision_box.volume())
+                .sum::<usize>(),
+            solids
+        );
+    }
+}
@@ -0,0 +1,294 @@
+use crate::generate_drd::{ConfigViolation, Dungeon3DGeneratorConfig};
+use crate::room::RoomId;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigFileError {
+    Parse(String), // Syntax error, unknown field or wrong type, with the line and column reported by toml
+    Invalid(Vec<ConfigViolation>), // The file parsed but the config breaks these constraints
+    Serialize(String), // The config holds a value TOML cannot represent, e.g. a seed above i64::MAX
+}
+
+impl fmt::Display for ConfigFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigFileError::Parse(message) => write!(f, "could not read config: {}", message),
+            ConfigFileError::Invalid(violations) => {
+                write!(f, "invalid config: ")?;
+                for (i, violation) in violations.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", violation)?;
+                }
+                Ok(())
+            }
+            ConfigFileError::Serialize(message) => {
+                write!(f, "could not write config: {}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigFileError {}
+
+// 設定ファイルに書ける項目。範囲は [start, end] の配列で書く
+// 地表の高さや評価関数などの関数と material_palette は書けないため、読み込んだ設定では既定値になる
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    width: u32,
+    height: u32,
+    depth: u32,
+    seed: Option<u64>,
+    room_hierarchy: u32,
+    room_width_range: [u32; 2],
+    room_height_range: [u32; 2],
+    room_depth_range: [u32; 2],
+    room_margin_x: u32,
+    room_margin_y: u32,
+    room_margin_z: u32,
+    room_density: f32,
+    room_aspect_ratio_range: Option<[f32; 2]>,
+    block_split_jitter: f32,
+    passage_height: u32,
+    margin_for_bounds: u32,
+    boundary_margin: u32,
+    room_anchors: Vec<(u32, u32, u32)>,
+    surface_opening: bool,
+    passage_wall_noise: f64,
+    direct_doors: bool,
+    merge_adjacent_rooms: bool,
+    wide_doorway_room_size: Option<u32>,
+    trim_dead_ends: bool,
+    min_inter_hierarchy_connections: u32,
+    min_room_degree: u32,
+    entrance_rooms: Vec<u64>,
+    objective_rooms: Vec<u64>,
+    max_objective_distance: Option<u32>,
+    hub_min_degree: u32,
+    hub_growth: u32,
+    loop_probability: f64,
+    max_connection_length: Option<u32>,
+    split_long_connections: bool,
+    max_connection_rise: Option<u32>,
+    excluded_room_pairs: Vec<(u64, u64)>,
+    excluded_hierarchy_pairs: Vec<(u32, u32)>,
+    avoid_foreign_rooms: bool,
+    foreign_room_penalty: u32,
+    skip_connected_rooms: bool,
+    max_route_nodes: Option<u32>,
+    guided_route_search: bool,
+    stair_run: u32,
+    stair_clearance: Option<u32>,
+    report: bool,
+}
+
+impl Default for ConfigFile {
+    fn default() -> Self {
+        ConfigFile::from(&Dungeon3DGeneratorConfig::default())
+    }
+}
+
+impl From<&Dungeon3DGeneratorConfig> for ConfigFile {
+    fn from(config: &Dungeon3DGeneratorConfig) -> Self {
+        let range = |range: &std::ops::RangeInclusive<u32>| [*range.start(), *range.end()];
+        ConfigFile {
+            width: config.width,
+            height: config.height,
+            depth: config.depth,
+            seed: config.seed,
+            room_hierarchy: config.room_hierarchy,
+            room_width_range: range(&config.room_width_range),
+            room_height_range: range(&config.room_height_range),
+            room_depth_range: range(&config.room_depth_range),
+            room_margin_x: config.room_margin_x,
+            room_margin_y: config.room_margin_y,
+            room_margin_z: config.room_margin_z,
+            room_density: config.room_density,
+            room_aspect_ratio_range: config
+                .room_aspect_ratio_range
+                .as_ref()
+                .map(|range| [*range.start(), *range.end()]),
+            block_split_jitter: config.block_split_jitter,
+            passage_height: config.passage_height,
+            margin_for_bounds: config.margin_for_bounds,
+            boundary_margin: config.boundary_margin,
+            room_anchors: config.room_anchors.clone(),
+            surface_opening: config.surface_opening,
+            passage_wall_noise: config.passage_wall_noise,
+            direct_doors: config.direct_doors,
+            merge_adjacent_rooms: config.merge_adjacent_rooms,
+            wide_doorway_room_size: config.wide_doorway_room_size,
+            trim_dead_ends: config.trim_dead_ends,
+            min_inter_hierarchy_connections: config.min_inter_hierarchy_connections,
+            min_room_degree: config.min_room_degree,
+            entrance_rooms: config.entrance_rooms.iter().map(RoomId::inner).collect(),
+            objective_rooms: config.objective_rooms.iter().map(RoomId::inner).collect(),
+            max_objective_distance: config.max_objective_distance,
+            hub_min_degree: config.hub_min_degree,
+            hub_growth: config.hub_growth,
+            loop_probability: config.loop_probability,
+            max_connection_length: config.max_connection_length,
+            split_long_connections: config.split_long_connections,
+            max_connection_rise: config.max_connection_rise,
+            excluded_room_pairs: config
+                .excluded_room_pairs
+                .iter()
+                .map(|(room0, room1)| (room0.inner(), room1.inner()))
+                .collect(),
+            excluded_hierarchy_pairs: config.excluded_hierarchy_pairs.clone(),
+            avoid_foreign_rooms: config.avoid_foreign_rooms,
+            foreign_room_penalty: config.foreign_room_penalty,
+            skip_connected_rooms: config.skip_connected_rooms,
+            max_route_nodes: config.max_route_nodes,
+            guided_route_search: config.guided_route_search,
+            stair_run: config.stair_run,
+            stair_clearance: config.stair_clearance,
+            report: config.report,
+        }
+    }
+}
+
+impl From<ConfigFile> for Dungeon3DGeneratorConfig {
+    fn from(file: ConfigFile) -> Self {
+        let range = |[start, end]: [u32; 2]| start..=end;
+        Dungeon3DGeneratorConfig {
+            width: file.width,
+            height: file.height,
+            depth: file.depth,
+            seed: file.seed,
+            room_hierarchy: file.room_hierarchy,
+            room_width_range: range(file.room_width_range),
+            room_height_range: range(file.room_height_range),
+            room_depth_range: range(file.room_depth_range),
+            room_margin_x: file.room_margin_x,
+            room_margin_y: file.room_margin_y,
+            room_margin_z: file.room_margin_z,
+            room_density: file.room_density,
+            room_aspect_ratio_range: file.room_aspect_ratio_range.map(|[start, end]| start..=end),
+            block_split_jitter: file.block_split_jitter,
+            passage_height: file.passage_height,
+            margin_for_bounds: file.margin_for_bounds,
+            boundary_margin: file.boundary_margin,
+            room_anchors: file.room_anchors,
+            surface_opening: file.surface_opening,
+            passage_wall_noise: file.passage_wall_noise,
+            direct_doors: file.direct_doors,
+            merge_adjacent_rooms: file.merge_adjacent_rooms,
+            wide_doorway_room_size: file.wide_doorway_room_size,
+            trim_dead_ends: file.trim_dead_ends,
+            min_inter_hierarchy_connections: file.min_inter_hierarchy_connections,
+            min_room_degree: file.min_room_degree,
+            entrance_rooms: file
+                .entrance_rooms
+                .into_iter()
+                .map(RoomId::from_inner)
+                .collect(),
+            objective_rooms: file
+                .objective_rooms
+                .into_iter()
+                .map(RoomId::from_inner)
+                .collect(),
+            max_objective_distance: file.max_objective_distance,
+            hub_min_degree: file.hub_min_degree,
+            hub_growth: file.hub_growth,
+            loop_probability: file.loop_probability,
+            max_connection_length: file.max_connection_length,
+            split_long_connections: file.split_long_connections,
+            max_connection_rise: file.max_connection_rise,
+            excluded_room_pairs: file
+                .excluded_room_pairs
+                .into_iter()
+                .map(|(room0, room1)| (RoomId::from_inner(room0), RoomId::from_inner(room1)))
+                .collect(),
+            excluded_hierarchy_pairs: file.excluded_hierarchy_pairs,
+            avoid_foreign_rooms: file.avoid_foreign_rooms,
+            foreign_room_penalty: file.foreign_room_penalty,
+            skip_connected_rooms: file.skip_connected_rooms,
+            max_route_nodes: file.max_route_nodes,
+            guided_route_search: file.guided_route_search,
+            stair_run: file.stair_run,
+            stair_clearance: file.stair_clearance,
+            report: file.report,
+            ..Default::default()
+        }
+    }
+}
+
+impl Dungeon3DGeneratorConfig {
+    // TOML から設定を読む。書かれていない項目は既定値になり、知らない項目は誤字として Parse エラーにする
+    // 読めた設定は violations で検証し、満たされていない制約を全て返す
+    pub fn from_toml_str(text: &str) -> Result<Self, ConfigFileError> {
+        let file: ConfigFile =
+            toml::from_str(text).map_err(|error| ConfigFileError::Parse(error.to_string()))?;
+        let config = Dungeon3DGeneratorConfig::from(file);
+        let violations = config.violations();
+        if !violations.is_empty() {
+            return Err(ConfigFileError::Invalid(violations));
+        }
+        Ok(config)
+    }
+
+    // from_toml_str で読める TOML に書き出す。関数と material_palette は書き出さない
+    pub fn to_toml_string(&self) -> Result<String, ConfigFileError> {
+        toml::to_string(&ConfigFile::from(self))
+            .map_err(|error| ConfigFileError::Serialize(error.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config_file::ConfigFileError;
+    use crate::generate_drd::{generate_dungeon_3d, Dungeon3DGeneratorConfig};
+    use crate::room::RoomId;
+
+    #[test]
+    fn test_toml_round_trip() {
+        let config = Dungeon3DGeneratorConfig {
+            seed: Some(7),
+            width: 40,
+            room_width_range: 4..=8,
+            room_aspect_ratio_range: Some(1.0..=1.5),
+            excluded_room_pairs: vec![(RoomId::first(), RoomId::first().shifted(2))],
+            guided_route_search: true,
+            ..Default::default()
+        };
+        let text = config.to_toml_string().unwrap();
+        assert!(text.contains("room_width_range = [4, 8]"));
+        let loaded = Dungeon3DGeneratorConfig::from_toml_str(&text).unwrap();
+        assert_eq!(
+            loaded.to_toml_string().unwrap(),
+            config.to_toml_string().unwrap()
+        );
+        assert_eq!(
+            generate_dungeon_3d(loaded).unwrap().to_canonical_string(),
+            generate_dungeon_3d(config).unwrap().to_canonical_string()
+        );
+
+        // 書かれていない項目は既定値になる
+        let config = Dungeon3DGeneratorConfig::from_toml_str("seed = 3\nwidth = 48").unwrap();
+        assert_eq!((config.seed, config.width), (Some(3), 48));
+        assert_eq!(config.depth, Dungeon3DGeneratorConfig::default().depth);
+
+        // 誤字と範囲外の値
+        let error = Dungeon3DGeneratorConfig::from_toml_str("widht = 48")
+            .err()
+            .unwrap();
+        assert!(matches!(&error, ConfigFileError::Parse(message) if message.contains("widht")));
+        let error = Dungeon3DGeneratorConfig::from_toml_str(
+            "room_width_range = [0, 4]\nroom_density = 2.0",
+        )
+        .err()
+        .unwrap();
+        let ConfigFileError::Invalid(violations) = &error else {
+            panic!("{:?}", error);
+        };
+        assert_eq!(
+            violations.iter().map(|v| v.field).collect::<Vec<_>>(),
+            vec!["room_width_range", "room_density"]
+        );
+        assert!(error.to_string().contains("room_density = 2.0"));
+    }
+}
@@ -0,0 +1,153 @@
+use crate::passage::PassageId;
+use crate::room::{Room, RoomId};
+use crate::voxel_map::VoxelMap;
+use nalgebra::Vector3;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ConnectivityRepair {
+    pub room0_id: RoomId,
+    pub room1_id: RoomId,
+    pub passage_id: PassageId, // Id of the repair passage in the result passages
+}
+
+const NEIGHBORS: [(i32, i32, i32); 6] = [
+    (-1, 0, 0),
+    (1, 0, 0),
+    (0, -1, 0),
+    (0, 1, 0),
+    (0, 0, -1),
+    (0, 0, 1),
+];
+
+// 通れるボクセルでたどり着ける部屋同士をまとめる
+pub fn room_components(
+    voxel_map: &VoxelMap,
+    rooms: &BTreeMap<RoomId, Room>,
+) -> Vec<BTreeSet<RoomId>> {
+    let mut visited: HashSet<Vector3<i32>> = HashSet::new();
+    let mut assigned: BTreeSet<RoomId> = BTreeSet::new();
+    let mut components = Vec::new();
+    for room in rooms.values() {
+        if assigned.contains(&room.id) {
+            continue;
+        }
+        let origin = Vector3::new(
+            room.origin.0 as i32,
+            room.origin.1 as i32,
+            room.origin.2 as i32,
+        );
+        let mut component = BTreeSet::from([room.id]);
+        let mut stack = vec![origin];
+        while let Some(p) = stack.pop() {
+            if !visited.insert(p) {
+                continue;
+            }
+            if let Some(room_id) = voxel_map.get(&p).room_id() {
+                component.insert(room_id);
+            }
+            for (x, y, z) in NEIGHBORS {
+                let next = p + Vector3::new(x, y, z);
+                if voxel_map.get(&next).is_passable() && !visited.contains(&next) {
+                    stack.push(next);
+                }
+            }
+        }
+        assigned.extend(component.iter().copied());
+        components.push(component);
+    }
+    components
+}
+
+// 2つの部屋が通れるボクセルでつながっているか。room1 に届いた時点で探索をやめる
+pub fn rooms_connected(voxel_map: &VoxelMap, room0: &Room, room1: &Room) -> bool {
+    let origin = Vector3::new(
+        room0.origin.0 as i32,
+        room0.origin.1 as i32,
+        room0.origin.2 as i32,
+    );
+    let mut visited: HashSet<Vector3<i32>> = HashSet::new();
+    let mut stack = vec![origin];
+    while let Some(p) = stack.pop() {
+        if !visited.insert(p) {
+            continue;
+        }
+        if voxel_map.get(&p).room_id() == Some(room1.id) {
+            return true;
+        }
+        for (x, y, z) in NEIGHBORS {
+            let next = p + Vector3::new(x, y, z);
+            if voxel_map.get(&next).is_passable() && !visited.contains(&next) {
+                stack.push(next);
+            }
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::connectivity::room_components;
+    use crate::generate_drd::{Dungeon3DGeneratorConfig, Dungeon3DGeneratorError};
+    use crate::pipeline::{GenerationPipeline, GenerationState, SelectEdges};
+    use std::collections::BTreeSet;
+
+    // 辺を1つも選ばず、全ての通路を接続性の修復で掘らせる
+    struct NoEdges;
+    impl SelectEdges for NoEdges {
+        fn select_edges(&self, _: &mut GenerationState) -> Result<(), Dungeon3DGeneratorError> {
+            Ok(())
+        }
+    }
+
+    fn config() -> Dungeon3DGeneratorConfig {
+        Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            width: 32,
+            depth: 32,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_repair_connectivity() {
+        let pipeline = GenerationPipeline {
+            select_edges: Box::new(NoEdges),
+            ..Default::default()
+        };
+        let result = pipeline.generate(config()).unwrap();
+        assert!(result.rooms.len() > 2);
+        assert!(!result.repairs.is_empty());
+        assert_eq!(result.repairs.len(), result.passages.len());
+        for repair in result.repairs.iter() {
+            let passage = &result.passages[&repair.passage_id];
+            assert_eq!(
+                BTreeSet::from([passage.start_room_id, passage.end_room_id]),
+                BTreeSet::from([repair.room0_id, repair.room1_id])
+            );
+        }
+        assert_eq!(room_components(&result.voxel_map, &result.rooms).len(), 1);
+    }
+
+    #[test]
+    fn test_disconnected() {
+        // 経路探索が最初の1歩で打ち切られるため、どの部屋の組もつなげない
+        let config = Dungeon3DGeneratorConfig {
+            max_route_nodes: Some(1),
+            ..config()
+        };
+        let layout = GenerationPipeline::default()
+            .generate_layout(config.clone())
+            .unwrap();
+        let Err(Dungeon3DGeneratorError::Disconnected(components)) =
+            GenerationPipeline::default().generate(config)
+        else {
+            panic!("generation should fail as disconnected");
+        };
+        assert_eq!(components.len(), layout.rooms.len());
+        assert_eq!(
+            components.into_iter().flatten().collect::<BTreeSet<_>>(),
+            layout.rooms.keys().copied().collect()
+        );
+    }
+}
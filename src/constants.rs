@@ -20,6 +20,11 @@ impl Direction4 {
         }
     }
 
+    // 水平方向の単位ベクトルに対応する向き
+    pub fn from_vec3(v: &Vector3<i32>) -> Option<Self> {
+        DIRECTIONS.iter().copied().find(|dir| dir.to_vec3() == *v)
+    }
+
     pub fn is_opposite(&self, other: &Self) -> bool {
         match self {
             Direction4::Left => other == &Direction4::Right,
@@ -39,6 +44,13 @@ impl Direction4 {
     }
 }
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
 pub static DIRECTIONS: LazyLock<[Direction4; 4]> = LazyLock::new(|| {
     [
         Direction4::Left,
@@ -48,7 +60,7 @@ pub static DIRECTIONS: LazyLock<[Direction4; 4]> = LazyLock::new(|| {
     ]
 });
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub enum VoxelType {
     RoomSpace(RoomId),       // 部屋の空間
     RoomFloor(RoomId),       // 部屋の床
@@ -58,4 +70,53 @@ pub enum VoxelType {
     PassageStair(Direction4),
     PassageSpace,
     PassageFloor,
+    CaveSpace,
+    CaveFloor,
+    Doorway(RoomId), // 通路が部屋に入る境界の空間
+    Custom(u16), // 利用側が後処理で置く独自の種類 (溶岩、瓦礫など)。生成器は置かず、通れないものとして扱う
+}
+
+impl VoxelType {
+    // 人や通路が通り抜けられる空間か
+    pub fn is_passable(&self) -> bool {
+        matches!(
+            self,
+            VoxelType::RoomSpace(_)
+                | VoxelType::RoomBottomSpace(_)
+                | VoxelType::PassageStair(_)
+                | VoxelType::PassageSpace
+                | VoxelType::CaveSpace
+                | VoxelType::Doorway(_)
+        )
+    }
+
+    pub fn room_id(&self) -> Option<RoomId> {
+        match self {
+            VoxelType::RoomSpace(room_id)
+            | VoxelType::RoomFloor(room_id)
+            | VoxelType::RoomBottomSpace(room_id)
+            | VoxelType::RoomWall(room_id) => Some(*room_id),
+            _ => None,
+        }
+    }
+
+    // 階段の向きを変換した同じ種類のボクセル
+    pub fn map_direction<F: Fn(Direction4) -> Direction4>(&self, f: F) -> Self {
+        match *self {
+            VoxelType::PassageStair(direction) => VoxelType::PassageStair(f(direction)),
+            voxel_type => voxel_type,
+        }
+    }
+
+    // 部屋の番号を付け替えた同じ種類のボクセル
+    pub fn map_room_id<F: Fn(RoomId) -> RoomId>(&self, f: F) -> Self {
+        match *self {
+            VoxelType::RoomSpace(room_id) => VoxelType::RoomSpace(f(room_id)),
+            VoxelType::RoomFloor(room_id) => VoxelType::RoomFloor(f(room_id)),
+            VoxelType::RoomBottomSpace(room_id) => VoxelType::RoomBottomSpace(f(room_id)),
+            VoxelType::RoomWall(room_id) => VoxelType::RoomWall(f(room_id)),
+            VoxelType::Doorway(room_id) => VoxelType::Doorway(f(room_id)),
+            voxel_type => voxel_type,
+        }
+    }
 }
@@ -1,14 +1,34 @@
 use crate::constants::{Direction4, DIRECTIONS};
 use crate::room::RoomId;
 use nalgebra::Vector3;
+use rand::distributions::{Distribution, WeightedIndex};
 use rand::prelude::SliceRandom;
 use rand::{Rng, SeedableRng};
 use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fmt;
 
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct CEDConfig {
     pub room_candidates: Vec<CEDRoomCandidate>,
     pub room_size_max: usize,
-    pub seed: Option<u64>, // Seed value for random dungeon generation
+    pub frontier: CEDFrontierStrategy, // Order in which placed rooms are expanded
+    pub seed_rooms: Vec<CEDSeedRoom>, // Rooms expansion starts from; empty places one random room at the origin
+    pub seed: Option<u64>,            // Seed value for random dungeon generation
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct CEDSeedRoom {
+    pub origin: (i32, i32, i32),
+    pub room_candidate_index: Option<usize>, // None picks a random room candidate
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum CEDFrontierStrategy {
+    #[default]
+    BreadthFirst, // Oldest room first; compact, blob-like layouts
+    DepthFirst,       // Newest room first; long snaking layouts
+    Random,           // Any room with equal probability; branchy layouts
+    DistanceWeighted, // Rooms farther from the first room are more likely
 }
 
 impl Default for CEDConfig {
@@ -26,6 +46,8 @@ impl Default for CEDConfig {
                     ((1, 0, 0), Direction4::Far),
                 ],
                 can_be_terminal: true,
+                footprint: None,
+                exit_tags: BTreeMap::new(),
             },
             // T0
             CEDRoomCandidate {
@@ -38,6 +60,8 @@ impl Default for CEDConfig {
                     ((1, 0, 1), Direction4::Near),
                 ],
                 can_be_terminal: true,
+                footprint: None,
+                exit_tags: BTreeMap::new(),
             },
             // T1
             CEDRoomCandidate {
@@ -50,6 +74,8 @@ impl Default for CEDConfig {
                     ((1, 0, 0), Direction4::Far),
                 ],
                 can_be_terminal: true,
+                footprint: None,
+                exit_tags: BTreeMap::new(),
             },
             // T2
             CEDRoomCandidate {
@@ -62,6 +88,8 @@ impl Default for CEDConfig {
                     ((0, 0, 1), Direction4::Left),
                 ],
                 can_be_terminal: true,
+                footprint: None,
+                exit_tags: BTreeMap::new(),
             },
             // T3
             CEDRoomCandidate {
@@ -74,6 +102,8 @@ impl Default for CEDConfig {
                     ((1, 0, 1), Direction4::Right),
                 ],
                 can_be_terminal: true,
+                footprint: None,
+                exit_tags: BTreeMap::new(),
             },
             // Stair left-right
             CEDRoomCandidate {
@@ -85,6 +115,8 @@ impl Default for CEDConfig {
                     ((0, 1, 0), Direction4::Right),
                 ],
                 can_be_terminal: false,
+                footprint: None,
+                exit_tags: BTreeMap::new(),
             },
             // Stair right-left
             CEDRoomCandidate {
@@ -96,6 +128,8 @@ impl Default for CEDConfig {
                     ((0, 0, 0), Direction4::Right),
                 ],
                 can_be_terminal: false,
+                footprint: None,
+                exit_tags: BTreeMap::new(),
             },
             // Stair far-near
             CEDRoomCandidate {
@@ -107,6 +141,8 @@ impl Default for CEDConfig {
                     ((0, 1, 0), Direction4::Far),
                 ],
                 can_be_terminal: false,
+                footprint: None,
+                exit_tags: BTreeMap::new(),
             },
             // Stair far-near
             CEDRoomCandidate {
@@ -118,23 +154,29 @@ impl Default for CEDConfig {
                     ((0, 0, 0), Direction4::Far),
                 ],
                 can_be_terminal: false,
+                footprint: None,
+                exit_tags: BTreeMap::new(),
             },
         ];
         CEDConfig {
             room_candidates,
             room_size_max: 20,
+            frontier: CEDFrontierStrategy::BreadthFirst,
+            seed_rooms: Vec::new(),
             seed: None,
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct CEDRoomCandidate {
     pub width: u32,
     pub height: u32,
     pub depth: u32,
     pub exit_and_entrances: Vec<((i32, i32, i32), Direction4)>, // x, y, z
     pub can_be_terminal: bool,
+    pub footprint: Option<BTreeSet<(i32, i32, i32)>>, // Occupied cells within the box; None occupies the whole box
+    pub exit_tags: BTreeMap<Direction4, String>, // Tagged exits only connect to exits with the same tag
 }
 
 impl Default for CEDRoomCandidate {
@@ -145,57 +187,141 @@ impl Default for CEDRoomCandidate {
             depth: 3,
             exit_and_entrances: vec![],
             can_be_terminal: true,
+            footprint: None,
+            exit_tags: BTreeMap::new(),
         }
     }
 }
 
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct RoomCandidateEntity {
     pub index: usize,
     pub origin: (i32, i32, i32),
 }
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CEDRemovalReason {
+    NonTerminalDeadEnd, // Had fewer than two connections but cannot be terminal
+    Disconnected,       // Cut off from the first room after pruning
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RemovedRoomCandidate {
+    pub entity: RoomCandidateEntity,
+    pub reason: CEDRemovalReason,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct CEDResult {
     pub room_candidates: Vec<CEDRoomCandidate>,
     pub room_candidate_entities: BTreeMap<RoomId, RoomCandidateEntity>,
     pub room_candidate_connections: BTreeMap<RoomId, BTreeSet<RoomId>>,
+    pub removed_room_candidates: BTreeMap<RoomId, RemovedRoomCandidate>, // Placed during expansion but dropped afterwards
+    pub cell_map: HashMap<Vector3<i32>, RoomId>, // Cells occupied by the remaining rooms
 }
 
 #[derive(Debug)]
 pub enum CEDError {
     InvalidRoomCandidateExitAndEntrance { index: usize },
+    InvalidRoomCandidateFootprint { index: usize }, // The footprint is empty or leaves the box
+    InvalidSeedRoom { index: usize }, // Unknown room candidate, or overlaps an earlier seed room
+    SeedRoomsNotMerged,               // Rooms grown from different seed rooms never joined
 }
 
+impl fmt::Display for CEDError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CEDError::InvalidRoomCandidateExitAndEntrance { index } => write!(
+                f,
+                "room candidate {} has an invalid exit or entrance",
+                index
+            ),
+            CEDError::InvalidRoomCandidateFootprint { index } => write!(
+                f,
+                "room candidate {} has an empty footprint or cells outside its box",
+                index
+            ),
+            CEDError::InvalidSeedRoom { index } => write!(
+                f,
+                "seed room {} has an unknown room candidate or overlaps another seed room",
+                index
+            ),
+            CEDError::SeedRoomsNotMerged => {
+                write!(f, "rooms grown from different seed rooms never joined")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CEDError {}
+
+type EntranceCandidate = (usize, (i32, i32, i32)); // room candidate index, entrance offset
+
 #[derive(Debug)]
 struct OptimizedRoomCandidate {
-    pub width: u32,
-    pub height: u32,
-    pub depth: u32,
+    pub cells: Vec<Vector3<i32>>, // Occupied cells relative to the origin
     pub exit_and_entrances: BTreeMap<Direction4, (i32, i32, i32)>, // x, y, z
+    pub exit_tags: BTreeMap<Direction4, String>,
+}
+
+impl CEDRoomCandidate {
+    // 占有するセル。footprint がなければ箱全体を占有する
+    pub fn cells(&self) -> BTreeSet<(i32, i32, i32)> {
+        match self.footprint.as_ref() {
+            Some(footprint) => footprint.clone(),
+            None => (0..self.width as i32)
+                .flat_map(|x| {
+                    (0..self.height as i32)
+                        .flat_map(move |y| (0..self.depth as i32).map(move |z| (x, y, z)))
+                })
+                .collect(),
+        }
+    }
+
+    fn contains(&self, (x, y, z): (i32, i32, i32)) -> bool {
+        (0..self.width as i32).contains(&x)
+            && (0..self.height as i32).contains(&y)
+            && (0..self.depth as i32).contains(&z)
+    }
 }
 
 pub fn generate_ced(config: CEDConfig) -> Result<CEDResult, CEDError> {
-    if let Some((index, _)) =
-        config
-            .room_candidates
+    let mut rng: rand::rngs::StdRng = config
+        .seed
+        .map(SeedableRng::seed_from_u64)
+        .unwrap_or_else(rand::rngs::StdRng::from_entropy);
+    generate_ced_with_rng(config, &mut rng)
+}
+
+// ゲーム全体で決まった乱数生成器を使う場合向けに、config.seed の代わりに渡された乱数で生成する
+pub fn generate_ced_with_rng<R: Rng>(
+    config: CEDConfig,
+    rng: &mut R,
+) -> Result<CEDResult, CEDError> {
+    if let Some(index) = config.room_candidates.iter().position(|room_candidate| {
+        room_candidate.footprint.as_ref().is_some_and(|footprint| {
+            footprint.is_empty() || !footprint.iter().all(|p| room_candidate.contains(*p))
+        })
+    }) {
+        return Err(CEDError::InvalidRoomCandidateFootprint { index });
+    }
+    // 出入口は占有するセルのうち、その向きの隣が占有されていないセルに置く
+    if let Some(index) = config.room_candidates.iter().position(|room_candidate| {
+        let cells = room_candidate.cells();
+        room_candidate
+            .exit_and_entrances
             .iter()
-            .enumerate()
-            .find(|(_, room_candidate)| {
-                room_candidate
+            .any(|((x, y, z), dir)| {
+                let v = dir.to_vec3();
+                !cells.contains(&(*x, *y, *z)) || cells.contains(&(x + v.x, y + v.y, z + v.z))
+            })
+            || room_candidate.exit_tags.keys().any(|tag_dir| {
+                !room_candidate
                     .exit_and_entrances
                     .iter()
-                    .any(|((x, y, z), dir)| {
-                        *y < 0
-                            || room_candidate.height as i32 <= *y
-                            || !validate_dir_of_room_candidate(
-                                *x,
-                                *z,
-                                room_candidate.width,
-                                room_candidate.depth,
-                                *dir,
-                            )
-                    })
+                    .any(|(_, dir)| dir == tag_dir)
             })
-    {
+    }) {
         return Err(CEDError::InvalidRoomCandidateExitAndEntrance { index });
     }
 
@@ -203,24 +329,21 @@ pub fn generate_ced(config: CEDConfig) -> Result<CEDResult, CEDError> {
         .room_candidates
         .iter()
         .map(|room_candidate| OptimizedRoomCandidate {
-            width: room_candidate.width,
-            height: room_candidate.height,
-            depth: room_candidate.depth,
+            cells: room_candidate
+                .cells()
+                .into_iter()
+                .map(|(x, y, z)| Vector3::new(x, y, z))
+                .collect(),
             exit_and_entrances: room_candidate
                 .exit_and_entrances
                 .iter()
                 .map(|((x, y, z), dir)| (*dir, (*x, *y, *z)))
                 .collect(),
+            exit_tags: room_candidate.exit_tags.clone(),
         })
         .collect::<Vec<_>>();
 
-    let mut rng: rand::rngs::StdRng = config
-        .seed
-        .map(SeedableRng::seed_from_u64)
-        .unwrap_or_else(rand::rngs::StdRng::from_entropy);
-
-    let mut room_candidates_by_dir: HashMap<Direction4, Vec<(usize, (i32, i32, i32))>> =
-        HashMap::new();
+    let mut room_candidates_by_dir: HashMap<Direction4, Vec<EntranceCandidate>> = HashMap::new();
     for (dir, (index, (x, y, z))) in config
         .room_candidates
         .iter()
@@ -243,48 +366,65 @@ pub fn generate_ced(config: CEDConfig) -> Result<CEDResult, CEDError> {
     struct Node {
         room_candidate_index: usize,
         origin: Vector3<i32>,
-        from_room_id: Option<RoomId>,
+        room_id: RoomId,
+        distance: u32, // Connections from the seed room
     }
 
     let mut current_room_id = RoomId::first();
     let mut room_candidate_entities = BTreeMap::new();
     let mut room_candidate_connections: BTreeMap<RoomId, BTreeSet<RoomId>> = BTreeMap::new();
-    let mut cell_map: HashMap<Vector3<i32>, usize> = HashMap::new();
+    let mut cell_map: HashMap<Vector3<i32>, RoomId> = HashMap::new();
     let mut queue: VecDeque<Node> = VecDeque::new();
 
-    let first_room_candidate_index = rng.gen_range(0..config.room_candidates.len());
-    let first_room_candidate = &optimized_room_candidates[first_room_candidate_index];
-    queue.push_back(Node {
-        room_candidate_index: first_room_candidate_index,
-        origin: Vector3::new(0, 0, 0),
-        from_room_id: None,
-    });
-    room_candidate_entities.insert(
-        current_room_id.gen_id(),
-        RoomCandidateEntity {
-            index: first_room_candidate_index,
-            origin: (0, 0, 0),
-        },
-    );
-    for x in 0..first_room_candidate.width {
-        for y in 0..first_room_candidate.height {
-            for z in 0..first_room_candidate.depth {
-                cell_map.insert(
-                    Vector3::new(x as i32, y as i32, z as i32),
-                    first_room_candidate_index,
-                );
-            }
+    // 起点の部屋を全て置いてから同時に広げる。起点ごとに広がった部屋の集まりを記録し、出会ったら1つにまとめる
+    let seed_rooms = match config.seed_rooms.is_empty() {
+        true => vec![CEDSeedRoom::default()],
+        false => config.seed_rooms.clone(),
+    };
+    let mut seed_groups = (0..seed_rooms.len()).collect::<Vec<_>>();
+    let mut room_seeds: BTreeMap<RoomId, usize> = BTreeMap::new();
+    let mut seed_room_ids = BTreeSet::new();
+    for (seed_index, seed_room) in seed_rooms.iter().enumerate() {
+        let room_candidate_index = seed_room
+            .room_candidate_index
+            .unwrap_or_else(|| rng.gen_range(0..config.room_candidates.len()));
+        let Some(room_candidate) = optimized_room_candidates.get(room_candidate_index) else {
+            return Err(CEDError::InvalidSeedRoom { index: seed_index });
+        };
+        let origin = Vector3::new(seed_room.origin.0, seed_room.origin.1, seed_room.origin.2);
+        if any_cell(room_candidate, |p| cell_map.contains_key(&(origin + p))) {
+            return Err(CEDError::InvalidSeedRoom { index: seed_index });
+        }
+        let room_id = current_room_id.gen_id();
+        queue.push_back(Node {
+            room_candidate_index,
+            origin,
+            room_id,
+            distance: 0,
+        });
+        room_candidate_entities.insert(
+            room_id,
+            RoomCandidateEntity {
+                index: room_candidate_index,
+                origin: seed_room.origin,
+            },
+        );
+        room_seeds.insert(room_id, seed_index);
+        seed_room_ids.insert(room_id);
+        for p in room_candidate.cells.iter() {
+            cell_map.insert(origin + p, room_id);
         }
     }
+    let first_room_id = RoomId::first();
 
-    while let Some(node) = queue.pop_front() {
+    while let Some(node) = pop_frontier(&mut queue, config.frontier, |node| node.distance, rng) {
         if room_candidate_entities.len() >= config.room_size_max {
             break;
         }
 
         let room_candidate = &optimized_room_candidates[node.room_candidate_index];
         let mut dirs = *DIRECTIONS;
-        dirs.shuffle(&mut rng);
+        dirs.shuffle(rng);
 
         // 次のエントランスを探す
         for (dir, (x, y, z)) in dirs.iter().filter_map(|dir| {
@@ -300,14 +440,49 @@ pub fn generate_ced(config: CEDConfig) -> Result<CEDResult, CEDError> {
             let next_candidate_entrance_and_exit =
                 node.origin + Vector3::new(*x, *y, *z) + dir.to_vec3();
             let next_candidate_dir = dir.opposite();
+            // タグ付きの出口は同じタグの入口にだけ、タグのない出口はタグのない入口にだけつながる
+            let tag = room_candidate.exit_tags.get(dir);
+
+            // 別の起点から広がった部屋の入口と向かい合っていれば、その部屋とつなぐ
+            if let Some(other_room_id) = cell_map.get(&next_candidate_entrance_and_exit).copied() {
+                let group = find_seed_group(&seed_groups, room_seeds[&node.room_id]);
+                let other_group = find_seed_group(&seed_groups, room_seeds[&other_room_id]);
+                if group != other_group {
+                    let other: &RoomCandidateEntity = &room_candidate_entities[&other_room_id];
+                    let other_candidate = &optimized_room_candidates[other.index];
+                    let faces = other_candidate
+                        .exit_and_entrances
+                        .get(&next_candidate_dir)
+                        .is_some_and(|(x, y, z)| {
+                            Vector3::new(other.origin.0 + x, other.origin.1 + y, other.origin.2 + z)
+                                == next_candidate_entrance_and_exit
+                        });
+                    if faces && other_candidate.exit_tags.get(&next_candidate_dir) == tag {
+                        seed_groups[other_group] = group;
+                        room_candidate_connections
+                            .entry(node.room_id)
+                            .or_default()
+                            .insert(other_room_id);
+                        room_candidate_connections
+                            .entry(other_room_id)
+                            .or_default()
+                            .insert(node.room_id);
+                    }
+                    continue;
+                }
+            }
+
             let Some(next_candidates) = room_candidates_by_dir.get_mut(&next_candidate_dir) else {
                 continue;
             };
-            next_candidates.shuffle(&mut rng);
+            next_candidates.shuffle(rng);
 
             let Some((next_candidate_index, next_candidate_entrance_and_exit_offset)) =
                 next_candidates.iter().find(|(index, _)| {
                     let room_candidate = &optimized_room_candidates[*index];
+                    if room_candidate.exit_tags.get(&next_candidate_dir) != tag {
+                        return false;
+                    }
                     let entrance_and_exit = room_candidate
                         .exit_and_entrances
                         .get(&next_candidate_dir)
@@ -331,30 +506,23 @@ pub fn generate_ced(config: CEDConfig) -> Result<CEDResult, CEDError> {
                     next_candidate_entrance_and_exit_offset.1,
                     next_candidate_entrance_and_exit_offset.2,
                 );
-            for x in 0..next_candidate_room.width {
-                for y in 0..next_candidate_room.height {
-                    for z in 0..next_candidate_room.depth {
-                        cell_map.insert(
-                            next_candidate_origin + Vector3::new(x as i32, y as i32, z as i32),
-                            *next_candidate_index,
-                        );
-                    }
-                }
-            }
-            if let Some(from_room_id) = node.from_room_id {
-                room_candidate_connections
-                    .entry(from_room_id)
-                    .or_default()
-                    .insert(next_room_id);
-                room_candidate_connections
-                    .entry(next_room_id)
-                    .or_default()
-                    .insert(from_room_id);
+            for p in next_candidate_room.cells.iter() {
+                cell_map.insert(next_candidate_origin + p, next_room_id);
             }
+            room_candidate_connections
+                .entry(node.room_id)
+                .or_default()
+                .insert(next_room_id);
+            room_candidate_connections
+                .entry(next_room_id)
+                .or_default()
+                .insert(node.room_id);
+            room_seeds.insert(next_room_id, room_seeds[&node.room_id]);
             queue.push_back(Node {
                 room_candidate_index: *next_candidate_index,
                 origin: next_candidate_origin,
-                from_room_id: Some(next_room_id),
+                room_id: next_room_id,
+                distance: node.distance + 1,
             });
             room_candidate_entities.insert(
                 next_room_id,
@@ -370,6 +538,7 @@ pub fn generate_ced(config: CEDConfig) -> Result<CEDResult, CEDError> {
         }
     }
 
+    let mut removed_room_candidates = BTreeMap::new();
     let mut queue = room_candidate_entities
         .keys()
         .cloned()
@@ -378,13 +547,22 @@ pub fn generate_ced(config: CEDConfig) -> Result<CEDResult, CEDError> {
         let Some(room_ids) = room_candidate_connections.get(&room_id) else {
             continue;
         };
-        if room_ids.len() >= 2
+        // 起点の部屋は固定した位置として残すため、行き止まりでも取り除かない
+        if seed_room_ids.contains(&room_id)
+            || room_ids.len() >= 2
             || config.room_candidates[room_candidate_entities.get(&room_id).unwrap().index]
                 .can_be_terminal
         {
             continue;
         }
-        room_candidate_entities.remove(&room_id);
+        let entity = room_candidate_entities.remove(&room_id).unwrap();
+        removed_room_candidates.insert(
+            room_id,
+            RemovedRoomCandidate {
+                entity,
+                reason: CEDRemovalReason::NonTerminalDeadEnd,
+            },
+        );
         for room_id in room_candidate_connections.remove(&room_id).unwrap() {
             queue.push_back(room_id);
         }
@@ -393,32 +571,346 @@ pub fn generate_ced(config: CEDConfig) -> Result<CEDResult, CEDError> {
         }
     }
 
+    // 刈り込みの後も全ての部屋が1つにつながるように、最初の部屋から届かない部屋を取り除く
+    // 起点の部屋は刈り込まないため、最初の部屋は必ず残っている
+    let mut reachable = BTreeSet::from([first_room_id]);
+    let mut queue = VecDeque::from([first_room_id]);
+    while let Some(room_id) = queue.pop_front() {
+        for next in room_candidate_connections
+            .get(&room_id)
+            .into_iter()
+            .flatten()
+        {
+            if reachable.insert(*next) {
+                queue.push_back(*next);
+            }
+        }
+    }
+    let orphans = room_candidate_entities
+        .keys()
+        .filter(|room_id| !reachable.contains(room_id))
+        .copied()
+        .collect::<Vec<_>>();
+    for room_id in orphans {
+        let entity = room_candidate_entities.remove(&room_id).unwrap();
+        removed_room_candidates.insert(
+            room_id,
+            RemovedRoomCandidate {
+                entity,
+                reason: CEDRemovalReason::Disconnected,
+            },
+        );
+        room_candidate_connections.remove(&room_id);
+    }
+
+    // 最初の起点から届かない起点が取り除かれていれば、起点から広げた部屋は1つにまとまっていない
+    if seed_room_ids
+        .iter()
+        .any(|room_id| !room_candidate_entities.contains_key(room_id))
+    {
+        return Err(CEDError::SeedRoomsNotMerged);
+    }
+
+    cell_map.retain(|_, room_id| room_candidate_entities.contains_key(room_id));
+
     Ok(CEDResult {
         room_candidates: config.room_candidates,
         room_candidate_entities,
         room_candidate_connections,
+        removed_room_candidates,
+        cell_map,
     })
 }
 
+fn find_seed_group(seed_groups: &[usize], mut group: usize) -> usize {
+    while seed_groups[group] != group {
+        group = seed_groups[group];
+    }
+    group
+}
+
+// 次に広げる部屋を取り出す
+fn pop_frontier<T, F, R>(
+    queue: &mut VecDeque<T>,
+    frontier: CEDFrontierStrategy,
+    distance: F,
+    rng: &mut R,
+) -> Option<T>
+where
+    F: Fn(&T) -> u32,
+    R: Rng,
+{
+    if queue.is_empty() {
+        return None;
+    }
+    match frontier {
+        CEDFrontierStrategy::BreadthFirst => queue.pop_front(),
+        CEDFrontierStrategy::DepthFirst => queue.pop_back(),
+        CEDFrontierStrategy::Random => {
+            let index = rng.gen_range(0..queue.len());
+            queue.remove(index)
+        }
+        CEDFrontierStrategy::DistanceWeighted => {
+            let weights = queue.iter().map(|node| distance(node) + 1);
+            let index = WeightedIndex::new(weights).unwrap().sample(rng);
+            queue.remove(index)
+        }
+    }
+}
+
 fn any_cell<F>(room_candidate: &OptimizedRoomCandidate, f: F) -> bool
 where
     F: Fn(&Vector3<i32>) -> bool,
 {
-    for x in 0..room_candidate.width {
-        for y in 0..room_candidate.height {
-            for z in 0..room_candidate.depth {
-                if f(&Vector3::new(x as i32, y as i32, z as i32)) {
-                    return true;
+    room_candidate.cells.iter().any(f)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::Direction4;
+    use crate::core_expansion_dungeon::{
+        generate_ced, CEDConfig, CEDError, CEDFrontierStrategy, CEDRemovalReason, CEDResult,
+        CEDRoomCandidate, CEDSeedRoom,
+    };
+    use nalgebra::Vector3;
+    use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+    // 手前の右側が欠けた L 字の部屋。欠けた部分に面した出入口を持つ
+    fn l_shaped() -> CEDRoomCandidate {
+        CEDRoomCandidate {
+            width: 2,
+            height: 1,
+            depth: 2,
+            exit_and_entrances: vec![
+                ((0, 0, 0), Direction4::Left),
+                ((1, 0, 0), Direction4::Right),
+                ((0, 0, 0), Direction4::Far),
+                ((1, 0, 0), Direction4::Near),
+            ],
+            can_be_terminal: true,
+            footprint: Some(BTreeSet::from([(0, 0, 0), (1, 0, 0), (0, 0, 1)])),
+            exit_tags: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_footprint() {
+        let mut config = CEDConfig {
+            seed: Some(0),
+            ..Default::default()
+        };
+        config.room_candidates.push(l_shaped());
+        let l_index = config.room_candidates.len() - 1;
+        let result = generate_ced(config).unwrap();
+        assert!(result
+            .room_candidate_entities
+            .values()
+            .any(|entity| entity.index == l_index));
+
+        // 部屋同士は占有するセルが重ならない
+        let mut occupied = BTreeSet::new();
+        for entity in result.room_candidate_entities.values() {
+            let (ox, oy, oz) = entity.origin;
+            for (x, y, z) in result.room_candidates[entity.index].cells() {
+                assert!(occupied.insert((ox + x, oy + y, oz + z)));
+            }
+        }
+        assert_eq!(result.cell_map.len(), occupied.len());
+        for (room_id, entity) in result.room_candidate_entities.iter() {
+            let (ox, oy, oz) = entity.origin;
+            for (x, y, z) in result.room_candidates[entity.index].cells() {
+                let p = Vector3::new(ox + x, oy + y, oz + z);
+                assert_eq!(result.cell_map.get(&p), Some(room_id));
+            }
+        }
+
+        let mut invalid = l_shaped();
+        invalid.footprint = Some(BTreeSet::from([(0, 0, 0), (2, 0, 0)]));
+        let result = generate_ced(CEDConfig {
+            room_candidates: vec![invalid],
+            ..Default::default()
+        });
+        assert!(matches!(
+            result,
+            Err(CEDError::InvalidRoomCandidateFootprint { index: 0 })
+        ));
+    }
+
+    #[test]
+    fn test_exit_tags() {
+        let corridor = |exit_tags: BTreeMap<Direction4, String>| CEDRoomCandidate {
+            width: 1,
+            height: 1,
+            depth: 1,
+            exit_and_entrances: vec![
+                ((0, 0, 0), Direction4::Left),
+                ((0, 0, 0), Direction4::Right),
+            ],
+            can_be_terminal: true,
+            footprint: None,
+            exit_tags,
+        };
+        let result = generate_ced(CEDConfig {
+            room_candidates: vec![
+                corridor(BTreeMap::from([(Direction4::Left, "sewer".to_string())])),
+                corridor(BTreeMap::new()),
+            ],
+            room_size_max: 10,
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        // 左の出口に合うタグの入口がないため、0 番の部屋の左には何もつながらない
+        let origins = result
+            .room_candidate_entities
+            .values()
+            .map(|entity| entity.origin)
+            .collect::<BTreeSet<_>>();
+        assert_eq!(origins.len(), result.room_candidate_entities.len());
+        assert!(result
+            .room_candidate_entities
+            .values()
+            .any(|entity| entity.index == 0));
+        for entity in result.room_candidate_entities.values() {
+            let (x, y, z) = entity.origin;
+            if entity.index == 0 {
+                assert!(!origins.contains(&(x - 1, y, z)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_connected_after_prune() {
+        for seed in 0..20 {
+            let result = generate_ced(CEDConfig {
+                seed: Some(seed),
+                ..Default::default()
+            })
+            .unwrap();
+            let first = *result.room_candidate_entities.keys().next().unwrap();
+            let mut reachable = BTreeSet::from([first]);
+            let mut stack = vec![first];
+            while let Some(room_id) = stack.pop() {
+                for next in result
+                    .room_candidate_connections
+                    .get(&room_id)
+                    .into_iter()
+                    .flatten()
+                {
+                    assert!(result.room_candidate_entities.contains_key(next));
+                    if reachable.insert(*next) {
+                        stack.push(*next);
+                    }
+                }
+            }
+            assert_eq!(reachable.len(), result.room_candidate_entities.len());
+            for (room_id, removed) in result.removed_room_candidates.iter() {
+                assert!(!result.room_candidate_entities.contains_key(room_id));
+                assert!(!result.room_candidate_connections.contains_key(room_id));
+                if removed.reason == CEDRemovalReason::NonTerminalDeadEnd {
+                    assert!(!result.room_candidates[removed.entity.index].can_be_terminal);
                 }
             }
         }
     }
-    false
-}
 
-fn validate_dir_of_room_candidate(x: i32, z: i32, width: u32, depth: u32, dir: Direction4) -> bool {
-    (x == 0 && dir == Direction4::Left)
-        || (x == width as i32 - 1 && dir == Direction4::Right)
-        || (z == 0 && dir == Direction4::Far)
-        || (z == depth as i32 - 1 && dir == Direction4::Near)
+    // 最初の部屋から最も遠い部屋までのつながりの数
+    fn longest_distance(result: &CEDResult) -> usize {
+        let first = *result.room_candidate_entities.keys().next().unwrap();
+        let mut distances = BTreeMap::from([(first, 0)]);
+        let mut queue = VecDeque::from([first]);
+        while let Some(room_id) = queue.pop_front() {
+            for next in result
+                .room_candidate_connections
+                .get(&room_id)
+                .into_iter()
+                .flatten()
+            {
+                if !distances.contains_key(next) {
+                    distances.insert(*next, distances[&room_id] + 1);
+                    queue.push_back(*next);
+                }
+            }
+        }
+        distances.values().copied().max().unwrap()
+    }
+
+    #[test]
+    fn test_frontier_strategy() {
+        let generate = |frontier: CEDFrontierStrategy, seed: u64| {
+            generate_ced(CEDConfig {
+                frontier,
+                seed: Some(seed),
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        let total = |frontier: CEDFrontierStrategy| {
+            (0..10)
+                .map(|seed| longest_distance(&generate(frontier, seed)))
+                .sum::<usize>()
+        };
+        // 深さ優先や遠い部屋を優先すると、幅優先よりも細長くなる
+        let breadth_first = total(CEDFrontierStrategy::BreadthFirst);
+        assert!(total(CEDFrontierStrategy::DepthFirst) > breadth_first);
+        assert!(total(CEDFrontierStrategy::DistanceWeighted) > breadth_first);
+        for frontier in [
+            CEDFrontierStrategy::Random,
+            CEDFrontierStrategy::DistanceWeighted,
+        ] {
+            assert_eq!(
+                generate(frontier, 0).cell_map,
+                generate(frontier, 0).cell_map
+            );
+        }
+    }
+
+    #[test]
+    fn test_seed_rooms() {
+        let config = |seed_rooms: Vec<CEDSeedRoom>, seed: u64| CEDConfig {
+            seed_rooms,
+            room_size_max: 40,
+            seed: Some(seed),
+            ..Default::default()
+        };
+        let seed_room = |origin: (i32, i32, i32), room_candidate_index: usize| CEDSeedRoom {
+            origin,
+            room_candidate_index: Some(room_candidate_index),
+        };
+
+        // 離れた2つの起点から広げた部屋が、どこかで出会って1つにつながる
+        // 行き止まりにできない階段を起点にしても、起点の部屋は刈り込まれずに残る
+        for room_candidate_index in [0, 5] {
+            let result = generate_ced(config(
+                vec![
+                    seed_room((0, 0, 0), room_candidate_index),
+                    seed_room((6, 0, 0), room_candidate_index),
+                ],
+                0,
+            ))
+            .unwrap();
+            for origin in [(0, 0, 0), (6, 0, 0)] {
+                assert!(result
+                    .room_candidate_entities
+                    .values()
+                    .any(|entity| entity.origin == origin && entity.index == room_candidate_index));
+            }
+        }
+
+        // 出会わなかった起点は取り除かず、エラーにする
+        let result = generate_ced(config(
+            vec![seed_room((0, 0, 0), 0), seed_room((6, 0, 0), 0)],
+            8,
+        ));
+        assert!(matches!(result, Err(CEDError::SeedRoomsNotMerged)));
+
+        let result = generate_ced(config(
+            vec![seed_room((0, 0, 0), 0), seed_room((1, 0, 0), 0)],
+            0,
+        ));
+        assert!(matches!(
+            result,
+            Err(CEDError::InvalidSeedRoom { index: 1 })
+        ));
+    }
 }
@@ -1,13 +1,58 @@
 use crate::constants::Direction4;
-use crate::intersect_rect_with_line::intersect_rect_with_line;
+use crate::geometry::intersect_rect_with_line;
 use crate::room::{Room, RoomId};
 use nalgebra::{Vector2, Vector3};
 use std::collections::BTreeSet;
 
-pub fn create_start(
+pub(crate) fn create_start(
     room0: &Room,
     room1: &Room,
 ) -> (RoomId, RoomId, Vector3<i32>, BTreeSet<Direction4>) {
+    let (room_start, room_end, mut points) = intersect_points(room0, room1);
+    let (p, dirs) = start_on_edge(
+        room_start,
+        points.pop().unwrap_or_else(|| {
+            Vector2::new(room_start.origin.0 as f32, room_start.origin.2 as f32)
+        }),
+    );
+    (room_start.id, room_end.id, p, dirs)
+}
+
+// 最初の開始地点で通路が届かなかった場合に備え、もう一方の交点と各面の中央も候補にする
+pub(crate) fn create_starts(
+    room0: &Room,
+    room1: &Room,
+) -> Vec<(RoomId, RoomId, Vector3<i32>, BTreeSet<Direction4>)> {
+    let (start_room_id, end_room_id, start, dirs) = create_start(room0, room1);
+    let (room_start, _, mut points) = intersect_points(room0, room1);
+    points.pop();
+
+    let mut starts = vec![(start, dirs)];
+    starts.extend(
+        points
+            .into_iter()
+            .rev()
+            .map(|p| start_on_edge(room_start, p)),
+    );
+    starts.extend(
+        face_starts(room_start)
+            .into_iter()
+            .map(|(p, dir)| (p, BTreeSet::from([dir]))),
+    );
+    let mut candidates: Vec<(RoomId, RoomId, Vector3<i32>, BTreeSet<Direction4>)> = Vec::new();
+    for (p, dirs) in starts {
+        if !candidates.iter().any(|(_, _, q, d)| *q == p && *d == dirs) {
+            candidates.push((start_room_id, end_room_id, p, dirs));
+        }
+    }
+    candidates
+}
+
+// 低い方の部屋から通路を始め、部屋の中心同士を結ぶ線と部屋の縁との交点を求める
+fn intersect_points<'a>(
+    room0: &'a Room,
+    room1: &'a Room,
+) -> (&'a Room, &'a Room, Vec<Vector2<f32>>) {
     let (room_start, room_end) = if room0.origin.1 <= room1.origin.1 {
         (room0, room1)
     } else {
@@ -21,7 +66,7 @@ pub fn create_start(
     );
     let width = room_start.width + room_end.width;
     let depth = room_start.depth + room_end.depth;
-    let mut points = intersect_rect_with_line(
+    let points = intersect_rect_with_line(
         (
             &Vector2::new(room_start.origin.0 as f32, room_start.origin.2 as f32),
             &Vector2::new(room_start.width as f32, room_start.depth as f32),
@@ -29,17 +74,29 @@ pub fn create_start(
         &Vector2::new(room_start_center.0, room_start_center.2),
         &Vector2::new(diff_center.0 * width as f32, diff_center.1 * depth as f32),
     );
+    (room_start, room_end, points)
+}
+
+// 部屋の4つの面それぞれの中央の床
+pub(crate) fn face_starts(room: &Room) -> [(Vector3<i32>, Direction4); 4] {
+    let (x, y, z) = (
+        room.origin.0 as i32,
+        room.origin.1 as i32,
+        room.origin.2 as i32,
+    );
+    let (end_x, end_z) = (room.end().0 as i32 - 1, room.end().2 as i32 - 1);
+    let (center_x, center_z) = ((x + end_x) / 2, (z + end_z) / 2);
+    [
+        (Vector3::new(x, y, center_z), Direction4::Left),
+        (Vector3::new(end_x, y, center_z), Direction4::Right),
+        (Vector3::new(center_x, y, z), Direction4::Far),
+        (Vector3::new(center_x, y, end_z), Direction4::Near),
+    ]
+}
+
+fn start_on_edge(room_start: &Room, p: Vector2<f32>) -> (Vector3<i32>, BTreeSet<Direction4>) {
     let mut dirs = BTreeSet::new();
-    let mut p = points
-        .pop()
-        .map(|p| Vector3::new(p.x as i32, room_start.origin.1 as i32, p.y as i32))
-        .unwrap_or_else(|| {
-            Vector3::new(
-                room_start.origin.0 as i32,
-                room_start.origin.1 as i32,
-                room_start.origin.2 as i32,
-            )
-        });
+    let mut p = Vector3::new(p.x as i32, room_start.origin.1 as i32, p.y as i32);
 
     if p.x == room_start.origin.0 as i32 {
         dirs.insert(Direction4::Left);
@@ -54,6 +111,5 @@ pub fn create_start(
         p.z -= 1;
         dirs.insert(Direction4::Near);
     }
-
-    (room_start.id, room_end.id, p, dirs)
+    (p, dirs)
 }
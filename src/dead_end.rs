@@ -0,0 +1,168 @@
+use crate::constants::{VoxelType, DIRECTIONS};
+use crate::passage::{Passage, PassageId};
+use crate::voxel_map::VoxelMap;
+use nalgebra::Vector3;
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DeadEnd {
+    pub passage_id: PassageId,
+    pub cells: Vec<(i32, i32, i32)>, // Walkable cells of the stub, from the tip toward the passage
+}
+
+// 部屋にも他の通路の分岐にもつながらない行き止まりの枝を検出する
+pub fn find_dead_ends(
+    voxel_map: &VoxelMap,
+    passages: &BTreeMap<PassageId, Passage>,
+) -> Vec<DeadEnd> {
+    let walkable = walkable_cells(voxel_map, passages);
+    let mut visited = BTreeSet::new();
+    let mut dead_ends = Vec::new();
+    for (tip, passage_id) in walkable.iter() {
+        if visited.contains(tip) || touches_room(voxel_map, tip) {
+            continue;
+        }
+        if neighbors(&walkable, tip).len() > 1 {
+            continue;
+        }
+        let mut cells = vec![*tip];
+        visited.insert(*tip);
+        let mut current = *tip;
+        loop {
+            let next = neighbors(&walkable, &current)
+                .into_iter()
+                .filter(|p| !cells.contains(p))
+                .collect::<Vec<_>>();
+            let [next] = next[..] else {
+                break;
+            };
+            // 分岐点と部屋の出入口は枝に含めない
+            if neighbors(&walkable, &next).len() > 2
+                || touches_room(voxel_map, &next)
+                || visited.contains(&next)
+            {
+                break;
+            }
+            visited.insert(next);
+            cells.push(next);
+            current = next;
+        }
+        dead_ends.push(DeadEnd {
+            passage_id: *passage_id,
+            cells,
+        });
+    }
+    dead_ends
+}
+
+// 行き止まりの枝を床から天井まで取り除き、取り除いた枝を返す
+pub fn trim_dead_ends(
+    voxel_map: &mut VoxelMap,
+    passages: &mut BTreeMap<PassageId, Passage>,
+) -> Vec<DeadEnd> {
+    let mut trimmed = Vec::new();
+    loop {
+        let dead_ends = find_dead_ends(voxel_map, passages);
+        if dead_ends.is_empty() {
+            return trimmed;
+        }
+        let mut removed = BTreeSet::new();
+        for dead_end in dead_ends.iter() {
+            let height = passages[&dead_end.passage_id].height;
+            for (x, y, z) in dead_end.cells.iter() {
+                let p = Vector3::new(*x, *y, *z);
+                let top = match voxel_map.get(&p) {
+                    VoxelType::PassageStair(_) => height,
+                    _ => height - 1,
+                };
+                for dy in -1..=top {
+                    let q = p + Vector3::new(0, dy, 0);
+                    // 階段の下は床とは限らないため、通路の床のみ取り除く
+                    if dy == -1 && voxel_map.get(&q) != VoxelType::PassageFloor {
+                        continue;
+                    }
+                    voxel_map.map.remove(&q);
+                    removed.insert((q.x, q.y, q.z));
+                }
+            }
+        }
+        for passage in passages.values_mut() {
+            passage.cells.retain(|(p, _)| !removed.contains(p));
+        }
+        trimmed.extend(dead_ends);
+    }
+}
+
+fn walkable_cells(
+    voxel_map: &VoxelMap,
+    passages: &BTreeMap<PassageId, Passage>,
+) -> BTreeMap<(i32, i32, i32), PassageId> {
+    let mut walkable = BTreeMap::new();
+    for (passage_id, passage) in passages.iter() {
+        for ((x, y, z), voxel_type) in passage.cells.iter() {
+            let p = Vector3::new(*x, *y, *z);
+            let is_walkable = match voxel_type {
+                VoxelType::PassageStair(_) => true,
+                VoxelType::PassageSpace | VoxelType::Doorway(_) => {
+                    voxel_map.get(&(p - Vector3::y())) == VoxelType::PassageFloor
+                }
+                _ => false,
+            };
+            if is_walkable {
+                walkable.entry((*x, *y, *z)).or_insert(*passage_id);
+            }
+        }
+    }
+    walkable
+}
+
+// 階段で1段上下した先も隣接するセルとして扱う
+fn neighbors(
+    walkable: &BTreeMap<(i32, i32, i32), PassageId>,
+    (x, y, z): &(i32, i32, i32),
+) -> Vec<(i32, i32, i32)> {
+    let mut result = Vec::new();
+    for dir in DIRECTIONS.iter() {
+        let v = dir.to_vec3();
+        for dy in -1..=1 {
+            let p = (x + v.x, y + dy, z + v.z);
+            if walkable.contains_key(&p) {
+                result.push(p);
+            }
+        }
+    }
+    result
+}
+
+// 階段は1段上の部屋の床へ直接つながることがある
+fn touches_room(voxel_map: &VoxelMap, (x, y, z): &(i32, i32, i32)) -> bool {
+    DIRECTIONS.iter().any(|dir| {
+        (0..=1).any(|dy| {
+            matches!(
+                voxel_map.get(&(Vector3::new(*x, y + dy, *z) + dir.to_vec3())),
+                VoxelType::RoomBottomSpace(_)
+            )
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dead_end::find_dead_ends;
+    use crate::generate_drd::{generate_dungeon_3d, Dungeon3DGeneratorConfig};
+    use crate::validation::validate;
+
+    #[test]
+    fn test_trim_dead_ends() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            passage_wall_noise: 0.5,
+            trim_dead_ends: true,
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(!result.dead_ends.is_empty());
+        assert_eq!(find_dead_ends(&result.voxel_map, &result.passages), vec![]);
+        assert_eq!(validate(&result), vec![]);
+    }
+}
@@ -12,30 +12,27 @@ pub struct Vertex {
     pub position: Vector3<f32>,
 }
 
-impl PartialEq for Vertex {
-    fn eq(&self, other: &Self) -> bool {
+impl Vertex {
+    fn key(&self) -> (i64, i64, i64) {
         (
             (self.position.x * ACCURACY) as i64,
             (self.position.y * ACCURACY) as i64,
             (self.position.z * ACCURACY) as i64,
-        ) == (
-            (other.position.x * ACCURACY) as i64,
-            (other.position.y * ACCURACY) as i64,
-            (other.position.z * ACCURACY) as i64,
         )
     }
 }
 
+impl PartialEq for Vertex {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
 impl Eq for Vertex {}
 
 impl Hash for Vertex {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        (
-            (self.position.x * ACCURACY) as i64,
-            (self.position.y * ACCURACY) as i64,
-            (self.position.z * ACCURACY) as i64,
-        )
-            .hash(state);
+        self.key().hash(state);
     }
 }
 
@@ -196,11 +193,12 @@ pub struct Triangle {
     pub is_bad: bool,
 }
 
+// 頂点の順序によらず等価なので、ハッシュも順序に依存させない
 impl Hash for Triangle {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        self.u.hash(state);
-        self.v.hash(state);
-        self.w.hash(state);
+        let mut keys = [self.u.key(), self.v.key(), self.w.key()];
+        keys.sort();
+        keys.hash(state);
     }
 }
 
@@ -232,8 +230,9 @@ pub struct Edge {
 
 impl Hash for Edge {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        self.u.hash(state);
-        self.v.hash(state);
+        let mut keys = [self.u.key(), self.v.key()];
+        keys.sort();
+        keys.hash(state);
     }
 }
 
@@ -458,3 +457,66 @@ impl<T> Delaunay3D<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::delaunary_3d::{Delaunay3D, Edge, Triangle, Vertex};
+    use nalgebra::Vector3;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_duplicate_edges() {
+        let vertex = |x, y, z| Vertex {
+            position: Vector3::new(x, y, z),
+        };
+        let (a, b, c) = (
+            vertex(0.0, 0.0, 0.0),
+            vertex(1.0, 0.0, 0.0),
+            vertex(0.0, 2.0, 0.0),
+        );
+        // 頂点の順序が違っても、ハッシュの種によらず同じ辺と面として1つにまとまる
+        for _ in 0..32 {
+            let edges = HashSet::from([
+                Edge::new(a.clone(), b.clone()),
+                Edge::new(b.clone(), a.clone()),
+            ]);
+            assert_eq!(edges.len(), 1);
+            let triangles = HashSet::from([
+                Triangle::new(a.clone(), b.clone(), c.clone()),
+                Triangle::new(c.clone(), a.clone(), b.clone()),
+                Triangle::new(b.clone(), a.clone(), c.clone()),
+            ]);
+            assert_eq!(triangles.len(), 1);
+        }
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let points = (0..24)
+            .map(|i| {
+                let position = Vector3::new(
+                    rng.gen_range(0.0..32.0),
+                    rng.gen_range(0.0..8.0),
+                    rng.gen_range(0.0..32.0),
+                );
+                (i, position)
+            })
+            .collect::<Vec<_>>();
+        let edges = |delaunay: &Delaunay3D<usize>| {
+            delaunay
+                .edges
+                .iter()
+                .map(|edge| (delaunay.id_map[&edge.u], delaunay.id_map[&edge.v]))
+                .collect::<Vec<_>>()
+        };
+        let expected = edges(&Delaunay3D::new(points.clone()));
+        let pairs = expected
+            .iter()
+            .map(|(u, v)| (*u.min(v), *u.max(v)))
+            .collect::<HashSet<_>>();
+        assert_eq!(pairs.len(), expected.len());
+        for _ in 0..8 {
+            assert_eq!(edges(&Delaunay3D::new(points.clone())), expected);
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use crate::voxel_map::VoxelMap;
+use nalgebra::Vector3;
+
+#[derive(Debug, Clone)]
+pub struct DensityField {
+    pub start: Vector3<i32>,  // Voxel whose center holds the first value
+    pub size: Vector3<usize>, // Number of values along each axis
+    pub values: Vec<f32>, // -1 in open space and 1 in solid voxels; x varies fastest, then y, then z
+}
+
+impl DensityField {
+    // ボクセル p の中心の値。範囲外は固いものとして扱う
+    pub fn get(&self, p: &Vector3<i32>) -> f32 {
+        let q = p - self.start;
+        if (0..3).any(|i| q[i] < 0 || q[i] as usize >= self.size[i]) {
+            return 1.0;
+        }
+        let (x, y, z) = (q.x as usize, q.y as usize, q.z as usize);
+        self.values[(z * self.size.y + y) * self.size.x + x]
+    }
+
+    // 任意の位置の密度。周りの8つのボクセルの中心の値を三線形補間する
+    pub fn sample(&self, p: &Vector3<f32>) -> f32 {
+        let q = p - Vector3::repeat(0.5);
+        let base = q.map(|v| v.floor());
+        let t = q - base;
+        let base = base.map(|v| v as i32);
+        let mut value = 0.0;
+        for (dx, dy, dz) in (0..8).map(|i| (i & 1, (i >> 1) & 1, (i >> 2) & 1)) {
+            let weight = (if dx == 1 { t.x } else { 1.0 - t.x })
+                * (if dy == 1 { t.y } else { 1.0 - t.y })
+                * (if dz == 1 { t.z } else { 1.0 - t.z });
+            value += weight * self.get(&(base + Vector3::new(dx, dy, dz)));
+        }
+        value
+    }
+}
+
+// マーチングキューブやサーフェスネッツ向けに、ボクセルの中心を頂点とする双対格子上の密度を書き出す
+// 等値面 0 が通れる空間と固いボクセルの境界になる。面が閉じるよう、使われている範囲の外側に1層余白を取る
+pub fn export_density_field(voxel_map: &VoxelMap) -> DensityField {
+    let (start, end) = voxel_map.occupied_bounds().unwrap_or_default();
+    let (start, end) = (start - Vector3::repeat(1), end + Vector3::repeat(1));
+    let size = (end - start).map(|v| v as usize);
+    let mut values = Vec::with_capacity(size.x * size.y * size.z);
+    for z in start.z..end.z {
+        for y in start.y..end.y {
+            for x in start.x..end.x {
+                let value = match voxel_map.get(&Vector3::new(x, y, z)).is_passable() {
+                    true => -1.0,
+                    false => 1.0,
+                };
+                values.push(value);
+            }
+        }
+    }
+    DensityField {
+        start,
+        size,
+        values,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::DIRECTIONS;
+    use crate::density::export_density_field;
+    use crate::generate_drd::{generate_dungeon_3d, Dungeon3DGeneratorConfig};
+    use nalgebra::Vector3;
+
+    #[test]
+    fn test_export_density_field() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        let field = export_density_field(&result.voxel_map);
+        assert_eq!(
+            field.values.len(),
+            field.size.x * field.size.y * field.size.z
+        );
+        for (p, voxel_type) in result.voxel_map.map.iter() {
+            let center = p.map(|v| v as f32 + 0.5);
+            assert_eq!(field.get(p) < 0.0, voxel_type.is_passable());
+            assert_eq!(field.sample(&center), field.get(p));
+            // 通れる空間と固いボクセルの間で密度が 0 になる
+            for dir in DIRECTIONS.iter() {
+                let q = p + dir.to_vec3();
+                if voxel_type.is_passable() != result.voxel_map.get(&q).is_passable() {
+                    let middle = center + dir.to_vec3().map(|v| v as f32 * 0.5);
+                    assert!(field.sample(&middle).abs() < 1e-6);
+                }
+            }
+        }
+        // 余白は固いものとして扱われる
+        assert!(field.values.iter().take(field.size.x).all(|v| *v > 0.0));
+        assert_eq!(field.get(&(field.start - Vector3::repeat(1))), 1.0);
+    }
+}
@@ -0,0 +1,158 @@
+use crate::constants::{VoxelType, DIRECTIONS};
+use crate::passage::{Passage, PassageId};
+use crate::room::{Room, RoomId};
+use crate::voxel_map::VoxelMap;
+use nalgebra::Vector3;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Doorway {
+    pub position: (i32, i32, i32),
+    pub room_id: RoomId,
+    pub passage_id: PassageId,
+    pub wide: bool, // One of the two voxels of a widened doorway
+}
+
+// 部屋の床の高さで部屋に接している通路のボクセルを出入口に置き換える
+pub fn mark_doorways(
+    voxel_map: &mut VoxelMap,
+    passages: &mut BTreeMap<PassageId, Passage>,
+) -> Vec<Doorway> {
+    let mut doorways = Vec::new();
+    for (passage_id, passage) in passages.iter_mut() {
+        for ((x, y, z), voxel_type) in passage.cells.iter_mut() {
+            let p = Vector3::new(*x, *y, *z);
+            if *voxel_type != VoxelType::PassageSpace
+                || voxel_map.get(&(p + Vector3::new(0, -1, 0))) != VoxelType::PassageFloor
+            {
+                continue;
+            }
+            let Some(room_id) =
+                DIRECTIONS
+                    .iter()
+                    .find_map(|dir| match voxel_map.get(&(p + dir.to_vec3())) {
+                        VoxelType::RoomBottomSpace(room_id) => Some(room_id),
+                        _ => None,
+                    })
+            else {
+                continue;
+            };
+            *voxel_type = VoxelType::Doorway(room_id);
+            voxel_map.map.insert(p, VoxelType::Doorway(room_id));
+            doorways.push(Doorway {
+                position: (*x, *y, *z),
+                room_id,
+                passage_id: *passage_id,
+                wide: false,
+            });
+        }
+    }
+    doorways
+}
+
+// 大きな部屋への出入口を、部屋に沿って隣の壁の1ボクセル分広げる
+// 広げる先の床と頭上が全て壁で地表より下にある場合だけ広げ、広げた出入口は通路の一部にする
+pub fn widen_doorways(
+    voxel_map: &mut VoxelMap,
+    rooms: &BTreeMap<RoomId, Room>,
+    passages: &mut BTreeMap<PassageId, Passage>,
+    doorways: &mut Vec<Doorway>,
+    min_room_size: u32,
+) {
+    for index in 0..doorways.len() {
+        let doorway = doorways[index].clone();
+        let Some(room) = rooms.get(&doorway.room_id) else {
+            continue;
+        };
+        if doorway.wide || room.width.min(room.depth) < min_room_size {
+            continue;
+        }
+        let Some(passage) = passages.get_mut(&doorway.passage_id) else {
+            continue;
+        };
+        let room_space = VoxelType::RoomBottomSpace(doorway.room_id);
+        let p = Vector3::new(doorway.position.0, doorway.position.1, doorway.position.2);
+        let Some(inward) = DIRECTIONS
+            .iter()
+            .map(|dir| dir.to_vec3())
+            .find(|v| voxel_map.get(&(p + v)) == room_space)
+        else {
+            continue;
+        };
+        // 部屋の壁に沿った両隣のうち、部屋に面していて掘れる方
+        let cells = DIRECTIONS
+            .iter()
+            .map(|dir| p + dir.to_vec3())
+            .filter(|q| (q - p).dot(&inward) == 0 && voxel_map.get(&(q + inward)) == room_space)
+            .map(|q| {
+                let mut cells = vec![((q.x, q.y - 1, q.z), VoxelType::PassageFloor)];
+                cells.push(((q.x, q.y, q.z), VoxelType::Doorway(doorway.room_id)));
+                for dy in 1..passage.height.max(1) {
+                    cells.push(((q.x, q.y + dy, q.z), VoxelType::PassageSpace));
+                }
+                cells
+            })
+            .find(|cells| {
+                cells.iter().all(|((x, y, z), _)| {
+                    let q = Vector3::new(*x, *y, *z);
+                    voxel_map.get(&q) == VoxelType::Wall && voxel_map.is_below_surface(&q)
+                })
+            });
+        let Some(cells) = cells else {
+            continue;
+        };
+        for ((x, y, z), voxel_type) in cells.iter() {
+            voxel_map.map.insert(Vector3::new(*x, *y, *z), *voxel_type);
+        }
+        let widened = Doorway {
+            position: cells[1].0,
+            room_id: doorway.room_id,
+            passage_id: doorway.passage_id,
+            wide: true,
+        };
+        passage.cells.extend(cells);
+        doorways[index].wide = true;
+        doorways.push(widened);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::VoxelType;
+    use crate::generate_drd::{generate_dungeon_3d, Dungeon3DGeneratorConfig};
+    use crate::validation::validate;
+    use nalgebra::Vector3;
+
+    #[test]
+    fn test_wide_doorways() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            wide_doorway_room_size: Some(4),
+            ..Default::default()
+        })
+        .unwrap();
+        let wide = result
+            .doorways
+            .iter()
+            .filter(|doorway| doorway.wide)
+            .collect::<Vec<_>>();
+        assert!(!wide.is_empty());
+        // 広げた出入口は同じ部屋の出入口と隣り合う2ボクセルになる
+        for doorway in wide.iter() {
+            let room = &result.rooms[&doorway.room_id];
+            assert!(room.width.min(room.depth) >= 4);
+            let p = doorway.position;
+            assert!(wide.iter().any(|other| {
+                let q = other.position;
+                other.room_id == doorway.room_id
+                    && q.1 == p.1
+                    && (q.0 - p.0).abs() + (q.2 - p.2).abs() == 1
+            }));
+            assert_eq!(
+                result.voxel_map.get(&Vector3::new(p.0, p.1, p.2)),
+                VoxelType::Doorway(doorway.room_id)
+            );
+        }
+        assert!(validate(&result).is_empty());
+    }
+}
@@ -0,0 +1,439 @@
+use crate::constants::VoxelType;
+use crate::generate_drd::Dungeon3DGeneratorResult;
+use crate::passage::PassageId;
+use crate::room::RoomId;
+use nalgebra::Vector3;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::Rng;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum EncounterKind {
+    Trap,      // Placed on a passage floor
+    Encounter, // Placed on a room floor
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct EncounterPlacement {
+    pub kind: EncounterKind,
+    pub position: (i32, i32, i32), // Walkable cell above the floor
+    pub cost: u32,                 // Part of the difficulty budget spent on this placement
+    pub critical: bool,            // Lies on the critical path
+    pub passage_id: Option<PassageId>,
+    pub room_id: Option<RoomId>,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct CriticalPath {
+    pub rooms: Vec<RoomId>, // From the entrance to the farthest room, or to the goal given to critical_path_to
+    pub passages: Vec<PassageId>, // Passages between consecutive rooms
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum GoalCriteria {
+    FarthestFromStart, // Most passages away from the entrance
+    LargestDeadEnd,    // Largest floor area among rooms joined to a single other room
+    DeepestHierarchy,  // Lowest hierarchy level; the farthest of them from the entrance
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum GoalReason {
+    FarthestFromStart { passages: usize },
+    LargestDeadEnd { area: u32, passages: usize },
+    DeepestHierarchy { hierarchy: u32, passages: usize },
+    NoDeadEnd { passages: usize }, // LargestDeadEnd found no dead end and fell back to the farthest room
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct GoalSelection {
+    pub room_id: RoomId,
+    pub reason: GoalReason, // Why the room was chosen, with the measured values
+}
+
+impl fmt::Display for GoalReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GoalReason::FarthestFromStart { passages } => {
+                write!(f, "farthest room from the entrance ({} passages)", passages)
+            }
+            GoalReason::LargestDeadEnd { area, passages } => write!(
+                f,
+                "largest dead end ({} voxels of floor, {} passages from the entrance)",
+                area, passages
+            ),
+            GoalReason::DeepestHierarchy {
+                hierarchy,
+                passages,
+            } => write!(
+                f,
+                "deepest hierarchy level {} ({} passages from the entrance)",
+                hierarchy, passages
+            ),
+            GoalReason::NoDeadEnd { passages } => write!(
+                f,
+                "no dead end, farthest room from the entrance instead ({} passages)",
+                passages
+            ),
+        }
+    }
+}
+
+pub const TRAP_COST: u32 = 1;
+pub const ENCOUNTER_COST: u32 = 3;
+const CRITICAL_WEIGHT: u32 = 3;
+const MIN_DISTANCE: i32 = 3;
+
+// 入口から通路の数で最も遠い部屋までの最短経路を必ず通る経路とする
+pub fn critical_path(result: &Dungeon3DGeneratorResult, entrance: RoomId) -> CriticalPath {
+    let (parents, farthest) = search_from(result, entrance);
+    path_to(&parents, farthest)
+}
+
+// 入口からゴールの部屋までの通路の数で最短の経路。ゴールに辿り着けなければ None
+pub fn critical_path_to(
+    result: &Dungeon3DGeneratorResult,
+    entrance: RoomId,
+    goal: RoomId,
+) -> Option<CriticalPath> {
+    let (parents, _) = search_from(result, entrance);
+    parents.contains_key(&goal).then(|| path_to(&parents, goal))
+}
+
+// 必ず通る経路での進み具合を部屋ごとに 0 から 1 で返す。入口が 0、経路の終わりが 1
+// 経路から外れた部屋は、分かれ道になる経路上の部屋の値を使う。入口から辿り着けない部屋は含まない
+pub fn room_progress(
+    result: &Dungeon3DGeneratorResult,
+    path: &CriticalPath,
+) -> BTreeMap<RoomId, f32> {
+    let graph = passage_graph(result);
+    let steps = path.rooms.len().saturating_sub(1).max(1) as f32;
+    let mut progress = path
+        .rooms
+        .iter()
+        .enumerate()
+        .map(|(i, room_id)| (*room_id, i as f32 / steps))
+        .collect::<BTreeMap<_, _>>();
+    let mut queue = path.rooms.iter().copied().collect::<VecDeque<_>>();
+    while let Some(room_id) = queue.pop_front() {
+        let value = progress[&room_id];
+        for (next, _) in graph.get(&room_id).into_iter().flatten() {
+            if !progress.contains_key(next) {
+                progress.insert(*next, value);
+                queue.push_back(*next);
+            }
+        }
+    }
+    progress
+}
+
+// 条件に従ってゴールの部屋を1つ選び、選んだ理由を添えて返す。入口と入口から辿り着けない部屋は選ばない
+// 同じ評価の部屋は入口から遠い方、さらに番号の小さい方を選ぶ。選べる部屋がなければ None
+pub fn select_goal_room(
+    result: &Dungeon3DGeneratorResult,
+    entrance: RoomId,
+    criteria: GoalCriteria,
+) -> Option<GoalSelection> {
+    let graph = passage_graph(result);
+    let (parents, _) = search_from(result, entrance);
+    let distance = |room_id: RoomId| path_to(&parents, room_id).passages.len();
+    let candidates = parents
+        .keys()
+        .filter(|room_id| **room_id != entrance && result.rooms.contains_key(room_id))
+        .map(|room_id| (*room_id, distance(*room_id)))
+        .collect::<Vec<_>>();
+    // 評価の大きい部屋を選ぶ。max_by_key は同じ値なら後の要素を返すため、番号の逆順に並べて比べる
+    let best = |key: &dyn Fn(RoomId, usize) -> Option<(i64, usize)>| {
+        candidates
+            .iter()
+            .rev()
+            .filter_map(|(room_id, passages)| {
+                key(*room_id, *passages).map(|value| (value, *room_id, *passages))
+            })
+            .max_by_key(|(value, _, _)| *value)
+            .map(|(_, room_id, passages)| (room_id, passages))
+    };
+    let farthest = || best(&|_, passages| Some((0, passages)));
+
+    match criteria {
+        GoalCriteria::FarthestFromStart => farthest().map(|(room_id, passages)| GoalSelection {
+            room_id,
+            reason: GoalReason::FarthestFromStart { passages },
+        }),
+        GoalCriteria::LargestDeadEnd => {
+            let area = |room_id: RoomId| {
+                result
+                    .room_extensions
+                    .get(&room_id)
+                    .into_iter()
+                    .flatten()
+                    .chain([&result.rooms[&room_id]])
+                    .map(|room| room.width * room.depth)
+                    .sum::<u32>()
+            };
+            let neighbors = |room_id: RoomId| {
+                graph
+                    .get(&room_id)
+                    .into_iter()
+                    .flatten()
+                    .map(|(next, _)| *next)
+                    .collect::<BTreeSet<_>>()
+                    .len()
+            };
+            match best(&|room_id, passages| {
+                (neighbors(room_id) == 1).then(|| (area(room_id) as i64, passages))
+            }) {
+                Some((room_id, passages)) => Some(GoalSelection {
+                    room_id,
+                    reason: GoalReason::LargestDeadEnd {
+                        area: area(room_id),
+                        passages,
+                    },
+                }),
+                None => farthest().map(|(room_id, passages)| GoalSelection {
+                    room_id,
+                    reason: GoalReason::NoDeadEnd { passages },
+                }),
+            }
+        }
+        GoalCriteria::DeepestHierarchy => {
+            best(&|room_id, passages| Some((-(result.rooms[&room_id].hierarchy as i64), passages)))
+                .map(|(room_id, passages)| GoalSelection {
+                    room_id,
+                    reason: GoalReason::DeepestHierarchy {
+                        hierarchy: result.rooms[&room_id].hierarchy,
+                        passages,
+                    },
+                })
+        }
+    }
+}
+
+fn passage_graph(result: &Dungeon3DGeneratorResult) -> BTreeMap<RoomId, Vec<(RoomId, PassageId)>> {
+    let mut graph: BTreeMap<RoomId, Vec<(RoomId, PassageId)>> = BTreeMap::new();
+    for (passage_id, passage) in result.passages.iter() {
+        if passage.start_room_id == passage.end_room_id {
+            continue;
+        }
+        graph
+            .entry(passage.start_room_id)
+            .or_default()
+            .push((passage.end_room_id, *passage_id));
+        graph
+            .entry(passage.end_room_id)
+            .or_default()
+            .push((passage.start_room_id, *passage_id));
+    }
+    graph
+}
+
+type Parents = BTreeMap<RoomId, Option<(RoomId, PassageId)>>;
+
+// 入口から幅優先で辿った各部屋の親と、最後に辿った最も遠い部屋
+fn search_from(result: &Dungeon3DGeneratorResult, entrance: RoomId) -> (Parents, RoomId) {
+    let graph = passage_graph(result);
+    let mut parents: Parents = BTreeMap::from([(entrance, None)]);
+    let mut queue = VecDeque::from([entrance]);
+    let mut farthest = entrance;
+    while let Some(room_id) = queue.pop_front() {
+        farthest = room_id;
+        for (next, passage_id) in graph.get(&room_id).into_iter().flatten() {
+            if !parents.contains_key(next) {
+                parents.insert(*next, Some((room_id, *passage_id)));
+                queue.push_back(*next);
+            }
+        }
+    }
+    (parents, farthest)
+}
+
+fn path_to(parents: &Parents, goal: RoomId) -> CriticalPath {
+    let mut path = CriticalPath::default();
+    let mut current = goal;
+    path.rooms.push(current);
+    while let Some(Some((parent, passage_id))) = parents.get(&current) {
+        path.passages.push(*passage_id);
+        path.rooms.push(*parent);
+        current = *parent;
+    }
+    path.rooms.reverse();
+    path.passages.reverse();
+    path
+}
+
+// 難易度の予算内で罠と遭遇の位置を提案する
+// 必ず通る経路上のセルほど選ばれやすく、入口の部屋と互いに近すぎる位置は避ける
+pub fn propose_encounters<R: Rng>(
+    result: &Dungeon3DGeneratorResult,
+    entrance: RoomId,
+    budget: u32,
+    rng: &mut R,
+) -> Vec<EncounterPlacement> {
+    let path = critical_path(result, entrance);
+    let critical_rooms = path.rooms.iter().copied().collect::<BTreeSet<_>>();
+    let critical_passages = path.passages.iter().copied().collect::<BTreeSet<_>>();
+
+    let mut candidates = Vec::new();
+    for (passage_id, passage) in result.passages.iter() {
+        for ((x, y, z), voxel_type) in passage.cells.iter() {
+            let p = Vector3::new(*x, *y, *z);
+            if *voxel_type == VoxelType::PassageSpace
+                && result.voxel_map.get(&(p - Vector3::y())) == VoxelType::PassageFloor
+            {
+                candidates.push(EncounterPlacement {
+                    kind: EncounterKind::Trap,
+                    position: (*x, *y, *z),
+                    cost: TRAP_COST,
+                    critical: critical_passages.contains(passage_id),
+                    passage_id: Some(*passage_id),
+                    room_id: None,
+                });
+            }
+        }
+    }
+    for room in result.rooms.values().filter(|room| room.id != entrance) {
+        let y = room.origin.1 as i32;
+        for x in room.origin.0..room.end().0 {
+            for z in room.origin.2..room.end().2 {
+                candidates.push(EncounterPlacement {
+                    kind: EncounterKind::Encounter,
+                    position: (x as i32, y, z as i32),
+                    cost: ENCOUNTER_COST,
+                    critical: critical_rooms.contains(&room.id),
+                    passage_id: None,
+                    room_id: Some(room.id),
+                });
+            }
+        }
+    }
+    // 同じ位置を複数の通路が共有する場合は最初の通路のものだけを残す
+    let mut seen = BTreeSet::new();
+    candidates.retain(|candidate| seen.insert(candidate.position));
+
+    let mut remaining = budget;
+    let mut placements = Vec::new();
+    loop {
+        candidates.retain(|candidate| candidate.cost <= remaining);
+        let weights = candidates.iter().map(|candidate| match candidate.critical {
+            true => CRITICAL_WEIGHT,
+            false => 1,
+        });
+        let Ok(distribution) = WeightedIndex::new(weights) else {
+            return placements;
+        };
+        let placement = candidates.swap_remove(distribution.sample(rng));
+        remaining -= placement.cost;
+        let (x, y, z) = placement.position;
+        candidates.retain(|candidate| {
+            let (cx, cy, cz) = candidate.position;
+            (cx - x).abs() + (cy - y).abs() + (cz - z).abs() >= MIN_DISTANCE
+        });
+        placements.push(placement);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encounter::{
+        critical_path, critical_path_to, propose_encounters, room_progress, select_goal_room,
+        GoalCriteria, GoalReason,
+    };
+    use crate::generate_drd::{generate_dungeon_3d, Dungeon3DGeneratorConfig};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_propose_encounters() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        let entrance = *result.rooms.keys().next().unwrap();
+        let path = critical_path(&result, entrance);
+        assert_eq!(path.rooms.first(), Some(&entrance));
+        assert_eq!(path.passages.len() + 1, path.rooms.len());
+
+        let placements = propose_encounters(&result, entrance, 20, &mut StdRng::seed_from_u64(0));
+        assert!(!placements.is_empty());
+        assert!(placements.iter().map(|p| p.cost).sum::<u32>() <= 20);
+        for placement in placements.iter() {
+            assert_ne!(placement.room_id, Some(entrance));
+            if let Some(passage_id) = placement.passage_id {
+                assert_eq!(placement.critical, path.passages.contains(&passage_id));
+            }
+        }
+    }
+
+    #[test]
+    fn test_room_progress() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        let entrance = *result.rooms.keys().next().unwrap();
+        let path = critical_path(&result, entrance);
+        let goal = *path.rooms.last().unwrap();
+        assert_eq!(
+            critical_path_to(&result, entrance, goal),
+            Some(path.clone())
+        );
+
+        let progress = room_progress(&result, &path);
+        assert_eq!(progress.len(), result.rooms.len());
+        assert_eq!(progress[&entrance], 0.0);
+        assert_eq!(progress[&goal], 1.0);
+        assert!(progress.values().all(|value| (0.0..=1.0).contains(value)));
+        // 経路上では入口から離れるほど大きくなる
+        assert!(path
+            .rooms
+            .windows(2)
+            .all(|rooms| progress[&rooms[0]] < progress[&rooms[1]]));
+    }
+
+    #[test]
+    fn test_select_goal_room() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        let entrance = *result.rooms.keys().next().unwrap();
+        let path = critical_path(&result, entrance);
+
+        let goal = select_goal_room(&result, entrance, GoalCriteria::FarthestFromStart).unwrap();
+        assert_eq!(
+            goal.reason,
+            GoalReason::FarthestFromStart {
+                passages: path.passages.len()
+            }
+        );
+        assert_eq!(
+            critical_path_to(&result, entrance, goal.room_id)
+                .unwrap()
+                .passages
+                .len(),
+            path.passages.len()
+        );
+
+        let goal = select_goal_room(&result, entrance, GoalCriteria::DeepestHierarchy).unwrap();
+        let deepest = result
+            .rooms
+            .values()
+            .filter(|room| room.id != entrance)
+            .map(|room| room.hierarchy)
+            .min()
+            .unwrap();
+        assert_eq!(result.rooms[&goal.room_id].hierarchy, deepest);
+        assert_ne!(goal.room_id, entrance);
+
+        let goal = select_goal_room(&result, entrance, GoalCriteria::LargestDeadEnd).unwrap();
+        assert_ne!(goal.room_id, entrance);
+        assert!(!goal.reason.to_string().is_empty());
+        if let GoalReason::LargestDeadEnd { area, .. } = goal.reason {
+            let room = &result.rooms[&goal.room_id];
+            assert_eq!(area, room.width * room.depth);
+        }
+    }
+}
@@ -0,0 +1,280 @@
+use crate::constants::VoxelType;
+use crate::passage::{Passage, PassageId};
+use crate::pipeline::GenerationState;
+use crate::room::{Room, RoomId};
+use crate::room_connection::ConnectionId;
+use nalgebra::Vector3;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GenerationEvent {
+    RoomPlaced {
+        room: Room, // Geometry when placed, before any merge
+    },
+    RoomMerged {
+        room_id: RoomId, // Absorbed room; its space now belongs to `into`
+        into: RoomId,
+    },
+    EdgeSelected {
+        connection_id: ConnectionId,
+        room0_id: RoomId,
+        room1_id: RoomId,
+        required: bool,
+    },
+    PassageCarved {
+        passage_id: PassageId,
+        connection_id: Option<ConnectionId>, // None for passages added after the selected edges, e.g. connectivity repairs
+        room0_id: RoomId,
+        room1_id: RoomId,
+        voxels: Vec<((i32, i32, i32), VoxelType)>, // Voxels the passage wrote when it was carved, as listed in Passage::cells
+    },
+    PassageFailed {
+        connection_id: ConnectionId,
+        room0_id: RoomId,
+        room1_id: RoomId,
+    },
+    PassageSkipped {
+        connection_id: ConnectionId, // Rooms were already connected and skip_connected_rooms is enabled
+        room0_id: RoomId,
+        room1_id: RoomId,
+    },
+    SurfaceEntranceCarved {
+        room_id: RoomId,
+        voxels: Vec<((i32, i32, i32), VoxelType)>,
+    },
+    VoxelsChanged {
+        voxels: Vec<((i32, i32, i32), Option<VoxelType>)>, // Voxels post-processing rewrote, or removed with None
+    },
+}
+
+impl GenerationEvent {
+    pub(crate) fn carved(
+        passage_id: PassageId,
+        passage: &Passage,
+        connection_id: Option<ConnectionId>,
+    ) -> Self {
+        GenerationEvent::PassageCarved {
+            passage_id,
+            connection_id,
+            room0_id: passage.start_room_id,
+            room1_id: passage.end_room_id,
+            voxels: passage.cells.clone(),
+        }
+    }
+}
+
+// 段階の中で記録されなかった部屋を番号順に記録する。経由地の部屋や差し替えた段階で置いた部屋をここで拾う
+pub(crate) fn record_rooms(state: &mut GenerationState<'_>) {
+    let placed = state
+        .events
+        .iter()
+        .filter_map(|event| match event {
+            GenerationEvent::RoomPlaced { room } => Some(room.id),
+            _ => None,
+        })
+        .collect::<BTreeSet<_>>();
+    let rooms = state
+        .rooms
+        .values()
+        .filter(|room| !placed.contains(&room.id))
+        .map(|room| GenerationEvent::RoomPlaced { room: room.clone() })
+        .collect::<Vec<_>>();
+    state.events.extend(rooms);
+}
+
+pub(crate) fn record_edges(state: &mut GenerationState<'_>) {
+    let edges = state
+        .connections
+        .iter()
+        .map(
+            |(connection_id, connection)| GenerationEvent::EdgeSelected {
+                connection_id: *connection_id,
+                room0_id: connection.start_room.id,
+                room1_id: connection.end_room.id,
+                required: connection.required,
+            },
+        )
+        .collect::<Vec<_>>();
+    state.events.extend(edges);
+}
+
+// first 以降の番号の通路を記録する。番号は掘った順に振られる
+pub(crate) fn record_passages_from(
+    events: &mut Vec<GenerationEvent>,
+    passages: &BTreeMap<PassageId, Passage>,
+    first: PassageId,
+) {
+    events.extend(
+        passages
+            .range(first..)
+            .map(|(passage_id, passage)| GenerationEvent::carved(*passage_id, passage, None)),
+    );
+}
+
+// 差し替えた段階が記録しなかった通路を番号順に記録する
+pub(crate) fn record_passages(state: &mut GenerationState<'_>) {
+    let carved = state
+        .events
+        .iter()
+        .filter_map(|event| match event {
+            GenerationEvent::PassageCarved { passage_id, .. } => Some(*passage_id),
+            _ => None,
+        })
+        .collect::<BTreeSet<_>>();
+    let passages = state
+        .passages
+        .iter()
+        .filter(|(passage_id, _)| !carved.contains(passage_id))
+        .map(|(passage_id, passage)| GenerationEvent::carved(*passage_id, passage, None))
+        .collect::<Vec<_>>();
+    state.events.extend(passages);
+}
+
+// 後処理の前のボクセルと比べて、書き換えたボクセルと取り除いたボクセルを位置の順に記録する
+pub(crate) fn record_voxel_changes(
+    state: &mut GenerationState<'_>,
+    before: &HashMap<Vector3<i32>, VoxelType>,
+) {
+    let after = &state.voxel_map.map;
+    let mut voxels = after
+        .iter()
+        .filter(|(p, voxel_type)| before.get(*p) != Some(*voxel_type))
+        .map(|(p, voxel_type)| ((p.x, p.y, p.z), Some(*voxel_type)))
+        .chain(
+            before
+                .keys()
+                .filter(|p| !after.contains_key(*p))
+                .map(|p| ((p.x, p.y, p.z), None)),
+        )
+        .collect::<Vec<_>>();
+    if voxels.is_empty() {
+        return;
+    }
+    voxels.sort_by_key(|(p, _)| *p);
+    state.events.push(GenerationEvent::VoxelsChanged { voxels });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::VoxelType;
+    use crate::events::GenerationEvent;
+    use crate::generate_drd::{generate_dungeon_3d, Dungeon3DGeneratorConfig};
+    use nalgebra::Vector3;
+    use std::collections::{BTreeMap, BTreeSet};
+
+    #[test]
+    fn test_generation_events() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            loop_probability: 1.0,
+            ..Default::default()
+        })
+        .unwrap();
+        let rooms = result
+            .events
+            .iter()
+            .filter(|event| matches!(event, GenerationEvent::RoomPlaced { .. }))
+            .count();
+        assert_eq!(rooms, result.rooms.len());
+
+        // 通路は掘った順に記録され、選ばれた辺の通路はその辺の部屋の組をつなぐ
+        let edges = result
+            .events
+            .iter()
+            .filter_map(|event| match event {
+                GenerationEvent::EdgeSelected {
+                    connection_id,
+                    room0_id,
+                    room1_id,
+                    ..
+                } => Some((*connection_id, BTreeSet::from([*room0_id, *room1_id]))),
+                _ => None,
+            })
+            .collect::<BTreeMap<_, _>>();
+        let mut carved = Vec::new();
+        let mut failed = 0;
+        for event in result.events.iter() {
+            match event {
+                GenerationEvent::PassageCarved {
+                    passage_id,
+                    connection_id,
+                    room0_id,
+                    room1_id,
+                    ..
+                } => {
+                    if let Some(connection_id) = connection_id {
+                        assert_eq!(edges[connection_id], BTreeSet::from([*room0_id, *room1_id]));
+                    }
+                    carved.push(*passage_id);
+                }
+                GenerationEvent::PassageFailed { connection_id, .. } => {
+                    assert!(edges.contains_key(connection_id));
+                    failed += 1;
+                }
+                _ => {}
+            }
+        }
+        assert!(carved.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(carved, result.passages.keys().copied().collect::<Vec<_>>());
+        assert_eq!(failed, result.failed_connections.len());
+
+        // 記録したボクセルを順に書くと、書いた位置は全て生成結果と一致する
+        let mut replay: BTreeMap<(i32, i32, i32), Option<VoxelType>> = BTreeMap::new();
+        for event in result.events.iter() {
+            match event {
+                GenerationEvent::PassageCarved { voxels, .. }
+                | GenerationEvent::SurfaceEntranceCarved { voxels, .. } => {
+                    replay.extend(voxels.iter().map(|(p, voxel_type)| (*p, Some(*voxel_type))));
+                }
+                GenerationEvent::VoxelsChanged { voxels } => replay.extend(voxels.iter().copied()),
+                _ => {}
+            }
+        }
+        assert!(matches!(
+            result.events.last(),
+            Some(GenerationEvent::VoxelsChanged { .. })
+        ));
+        for ((x, y, z), voxel_type) in replay {
+            let p = Vector3::new(x, y, z);
+            assert_eq!(result.voxel_map.map.get(&p).copied(), voxel_type, "{:?}", p);
+        }
+    }
+
+    #[test]
+    fn test_merged_room_events() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            room_margin_x: 1,
+            room_margin_z: 1,
+            room_hierarchy: 1,
+            room_width_range: 5..=12,
+            room_depth_range: 5..=12,
+            merge_adjacent_rooms: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let placed = result
+            .events
+            .iter()
+            .filter_map(|event| match event {
+                GenerationEvent::RoomPlaced { room } => Some(room.id),
+                _ => None,
+            })
+            .collect::<BTreeSet<_>>();
+        let merged = result
+            .events
+            .iter()
+            .filter_map(|event| match event {
+                GenerationEvent::RoomMerged { room_id, into } => Some((*room_id, *into)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert!(!merged.is_empty());
+        // まとめられた部屋も置いた時点で記録され、生成結果には残らない
+        assert_eq!(placed.len(), result.rooms.len() + merged.len());
+        for (room_id, into) in merged {
+            assert!(placed.contains(&room_id) && placed.contains(&into));
+            assert!(!result.rooms.contains_key(&room_id));
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use crate::constants::{Direction4, VoxelType, DIRECTIONS};
+use crate::room::{Room, RoomId};
+use crate::voxel_map::VoxelMap;
+use nalgebra::Vector3;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum FurnitureAnchorKind {
+    Wall,   // Against a single wall
+    Corner, // Against two walls
+    Center, // Middle of the floor, away from the walls
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FurnitureAnchor {
+    pub room_id: RoomId,
+    pub kind: FurnitureAnchorKind,
+    pub position: (i32, i32, i32), // Floor-level cell in the room bottom space
+    pub facing: Option<Direction4>, // Direction toward the room interior; None for Center
+    pub clearance: u32,            // Free cells in front of the anchor up to the opposite wall
+    pub height: u32,               // Free cells above the floor
+}
+
+// 小物配置向けに、部屋ごとに壁際、隅、中央の設置位置を求める
+// 通路の出入口をふさがないよう、部屋の外の通れる空間に面したセルとその両隣は除く
+pub fn find_furniture_anchors(
+    rooms: &BTreeMap<RoomId, Room>,
+    voxel_map: &VoxelMap,
+) -> Vec<FurnitureAnchor> {
+    let mut anchors = Vec::new();
+    for room in rooms.values() {
+        let y = room.origin.1 as i32;
+        let (x0, z0) = (room.origin.0 as i32, room.origin.2 as i32);
+        let (x1, z1) = (room.end().0 as i32, room.end().2 as i32);
+        for z in z0..z1 {
+            for x in x0..x1 {
+                let p = Vector3::new(x, y, z);
+                if voxel_map.get(&p) != VoxelType::RoomBottomSpace(room.id)
+                    || is_near_entrance(voxel_map, room.id, &p)
+                {
+                    continue;
+                }
+                let walls = DIRECTIONS
+                    .iter()
+                    .filter(|dir| !is_room_floor(voxel_map, room.id, &(p + dir.to_vec3())))
+                    .copied()
+                    .collect::<Vec<_>>();
+                let kind = match walls.len() {
+                    1 => FurnitureAnchorKind::Wall,
+                    2 if !walls[0].is_opposite(&walls[1]) => FurnitureAnchorKind::Corner,
+                    _ => continue,
+                };
+                let facing = walls[0].opposite();
+                anchors.push(FurnitureAnchor {
+                    room_id: room.id,
+                    kind,
+                    position: (x, y, z),
+                    facing: Some(facing),
+                    clearance: clearance(voxel_map, room.id, &p, facing),
+                    height: room.height,
+                });
+            }
+        }
+
+        let center = room.center();
+        let p = Vector3::new(center.0 as i32, y, center.2 as i32);
+        if voxel_map.get(&p) == VoxelType::RoomBottomSpace(room.id) {
+            anchors.push(FurnitureAnchor {
+                room_id: room.id,
+                kind: FurnitureAnchorKind::Center,
+                position: (p.x, p.y, p.z),
+                facing: None,
+                clearance: DIRECTIONS
+                    .iter()
+                    .map(|dir| clearance(voxel_map, room.id, &p, *dir))
+                    .min()
+                    .unwrap_or(0),
+                height: room.height,
+            });
+        }
+    }
+    anchors
+}
+
+fn is_room_floor(voxel_map: &VoxelMap, room_id: RoomId, p: &Vector3<i32>) -> bool {
+    voxel_map.get(p) == VoxelType::RoomBottomSpace(room_id)
+}
+
+// 部屋の外の通れる空間に面しているセル、またはその隣のセルか
+fn is_near_entrance(voxel_map: &VoxelMap, room_id: RoomId, p: &Vector3<i32>) -> bool {
+    let faces_passage = |q: &Vector3<i32>| {
+        DIRECTIONS.iter().any(|dir| {
+            let voxel_type = voxel_map.get(&(q + dir.to_vec3()));
+            voxel_type.is_passable() && voxel_type.room_id() != Some(room_id)
+        })
+    };
+    faces_passage(p)
+        || DIRECTIONS.iter().any(|dir| {
+            let q = p + dir.to_vec3();
+            is_room_floor(voxel_map, room_id, &q) && faces_passage(&q)
+        })
+}
+
+fn clearance(voxel_map: &VoxelMap, room_id: RoomId, p: &Vector3<i32>, facing: Direction4) -> u32 {
+    let mut q = p + facing.to_vec3();
+    let mut count = 0;
+    while is_room_floor(voxel_map, room_id, &q) {
+        count += 1;
+        q += facing.to_vec3();
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::furniture::{find_furniture_anchors, FurnitureAnchorKind};
+    use crate::generate_drd::{generate_dungeon_3d, Dungeon3DGeneratorConfig};
+
+    #[test]
+    fn test_find_furniture_anchors() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        let anchors = find_furniture_anchors(&result.rooms, &result.voxel_map);
+        for room in result.rooms.values() {
+            let room_anchors = anchors
+                .iter()
+                .filter(|anchor| anchor.room_id == room.id)
+                .collect::<Vec<_>>();
+            assert!(room_anchors
+                .iter()
+                .any(|anchor| anchor.kind == FurnitureAnchorKind::Center));
+            for anchor in room_anchors {
+                let (x, y, z) = anchor.position;
+                assert!(room.contains_point(x as u32, y as u32, z as u32));
+                match anchor.kind {
+                    FurnitureAnchorKind::Center => assert_eq!(anchor.facing, None),
+                    _ => assert!(anchor.facing.is_some() && anchor.clearance > 0),
+                }
+            }
+        }
+    }
+}
@@ -1,17 +1,38 @@
-use crate::create_start::create_start;
+use crate::connectivity::{room_components, rooms_connected, ConnectivityRepair};
+use crate::constants::{Axis, Direction4, VoxelType};
+use crate::create_start::face_starts;
+use crate::dead_end::{find_dead_ends, trim_dead_ends, DeadEnd};
 use crate::delaunary_3d::Delaunay3D;
-use crate::passage::Passage;
+use crate::doorway::{mark_doorways, widen_doorways, Doorway};
+#[cfg(feature = "events")]
+use crate::events::{record_passages_from, GenerationEvent};
+use crate::generation_report::{GenerationPhase, GenerationReport};
+use crate::junction::{find_junctions, Junction};
+use crate::lighting::{suggest_lights, LightSuggestion};
+use crate::material::{assign_materials, MaterialId, MaterialPalette};
+use crate::passage::{passage_owners, Passage, PassageId};
+use crate::pipeline::{seeded_rng, GenerationPipeline, GenerationState};
 use crate::room::{Room, RoomId};
-use crate::room_connection::RoomConnection;
-use crate::voxel_map::{VoxelMap, VoxelMapError};
+use crate::room_adjacency::{find_adjacent_rooms, RoomAdjacency};
+use crate::room_connection::{ConnectionId, RoomConnection};
+use crate::room_merge::merge_adjacent_rooms;
+use crate::stair::{find_stairs, Stair};
+use crate::voxel_map::{RouteStats, VoxelMap, VoxelMapError};
 use nalgebra::Vector3;
 use pathfinding::prelude::kruskal;
-use rand::{Rng, SeedableRng};
-use std::cmp::Ordering;
-use std::collections::BTreeMap;
+use rand::{Rng, RngCore};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fmt;
 use std::ops::RangeInclusive;
-use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+pub type SurfaceHeight = Arc<dyn Fn(i32, i32) -> i32 + Send + Sync>;
+pub type RoomPlacementScore = Arc<dyn Fn(&Room) -> f64 + Send + Sync>;
+pub type PassageStepCost = Arc<dyn Fn(i32, i32, i32) -> i32 + Send + Sync>;
+
+#[derive(Clone)]
 pub struct Dungeon3DGeneratorConfig {
     pub width: u32,        // Width of entire dungeon (x-axis)
     pub height: u32,       // Height of entire dungeon (y-axis)
@@ -24,8 +45,136 @@ pub struct Dungeon3DGeneratorConfig {
     pub room_margin_x: u32,
     pub room_margin_y: u32,
     pub room_margin_z: u32,
+    pub room_density: f32, // Probability that each block receives a room; blocks containing anchors always do
+    pub room_aspect_ratio_range: Option<RangeInclusive<f32>>, // Allowed ratio of the longer to the shorter horizontal side; other sizes are resampled
+    pub block_split_jitter: f32, // Fraction of the block size each split between blocks may shift (0 to 0.5); 0 keeps equal blocks
     pub passage_height: u32,
     pub margin_for_bounds: u32, // Margin used to specify a range for all elements to fit, such as passages
+    pub boundary_margin: u32,   // Minimum distance between rooms and the dungeon bounds
+    pub room_anchors: Vec<(u32, u32, u32)>, // Points which must be inside the space of some room
+    pub surface_height: Option<SurfaceHeight>, // Terrain height at (x, z); rooms and passages stay below it
+    pub surface_opening: bool, // Carve one passage from the highest room up to the surface
+    pub passage_wall_noise: f64, // Probability of bulging each passage wall one voxel outward
+    pub direct_doors: bool, // Connect wall-adjacent rooms of the mst with a door instead of a corridor
+    pub merge_adjacent_rooms: bool, // Merge rooms on the same floor whose walls face each other within one voxel into one room
+    pub wide_doorway_room_size: Option<u32>, // Doorways into rooms at least this wide and deep are widened to two voxels where the wall allows
+    pub trim_dead_ends: bool, // Remove passage stubs which lead nowhere instead of only reporting them
+    pub min_inter_hierarchy_connections: u32, // Minimum passages crossing each boundary between hierarchy levels
+    pub min_room_degree: u32, // Minimum rooms each room is joined to by carved passages; fallback passages are added when short
+    pub entrance_rooms: Vec<RoomId>, // Rooms where players start; each must reach every objective room
+    pub objective_rooms: Vec<RoomId>, // Rooms every entrance room must reach
+    pub max_objective_distance: Option<u32>, // Most passages between each entrance and objective room; shortcuts are carved when farther
+    pub hub_min_degree: u32, // Rooms with at least this many mst edges are enlarged as hubs (0 disables)
+    pub hub_growth: u32, // Voxels a hub room grows on each horizontal side, limited by its block
+    pub loop_probability: f64, // Probability of adding each non-mst Delaunay edge as an extra passage
+    pub max_connection_length: Option<u32>, // Extra edges whose room centers are farther apart are dropped
+    pub split_long_connections: bool, // Break mst edges longer than max_connection_length with waypoint rooms
+    pub max_connection_rise: Option<u32>, // Mst edges climbing more voxels than this get landing rooms in between
+    pub excluded_room_pairs: Vec<(RoomId, RoomId)>, // Room pairs which must not be joined by a direct passage
+    pub excluded_hierarchy_pairs: Vec<(u32, u32)>, // Hierarchy levels which must not be joined directly
+    pub avoid_foreign_rooms: bool, // Passages may not run alongside rooms other than the two they connect
+    pub foreign_room_penalty: u32, // Extra route cost per step near rooms other than the two being connected
+    pub skip_connected_rooms: bool, // Do not carve a connection whose rooms are already joined by earlier passages
+    pub max_route_nodes: Option<u32>, // Each route search gives up with VoxelMapError::Budget after exploring this many nodes
+    pub guided_route_search: bool, // Steer each passage search by a coarse walkable distance around rooms instead of the straight distance
+    pub room_placement_score: Option<RoomPlacementScore>, // Picks the best scored of several candidate placements per block
+    pub passage_step_cost: Option<PassageStepCost>, // Extra route cost of each passage step at (x, y, z)
+    pub stair_run: u32, // Horizontal voxels a stair advances per one-voxel climb; 1 is a 45 degree slope
+    pub stair_clearance: Option<u32>, // Head room above and ahead of each stair step; None or lower values use passage_height
+    pub material_palette: MaterialPalette, // Material ids assigned to each rendered voxel by role, hierarchy and room
+    pub report: bool, // Collect a GenerationReport with timings and search statistics
+}
+
+impl Dungeon3DGeneratorConfig {
+    // 区画に分けて部屋を置く既定の配置で、満たされていない制約を全て集める。生成を始める前に一度で直せるようにする
+    // 最大サイズが収まらない場合は区画ごとにサイズを縮めるため、最小サイズのみ検証する
+    pub fn violations(&self) -> Vec<ConfigViolation> {
+        let mut violations = Vec::new();
+        for (field, range) in [
+            ("room_width_range", &self.room_width_range),
+            ("room_height_range", &self.room_height_range),
+            ("room_depth_range", &self.room_depth_range),
+        ] {
+            if *range.start() == 0 || range.start() > range.end() {
+                violations.push(ConfigViolation::new(field, range, "1 <= start <= end"));
+            }
+        }
+        if !(0.0..=1.0).contains(&self.room_density) {
+            violations.push(ConfigViolation::new(
+                "room_density",
+                self.room_density,
+                "0.0 <= room_density <= 1.0",
+            ));
+        }
+        if let Some(ratio_range) = &self.room_aspect_ratio_range {
+            if *ratio_range.start() < 1.0 || ratio_range.start() > ratio_range.end() {
+                violations.push(ConfigViolation::new(
+                    "room_aspect_ratio_range",
+                    ratio_range,
+                    "1.0 <= start <= end",
+                ));
+            }
+        }
+        if !(0.0..=0.5).contains(&self.block_split_jitter) {
+            violations.push(ConfigViolation::new(
+                "block_split_jitter",
+                self.block_split_jitter,
+                "0.0 <= block_split_jitter <= 0.5",
+            ));
+        }
+        let min_width =
+            self.room_width_range.start() + self.room_margin_x.max(1) + self.boundary_margin;
+        if self.width < min_width {
+            violations.push(ConfigViolation::new(
+                "width",
+                self.width,
+                format!(">= {}", min_width),
+            ));
+        }
+        let min_depth =
+            self.room_depth_range.start() + self.room_margin_z.max(1) + self.boundary_margin;
+        if self.depth < min_depth {
+            violations.push(ConfigViolation::new(
+                "depth",
+                self.depth,
+                format!(">= {}", min_depth),
+            ));
+        }
+        if self.room_hierarchy == 0 {
+            violations.push(ConfigViolation::new(
+                "room_hierarchy",
+                self.room_hierarchy,
+                ">= 1",
+            ));
+        } else {
+            let min_height = self
+                .room_hierarchy
+                .saturating_mul(self.room_height_range.start() + self.room_margin_y.max(1));
+            if self.height < min_height {
+                violations.push(ConfigViolation::new(
+                    "height",
+                    self.height,
+                    format!(">= {}", min_height),
+                ));
+            }
+        }
+        violations
+    }
+
+    // 2つの部屋を通路で直接つなぐことが禁止されているか
+    pub(crate) fn forbids(&self, room0: &Room, room1: &Room) -> bool {
+        self.excluded_room_pairs
+            .iter()
+            .any(|pair| is_same_pair(*pair, (room0.id, room1.id)))
+            || self
+                .excluded_hierarchy_pairs
+                .iter()
+                .any(|pair| is_same_pair(*pair, (room0.hierarchy, room1.hierarchy)))
+    }
+}
+
+fn is_same_pair<T: PartialEq>((a, b): (T, T), (c, d): (T, T)) -> bool {
+    (a == c && b == d) || (a == d && b == c)
 }
 
 impl Default for Dungeon3DGeneratorConfig {
@@ -42,117 +191,646 @@ impl Default for Dungeon3DGeneratorConfig {
             room_margin_x: 4,
             room_margin_y: 1,
             room_margin_z: 4,
+            room_density: 1.0,
+            block_split_jitter: 0.0,
+            room_aspect_ratio_range: None,
             passage_height: 2,
             margin_for_bounds: 4,
+            boundary_margin: 0,
+            room_anchors: Vec::new(),
+            surface_height: None,
+            surface_opening: false,
+            passage_wall_noise: 0.0,
+            direct_doors: false,
+            merge_adjacent_rooms: false,
+            wide_doorway_room_size: None,
+            trim_dead_ends: false,
+            min_inter_hierarchy_connections: 0,
+            min_room_degree: 0,
+            entrance_rooms: Vec::new(),
+            objective_rooms: Vec::new(),
+            max_objective_distance: None,
+            hub_min_degree: 0,
+            hub_growth: 2,
+            loop_probability: 0.3,
+            max_connection_length: None,
+            split_long_connections: false,
+            max_connection_rise: None,
+            excluded_room_pairs: Vec::new(),
+            excluded_hierarchy_pairs: Vec::new(),
+            avoid_foreign_rooms: false,
+            foreign_room_penalty: 0,
+            skip_connected_rooms: false,
+            max_route_nodes: None,
+            guided_route_search: false,
+            room_placement_score: None,
+            passage_step_cost: None,
+            stair_run: 1,
+            stair_clearance: None,
+            material_palette: MaterialPalette::default(),
+            report: false,
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Dungeon3DGeneratorResult {
     pub rooms: BTreeMap<RoomId, Room>,
     pub voxel_map: VoxelMap,
-    pub passages: Vec<Passage>,
+    pub passages: BTreeMap<PassageId, Passage>,
+    pub room_connections: Vec<RoomConnection>, // Selected edges between rooms, whether or not a passage was carved for them
+    pub failed_connections: Vec<FailedConnection>, // Selected edges whose passage could not be carved
+    pub report: Option<GenerationReport>,
+    pub repairs: Vec<ConnectivityRepair>, // Passages carved because the rooms were disconnected
+    pub objective_passages: Vec<(RoomId, RoomId, PassageId)>, // Shortcuts carved from a room near an entrance to an objective room: (from, objective, passage)
+    pub unmet_hierarchy_boundaries: Vec<UnmetHierarchyBoundary>, // Boundaries still short of min_inter_hierarchy_connections
+    pub under_connected_rooms: BTreeMap<RoomId, usize>, // Rooms still short of min_room_degree, with the number of rooms they are joined to
+    pub surface_entrance: Option<SurfaceEntrance>,
+    pub doorways: Vec<Doorway>,
+    pub junctions: Vec<Junction>,
+    pub dead_ends: Vec<DeadEnd>, // Stubs found after carving; already removed when trim_dead_ends is set
+    pub stairs: Vec<Stair>,
+    pub stairwell: Vec<RoomId>, // Stairwell rooms from the bottom floor up; empty unless generated by generate_tower
+    pub central_room: Option<RoomId>, // Central chamber; None unless generated by generate_arena
+    pub waypoint_rooms: Vec<RoomId>, // Connector rooms inserted along long or steep mst edges
+    #[cfg(feature = "events")]
+    pub events: Vec<crate::events::GenerationEvent>, // Ordered log of rooms, edges, passages and post-processing changes as they happened
+    pub room_extensions: BTreeMap<RoomId, Vec<Room>>, // Boxes of rooms merged into the room; they carry the id of the room they were merged into
+    pub materials: HashMap<Vector3<i32>, MaterialId>, // Material of each rendered voxel, including the rock around open space
+}
+
+impl Dungeon3DGeneratorResult {
+    // 利用側のスナップショットテスト向けに、実行ごとに変わらない順序で書き出す
+    pub fn to_canonical_string(&self) -> String {
+        let mut text = String::from("rooms\n");
+        for room in self.rooms.values() {
+            text += &format!(
+                "{} origin={:?} size=({}, {}, {})\n",
+                room.id.inner(),
+                room.origin,
+                room.width,
+                room.height,
+                room.depth
+            );
+        }
+        text += "passages\n";
+        for (passage_id, passage) in self.passages.iter() {
+            text += &format!(
+                "{} rooms={}->{} start={:?} height={} cells={}\n",
+                passage_id.inner(),
+                passage.start_room_id.inner(),
+                passage.end_room_id.inner(),
+                passage.start,
+                passage.height,
+                passage.cells.len()
+            );
+        }
+        text += "voxels\n";
+        text += &self.voxel_map.to_canonical_string();
+        text
+    }
+
+    pub fn suggest_lights(&self, spacing: u32) -> Vec<LightSuggestion> {
+        suggest_lights(&self.rooms, &self.voxel_map, &self.passages, spacing)
+    }
+
+    // 通路のボクセルごとの通路の番号。通路ごとに描画や照明を変える場合や、どの接続がボクセルを掘ったかの確認に使う
+    pub fn passage_map(&self) -> HashMap<Vector3<i32>, PassageId> {
+        passage_owners(&self.passages)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DungeonLayout {
+    pub rooms: BTreeMap<RoomId, Room>,
+    pub voxel_map: VoxelMap, // Voxelized rooms without passages
+    pub connections: BTreeMap<ConnectionId, LayoutConnection>, // Required connections come first
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayoutConnection {
+    pub passage: Passage, // Start of the passage; cells stay empty until it is carved
+    pub start_room: Room,
+    pub end_room: Room,
+    pub required: bool, // Part of the minimum spanning tree of the rooms
+}
+
+impl LayoutConnection {
+    // 通路を掘る前の部屋の組として、部屋の中心間の距離とともに返す
+    pub fn room_connection(&self) -> RoomConnection {
+        let (center0, center1) = (self.start_room.center(), self.end_room.center());
+        RoomConnection {
+            room0_id: self.start_room.id,
+            room1_id: self.end_room.id,
+            squared_length: (center1.0 - center0.0).powi(2)
+                + (center1.1 - center0.1).powi(2)
+                + (center1.2 - center0.2).powi(2),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailedConnection {
+    pub connection: RoomConnection,
+    pub required: bool, // Failed required edges are bridged afterwards by the connectivity repair
+    pub reason: VoxelMapError, // Error of the last start candidate tried
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct UnmetHierarchyBoundary {
+    pub boundary: u32, // Passages cross it when one room is at this hierarchy or below and the other above
+    pub connections: usize, // Distinct room pairs joined across it after force_vertical_connections gave up
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SurfaceEntrance {
+    pub room_id: RoomId,
+    pub opening: (i32, i32, i32), // First open-air point on the surface
+    pub cells: Vec<((i32, i32, i32), VoxelType)>,
 }
 
 #[derive(Debug)]
 pub enum Dungeon3DGeneratorError {
-    NarrowWidthOrRoomWidthTooLarge,
-    NarrowDepthOrRoomDepthTooLarge,
-    NarrowHeightOrRoomHierarchyTooSmall,
+    ConfigValidation(Vec<ConfigViolation>), // Every violated constraint of the config, checked before generating
+    RoomDoesNotFit { axis: Axis, block: (u32, u32, u32) }, // Even the minimum room size exceeds the block
+    AnchorUnsatisfiable((u32, u32, u32)), // No room could be placed around the anchor
+    RoomAboveSurface { block: (u32, u32, u32) }, // No room in the block fits below the surface
+    SurfaceUnreachable,
     VoxelMapError(VoxelMapError),
+    Disconnected(Vec<BTreeSet<RoomId>>),
+    ObjectiveUnreachable { entrance: RoomId, objective: RoomId }, // No passage could bring the objective within max_objective_distance
+    Panicked(String), // Message of a panic caught by generate_dungeon_3d_checked
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ConfigViolation {
+    pub field: &'static str, // Name of the config field to change
+    pub value: String,       // Provided value, formatted with Debug
+    pub allowed: String,     // Allowed values, e.g. ">= 10"
+}
+
+impl ConfigViolation {
+    pub fn new<T: fmt::Debug>(field: &'static str, value: T, allowed: impl Into<String>) -> Self {
+        ConfigViolation {
+            field,
+            value: format!("{:?}", value),
+            allowed: allowed.into(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} = {} (allowed: {})",
+            self.field, self.value, self.allowed
+        )
+    }
+}
+
+impl fmt::Display for Dungeon3DGeneratorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Dungeon3DGeneratorError::ConfigValidation(violations) => {
+                write!(f, "invalid config: ")?;
+                for (i, violation) in violations.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", violation)?;
+                }
+                Ok(())
+            }
+            Dungeon3DGeneratorError::RoomDoesNotFit { axis, block } => write!(
+                f,
+                "minimum room size exceeds block {:?} along the {:?} axis",
+                block, axis
+            ),
+            Dungeon3DGeneratorError::AnchorUnsatisfiable(anchor) => {
+                write!(f, "no room could be placed around anchor {:?}", anchor)
+            }
+            Dungeon3DGeneratorError::RoomAboveSurface { block } => {
+                write!(f, "no room in block {:?} fits below the surface", block)
+            }
+            Dungeon3DGeneratorError::SurfaceUnreachable => {
+                write!(f, "no room can reach the surface")
+            }
+            Dungeon3DGeneratorError::VoxelMapError(_) => write!(f, "failed to build the voxel map"),
+            Dungeon3DGeneratorError::Disconnected(components) => write!(
+                f,
+                "rooms are split into {} disconnected groups",
+                components.len()
+            ),
+            Dungeon3DGeneratorError::ObjectiveUnreachable { entrance, objective } => write!(
+                f,
+                "objective room {} cannot be reached from entrance room {} within the allowed passages",
+                objective.inner(),
+                entrance.inner()
+            ),
+            Dungeon3DGeneratorError::Panicked(message) => {
+                write!(f, "generation panicked: {}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Dungeon3DGeneratorError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Dungeon3DGeneratorError::VoxelMapError(error) => Some(error),
+            _ => None,
+        }
+    }
 }
 
 pub fn generate_dungeon_3d(
-    mut config: Dungeon3DGeneratorConfig,
+    config: Dungeon3DGeneratorConfig,
+) -> Result<Dungeon3DGeneratorResult, Dungeon3DGeneratorError> {
+    GenerationPipeline::default().generate(config)
+}
+
+// ゲーム全体で決まった乱数生成器を使う場合向けに、config.seed の代わりに渡された乱数で生成する
+pub fn generate_dungeon_3d_with_rng<R: RngCore>(
+    config: Dungeon3DGeneratorConfig,
+    rng: &mut R,
+) -> Result<Dungeon3DGeneratorResult, Dungeon3DGeneratorError> {
+    GenerationPipeline::default().generate_with_rng(config, rng)
+}
+
+// 時間内にシードを変えて生成を繰り返し、修復や失敗した通路が最も少ない結果を返す
+// 最初の1回は時間に関わらず最後まで生成する。2回目以降は、それまでで最も時間のかかった1回分が残り時間に収まる場合だけ始める
+// 生成の時間は一定でないため、最初の1回より後でも budget をわずかに超えることはある
+pub fn generate_with_budget(
+    config: Dungeon3DGeneratorConfig,
+    budget: Duration,
 ) -> Result<Dungeon3DGeneratorResult, Dungeon3DGeneratorError> {
+    let started = Instant::now();
+    let mut longest = Duration::ZERO;
+    let mut last_started = started;
+    generate_best_of(config, |_| {
+        let now = Instant::now();
+        longest = longest.max(now - last_started);
+        last_started = now;
+        now - started + longest <= budget
+    })
+}
+
+// should_continue が false を返すまでシードを変えて生成し、score の最も小さい結果を返す。最初の1回は必ず生成する
+fn generate_best_of<F>(
+    config: Dungeon3DGeneratorConfig,
+    mut should_continue: F,
+) -> Result<Dungeon3DGeneratorResult, Dungeon3DGeneratorError>
+where
+    F: FnMut(u64) -> bool,
+{
+    let mut best: Option<Dungeon3DGeneratorResult> = None;
+    let mut last_error = None;
+    let mut attempt = 0u64;
+    while attempt == 0 || should_continue(attempt) {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: config.seed.map(|seed| seed.wrapping_add(attempt)),
+            ..config.clone()
+        });
+        attempt += 1;
+        match result {
+            Ok(result) => {
+                if best
+                    .as_ref()
+                    .is_none_or(|best| budget_score(&result) < budget_score(best))
+                {
+                    best = Some(result);
+                }
+            }
+            Err(error) => last_error = Some(error),
+        }
+    }
+    best.ok_or_else(|| last_error.unwrap())
+}
+
+// 接続性の修復で掘った通路と掘れなかった接続の数。同じなら通路の多い方を良いとする
+// 目的の部屋への近道は設定どおりに掘ったものなので数えない
+fn budget_score(result: &Dungeon3DGeneratorResult) -> (usize, Reverse<usize>) {
+    (
+        result.repairs.len() + result.failed_connections.len(),
+        Reverse(result.passages.len()),
+    )
+}
+
+// 生成後の乱数で部屋ごとのメタデータを割り当てるため、同じシードなら配置は generate_dungeon_3d と変わらない
+pub fn generate_dungeon_3d_with_metadata<T, F>(
+    config: Dungeon3DGeneratorConfig,
+    mut assign: F,
+) -> Result<(Dungeon3DGeneratorResult, BTreeMap<RoomId, T>), Dungeon3DGeneratorError>
+where
+    F: FnMut(&Room, &mut rand::rngs::StdRng) -> T,
+{
+    let mut rng = seeded_rng(config.seed);
+    let result = GenerationPipeline::default().generate_with_rng(config, &mut rng)?;
+    let metadata = result
+        .rooms
+        .values()
+        .map(|room| (room.id, assign(room, &mut rng)))
+        .collect();
+    Ok((result, metadata))
+}
+
+// 部屋の配置と接続する部屋の組だけを求め、通路は carve_passage で少しずつ掘れるようにする
+pub fn generate_layout(
+    config: Dungeon3DGeneratorConfig,
+) -> Result<DungeonLayout, Dungeon3DGeneratorError> {
+    GenerationPipeline::default().generate_layout(config)
+}
+
+pub fn carve_passage(
+    voxel_map: &mut VoxelMap,
+    connection: &LayoutConnection,
+) -> Result<Passage, VoxelMapError> {
+    carve_passage_with_stats(voxel_map, connection, &mut RouteStats::default())
+}
+
+fn carve_passage_with_stats(
+    voxel_map: &mut VoxelMap,
+    connection: &LayoutConnection,
+    stats: &mut RouteStats,
+) -> Result<Passage, VoxelMapError> {
+    carve_between(
+        voxel_map,
+        &connection.start_room,
+        &connection.end_room,
+        connection.passage.height,
+        stats,
+    )
+}
+
+// 届かなかった場合は開始地点を変えて掘り直し、成功した候補を Passage::attempt に残す
+pub(crate) fn carve_between(
+    voxel_map: &mut VoxelMap,
+    room0: &Room,
+    room1: &Room,
+    passage_height: i32,
+    stats: &mut RouteStats,
+) -> Result<Passage, VoxelMapError> {
+    let mut error = VoxelMapError::Unreachable;
+    for mut passage in Passage::candidates(room0, room1, passage_height) {
+        let end_room = if passage.end_room_id == room0.id {
+            room0
+        } else {
+            room1
+        };
+        match voxel_map.add_passage_to_room(&mut passage, end_room, stats) {
+            Ok(()) => return Ok(passage),
+            Err(e) => error = e,
+        }
+    }
+    Err(error)
+}
+
+pub(crate) fn place_rooms(state: &mut GenerationState<'_>) -> Result<(), Dungeon3DGeneratorError> {
+    let GenerationState {
+        config,
+        rng,
+        report,
+        rooms,
+        voxel_map,
+        room_spans,
+        room_extensions,
+        #[cfg(feature = "events")]
+        events,
+        ..
+    } = state;
     config.room_margin_x = config.room_margin_x.max(1);
     config.room_margin_y = config.room_margin_y.max(1);
     config.room_margin_z = config.room_margin_z.max(1);
 
-    // validate
-    let w_divisions_max = config.width / (config.room_width_range.start() + config.room_margin_x);
-    let w_divisions_min = config.width / (config.room_width_range.end() + config.room_margin_x);
-    if w_divisions_min == 0 {
-        return Err(Dungeon3DGeneratorError::NarrowWidthOrRoomWidthTooLarge);
+    let violations = config.violations();
+    if !violations.is_empty() {
+        return Err(Dungeon3DGeneratorError::ConfigValidation(violations));
     }
-    let d_divisions_max = config.width / (config.room_depth_range.start() + config.room_margin_z);
-    let d_divisions_min = config.width / (config.room_depth_range.end() + config.room_margin_z);
-    if d_divisions_min == 0 {
-        return Err(Dungeon3DGeneratorError::NarrowDepthOrRoomDepthTooLarge);
-    }
-    if config.room_hierarchy * (config.room_height_range.start() + config.room_margin_y)
-        > config.height
-    {
-        return Err(Dungeon3DGeneratorError::NarrowHeightOrRoomHierarchyTooSmall);
-    }
-
-    let mut rng: rand::rngs::StdRng = config
-        .seed
-        .map(SeedableRng::seed_from_u64)
-        .unwrap_or_else(rand::rngs::StdRng::from_entropy);
+    let w_divisions_max = config.width
+        / (config.room_width_range.start() + config.room_margin_x + config.boundary_margin);
+    let d_divisions_max = config.depth
+        / (config.room_depth_range.start() + config.room_margin_z + config.boundary_margin);
 
+    let phase_start = Instant::now();
     let mut room_id = RoomId::first();
-    let mut rooms = BTreeMap::new();
-    let mut room_ids = Vec::new();
     let h_block_size = config.height / config.room_hierarchy;
     for ry in 0..config.room_hierarchy {
         let w_divisions = rng.gen_range(1..=w_divisions_max);
         let w_block_size = config.width / w_divisions;
+        let x_splits = BlockSpan::split_positions(
+            rng,
+            w_divisions,
+            w_block_size,
+            config.block_split_jitter,
+            config.room_width_range.start() + config.room_margin_x + config.boundary_margin,
+        );
         for rx in 0..w_divisions {
             let d_divisions = rng.gen_range(1..=d_divisions_max);
             let d_block_size = config.depth / d_divisions;
+            let z_splits = BlockSpan::split_positions(
+                rng,
+                d_divisions,
+                d_block_size,
+                config.block_split_jitter,
+                config.room_depth_range.start() + config.room_margin_z + config.boundary_margin,
+            );
             for rz in 0..d_divisions {
-                let room_width = rng.gen_range(
-                    *config.room_width_range.start()
-                        ..=(w_block_size - config.room_margin_x)
-                            .min(*config.room_width_range.end()),
-                );
-                let room_height = rng.gen_range(
-                    *config.room_height_range.start()
-                        ..=(h_block_size - config.room_margin_y)
-                            .min(*config.room_height_range.end()),
+                let block = (rx, ry, rz);
+                let x_span = BlockSpan::from_bounds(
+                    rx,
+                    w_divisions,
+                    x_splits[rx as usize],
+                    x_splits[rx as usize + 1],
+                    config.width,
+                    config.room_margin_x,
+                    config.boundary_margin,
                 );
-                let room_depth = rng.gen_range(
-                    *config.room_depth_range.start()
-                        ..=(d_block_size - config.room_margin_z)
-                            .min(*config.room_depth_range.end()),
+                let y_span = BlockSpan::new(
+                    ry,
+                    config.room_hierarchy,
+                    h_block_size,
+                    config.height,
+                    config.room_margin_y,
+                    config.boundary_margin,
                 );
-                let (origin_x, origin_y, origin_z) =
-                    (rx * w_block_size, ry * h_block_size, rz * d_block_size);
-                let room_origin = (
-                    origin_x
-                        + rng.gen_range(0..=(w_block_size - room_width - config.room_margin_x)),
-                    origin_y
-                        + rng.gen_range(0..=(h_block_size - room_height - config.room_margin_y)),
-                    origin_z
-                        + rng.gen_range(0..=(d_block_size - room_depth - config.room_margin_z)),
+                let z_span = BlockSpan::from_bounds(
+                    rz,
+                    d_divisions,
+                    z_splits[rz as usize],
+                    z_splits[rz as usize + 1],
+                    config.depth,
+                    config.room_margin_z,
+                    config.boundary_margin,
                 );
+                // 区画内のアンカーは全て部屋の内側に含める
+                let block_anchors = config
+                    .room_anchors
+                    .iter()
+                    .filter(|(x, y, z)| x_span.claims(*x) && y_span.claims(*y) && z_span.claims(*z))
+                    .copied()
+                    .collect::<Vec<_>>();
+                // 密度が 1 未満なら区画を空けたままにして、部屋の間を広げる。部屋が1つも無いままにはしない
+                let last_block = ry + 1 == config.room_hierarchy
+                    && rx + 1 == w_divisions
+                    && rz + 1 == d_divisions;
+                if config.room_density < 1.0
+                    && block_anchors.is_empty()
+                    && !(last_block && rooms.is_empty())
+                    && !rng.gen_bool(config.room_density.max(0.0) as f64)
+                {
+                    continue;
+                }
+                // 評価関数があれば複数の配置候補から最も評価の高いものを選ぶ
+                let candidate_count = match config.room_placement_score {
+                    Some(_) => PLACEMENT_CANDIDATES,
+                    None => 1,
+                };
+                let mut best_placement = None;
+                for _ in 0..candidate_count {
+                    let mut attempts = 0;
+                    let placement = loop {
+                        let room_width = sample_room_size(
+                            rng,
+                            &config.room_width_range,
+                            x_span.available,
+                            Axis::X,
+                            block,
+                        )?;
+                        let room_height = sample_room_size(
+                            rng,
+                            &config.room_height_range,
+                            y_span.available,
+                            Axis::Y,
+                            block,
+                        )?;
+                        let room_depth = sample_room_size(
+                            rng,
+                            &config.room_depth_range,
+                            z_span.available,
+                            Axis::Z,
+                            block,
+                        )?;
+                        let (room_width, room_depth) = match &config.room_aspect_ratio_range {
+                            Some(ratio_range) => fit_aspect_ratio(
+                                rng,
+                                config,
+                                ratio_range,
+                                (room_width, room_depth),
+                                (x_span.available, z_span.available),
+                                block,
+                                report,
+                            )?,
+                            None => (room_width, room_depth),
+                        };
+                        let room_origin = x_span
+                            .sample_origin(rng, room_width, block_anchors.iter().map(|a| a.0))
+                            .zip(y_span.sample_origin(
+                                rng,
+                                room_height,
+                                block_anchors.iter().map(|a| a.1),
+                            ))
+                            .zip(z_span.sample_origin(
+                                rng,
+                                room_depth,
+                                block_anchors.iter().map(|a| a.2),
+                            ));
+                        if let Some(((x, y), z)) = room_origin {
+                            let room_size = (room_width, room_height, room_depth);
+                            if config.surface_height.as_ref().is_none_or(|surface_height| {
+                                is_below_surface((x, y, z), room_size, surface_height)
+                            }) {
+                                break (room_width, room_height, room_depth, (x, y, z));
+                            }
+                        }
+                        report.rejected_placements += 1;
+                        attempts += 1;
+                        if attempts >= MAX_PLACEMENT_ATTEMPTS {
+                            return Err(match block_anchors.first() {
+                                Some(anchor) => {
+                                    Dungeon3DGeneratorError::AnchorUnsatisfiable(*anchor)
+                                }
+                                None => Dungeon3DGeneratorError::RoomAboveSurface { block },
+                            });
+                        }
+                        report.retries += 1;
+                    };
+                    let score = config.room_placement_score.as_ref().map_or(0.0, |score| {
+                        let (room_width, room_height, room_depth, room_origin) = placement;
+                        score(&Room {
+                            hierarchy: ry,
+                            ..Room::new(room_id, room_width, room_height, room_depth, room_origin)
+                        })
+                    });
+                    if best_placement.is_none_or(|(best_score, _)| score > best_score) {
+                        best_placement = Some((score, placement));
+                    }
+                }
+                let (_, (room_width, room_height, room_depth, room_origin)) =
+                    best_placement.unwrap();
                 let new_room_id = room_id.gen_id();
-                room_ids.push(new_room_id);
+                room_spans.insert(new_room_id, (x_span, z_span));
                 rooms.insert(
                     new_room_id,
-                    Room::new(
-                        new_room_id,
-                        room_width,
-                        room_height,
-                        room_depth,
-                        room_origin,
-                    ),
+                    Room {
+                        hierarchy: ry,
+                        ..Room::new(
+                            new_room_id,
+                            room_width,
+                            room_height,
+                            room_depth,
+                            room_origin,
+                        )
+                    },
                 );
+                #[cfg(feature = "events")]
+                events.push(GenerationEvent::RoomPlaced {
+                    room: rooms[&new_room_id].clone(),
+                });
             }
         }
     }
 
-    let mut room_connections = Vec::new();
-    let mut room_connection_map: BTreeMap<RoomId, BTreeMap<RoomId, Rc<RoomConnection>>> =
-        BTreeMap::new();
-    for room_index in 0..room_ids.len() {
-        let current_room_id = room_ids[room_index];
-        let current_room = rooms.get(&current_room_id).unwrap();
+    if let Some(anchor) = config.room_anchors.iter().find(|anchor| {
+        !rooms
+            .values()
+            .any(|room: &Room| room.contains_point(anchor.0, anchor.1, anchor.2))
+    }) {
+        return Err(Dungeon3DGeneratorError::AnchorUnsatisfiable(*anchor));
+    }
+    report.record(GenerationPhase::PlaceRooms, phase_start);
+
+    let phase_start = Instant::now();
+    for room in rooms.values() {
+        voxel_map
+            .add_room(room)
+            .map_err(Dungeon3DGeneratorError::VoxelMapError)?;
+    }
+    report.record(GenerationPhase::VoxelizeRooms, phase_start);
+
+    if config.merge_adjacent_rooms {
+        let phase_start = Instant::now();
+        // まとめた部屋は区画をまたぐため、ハブとして広げない
+        for (absorbed, kept) in merge_adjacent_rooms(rooms, room_extensions, voxel_map) {
+            room_spans.remove(&absorbed);
+            room_spans.remove(&kept);
+            #[cfg(feature = "events")]
+            events.push(GenerationEvent::RoomMerged {
+                room_id: absorbed,
+                into: kept,
+            });
+        }
+        report.record(GenerationPhase::MergeRooms, phase_start);
+    }
+    Ok(())
+}
+
+pub(crate) fn build_graph(state: &mut GenerationState<'_>) -> Result<(), Dungeon3DGeneratorError> {
+    let phase_start = Instant::now();
+    let rooms = state.rooms.values().collect::<Vec<_>>();
+    for (room_index, current_room) in rooms.iter().enumerate() {
         let current_room_center = current_room.center();
-        for target_room_id in &room_ids[(room_index + 1)..rooms.len()] {
-            let target_room = rooms.get(target_room_id).unwrap();
+        for target_room in rooms.iter().skip(room_index + 1) {
             let target_room_center = target_room.center();
             let diff = (
                 current_room_center.0 - target_room_center.0,
@@ -160,39 +838,42 @@ pub fn generate_dungeon_3d(
                 current_room_center.2 - target_room_center.2,
             );
             let squared_length = diff.0 * diff.0 + diff.1 * diff.1 + diff.2 * diff.2;
-            let room_connection = Rc::new(RoomConnection {
+            state.room_connections.push(RoomConnection {
                 room0_id: current_room.id,
                 room1_id: target_room.id,
                 squared_length,
             });
-            room_connections.push(room_connection.clone());
-            room_connection_map
-                .entry(target_room.id)
-                .or_default()
-                .insert(current_room.id, room_connection.clone());
-            room_connection_map
-                .entry(current_room.id)
-                .or_default()
-                .insert(target_room.id, room_connection.clone());
-        }
-    }
-    let mut voxel_map = VoxelMap::new(
-        -(config.margin_for_bounds as i32),
-        -(config.margin_for_bounds as i32),
-        -(config.margin_for_bounds as i32),
-        (config.width + config.margin_for_bounds) as i32,
-        (config.height + config.margin_for_bounds) as i32,
-        (config.depth + config.margin_for_bounds) as i32,
-    );
-    for (_, room) in rooms.iter() {
-        voxel_map
-            .add_room(room)
-            .map_err(Dungeon3DGeneratorError::VoxelMapError)?;
+        }
     }
+    state
+        .report
+        .record(GenerationPhase::BuildGraph, phase_start);
+    Ok(())
+}
 
+pub(crate) fn select_edges(state: &mut GenerationState<'_>) -> Result<(), Dungeon3DGeneratorError> {
+    let GenerationState {
+        config,
+        rng,
+        report,
+        rooms,
+        voxel_map,
+        room_connections,
+        connections,
+        waypoint_rooms,
+        room_spans,
+        ..
+    } = state;
+    let phase_start = Instant::now();
     // Create mst of room neighbors
     let weighted_edges = room_connections
         .iter()
+        .filter(|room_connection| {
+            !config.forbids(
+                &rooms[&room_connection.room0_id],
+                &rooms[&room_connection.room1_id],
+            )
+        })
         .map(|room_connection| {
             (
                 room_connection.room0_id,
@@ -239,37 +920,36 @@ pub fn generate_dungeon_3d(
         .map(|(room0_id, room1_id, _)| {
             (
                 RoomConnectionKey::new(*room0_id, *room1_id),
-                Rc::clone(
-                    room_connection_map
-                        .get(room0_id)
-                        .unwrap()
-                        .get(room1_id)
-                        .unwrap(),
-                ),
+                (*room0_id, *room1_id),
             )
         })
         .collect::<BTreeMap<_, _>>();
-    // create passages
-    let mut passages = Vec::new();
-    for (_, room_connection) in necessary_room_connections.iter() {
-        let r0 = rooms.get(&room_connection.room0_id).unwrap();
-        let r1 = rooms.get(&room_connection.room1_id).unwrap();
-        let (start_room_id, end_room_id, start, dirs) = create_start(r0, r1);
-        passages.push(Passage {
-            cells: Vec::new(),
-            start: (start.x, start.y, start.z),
-            start_dirs: dirs,
-            start_room_id,
-            end_room_id,
-            height: config.passage_height as i32,
-        });
-    }
-    for passage in passages.iter() {
-        voxel_map
-            .add_passage(passage, &rooms)
-            .map_err(Dungeon3DGeneratorError::VoxelMapError)?;
+
+    // 最小全域木で多くの部屋とつながる部屋を広場として広げる
+    if config.hub_min_degree > 0 && config.hub_growth > 0 {
+        let mut degrees: BTreeMap<RoomId, u32> = BTreeMap::new();
+        for (room0_id, room1_id) in necessary_room_connections.values() {
+            *degrees.entry(*room0_id).or_default() += 1;
+            *degrees.entry(*room1_id).or_default() += 1;
+        }
+        for (hub_id, _) in degrees
+            .into_iter()
+            .filter(|(_, degree)| *degree >= config.hub_min_degree)
+        {
+            let Some((x_span, z_span)) = room_spans.get(&hub_id) else {
+                continue;
+            };
+            enlarge_hub_room(
+                rooms.get_mut(&hub_id).unwrap(),
+                x_span,
+                z_span,
+                config,
+                voxel_map,
+            );
+        }
     }
 
+    // 最小全域木に含まれない Delaunay の辺から追加の通路を選ぶ
     let delaunay = Delaunay3D::new(
         rooms
             .values()
@@ -289,49 +969,1989 @@ pub fn generate_dungeon_3d(
         })
         .collect::<Vec<_>>();
 
+    let mut selected = Vec::new();
+    for (room0_id, room1_id) in necessary_room_connections.values() {
+        let pairs = split_connection(
+            config,
+            rooms,
+            voxel_map,
+            waypoint_rooms,
+            (*room0_id, *room1_id),
+        )?;
+        selected.extend(
+            pairs
+                .into_iter()
+                .map(|(room0_id, room1_id)| (room0_id, room1_id, true)),
+        );
+    }
     for room_connection in additional_room_connections {
-        if rng.gen_bool(0.3)
+        if rng.gen_bool(config.loop_probability.clamp(0.0, 1.0))
+            && config
+                .max_connection_length
+                .is_none_or(|max_length| room_connection.squared_length.sqrt() <= max_length as f32)
             && !necessary_room_connections.contains_key(&RoomConnectionKey::new(
                 room_connection.room0_id,
                 room_connection.room1_id,
             ))
+            && !config.forbids(
+                &rooms[&room_connection.room0_id],
+                &rooms[&room_connection.room1_id],
+            )
         {
-            let r0 = rooms.get(&room_connection.room0_id).unwrap();
-            let r1 = rooms.get(&room_connection.room1_id).unwrap();
-            let (start_room_id, end_room_id, start, dirs) = create_start(r0, r1);
-            let passage = Passage {
-                cells: Vec::new(),
-                start: (start.x, start.y, start.z),
-                start_dirs: dirs,
-                start_room_id,
-                end_room_id,
-                height: config.passage_height as i32,
+            selected.push((room_connection.room0_id, room_connection.room1_id, false));
+        }
+    }
+    *connections = layout_connections(rooms, selected, config.passage_height);
+    report.record(GenerationPhase::SelectEdges, phase_start);
+    Ok(())
+}
+
+// 長すぎる辺や高低差の大きすぎる辺を、等間隔に置いた最小サイズの中継の部屋 (小部屋や踊り場) で区切る
+// 中継の部屋は部屋同士の余白に置くため room_margin より狭い余白で他の部屋との重なりを調べる
+// 重なる場合や範囲、地表からはみ出す場合は元の辺のままにする
+fn split_connection(
+    config: &Dungeon3DGeneratorConfig,
+    rooms: &mut BTreeMap<RoomId, Room>,
+    voxel_map: &mut VoxelMap,
+    waypoint_rooms: &mut Vec<RoomId>,
+    (room0_id, room1_id): (RoomId, RoomId),
+) -> Result<Vec<(RoomId, RoomId)>, Dungeon3DGeneratorError> {
+    let (room0, room1) = (&rooms[&room0_id], &rooms[&room1_id]);
+    let (center0, center1) = (room0.center(), room1.center());
+    let center0 = Vector3::new(center0.0, room0.origin.1 as f32, center0.2);
+    let center1 = Vector3::new(center1.0, room1.origin.1 as f32, center1.2);
+    let mut segments = 1;
+    if let (Some(max_length), true) = (config.max_connection_length, config.split_long_connections)
+    {
+        segments =
+            segments.max(((center1 - center0).norm() / max_length.max(1) as f32).ceil() as u32);
+    }
+    if let Some(max_rise) = config.max_connection_rise {
+        segments =
+            segments.max(((center1.y - center0.y).abs() / max_rise.max(1) as f32).ceil() as u32);
+    }
+    if segments <= 1 {
+        return Ok(vec![(room0_id, room1_id)]);
+    }
+    let size = (
+        *config.room_width_range.start(),
+        *config.room_height_range.start(),
+        *config.room_depth_range.start(),
+    );
+    let mut room_id = *rooms.keys().last().unwrap();
+    room_id.gen_id();
+    let mut waypoints: Vec<Room> = Vec::new();
+    for i in 1..segments {
+        // 床の高さも両端の部屋の間で補間する
+        let p = center0 + (center1 - center0) * (i as f32 / segments as f32);
+        let place = |value: f32, size: u32, total: u32| {
+            value.round().clamp(0.0, total.saturating_sub(size) as f32) as u32
+        };
+        let origin = (
+            place(p.x - size.0 as f32 / 2.0, size.0, config.width),
+            place(p.y, size.1, config.height),
+            place(p.z - size.2 as f32 / 2.0, size.2, config.depth),
+        );
+        let waypoint = Room {
+            hierarchy: room0.hierarchy.min(room1.hierarchy),
+            ..Room::new(room_id.gen_id(), size.0, size.1, size.2, origin)
+        };
+        if rooms
+            .values()
+            .chain(waypoints.iter())
+            .any(|room| room.is_contract(&waypoint, WAYPOINT_MARGIN))
+            || config
+                .surface_height
+                .as_ref()
+                .is_some_and(|surface_height| !is_below_surface(origin, size, surface_height))
+        {
+            return Ok(vec![(room0_id, room1_id)]);
+        }
+        waypoints.push(waypoint);
+    }
+    let mut chain = vec![room0_id];
+    for waypoint in waypoints {
+        voxel_map
+            .add_room(&waypoint)
+            .map_err(Dungeon3DGeneratorError::VoxelMapError)?;
+        chain.push(waypoint.id);
+        waypoint_rooms.push(waypoint.id);
+        rooms.insert(waypoint.id, waypoint);
+    }
+    chain.push(room1_id);
+    Ok(chain.windows(2).map(|pair| (pair[0], pair[1])).collect())
+}
+
+// 選ばれた部屋の組 (room0_id, room1_id, required) に番号を振り、通路の始点を決める
+pub(crate) fn layout_connections(
+    rooms: &BTreeMap<RoomId, Room>,
+    selected: Vec<(RoomId, RoomId, bool)>,
+    passage_height: u32,
+) -> BTreeMap<ConnectionId, LayoutConnection> {
+    let mut connection_id = ConnectionId::first();
+    selected
+        .into_iter()
+        .map(|(room0_id, room1_id, required)| {
+            let r0 = rooms.get(&room0_id).unwrap();
+            let r1 = rooms.get(&room1_id).unwrap();
+            let passage = Passage::new(r0, r1, passage_height as i32);
+            let connection = LayoutConnection {
+                start_room: rooms.get(&passage.start_room_id).unwrap().clone(),
+                end_room: rooms.get(&passage.end_room_id).unwrap().clone(),
+                passage,
+                required,
             };
-            if voxel_map.add_passage(&passage, &rooms).is_ok() {
-                passages.push(passage);
+            (connection_id.gen_id(), connection)
+        })
+        .collect()
+}
+
+pub(crate) fn carve_passages(
+    state: &mut GenerationState<'_>,
+) -> Result<(), Dungeon3DGeneratorError> {
+    let mut carver = PassageCarver::new(state);
+    while carver.carve_next(state) {}
+    carver.finish(state)
+}
+
+// 選ばれた辺の通路を1本ずつ掘り、最後に接続性の修復などをまとめて行う
+// 非同期版の生成が通路ごとに処理を譲れるように carve_passages から分けている
+pub(crate) struct PassageCarver {
+    adjacencies: Vec<RoomAdjacency>,
+    pending: Vec<ConnectionId>, // Connections left to carve, in reverse order; required ones are carved first
+    passage_id: PassageId,
+    phase: GenerationPhase, // CarveRequiredPassages or CarveExtraPassages
+    phase_start: Instant,
+}
+
+impl PassageCarver {
+    pub(crate) fn new(state: &GenerationState<'_>) -> Self {
+        let adjacencies = if state.config.direct_doors {
+            find_adjacent_rooms(&state.rooms, &state.voxel_map)
+        } else {
+            Vec::new()
+        };
+        let (required, optional): (Vec<_>, Vec<_>) = state
+            .connections
+            .iter()
+            .partition(|(_, connection)| connection.required);
+        PassageCarver {
+            adjacencies,
+            pending: required
+                .into_iter()
+                .chain(optional)
+                .rev()
+                .map(|(connection_id, _)| *connection_id)
+                .collect(),
+            passage_id: PassageId::first(),
+            phase: GenerationPhase::CarveRequiredPassages,
+            phase_start: Instant::now(),
+        }
+    }
+
+    // 次の辺の通路を掘る。掘る辺が残っていなければ false を返す
+    // 失敗した必須通路は finish の接続性の修復で補う
+    pub(crate) fn carve_next(&mut self, state: &mut GenerationState<'_>) -> bool {
+        let Some(connection_id) = self.pending.pop() else {
+            return false;
+        };
+        let GenerationState {
+            config,
+            report,
+            rooms,
+            voxel_map,
+            connections,
+            failed_connections,
+            passages,
+            route_stats,
+            #[cfg(feature = "events")]
+            events,
+            ..
+        } = state;
+        let connection = &connections[&connection_id];
+        if !connection.required && self.phase == GenerationPhase::CarveRequiredPassages {
+            report.record(GenerationPhase::CarveRequiredPassages, self.phase_start);
+            self.phase = GenerationPhase::CarveExtraPassages;
+            self.phase_start = Instant::now();
+        }
+        // 先に掘った通路で既につながっている部屋の組は重複した通路を掘らない
+        if config.skip_connected_rooms
+            && rooms_connected(voxel_map, &connection.start_room, &connection.end_room)
+        {
+            report.skipped_passages += 1;
+            #[cfg(feature = "events")]
+            events.push(GenerationEvent::PassageSkipped {
+                connection_id,
+                room0_id: connection.start_room.id,
+                room1_id: connection.end_room.id,
+            });
+            return true;
+        }
+        if connection.required {
+            let r0 = rooms.get(&connection.passage.start_room_id).unwrap();
+            let r1 = rooms.get(&connection.passage.end_room_id).unwrap();
+            // 壁が隣接している部屋は通路を掘らずに扉で直接つなぐ
+            if let Some(passage) = self
+                .adjacencies
+                .iter()
+                .find(|adjacency| {
+                    BTreeSet::from([adjacency.room0_id, adjacency.room1_id])
+                        == BTreeSet::from([r0.id, r1.id])
+                })
+                .and_then(|adjacency| {
+                    add_direct_door(voxel_map, adjacency, r0, r1, config.passage_height)
+                })
+            {
+                let passage_id = self.passage_id.gen_id();
+                #[cfg(feature = "events")]
+                events.push(GenerationEvent::carved(
+                    passage_id,
+                    &passage,
+                    Some(connection_id),
+                ));
+                passages.insert(passage_id, passage);
+                return true;
             }
         }
+        match carve_passage_with_stats(voxel_map, connection, route_stats) {
+            Ok(passage) => {
+                let passage_id = self.passage_id.gen_id();
+                #[cfg(feature = "events")]
+                events.push(GenerationEvent::carved(
+                    passage_id,
+                    &passage,
+                    Some(connection_id),
+                ));
+                passages.insert(passage_id, passage);
+            }
+            Err(error) => {
+                report.failed_passages += 1;
+                #[cfg(feature = "events")]
+                events.push(GenerationEvent::PassageFailed {
+                    connection_id,
+                    room0_id: connection.start_room.id,
+                    room1_id: connection.end_room.id,
+                });
+                failed_connections.push((connection_id, error));
+            }
+        }
+        true
     }
 
-    Ok(Dungeon3DGeneratorResult {
-        rooms,
+    pub(crate) fn finish(
+        self,
+        state: &mut GenerationState<'_>,
+    ) -> Result<(), Dungeon3DGeneratorError> {
+        let GenerationState {
+            config,
+            report,
+            rooms,
+            voxel_map,
+            passages,
+            repairs,
+            objective_passages,
+            unmet_hierarchy_boundaries,
+            under_connected_rooms,
+            surface_entrance,
+            route_stats,
+            #[cfg(feature = "events")]
+            events,
+            ..
+        } = state;
+        let mut passage_id = self.passage_id;
+        let mut phase_start = self.phase_start;
+        if self.phase == GenerationPhase::CarveRequiredPassages {
+            report.record(GenerationPhase::CarveRequiredPassages, phase_start);
+            phase_start = Instant::now();
+        }
+        report.record(GenerationPhase::CarveExtraPassages, phase_start);
+
+        let phase_start = Instant::now();
+        loop {
+            let components = room_components(voxel_map, rooms);
+            if components.len() <= 1 {
+                break;
+            }
+            let Some((passage, repair)) = repair_connectivity(
+                &components,
+                rooms,
+                voxel_map,
+                passage_id,
+                config,
+                route_stats,
+                report,
+            ) else {
+                return Err(Dungeon3DGeneratorError::Disconnected(components));
+            };
+            let repair_passage_id = passage_id.gen_id();
+            #[cfg(feature = "events")]
+            events.push(GenerationEvent::carved(repair_passage_id, &passage, None));
+            passages.insert(repair_passage_id, passage);
+            repairs.push(repair);
+        }
+        report.record(GenerationPhase::RepairConnectivity, phase_start);
+
+        if config.min_inter_hierarchy_connections > 0 {
+            let phase_start = Instant::now();
+            #[cfg(feature = "events")]
+            let first = passage_id;
+            *unmet_hierarchy_boundaries = force_vertical_connections(
+                config,
+                rooms,
+                voxel_map,
+                passages,
+                &mut passage_id,
+                route_stats,
+                report,
+            );
+            #[cfg(feature = "events")]
+            record_passages_from(events, passages, first);
+            report.record(GenerationPhase::ForceVerticalConnections, phase_start);
+        }
+
+        if config.min_room_degree > 0 {
+            let phase_start = Instant::now();
+            #[cfg(feature = "events")]
+            let first = passage_id;
+            *under_connected_rooms = ensure_room_degree(
+                config,
+                rooms,
+                voxel_map,
+                passages,
+                &mut passage_id,
+                route_stats,
+                report,
+            );
+            #[cfg(feature = "events")]
+            record_passages_from(events, passages, first);
+            report.record(GenerationPhase::EnsureRoomDegree, phase_start);
+        }
+
+        if !config.entrance_rooms.is_empty() && !config.objective_rooms.is_empty() {
+            let phase_start = Instant::now();
+            #[cfg(feature = "events")]
+            let first = passage_id;
+            ensure_objective_paths(
+                config,
+                rooms,
+                voxel_map,
+                passages,
+                &mut passage_id,
+                objective_passages,
+                route_stats,
+                report,
+            )?;
+            #[cfg(feature = "events")]
+            record_passages_from(events, passages, first);
+            report.record(GenerationPhase::EnsureObjectivePaths, phase_start);
+        }
+
+        *surface_entrance = if config.surface_opening && config.surface_height.is_some() {
+            let phase_start = Instant::now();
+            let surface_entrance =
+                add_surface_entrance(rooms, voxel_map, config.passage_height as i32, route_stats)
+                    .ok_or(Dungeon3DGeneratorError::SurfaceUnreachable)?;
+            report.record(GenerationPhase::CarveSurfaceEntrance, phase_start);
+            #[cfg(feature = "events")]
+            events.push(GenerationEvent::SurfaceEntranceCarved {
+                room_id: surface_entrance.room_id,
+                voxels: surface_entrance.cells.clone(),
+            });
+            Some(surface_entrance)
+        } else {
+            None
+        };
+        Ok(())
+    }
+}
+
+pub(crate) fn post_process(state: &mut GenerationState<'_>) -> Result<(), Dungeon3DGeneratorError> {
+    let GenerationState {
+        config,
+        rng,
+        report,
         voxel_map,
         passages,
+        doorways,
+        junctions,
+        dead_ends,
+        stairs,
+        materials,
+        rooms,
+        ..
+    } = state;
+    if config.passage_wall_noise > 0.0 {
+        let phase_start = Instant::now();
+        for passage in passages.values_mut() {
+            voxel_map.roughen_passage(passage, config.passage_wall_noise.min(1.0), rng);
+        }
+        report.record(GenerationPhase::RoughenPassages, phase_start);
+    }
+    *dead_ends = if config.trim_dead_ends {
+        trim_dead_ends(voxel_map, passages)
+    } else {
+        find_dead_ends(voxel_map, passages)
+    };
+    *doorways = mark_doorways(voxel_map, passages);
+    if let Some(min_room_size) = config.wide_doorway_room_size {
+        widen_doorways(voxel_map, rooms, passages, doorways, min_room_size);
+    }
+    *junctions = find_junctions(voxel_map, passages);
+    *stairs = find_stairs(passages);
+    *materials = assign_materials(voxel_map, rooms, passages, &config.material_palette);
+    Ok(())
+}
+
+// 壁の間の1ボクセルを床と出入口に置き換える
+fn add_direct_door(
+    voxel_map: &mut VoxelMap,
+    adjacency: &RoomAdjacency,
+    room0: &Room,
+    room1: &Room,
+    passage_height: u32,
+) -> Option<Passage> {
+    let (x, y, z) = *adjacency.door_cells.get(adjacency.door_cells.len() / 2)?;
+    let height = passage_height.min(room0.height).min(room1.height).max(1) as i32;
+    let mut cells = vec![((x, y - 1, z), VoxelType::PassageFloor)];
+    cells.push(((x, y, z), VoxelType::Doorway(adjacency.room0_id)));
+    for dy in 1..height {
+        cells.push(((x, y + dy, z), VoxelType::PassageSpace));
+    }
+    if !cells.iter().all(|((x, y, z), _)| {
+        let p = Vector3::new(*x, *y, *z);
+        voxel_map.get(&p) == VoxelType::Wall && voxel_map.is_below_surface(&p)
+    }) {
+        return None;
+    }
+    for ((x, y, z), voxel_type) in cells.iter() {
+        voxel_map.map.insert(Vector3::new(*x, *y, *z), *voxel_type);
+    }
+    cells.sort_by_key(|(p, _)| *p);
+    Some(Passage {
+        cells,
+        start: (x, y, z),
+        start_dirs: match adjacency.axis {
+            Axis::X => BTreeSet::from([Direction4::Left, Direction4::Right]),
+            _ => BTreeSet::from([Direction4::Far, Direction4::Near]),
+        },
+        start_room_id: adjacency.room0_id,
+        end_room_id: adjacency.room1_id,
+        height,
+        attempt: 0,
     })
 }
 
-#[cfg(test)]
-mod tests {
-    use crate::generate_drd::{generate_dungeon_3d, Dungeon3DGeneratorConfig};
+fn is_below_surface(
+    origin: (u32, u32, u32),
+    size: (u32, u32, u32),
+    surface_height: &SurfaceHeight,
+) -> bool {
+    let top = (origin.1 + size.1) as i32;
+    (origin.0..origin.0 + size.0)
+        .all(|x| (origin.2..origin.2 + size.2).all(|z| top <= surface_height(x as i32, z as i32)))
+}
 
-    #[test]
-    fn test_default_generate() {
-        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
-            seed: Some(0),
-            ..Default::default()
+// 最も高い位置にある部屋から地表へ通じる通路を掘る
+fn add_surface_entrance(
+    rooms: &BTreeMap<RoomId, Room>,
+    voxel_map: &mut VoxelMap,
+    passage_height: i32,
+    route_stats: &mut RouteStats,
+) -> Option<SurfaceEntrance> {
+    let room = rooms
+        .values()
+        .max_by_key(|room| (room.origin.1 + room.height, std::cmp::Reverse(room.id)))?;
+    for (attempt, (start, dir)) in face_starts(room).into_iter().enumerate() {
+        let mut passage = Passage {
+            cells: Vec::new(),
+            start: (start.x, start.y, start.z),
+            start_dirs: BTreeSet::from([dir]),
+            start_room_id: room.id,
+            end_room_id: room.id,
+            height: passage_height,
+            attempt,
+        };
+        if let Ok(opening) = voxel_map.add_surface_passage(&mut passage, route_stats) {
+            return Some(SurfaceEntrance {
+                room_id: room.id,
+                opening: (opening.x, opening.y, opening.z),
+                cells: passage.cells,
+            });
+        }
+    }
+    None
+}
+
+// 区画内で部屋を配置できる範囲
+pub(crate) struct BlockSpan {
+    block_start: u32,
+    block_size: u32,
+    pub(crate) start: u32,
+    pub(crate) available: u32,
+}
+
+impl BlockSpan {
+    // 部屋同士の余白は区画の終端側に、外周の余白は最も外側の区画にのみ適用する
+    pub(crate) fn new(
+        index: u32,
+        count: u32,
+        block_size: u32,
+        total: u32,
+        margin: u32,
+        boundary_margin: u32,
+    ) -> Self {
+        Self::from_bounds(
+            index,
+            count,
+            index * block_size,
+            (index + 1) * block_size,
+            total,
+            margin,
+            boundary_margin,
+        )
+    }
+
+    // 境界をずらした区画向けに、区画の始点と終点を直接指定する
+    pub(crate) fn from_bounds(
+        index: u32,
+        count: u32,
+        block_start: u32,
+        block_end: u32,
+        total: u32,
+        margin: u32,
+        boundary_margin: u32,
+    ) -> Self {
+        let block_size = block_end - block_start;
+        let start = if index == 0 {
+            block_start + boundary_margin
+        } else {
+            block_start
+        };
+        let mut end = (block_start + block_size).saturating_sub(margin);
+        if index + 1 == count {
+            end = end.min(total.saturating_sub(boundary_margin));
+        }
+        BlockSpan {
+            block_start,
+            block_size,
+            start,
+            available: end.saturating_sub(start),
+        }
+    }
+
+    // 区画の境界の位置。jitter が正なら各境界を区画の大きさの jitter 倍までずらし、各区画は min_size 以上を保つ
+    fn split_positions<R: Rng + ?Sized>(
+        rng: &mut R,
+        count: u32,
+        block_size: u32,
+        jitter: f32,
+        min_size: u32,
+    ) -> Vec<u32> {
+        let mut positions = (0..=count).map(|i| i * block_size).collect::<Vec<_>>();
+        if jitter <= 0.0 {
+            return positions;
+        }
+        let max_shift = (block_size as f32 * jitter.min(0.5)) as i64;
+        for i in 1..count as usize {
+            let shift = rng.gen_range(-max_shift..=max_shift);
+            // 手前の境界はずらし済み、奥の境界はまだ元の位置にある
+            let min = (positions[i - 1] + min_size) as i64;
+            let max = (positions[i + 1] - min_size) as i64;
+            positions[i] = (positions[i] as i64 + shift).clamp(min, max) as u32;
+        }
+        positions
+    }
+
+    fn claims(&self, value: u32) -> bool {
+        self.block_start <= value && value < self.block_start + self.block_size
+    }
+
+    // 全ての値を内側に含む原点を抽選する
+    fn sample_origin<R: Rng, I: Iterator<Item = u32>>(
+        &self,
+        rng: &mut R,
+        size: u32,
+        values: I,
+    ) -> Option<u32> {
+        let mut min = self.start;
+        let mut max = self.start + self.available - size;
+        for value in values {
+            min = min.max((value + 1).saturating_sub(size));
+            max = max.min(value);
+        }
+        (min <= max).then(|| rng.gen_range(min..=max))
+    }
+}
+
+// 水平方向の長い辺と短い辺の比が範囲に収まるまで、幅と奥行きを抽選し直す
+// 区画が細長く収まらない場合は、長い辺を範囲の上限まで縮める
+fn fit_aspect_ratio<R: Rng + ?Sized>(
+    rng: &mut R,
+    config: &Dungeon3DGeneratorConfig,
+    ratio_range: &RangeInclusive<f32>,
+    (mut width, mut depth): (u32, u32),
+    (x_available, z_available): (u32, u32),
+    block: (u32, u32, u32),
+    report: &mut GenerationReport,
+) -> Result<(u32, u32), Dungeon3DGeneratorError> {
+    let aspect_ratio = |width: u32, depth: u32| width.max(depth) as f32 / width.min(depth) as f32;
+    for _ in 0..MAX_PLACEMENT_ATTEMPTS {
+        if ratio_range.contains(&aspect_ratio(width, depth)) {
+            return Ok((width, depth));
+        }
+        report.rejected_placements += 1;
+        width = sample_room_size(rng, &config.room_width_range, x_available, Axis::X, block)?;
+        depth = sample_room_size(rng, &config.room_depth_range, z_available, Axis::Z, block)?;
+    }
+    let longest = (width.min(depth) as f32 * ratio_range.end()) as u32;
+    if width > depth {
+        width = longest.max(*config.room_width_range.start()).min(width);
+    } else {
+        depth = longest.max(*config.room_depth_range.start()).min(depth);
+    }
+    Ok((width, depth))
+}
+
+// 区画に収まるように部屋サイズの上限を縮めて抽選する
+pub(crate) fn sample_room_size<R: Rng + ?Sized>(
+    rng: &mut R,
+    range: &RangeInclusive<u32>,
+    available: u32,
+    axis: Axis,
+    block: (u32, u32, u32),
+) -> Result<u32, Dungeon3DGeneratorError> {
+    if available < *range.start() {
+        return Err(Dungeon3DGeneratorError::RoomDoesNotFit { axis, block });
+    }
+    Ok(rng.gen_range(*range.start()..=available.min(*range.end())))
+}
+
+const MAX_PLACEMENT_ATTEMPTS: usize = 64;
+const PLACEMENT_CANDIDATES: usize = 8;
+const WAYPOINT_MARGIN: u32 = 1; // Gap kept around waypoint rooms, which sit between rooms inside their margin
+const MAX_REPAIR_ATTEMPTS: usize = 8;
+
+// 区画の範囲内で部屋を水平方向に広げ、地表や他の部屋と衝突する場合は元に戻す
+fn enlarge_hub_room(
+    room: &mut Room,
+    x_span: &BlockSpan,
+    z_span: &BlockSpan,
+    config: &Dungeon3DGeneratorConfig,
+    voxel_map: &mut VoxelMap,
+) {
+    let grow = |origin: u32, size: u32, span: &BlockSpan| {
+        let start = origin.saturating_sub(config.hub_growth).max(span.start);
+        let end = (origin + size + config.hub_growth).min(span.start + span.available);
+        (start, end.max(origin + size) - start)
+    };
+    let (x, width) = grow(room.origin.0, room.width, x_span);
+    let (z, depth) = grow(room.origin.2, room.depth, z_span);
+    let enlarged = Room {
+        hierarchy: room.hierarchy,
+        ..Room::new(room.id, width, room.height, depth, (x, room.origin.1, z))
+    };
+    if config
+        .surface_height
+        .as_ref()
+        .is_some_and(|surface_height| {
+            !is_below_surface(
+                enlarged.origin,
+                (enlarged.width, enlarged.height, enlarged.depth),
+                surface_height,
+            )
         })
-        .unwrap();
-        insta::assert_debug_snapshot!(result.passages);
-        insta::assert_debug_snapshot!(result.rooms);
+    {
+        return;
+    }
+
+    voxel_map.remove_room(room);
+    if voxel_map.add_room(&enlarged).is_ok() {
+        *room = enlarged;
+    } else {
+        voxel_map.remove_room(&enlarged);
+        voxel_map.add_room(room).unwrap();
+    }
+}
+
+// 隣り合う階層の間をまたぐ通路が足りなければ、近い部屋の組から順に追加する
+// 同じ部屋の組をつなぐ通路は何本あっても1つと数える
+// 追加しても足りないままの境界を返す
+fn force_vertical_connections(
+    config: &Dungeon3DGeneratorConfig,
+    rooms: &BTreeMap<RoomId, Room>,
+    voxel_map: &mut VoxelMap,
+    passages: &mut BTreeMap<PassageId, Passage>,
+    passage_id: &mut PassageId,
+    route_stats: &mut RouteStats,
+    report: &mut GenerationReport,
+) -> Vec<UnmetHierarchyBoundary> {
+    let min_connections = config.min_inter_hierarchy_connections as usize;
+    let mut unmet = Vec::new();
+    for boundary in 0..config.room_hierarchy.saturating_sub(1) {
+        let crosses = |room0_id: &RoomId, room1_id: &RoomId| {
+            let (h0, h1) = (rooms[room0_id].hierarchy, rooms[room1_id].hierarchy);
+            h0.min(h1) <= boundary && boundary < h0.max(h1)
+        };
+        let mut connected = passages
+            .values()
+            .filter(|passage| crosses(&passage.start_room_id, &passage.end_room_id))
+            .map(|passage| BTreeSet::from([passage.start_room_id, passage.end_room_id]))
+            .collect::<BTreeSet<_>>();
+        if connected.len() >= min_connections {
+            continue;
+        }
+
+        let mut candidates = rooms
+            .values()
+            .filter(|room| room.hierarchy <= boundary)
+            .flat_map(|r0| {
+                rooms
+                    .values()
+                    .filter(|r1| r1.hierarchy > boundary)
+                    .map(move |r1| (r0, r1))
+            })
+            .map(|(r0, r1)| {
+                let (c0, c1) = (r0.center(), r1.center());
+                let squared_length =
+                    (c0.0 - c1.0).powi(2) + (c0.1 - c1.1).powi(2) + (c0.2 - c1.2).powi(2);
+                (squared_length, r0, r1)
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut failures = 0;
+        for (_, r0, r1) in candidates {
+            if connected.len() >= min_connections || failures >= MAX_REPAIR_ATTEMPTS {
+                break;
+            }
+            if connected.contains(&BTreeSet::from([r0.id, r1.id])) || config.forbids(r0, r1) {
+                continue;
+            }
+            match carve_between(voxel_map, r0, r1, config.passage_height as i32, route_stats) {
+                Ok(passage) => {
+                    connected.insert(BTreeSet::from([r0.id, r1.id]));
+                    passages.insert(passage_id.gen_id(), passage);
+                }
+                Err(_) => {
+                    failures += 1;
+                    report.failed_passages += 1;
+                }
+            }
+        }
+        if connected.len() < min_connections {
+            unmet.push(UnmetHierarchyBoundary {
+                boundary,
+                connections: connected.len(),
+            });
+        }
+    }
+    unmet
+}
+
+// 通路でつながる部屋が min_room_degree に満たない部屋から、近い部屋へ順に通路を掘り足す
+// 掘れない組が MAX_REPAIR_ATTEMPTS 回続いた部屋は諦め、次の部屋に進む
+// 最後まで足りないままの部屋を、つながる部屋の数とともに返す
+fn ensure_room_degree(
+    config: &Dungeon3DGeneratorConfig,
+    rooms: &BTreeMap<RoomId, Room>,
+    voxel_map: &mut VoxelMap,
+    passages: &mut BTreeMap<PassageId, Passage>,
+    passage_id: &mut PassageId,
+    route_stats: &mut RouteStats,
+    report: &mut GenerationReport,
+) -> BTreeMap<RoomId, usize> {
+    let min_degree = config.min_room_degree as usize;
+    let mut neighbors: BTreeMap<RoomId, BTreeSet<RoomId>> = BTreeMap::new();
+    for passage in passages.values() {
+        let (room0_id, room1_id) = (passage.start_room_id, passage.end_room_id);
+        neighbors.entry(room0_id).or_default().insert(room1_id);
+        neighbors.entry(room1_id).or_default().insert(room0_id);
+    }
+    for r0 in rooms.values() {
+        if neighbors.get(&r0.id).map_or(0, |ids| ids.len()) >= min_degree {
+            continue;
+        }
+        let mut candidates = rooms
+            .values()
+            .filter(|r1| r1.id != r0.id && !config.forbids(r0, r1))
+            .map(|r1| {
+                let (c0, c1) = (r0.center(), r1.center());
+                let squared_length =
+                    (c0.0 - c1.0).powi(2) + (c0.1 - c1.1).powi(2) + (c0.2 - c1.2).powi(2);
+                (squared_length, r1)
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut failures = 0;
+        for (_, r1) in candidates {
+            let degree = neighbors.get(&r0.id).map_or(0, |ids| ids.len());
+            if degree >= min_degree || failures >= MAX_REPAIR_ATTEMPTS {
+                break;
+            }
+            if neighbors
+                .get(&r0.id)
+                .is_some_and(|ids| ids.contains(&r1.id))
+            {
+                continue;
+            }
+            match carve_between(voxel_map, r0, r1, config.passage_height as i32, route_stats) {
+                Ok(passage) => {
+                    neighbors.entry(r0.id).or_default().insert(r1.id);
+                    neighbors.entry(r1.id).or_default().insert(r0.id);
+                    passages.insert(passage_id.gen_id(), passage);
+                }
+                Err(_) => {
+                    failures += 1;
+                    report.failed_passages += 1;
+                }
+            }
+        }
+    }
+    // 後の部屋へ掘った通路で先の部屋の数が足りる場合もあるため、全て掘り終えてから数える
+    rooms
+        .keys()
+        .map(|room_id| (*room_id, neighbors.get(room_id).map_or(0, |ids| ids.len())))
+        .filter(|(_, degree)| *degree < min_degree)
+        .collect()
+}
+
+// 各入口から各目的の部屋まで、通路でつながる部屋を辿って max_objective_distance 本以内で着けるようにする
+// 遠すぎる組は、入口から max_objective_distance - 1 本以内の部屋のうち目的の部屋に近いものから順に通路を掘る
+#[allow(clippy::too_many_arguments)]
+fn ensure_objective_paths(
+    config: &Dungeon3DGeneratorConfig,
+    rooms: &BTreeMap<RoomId, Room>,
+    voxel_map: &mut VoxelMap,
+    passages: &mut BTreeMap<PassageId, Passage>,
+    passage_id: &mut PassageId,
+    objective_passages: &mut Vec<(RoomId, RoomId, PassageId)>,
+    route_stats: &mut RouteStats,
+    report: &mut GenerationReport,
+) -> Result<(), Dungeon3DGeneratorError> {
+    let max_distance = config.max_objective_distance.unwrap_or(u32::MAX);
+    for entrance_id in config.entrance_rooms.iter() {
+        for objective_id in config.objective_rooms.iter() {
+            let unreachable = Dungeon3DGeneratorError::ObjectiveUnreachable {
+                entrance: *entrance_id,
+                objective: *objective_id,
+            };
+            let (Some(entrance), Some(objective)) =
+                (rooms.get(entrance_id), rooms.get(objective_id))
+            else {
+                return Err(unreachable);
+            };
+            let distances = passage_distances(passages, entrance.id);
+            if distances
+                .get(&objective.id)
+                .is_some_and(|distance| *distance <= max_distance)
+            {
+                continue;
+            }
+            let c1 = objective.center();
+            let mut candidates = distances
+                .iter()
+                .filter(|(room_id, distance)| {
+                    **distance < max_distance && **room_id != objective.id
+                })
+                .filter_map(|(room_id, _)| rooms.get(room_id))
+                .filter(|r0| !config.forbids(r0, objective))
+                .map(|r0| {
+                    let c0 = r0.center();
+                    let squared_length =
+                        (c0.0 - c1.0).powi(2) + (c0.1 - c1.1).powi(2) + (c0.2 - c1.2).powi(2);
+                    (squared_length, r0)
+                })
+                .collect::<Vec<_>>();
+            candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+            let passage_height = config.passage_height as i32;
+            let carved = candidates
+                .into_iter()
+                .take(MAX_REPAIR_ATTEMPTS)
+                .find_map(|(_, r0)| {
+                    match carve_between(voxel_map, r0, objective, passage_height, route_stats) {
+                        Ok(passage) => Some((r0.id, passage)),
+                        Err(_) => {
+                            report.failed_passages += 1;
+                            None
+                        }
+                    }
+                });
+            let Some((room0_id, passage)) = carved else {
+                return Err(unreachable);
+            };
+            objective_passages.push((room0_id, objective.id, *passage_id));
+            passages.insert(passage_id.gen_id(), passage);
+        }
+    }
+    Ok(())
+}
+
+// 部屋から、通路でつながる部屋を辿った通路の本数
+fn passage_distances(
+    passages: &BTreeMap<PassageId, Passage>,
+    from: RoomId,
+) -> BTreeMap<RoomId, u32> {
+    let mut neighbors: BTreeMap<RoomId, BTreeSet<RoomId>> = BTreeMap::new();
+    for passage in passages.values() {
+        let (room0_id, room1_id) = (passage.start_room_id, passage.end_room_id);
+        neighbors.entry(room0_id).or_default().insert(room1_id);
+        neighbors.entry(room1_id).or_default().insert(room0_id);
+    }
+    let mut distances = BTreeMap::from([(from, 0)]);
+    let mut queue = VecDeque::from([from]);
+    while let Some(room_id) = queue.pop_front() {
+        let distance = distances[&room_id] + 1;
+        for next in neighbors.get(&room_id).into_iter().flatten() {
+            if !distances.contains_key(next) {
+                distances.insert(*next, distance);
+                queue.push_back(*next);
+            }
+        }
+    }
+    distances
+}
+
+// 最初の連結成分と他の連結成分を、近い部屋の組から順に通路で結ぶ
+fn repair_connectivity(
+    components: &[BTreeSet<RoomId>],
+    rooms: &BTreeMap<RoomId, Room>,
+    voxel_map: &mut VoxelMap,
+    passage_id: PassageId,
+    config: &Dungeon3DGeneratorConfig,
+    route_stats: &mut RouteStats,
+    report: &mut GenerationReport,
+) -> Option<(Passage, ConnectivityRepair)> {
+    let (main_component, other_components) = components.split_first()?;
+    for component in other_components {
+        let mut candidates = main_component
+            .iter()
+            .flat_map(|room0_id| component.iter().map(move |room1_id| (*room0_id, *room1_id)))
+            .filter(|(room0_id, room1_id)| {
+                !config.forbids(rooms.get(room0_id).unwrap(), rooms.get(room1_id).unwrap())
+            })
+            .map(|(room0_id, room1_id)| {
+                let c0 = rooms.get(&room0_id).unwrap().center();
+                let c1 = rooms.get(&room1_id).unwrap().center();
+                let squared_length =
+                    (c0.0 - c1.0).powi(2) + (c0.1 - c1.1).powi(2) + (c0.2 - c1.2).powi(2);
+                (squared_length, room0_id, room1_id)
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        for (_, room0_id, room1_id) in candidates.into_iter().take(MAX_REPAIR_ATTEMPTS) {
+            let r0 = rooms.get(&room0_id).unwrap();
+            let r1 = rooms.get(&room1_id).unwrap();
+            let passage_height = config.passage_height as i32;
+            if let Ok(passage) = carve_between(voxel_map, r0, r1, passage_height, route_stats) {
+                return Some((
+                    passage,
+                    ConnectivityRepair {
+                        room0_id,
+                        room1_id,
+                        passage_id,
+                    },
+                ));
+            }
+            report.retries += 1;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::{VoxelType, DIRECTIONS};
+    use crate::generate_drd::{
+        budget_score, carve_between, carve_passage, generate_best_of, generate_dungeon_3d,
+        generate_dungeon_3d_with_metadata, generate_dungeon_3d_with_rng, generate_layout,
+        generate_with_budget, passage_distances, select_edges, BlockSpan, Dungeon3DGeneratorConfig,
+        Dungeon3DGeneratorError, Dungeon3DGeneratorResult, LayoutConnection,
+    };
+    use crate::passage::Passage;
+    use crate::pipeline::{GenerationPipeline, GenerationState, SelectEdges};
+    use crate::room::{Room, RoomId};
+    use crate::room_connection::ConnectionId;
+    use crate::validation::validate;
+    use crate::voxel_map::{RouteStats, VoxelMap, VoxelMapError};
+    use nalgebra::Vector3;
+    use rand::rngs::StdRng;
+    use rand::{Rng, RngCore, SeedableRng};
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::ops::RangeInclusive;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_default_generate() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        // 掘ったセルは validate で確かめるため、スナップショットには含めない
+        let passages = result
+            .passages
+            .into_iter()
+            .map(|(passage_id, passage)| {
+                let passage = Passage {
+                    cells: Vec::new(),
+                    ..passage
+                };
+                (passage_id, passage)
+            })
+            .collect::<BTreeMap<_, _>>();
+        insta::assert_debug_snapshot!(passages);
+        insta::assert_debug_snapshot!(result.rooms);
+    }
+
+    #[test]
+    fn test_room_range_larger_than_block() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            width: 16,
+            depth: 12,
+            seed: Some(0),
+            room_width_range: 5..=40,
+            room_depth_range: 5..=40,
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(result
+            .rooms
+            .values()
+            .all(|room| room.end().0 <= 16 && room.end().2 <= 12));
+    }
+
+    #[test]
+    fn test_boundary_margin() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            height: 16,
+            seed: Some(0),
+            boundary_margin: 2,
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(result.rooms.values().all(|room| {
+            let end = room.end();
+            room.origin.0 >= 2
+                && room.origin.1 >= 2
+                && room.origin.2 >= 2
+                && end.0 <= 30
+                && end.1 <= 14
+                && end.2 <= 30
+        }));
+    }
+
+    #[test]
+    fn test_room_anchors() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            room_anchors: vec![(16, 3, 16), (2, 0, 20)],
+            ..Default::default()
+        })
+        .unwrap();
+        for (x, y, z) in [(16, 3, 16), (2, 0, 20)] {
+            assert!(result
+                .rooms
+                .values()
+                .any(|room| room.contains_point(x, y, z)));
+        }
+    }
+
+    #[test]
+    fn test_surface_height() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            height: 16,
+            seed: Some(0),
+            surface_height: Some(Arc::new(|x, _| 12 + x / 8)),
+            surface_opening: true,
+            ..Default::default()
+        })
+        .unwrap();
+        for (p, _) in result.voxel_map.map.iter().filter(|(p, _)| {
+            !result
+                .surface_entrance
+                .as_ref()
+                .unwrap()
+                .cells
+                .iter()
+                .any(|(cell, _)| *cell == (p.x, p.y, p.z))
+        }) {
+            assert!(p.y < 12 + p.x / 8);
+        }
+    }
+
+    #[test]
+    fn test_room_metadata() {
+        let plain = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        let (result, metadata) = generate_dungeon_3d_with_metadata(
+            Dungeon3DGeneratorConfig {
+                seed: Some(0),
+                ..Default::default()
+            },
+            |room, rng| (room.width * room.depth, rng.gen_range(0..4u32)),
+        )
+        .unwrap();
+        // メタデータの割り当てで配置が変わらないこと
+        assert_eq!(format!("{:?}", result.rooms), format!("{:?}", plain.rooms));
+        assert_eq!(metadata.len(), result.rooms.len());
+        for (room_id, (area, _)) in metadata.iter() {
+            let room = &result.rooms[room_id];
+            assert_eq!(*area, room.width * room.depth);
+        }
+    }
+
+    #[test]
+    fn test_direct_doors() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            room_margin_x: 1,
+            room_margin_z: 1,
+            room_width_range: 8..=10,
+            room_depth_range: 8..=10,
+            direct_doors: true,
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(result
+            .passages
+            .values()
+            .any(|passage| passage.cells.len() as i32 == passage.height + 1));
+        assert_eq!(validate(&result), vec![]);
+    }
+
+    #[test]
+    fn test_generate_with_budget() {
+        let result = generate_with_budget(
+            Dungeon3DGeneratorConfig {
+                seed: Some(0),
+                ..Default::default()
+            },
+            Duration::ZERO,
+        )
+        .unwrap();
+        assert!(!result.rooms.is_empty());
+    }
+
+    #[test]
+    fn test_generate_best_of() {
+        let config = Dungeon3DGeneratorConfig {
+            width: 32,
+            depth: 32,
+            seed: Some(0),
+            loop_probability: 0.5,
+            ..Default::default()
+        };
+        let mut attempts = 0;
+        let result = generate_best_of(config.clone(), |attempt| {
+            attempts = attempt;
+            attempt < 3
+        })
+        .unwrap();
+        assert_eq!(attempts, 3);
+
+        // 3回のうち最初に現れた最も良い結果を返す
+        let candidates = (0..3)
+            .map(|seed| {
+                generate_dungeon_3d(Dungeon3DGeneratorConfig {
+                    seed: Some(seed),
+                    ..config.clone()
+                })
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+        let scores = candidates.iter().map(budget_score).collect::<Vec<_>>();
+        assert!(scores.iter().any(|score| *score != scores[0]));
+        let best = scores.iter().min().unwrap();
+        let index = scores.iter().position(|score| score == best).unwrap();
+        assert_eq!(budget_score(&result), *best);
+        assert_eq!(
+            result.to_canonical_string(),
+            candidates[index].to_canonical_string()
+        );
+    }
+
+    #[test]
+    fn test_carve_passages_incrementally() {
+        let mut layout = generate_layout(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(layout
+            .voxel_map
+            .map
+            .values()
+            .all(|voxel_type| voxel_type.room_id().is_some()));
+        let plain = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        // 1本ずつ掘った通路は一括生成と同じになる
+        let passages = layout
+            .connections
+            .values()
+            .filter_map(|connection| carve_passage(&mut layout.voxel_map, connection).ok())
+            .collect::<Vec<_>>();
+        let positions =
+            |passage: &Passage| passage.cells.iter().map(|(p, _)| *p).collect::<Vec<_>>();
+        for (passage, expected) in passages.iter().zip(plain.passages.values()) {
+            assert_eq!(positions(passage), positions(expected));
+        }
+    }
+
+    #[test]
+    fn test_canonical_string() {
+        let generate = || {
+            generate_dungeon_3d(Dungeon3DGeneratorConfig {
+                seed: Some(0),
+                ..Default::default()
+            })
+            .unwrap()
+            .to_canonical_string()
+        };
+        let text = generate();
+        assert_eq!(text, generate());
+        assert!(text.starts_with("rooms\n1 origin="));
+    }
+
+    // 階層の境界をまたぐ通路でつながる部屋の組
+    fn crossing_pairs(
+        result: &Dungeon3DGeneratorResult,
+        boundary: u32,
+    ) -> (usize, BTreeSet<BTreeSet<RoomId>>) {
+        let crossing = result
+            .passages
+            .values()
+            .filter(|passage| {
+                let h0 = result.rooms[&passage.start_room_id].hierarchy;
+                let h1 = result.rooms[&passage.end_room_id].hierarchy;
+                h0.min(h1) <= boundary && boundary < h0.max(h1)
+            })
+            .map(|passage| BTreeSet::from([passage.start_room_id, passage.end_room_id]))
+            .collect::<Vec<_>>();
+        (crossing.len(), crossing.into_iter().collect())
+    }
+
+    #[test]
+    fn test_min_inter_hierarchy_connections() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            min_inter_hierarchy_connections: 3,
+            ..Default::default()
+        })
+        .unwrap();
+        for boundary in 0..2 {
+            let (_, pairs) = crossing_pairs(&result, boundary);
+            assert!(pairs.len() >= 3, "boundary: {}", boundary);
+        }
+        assert_eq!(result.unmet_hierarchy_boundaries, vec![]);
+    }
+
+    #[test]
+    fn test_inter_hierarchy_duplicate_pairs() {
+        // 境界をまたぐ最初の辺を2回選び、同じ部屋の組を2本の通路でつなぐ
+        struct DuplicateCrossing;
+        impl SelectEdges for DuplicateCrossing {
+            fn select_edges(
+                &self,
+                state: &mut GenerationState,
+            ) -> Result<(), Dungeon3DGeneratorError> {
+                select_edges(state)?;
+                let crossing = state
+                    .connections
+                    .values()
+                    .find(|connection| {
+                        connection.start_room.hierarchy != connection.end_room.hierarchy
+                    })
+                    .unwrap()
+                    .clone();
+                let mut connection_id = ConnectionId::first();
+                while state.connections.contains_key(&connection_id) {
+                    connection_id.gen_id();
+                }
+                state.connections.insert(connection_id, crossing);
+                Ok(())
+            }
+        }
+        let pipeline = GenerationPipeline {
+            select_edges: Box::new(DuplicateCrossing),
+            ..Default::default()
+        };
+        let config = Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            width: 32,
+            depth: 32,
+            room_hierarchy: 2,
+            ..Default::default()
+        };
+        let original = pipeline.generate(config.clone()).unwrap();
+        let (passages, pairs) = crossing_pairs(&original, 0);
+        assert!(passages > pairs.len());
+
+        // 通路の本数では足りていても、部屋の組が足りなければ掘り足す
+        let result = pipeline
+            .generate(Dungeon3DGeneratorConfig {
+                min_inter_hierarchy_connections: passages as u32,
+                ..config
+            })
+            .unwrap();
+        let (_, pairs) = crossing_pairs(&result, 0);
+        assert!(pairs.len() >= passages);
+        assert_eq!(result.unmet_hierarchy_boundaries, vec![]);
+    }
+
+    #[test]
+    fn test_unmet_inter_hierarchy_connections() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            width: 32,
+            depth: 32,
+            min_inter_hierarchy_connections: 1000,
+            ..Default::default()
+        })
+        .unwrap();
+        // 部屋の組が 1000 に届かないため、全ての境界が足りないまま記録される
+        let boundaries = result
+            .unmet_hierarchy_boundaries
+            .iter()
+            .map(|unmet| unmet.boundary)
+            .collect::<Vec<_>>();
+        assert_eq!(boundaries, vec![0, 1]);
+        for unmet in result.unmet_hierarchy_boundaries.iter() {
+            let (_, pairs) = crossing_pairs(&result, unmet.boundary);
+            assert!(!pairs.is_empty());
+            assert_eq!(
+                unmet.connections,
+                pairs.len(),
+                "boundary: {}",
+                unmet.boundary
+            );
+        }
+    }
+
+    #[test]
+    fn test_config_violations() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            width: 4,
+            room_hierarchy: 0,
+            room_height_range: RangeInclusive::new(3, 2),
+            ..Default::default()
+        });
+        let Err(Dungeon3DGeneratorError::ConfigValidation(violations)) = result else {
+            panic!("config should be rejected");
+        };
+        let fields = violations
+            .iter()
+            .map(|violation| violation.field)
+            .collect::<Vec<_>>();
+        assert_eq!(fields, vec!["room_height_range", "width", "room_hierarchy"]);
+        assert_eq!(violations[1].value, "4");
+        assert_eq!(violations[1].allowed, ">= 9");
+        assert!(Dungeon3DGeneratorConfig::default().violations().is_empty());
+    }
+
+    #[test]
+    fn test_room_density() {
+        let config = || Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            width: 48,
+            height: 4,
+            depth: 48,
+            room_hierarchy: 1,
+            ..Default::default()
+        };
+        let dense = generate_dungeon_3d(config()).unwrap();
+        let sparse = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            room_density: 0.3,
+            ..config()
+        })
+        .unwrap();
+        assert!(sparse.rooms.len() * 2 < dense.rooms.len());
+        assert_eq!(validate(&sparse), vec![]);
+        // 全ての区画を空ける場合も部屋は1つ残る
+        let empty = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            room_density: 0.0,
+            ..config()
+        })
+        .unwrap();
+        assert_eq!(empty.rooms.len(), 1);
+    }
+
+    #[test]
+    fn test_block_split_jitter() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let equal = BlockSpan::split_positions(&mut rng, 4, 10, 0.0, 7);
+        assert_eq!(equal, vec![0, 10, 20, 30, 40]);
+        let positions = BlockSpan::split_positions(&mut rng, 4, 10, 0.5, 7);
+        assert_ne!(positions, equal);
+        assert_eq!((positions[0], positions[4]), (0, 40));
+        assert!(positions.windows(2).all(|pair| pair[1] - pair[0] >= 7));
+
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            block_split_jitter: 0.4,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(validate(&result), vec![]);
+    }
+
+    #[test]
+    fn test_room_aspect_ratio_range() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            room_aspect_ratio_range: Some(1.0..=1.25),
+            ..Default::default()
+        })
+        .unwrap();
+        for room in result.rooms.values() {
+            let ratio = room.width.max(room.depth) as f32 / room.width.min(room.depth) as f32;
+            assert!(ratio <= 1.25, "room: {:?}", room);
+        }
+        assert_eq!(validate(&result), vec![]);
+    }
+
+    #[test]
+    fn test_min_room_degree() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            min_room_degree: 3,
+            ..Default::default()
+        })
+        .unwrap();
+        for room_id in result.rooms.keys() {
+            let neighbors = result
+                .passages
+                .values()
+                .filter_map(
+                    |passage| match (passage.start_room_id, passage.end_room_id) {
+                        (start, end) if start == *room_id => Some(end),
+                        (start, end) if end == *room_id => Some(start),
+                        _ => None,
+                    },
+                )
+                .collect::<BTreeSet<_>>();
+            assert!(neighbors.len() >= 3, "room: {:?}", room_id);
+        }
+        assert_eq!(result.under_connected_rooms, BTreeMap::new());
+        assert_eq!(validate(&result), vec![]);
+    }
+
+    #[test]
+    fn test_under_connected_rooms() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            width: 32,
+            depth: 32,
+            min_room_degree: 100,
+            ..Default::default()
+        })
+        .unwrap();
+        // 部屋の数が 100 に届かないため、全ての部屋が足りないまま記録される
+        assert_eq!(
+            result.under_connected_rooms.keys().collect::<Vec<_>>(),
+            result.rooms.keys().collect::<Vec<_>>()
+        );
+        for (room_id, degree) in result.under_connected_rooms.iter() {
+            let neighbors = result
+                .passages
+                .values()
+                .filter_map(
+                    |passage| match (passage.start_room_id, passage.end_room_id) {
+                        (start, end) if start == *room_id => Some(end),
+                        (start, end) if end == *room_id => Some(start),
+                        _ => None,
+                    },
+                )
+                .collect::<BTreeSet<_>>();
+            assert_eq!(*degree, neighbors.len(), "room: {:?}", room_id);
+        }
+    }
+
+    #[test]
+    fn test_objective_rooms() {
+        let config = Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        };
+        let original = generate_dungeon_3d(config.clone()).unwrap();
+        let first = *original.rooms.keys().next().unwrap();
+        let last = *original.rooms.keys().last().unwrap();
+        let path = crate::encounter::critical_path(&original, first);
+        let objective = *path.rooms.last().unwrap();
+        assert!(path.passages.len() > 2);
+
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            entrance_rooms: vec![first, last],
+            objective_rooms: vec![objective],
+            max_objective_distance: Some(2),
+            ..config.clone()
+        })
+        .unwrap();
+        // 近道は接続性の修復とは別に記録する
+        assert!(!result.objective_passages.is_empty());
+        assert_eq!(result.repairs, original.repairs);
+        for (room0_id, room1_id, passage_id) in result.objective_passages.iter() {
+            let passage = &result.passages[passage_id];
+            assert_eq!(*room1_id, objective);
+            assert_eq!(
+                BTreeSet::from([passage.start_room_id, passage.end_room_id]),
+                BTreeSet::from([*room0_id, *room1_id])
+            );
+        }
+        assert!(original.objective_passages.is_empty());
+        assert_eq!(budget_score(&result).0, budget_score(&original).0);
+        for entrance in [first, last] {
+            assert!(passage_distances(&result.passages, entrance)[&objective] <= 2);
+        }
+        assert_eq!(validate(&result), vec![]);
+
+        let missing = crate::room::RoomId::from_inner(1000);
+        assert!(matches!(
+            generate_dungeon_3d(Dungeon3DGeneratorConfig {
+                entrance_rooms: vec![first],
+                objective_rooms: vec![missing],
+                ..config
+            }),
+            Err(Dungeon3DGeneratorError::ObjectiveUnreachable { objective, .. }) if objective == missing
+        ));
+    }
+
+    #[test]
+    fn test_room_connections() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            loop_probability: 1.0,
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(result.room_connections.len() >= result.rooms.len() - 1);
+        for room_connection in result.room_connections.iter() {
+            let pair = BTreeSet::from([room_connection.room0_id, room_connection.room1_id]);
+            let carved = result.passages.values().any(|passage| {
+                BTreeSet::from([passage.start_room_id, passage.end_room_id]) == pair
+            });
+            let failed = result
+                .failed_connections
+                .iter()
+                .any(|failed| failed.connection == *room_connection);
+            assert_eq!(carved, !failed, "connection: {:?}", room_connection);
+        }
+    }
+
+    #[test]
+    fn test_failed_connections() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            loop_probability: 1.0,
+            margin_for_bounds: 0,
+            stair_run: 4,
+            report: true,
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(!result.failed_connections.is_empty());
+        assert_eq!(
+            result.report.unwrap().failed_passages,
+            result.failed_connections.len()
+        );
+        for failed in result.failed_connections.iter() {
+            assert!(!failed.required);
+            assert_eq!(failed.reason, VoxelMapError::Unreachable);
+            assert!(result.room_connections.contains(&failed.connection));
+        }
+    }
+
+    #[test]
+    fn test_merge_adjacent_rooms() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            room_margin_x: 1,
+            room_margin_z: 1,
+            room_hierarchy: 1,
+            room_width_range: 5..=12,
+            room_depth_range: 5..=12,
+            merge_adjacent_rooms: true,
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(!result.room_extensions.is_empty());
+        for (room_id, extensions) in result.room_extensions.iter() {
+            assert!(result.rooms.contains_key(room_id));
+            assert!(extensions.iter().all(|room| room.id == *room_id));
+        }
+        assert_eq!(validate(&result), vec![]);
+    }
+
+    #[test]
+    fn test_hub_rooms() {
+        let config = || Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        };
+        let plain = generate_dungeon_3d(config()).unwrap();
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            hub_min_degree: 3,
+            ..config()
+        })
+        .unwrap();
+        let area = |room: &Room| room.width * room.depth;
+        assert!(result
+            .rooms
+            .values()
+            .any(|room| area(room) > area(&plain.rooms[&room.id])));
+        assert_eq!(validate(&result), vec![]);
+    }
+
+    #[test]
+    fn test_max_connection_length() {
+        let config = || Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            width: 48,
+            depth: 48,
+            room_width_range: 3..=4,
+            room_depth_range: 3..=4,
+            room_margin_x: 10,
+            room_margin_z: 10,
+            max_connection_length: Some(8),
+            report: true,
+            ..Default::default()
+        };
+        let length = |connection: &LayoutConnection| {
+            let (c0, c1) = (connection.start_room.center(), connection.end_room.center());
+            ((c1.0 - c0.0).powi(2) + (c1.1 - c0.1).powi(2) + (c1.2 - c0.2).powi(2)).sqrt()
+        };
+        let layout = generate_layout(config()).unwrap();
+        assert!(layout
+            .connections
+            .values()
+            .filter(|connection| !connection.required)
+            .all(|connection| length(connection) <= 8.0));
+        let split_config = || Dungeon3DGeneratorConfig {
+            split_long_connections: true,
+            ..config()
+        };
+        let split = generate_layout(split_config()).unwrap();
+        assert!(split.rooms.len() > layout.rooms.len());
+        let result = generate_dungeon_3d(split_config()).unwrap();
+        let report = result.report.as_ref().unwrap();
+        assert_eq!(
+            report.connection_lengths.values().sum::<usize>(),
+            split.connections.len()
+        );
+        assert_eq!(validate(&result), vec![]);
+    }
+
+    #[test]
+    fn test_waypoint_rooms() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            width: 48,
+            depth: 48,
+            room_width_range: 3..=4,
+            room_depth_range: 3..=4,
+            room_margin_x: 10,
+            room_margin_z: 10,
+            max_connection_rise: Some(2),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(!result.waypoint_rooms.is_empty());
+        for room_id in result.waypoint_rooms.iter() {
+            let room = &result.rooms[room_id];
+            assert_eq!((room.width, room.depth), (3, 3));
+            // 中継の部屋は少なくとも2つの通路がつながる
+            let passages = result
+                .passages
+                .values()
+                .filter(|passage| {
+                    passage.start_room_id == *room_id || passage.end_room_id == *room_id
+                })
+                .count();
+            assert!(passages >= 2);
+        }
+        assert_eq!(validate(&result), vec![]);
+    }
+
+    #[test]
+    fn test_excluded_hierarchy_pairs() {
+        for seed in 0..3 {
+            let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+                seed: Some(seed),
+                excluded_hierarchy_pairs: vec![(2, 0)],
+                ..Default::default()
+            })
+            .unwrap();
+            for passage in result.passages.values() {
+                let h0 = result.rooms[&passage.start_room_id].hierarchy;
+                let h1 = result.rooms[&passage.end_room_id].hierarchy;
+                assert_ne!((h0.min(h1), h0.max(h1)), (0, 2));
+            }
+        }
+    }
+
+    #[test]
+    fn test_skip_connected_rooms() {
+        let generate = |skip_connected_rooms| {
+            generate_dungeon_3d(Dungeon3DGeneratorConfig {
+                seed: Some(0),
+                loop_probability: 1.0,
+                skip_connected_rooms,
+                report: true,
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        let (all, skipped) = (generate(false), generate(true));
+        let report = skipped.report.as_ref().unwrap();
+        assert!(report.skipped_passages > 0);
+        assert!(skipped.passages.len() < all.passages.len());
+        assert_eq!(validate(&skipped), vec![]);
+    }
+
+    #[test]
+    fn test_carve_between_retries_start() {
+        let mut room_id = RoomId::first();
+        let room0 = Room::new(room_id.gen_id(), 5, 2, 5, (1, 1, 1));
+        let room1 = Room::new(room_id.gen_id(), 5, 2, 5, (16, 4, 16));
+        let mut voxel_map = VoxelMap::new(0, 0, 0, 24, 24, 24);
+        voxel_map.add_room(&room0).unwrap();
+        voxel_map.add_room(&room1).unwrap();
+
+        let unblocked = carve_between(
+            &mut voxel_map.clone(),
+            &room0,
+            &room1,
+            2,
+            &mut RouteStats::default(),
+        )
+        .unwrap();
+        assert_eq!(unblocked.attempt, 0);
+
+        // 最初の開始地点から出る先を壁で塞ぐ
+        let candidates = Passage::candidates(&room0, &room1, 2);
+        let first = &candidates[0];
+        let start = Vector3::new(first.start.0, first.start.1, first.start.2);
+        for dir in first.start_dirs.iter() {
+            for y in -1..=2 {
+                let p = start + dir.to_vec3() + Vector3::new(0, y, 0);
+                voxel_map.map.insert(p, VoxelType::Wall);
+            }
+        }
+        let passage = carve_between(
+            &mut voxel_map,
+            &room0,
+            &room1,
+            2,
+            &mut RouteStats::default(),
+        )
+        .unwrap();
+        assert!(passage.attempt > 0);
+        assert_eq!(passage.start, candidates[passage.attempt].start);
+        assert_ne!(passage.start, first.start);
+        assert!(!passage.cells.is_empty());
+    }
+
+    #[test]
+    fn test_max_route_nodes() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            loop_probability: 1.0,
+            max_route_nodes: Some(3000),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(result
+            .failed_connections
+            .iter()
+            .any(|failed| failed.reason == VoxelMapError::Budget));
+        assert_eq!(validate(&result), vec![]);
+
+        let error = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            max_route_nodes: Some(100),
+            ..Default::default()
+        });
+        assert!(matches!(
+            error,
+            Err(Dungeon3DGeneratorError::Disconnected(_))
+        ));
+    }
+
+    #[test]
+    fn test_guided_route_search() {
+        let generate = |guided_route_search| {
+            generate_dungeon_3d(Dungeon3DGeneratorConfig {
+                seed: Some(0),
+                guided_route_search,
+                report: true,
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        let (straight, guided) = (generate(false), generate(true));
+        assert_eq!(validate(&guided), vec![]);
+        let (straight, guided) = (straight.report.unwrap(), guided.report.unwrap());
+        assert_eq!(straight.explored_guide_nodes, 0);
+        assert!(guided.explored_guide_nodes > 0);
+        // 粗い区画の探索で調べた数も含めて、まっすぐな距離で探すより少ない
+        assert!(
+            guided.explored_route_nodes + guided.explored_guide_nodes
+                < straight.explored_route_nodes
+        );
+    }
+
+    #[test]
+    fn test_avoid_foreign_rooms() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(1),
+            avoid_foreign_rooms: true,
+            foreign_room_penalty: 20,
+            ..Default::default()
+        })
+        .unwrap();
+        for passage in result.passages.values() {
+            let own_rooms = [passage.start_room_id, passage.end_room_id];
+            for ((x, y, z), voxel_type) in passage.cells.iter() {
+                if !voxel_type.is_passable() {
+                    continue;
+                }
+                for dir in DIRECTIONS.iter() {
+                    let p = Vector3::new(*x, *y, *z) + dir.to_vec3();
+                    if let Some(room_id) = result.voxel_map.get(&p).room_id() {
+                        assert!(own_rooms.contains(&room_id), "{:?}", p);
+                    }
+                }
+            }
+        }
+        assert_eq!(validate(&result), vec![]);
+    }
+
+    #[test]
+    fn test_stair_run() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            stair_run: 2,
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(!result.stairs.is_empty());
+        for stair in result.stairs.iter() {
+            assert_eq!(stair.steps, stair.rise * 2);
+        }
+        assert_eq!(validate(&result), vec![]);
+    }
+
+    #[test]
+    fn test_stair_clearance() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            stair_clearance: Some(4),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(!result.stairs.is_empty());
+        for stair in result.stairs.iter() {
+            let (x, y, z) = stair.bottom;
+            let v = stair.direction.to_vec3();
+            // 各段の真上と一つ先の列に 4 ボクセルの空間がある
+            for step in 1..=stair.steps as i32 {
+                let p = Vector3::new(x, y + step - 1, z) + v * step;
+                for dy in 1..=4 {
+                    assert!(result
+                        .voxel_map
+                        .get(&(p + Vector3::new(0, dy, 0)))
+                        .is_passable());
+                    assert!(result
+                        .voxel_map
+                        .get(&(p + v + Vector3::new(0, dy, 0)))
+                        .is_passable());
+                }
+            }
+        }
+        assert_eq!(validate(&result), vec![]);
+    }
+
+    #[test]
+    fn test_generate_with_rng() {
+        // ゲーム側の乱数生成器を想定した xorshift
+        struct XorShift(u64);
+        impl RngCore for XorShift {
+            fn next_u32(&mut self) -> u32 {
+                self.next_u64() as u32
+            }
+            fn next_u64(&mut self) -> u64 {
+                self.0 ^= self.0 << 13;
+                self.0 ^= self.0 >> 7;
+                self.0 ^= self.0 << 17;
+                self.0
+            }
+            fn fill_bytes(&mut self, dest: &mut [u8]) {
+                for chunk in dest.chunks_mut(8) {
+                    let bytes = self.next_u64().to_le_bytes();
+                    chunk.copy_from_slice(&bytes[..chunk.len()]);
+                }
+            }
+            fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+                self.fill_bytes(dest);
+                Ok(())
+            }
+        }
+
+        let generate = || {
+            generate_dungeon_3d_with_rng(Dungeon3DGeneratorConfig::default(), &mut XorShift(7))
+                .unwrap()
+                .to_canonical_string()
+        };
+        assert_eq!(generate(), generate());
+
+        let seeded = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        let injected = generate_dungeon_3d_with_rng(
+            Dungeon3DGeneratorConfig::default(),
+            &mut StdRng::seed_from_u64(0),
+        )
+        .unwrap();
+        assert_eq!(injected.to_canonical_string(), seeded.to_canonical_string());
+    }
+
+    #[test]
+    fn test_clone_result() {
+        let config = Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        };
+        let result = generate_dungeon_3d(config.clone()).unwrap();
+        assert_eq!(result, generate_dungeon_3d(config).unwrap());
+
+        let mut cloned = result.clone();
+        assert_eq!(cloned, result);
+        cloned.passages.clear();
+        assert_ne!(cloned, result);
+    }
+
+    #[test]
+    fn test_error_source() {
+        use std::error::Error;
+
+        let error: Box<dyn Error> = Box::new(Dungeon3DGeneratorError::VoxelMapError(
+            VoxelMapError::Unreachable,
+        ));
+        assert_eq!(error.to_string(), "failed to build the voxel map");
+        assert_eq!(
+            error.source().map(|source| source.to_string()),
+            Some(VoxelMapError::Unreachable.to_string())
+        );
     }
 }
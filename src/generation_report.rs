@@ -0,0 +1,51 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum GenerationPhase {
+    PlaceRooms,
+    BuildGraph,
+    VoxelizeRooms,
+    MergeRooms,
+    SelectEdges,
+    CarveRequiredPassages,
+    CarveExtraPassages,
+    RepairConnectivity,
+    ForceVerticalConnections,
+    EnsureRoomDegree,
+    EnsureObjectivePaths,
+    CarveSurfaceEntrance,
+    RoughenPassages,
+}
+
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct GenerationReport {
+    pub phase_timings: Vec<(GenerationPhase, Duration)>,
+    pub explored_route_nodes: usize, // Number of nodes popped by the passage route search
+    pub explored_guide_nodes: usize, // Number of blocks visited by the coarse search of guided_route_search
+    pub rejected_placements: usize,  // Number of sampled room placements rejected by constraints
+    pub retries: usize,              // Number of times a placement or passage was retried
+    pub failed_passages: usize,      // Number of optional passages which could not be carved
+    pub skipped_passages: usize, // Number of connections left uncarved because their rooms were already joined
+    pub connection_lengths: BTreeMap<u32, usize>, // Selected connections per distance between room centers, rounded down
+}
+
+impl GenerationReport {
+    pub fn total_duration(&self) -> Duration {
+        self.phase_timings
+            .iter()
+            .map(|(_, duration)| *duration)
+            .sum()
+    }
+
+    pub fn phase_duration(&self, phase: GenerationPhase) -> Option<Duration> {
+        self.phase_timings
+            .iter()
+            .find(|(p, _)| *p == phase)
+            .map(|(_, duration)| *duration)
+    }
+
+    pub(crate) fn record(&mut self, phase: GenerationPhase, start: Instant) {
+        self.phase_timings.push((phase, start.elapsed()));
+    }
+}
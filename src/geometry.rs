@@ -0,0 +1,207 @@
+use nalgebra::{Vector2, Vector3};
+
+// 線分 p00-p01 と線分 p10-p11 の交点
+// 平行な場合は None。2本目の線分の終点 p11 は含まない
+pub fn intersect_line_and_line(
+    p00: &Vector2<f32>,
+    p01: &Vector2<f32>,
+    p10: &Vector2<f32>,
+    p11: &Vector2<f32>,
+) -> Option<Vector2<f32>> {
+    let d = (p01.x - p00.x) * (p11.y - p10.y) - (p01.y - p00.y) * (p11.x - p10.x);
+    if d == 0.0 {
+        return None;
+    }
+
+    let v = *p10 - *p00;
+    let d_r = ((p11.y - p10.y) * v.x - (p11.x - p10.x) * v.y) / d;
+    let d_s = ((p01.y - p00.y) * v.x - (p01.x - p00.x) * v.y) / d;
+
+    if (0.0..=1.0).contains(&d_r) && (0.0..1.0).contains(&d_s) {
+        Some(*p00 + d_r * (*p01 - *p00))
+    } else {
+        None
+    }
+}
+
+// 原点 rect.0、大きさ rect.1 の長方形の辺と線分 p0-p1 の交点を、上、下、左、右の辺の順に返す
+pub fn intersect_rect_with_line(
+    rect: (&Vector2<f32>, &Vector2<f32>),
+    p0: &Vector2<f32>,
+    p1: &Vector2<f32>,
+) -> Vec<Vector2<f32>> {
+    let l_b = Vector2::new(rect.0.x, rect.0.y);
+    let l_t = Vector2::new(rect.0.x, rect.0.y + rect.1.y);
+    let r_b = Vector2::new(rect.0.x + rect.1.x, rect.0.y);
+    let r_t = Vector2::new(rect.0.x + rect.1.x, rect.0.y + rect.1.y);
+    let mut ret = Vec::new();
+    if let Some(p) = intersect_line_and_line(p0, p1, &l_t, &r_t) {
+        ret.push(p);
+    }
+    if let Some(p) = intersect_line_and_line(p0, p1, &l_b, &r_b) {
+        ret.push(p);
+    }
+    if let Some(p) = intersect_line_and_line(p0, p1, &l_b, &l_t) {
+        ret.push(p);
+    }
+    if let Some(p) = intersect_line_and_line(p0, p1, &r_b, &r_t) {
+        ret.push(p);
+    }
+    ret
+}
+
+// 線分 p0-p1 のうち、min と max を対角とする軸に平行な直方体の中にある区間を
+// 線分上の位置 t (p0 が 0、p1 が 1) の範囲で返す。重ならなければ None
+pub fn segment_aabb_intersection_3d(
+    p0: &Vector3<f32>,
+    p1: &Vector3<f32>,
+    min: &Vector3<f32>,
+    max: &Vector3<f32>,
+) -> Option<(f32, f32)> {
+    let d = p1 - p0;
+    let (mut t_enter, mut t_exit) = (0.0f32, 1.0f32);
+    for axis in 0..3 {
+        if d[axis] == 0.0 {
+            // 軸に平行な線分は、その軸で範囲内にあるかだけを見る
+            if p0[axis] < min[axis] || max[axis] < p0[axis] {
+                return None;
+            }
+            continue;
+        }
+        let t0 = (min[axis] - p0[axis]) / d[axis];
+        let t1 = (max[axis] - p0[axis]) / d[axis];
+        t_enter = t_enter.max(t0.min(t1));
+        t_exit = t_exit.min(t0.max(t1));
+        if t_enter > t_exit {
+            return None;
+        }
+    }
+    Some((t_enter, t_exit))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::{
+        intersect_line_and_line, intersect_rect_with_line, segment_aabb_intersection_3d,
+    };
+    use nalgebra::{Vector2, Vector3};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const EPSILON: f32 = 1e-3;
+
+    fn random_vector2(rng: &mut StdRng) -> Vector2<f32> {
+        Vector2::new(rng.gen_range(-10.0..10.0), rng.gen_range(-10.0..10.0))
+    }
+
+    fn random_vector3(rng: &mut StdRng) -> Vector3<f32> {
+        Vector3::new(
+            rng.gen_range(-10.0..10.0),
+            rng.gen_range(-10.0..10.0),
+            rng.gen_range(-10.0..10.0),
+        )
+    }
+
+    // 点 p から線分 a-b までの距離
+    fn distance_to_segment(p: &Vector2<f32>, a: &Vector2<f32>, b: &Vector2<f32>) -> f32 {
+        let ab = b - a;
+        let t = ((p - a).dot(&ab) / ab.norm_squared()).clamp(0.0, 1.0);
+        (a + ab * t - p).norm()
+    }
+
+    #[test]
+    fn test_intersect_line_and_line() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut hits = 0;
+        for _ in 0..1000 {
+            let (p00, p01, p10, p11) = (
+                random_vector2(&mut rng),
+                random_vector2(&mut rng),
+                random_vector2(&mut rng),
+                random_vector2(&mut rng),
+            );
+            // 交点は両方の線分の上にある
+            if let Some(p) = intersect_line_and_line(&p00, &p01, &p10, &p11) {
+                hits += 1;
+                assert!(distance_to_segment(&p, &p00, &p01) < EPSILON);
+                assert!(distance_to_segment(&p, &p10, &p11) < EPSILON);
+            }
+        }
+        assert!(hits > 0);
+
+        let (a, b) = (Vector2::new(0.0, 0.0), Vector2::new(1.0, 1.0));
+        let offset = Vector2::new(1.0, 0.0);
+        assert_eq!(
+            intersect_line_and_line(&a, &b, &(a + offset), &(b + offset)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_intersect_rect_with_line() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..1000 {
+            let origin = random_vector2(&mut rng);
+            let size = Vector2::new(rng.gen_range(0.5..5.0), rng.gen_range(0.5..5.0));
+            let (p0, p1) = (random_vector2(&mut rng), random_vector2(&mut rng));
+            let points = intersect_rect_with_line((&origin, &size), &p0, &p1);
+            assert!(points.len() <= 4);
+            // 交点は線分の上かつ長方形の辺の上にある
+            for p in points.iter() {
+                assert!(distance_to_segment(p, &p0, &p1) < EPSILON);
+                let inside = origin.x - EPSILON <= p.x
+                    && p.x <= origin.x + size.x + EPSILON
+                    && origin.y - EPSILON <= p.y
+                    && p.y <= origin.y + size.y + EPSILON;
+                let on_edge = (p.x - origin.x).abs() < EPSILON
+                    || (p.x - origin.x - size.x).abs() < EPSILON
+                    || (p.y - origin.y).abs() < EPSILON
+                    || (p.y - origin.y - size.y).abs() < EPSILON;
+                assert!(inside && on_edge);
+            }
+        }
+    }
+
+    #[test]
+    fn test_segment_aabb_intersection_3d() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let (mut hits, mut misses) = (0, 0);
+        for _ in 0..1000 {
+            let (a, b) = (random_vector3(&mut rng), random_vector3(&mut rng));
+            let (min, max) = (a.inf(&b), a.sup(&b));
+            let (p0, p1) = (random_vector3(&mut rng), random_vector3(&mut rng));
+            // margin だけ広げた直方体に、線分上の位置 t の点が含まれるか
+            let contains = |t: f32, margin: f32| {
+                let p = p0 + (p1 - p0) * t;
+                (0..3).all(|i| min[i] - margin <= p[i] && p[i] <= max[i] + margin)
+            };
+            let samples = (0..=100).map(|i| i as f32 / 100.0);
+            match segment_aabb_intersection_3d(&p0, &p1, &min, &max) {
+                // 区間内の点は全て直方体の中にあり、区間の外の点は中にない
+                Some((t0, t1)) => {
+                    hits += 1;
+                    assert!(0.0 <= t0 && t0 <= t1 && t1 <= 1.0);
+                    for t in samples {
+                        if t0 <= t && t <= t1 {
+                            assert!(contains(t, EPSILON));
+                        } else if t < t0 - EPSILON || t1 + EPSILON < t {
+                            assert!(!contains(t, 0.0));
+                        }
+                    }
+                }
+                None => {
+                    misses += 1;
+                    assert!(samples.into_iter().all(|t| !contains(t, 0.0)));
+                }
+            }
+        }
+        assert!(hits > 0 && misses > 0);
+
+        let (min, max) = (Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 1.0, 1.0));
+        let segment = (Vector3::new(-1.0, 0.5, 0.5), Vector3::new(3.0, 0.5, 0.5));
+        assert_eq!(
+            segment_aabb_intersection_3d(&segment.0, &segment.1, &min, &max),
+            Some((0.25, 0.5))
+        );
+    }
+}
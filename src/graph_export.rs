@@ -0,0 +1,145 @@
+use crate::constants::VoxelType;
+use crate::generate_drd::Dungeon3DGeneratorResult;
+use crate::passage::Passage;
+use crate::room::{Room, RoomId};
+use crate::voxel_map::VoxelMap;
+use nalgebra::Vector3;
+use std::collections::BTreeMap;
+
+// ミニマップや進行の分析向けに、部屋を頂点、通路を辺としたグラフを書き出す
+pub fn export_graph_dot(result: &Dungeon3DGeneratorResult) -> String {
+    let kinds = room_kinds(result);
+    let mut text = String::from("graph dungeon {\n");
+    for room in result.rooms.values() {
+        let center = room.center();
+        text += &format!(
+            "  room{} [label=\"{}\", kind=\"{}\", pos=\"{},{}\", y={}];\n",
+            room.id.inner(),
+            room.id.inner(),
+            kinds[&room.id],
+            center.0,
+            center.2,
+            center.1
+        );
+    }
+    for (passage_id, passage) in result.passages.iter() {
+        text += &format!(
+            "  room{} -- room{} [id={}, length={}];\n",
+            passage.start_room_id.inner(),
+            passage.end_room_id.inner(),
+            passage_id.inner(),
+            passage_length(&result.voxel_map, passage)
+        );
+    }
+    text += "}\n";
+    text
+}
+
+pub fn export_graph_json(result: &Dungeon3DGeneratorResult) -> String {
+    let kinds = room_kinds(result);
+    let nodes = result
+        .rooms
+        .values()
+        .map(|room| {
+            let center = room.center();
+            format!(
+                "{{\"id\":{},\"kind\":\"{}\",\"hierarchy\":{},\"position\":[{},{},{}],\"size\":[{},{},{}]}}",
+                room.id.inner(),
+                kinds[&room.id],
+                room.hierarchy,
+                center.0,
+                center.1,
+                center.2,
+                room.width,
+                room.height,
+                room.depth
+            )
+        })
+        .collect::<Vec<_>>();
+    let edges = result
+        .passages
+        .iter()
+        .map(|(passage_id, passage)| {
+            format!(
+                "{{\"id\":{},\"source\":{},\"target\":{},\"length\":{}}}",
+                passage_id.inner(),
+                passage.start_room_id.inner(),
+                passage.end_room_id.inner(),
+                passage_length(&result.voxel_map, passage)
+            )
+        })
+        .collect::<Vec<_>>();
+    format!(
+        "{{\"nodes\":[{}],\"edges\":[{}]}}",
+        nodes.join(","),
+        edges.join(",")
+    )
+}
+
+// 地表への入口がある部屋、つながる通路が1本の行き止まり、3本以上の広場、それ以外に分ける
+fn room_kinds(result: &Dungeon3DGeneratorResult) -> BTreeMap<RoomId, &'static str> {
+    let mut degrees: BTreeMap<RoomId, usize> = BTreeMap::new();
+    for passage in result.passages.values() {
+        if passage.start_room_id != passage.end_room_id {
+            *degrees.entry(passage.start_room_id).or_default() += 1;
+            *degrees.entry(passage.end_room_id).or_default() += 1;
+        }
+    }
+    let entrance = result
+        .surface_entrance
+        .as_ref()
+        .map(|surface_entrance| surface_entrance.room_id);
+    result
+        .rooms
+        .values()
+        .map(|room: &Room| {
+            let kind = match degrees.get(&room.id).copied().unwrap_or(0) {
+                _ if Some(room.id) == entrance => "entrance",
+                0 | 1 => "dead_end",
+                2 => "room",
+                _ => "hub",
+            };
+            (room.id, kind)
+        })
+        .collect()
+}
+
+// 通路を歩くときに踏むセルの数
+pub(crate) fn passage_length(voxel_map: &VoxelMap, passage: &Passage) -> usize {
+    passage
+        .cells
+        .iter()
+        .filter(|((x, y, z), voxel_type)| match voxel_type {
+            VoxelType::PassageStair(_) => true,
+            VoxelType::PassageSpace | VoxelType::Doorway(_) => {
+                voxel_map.get(&Vector3::new(*x, y - 1, *z)) == VoxelType::PassageFloor
+            }
+            _ => false,
+        })
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generate_drd::{generate_dungeon_3d, Dungeon3DGeneratorConfig};
+    use crate::graph_export::{export_graph_dot, export_graph_json};
+
+    #[test]
+    fn test_export_graph() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        let dot = export_graph_dot(&result);
+        assert!(dot.starts_with("graph dungeon {"));
+        assert_eq!(dot.matches(" -- ").count(), result.passages.len());
+        assert_eq!(dot.matches("[label=").count(), result.rooms.len());
+
+        let json = export_graph_json(&result);
+        assert!(json.starts_with("{\"nodes\":["));
+        assert_eq!(json.matches("\"source\":").count(), result.passages.len());
+        assert_eq!(json.matches("\"hierarchy\":").count(), result.rooms.len());
+        assert!(!json.contains("\"length\":0"));
+    }
+}
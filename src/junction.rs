@@ -0,0 +1,84 @@
+use crate::constants::{VoxelType, DIRECTIONS};
+use crate::passage::{Passage, PassageId};
+use crate::voxel_map::VoxelMap;
+use nalgebra::Vector3;
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Junction {
+    pub position: (i32, i32, i32), // Walkable cell where the passages meet
+    pub connected_passages: Vec<PassageId>, // Passages meeting at the cell
+}
+
+// 複数の通路が共有する歩行可能なセルのうち、通路の組み合わせが変わる地点を交差点とする
+pub fn find_junctions(
+    voxel_map: &VoxelMap,
+    passages: &BTreeMap<PassageId, Passage>,
+) -> Vec<Junction> {
+    let mut owners: BTreeMap<(i32, i32, i32), BTreeSet<PassageId>> = BTreeMap::new();
+    for (passage_id, passage) in passages.iter() {
+        for ((x, y, z), voxel_type) in passage.cells.iter() {
+            if is_walkable(voxel_map, Vector3::new(*x, *y, *z), voxel_type) {
+                owners.entry((*x, *y, *z)).or_default().insert(*passage_id);
+            }
+        }
+    }
+
+    let mut junctions = Vec::new();
+    for ((x, y, z), passage_indices) in owners.iter() {
+        if passage_indices.len() < 2 {
+            continue;
+        }
+        let mut connected = passage_indices.clone();
+        let mut branches = false;
+        for dir in DIRECTIONS.iter() {
+            let v = dir.to_vec3();
+            // 階段で1段上下した先も隣接するセルとして扱う
+            for dy in -1..=1 {
+                if let Some(neighbor) = owners.get(&(x + v.x, y + v.y + dy, z + v.z)) {
+                    branches |= neighbor != passage_indices;
+                    connected.extend(neighbor.iter().copied());
+                }
+            }
+        }
+        if branches {
+            junctions.push(Junction {
+                position: (*x, *y, *z),
+                connected_passages: connected.into_iter().collect(),
+            });
+        }
+    }
+    junctions
+}
+
+fn is_walkable(voxel_map: &VoxelMap, p: Vector3<i32>, voxel_type: &VoxelType) -> bool {
+    match voxel_type {
+        VoxelType::PassageStair(_) => true,
+        VoxelType::PassageSpace | VoxelType::Doorway(_) => {
+            voxel_map.get(&(p - Vector3::y())) == VoxelType::PassageFloor
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generate_drd::{generate_dungeon_3d, Dungeon3DGeneratorConfig};
+
+    #[test]
+    fn test_find_junctions() {
+        for seed in 0..3 {
+            let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+                seed: Some(seed),
+                ..Default::default()
+            })
+            .unwrap();
+            for junction in result.junctions.iter() {
+                assert!(junction.connected_passages.len() >= 2);
+                for passage_id in junction.connected_passages.iter() {
+                    assert!(result.passages.contains_key(passage_id));
+                }
+            }
+        }
+    }
+}
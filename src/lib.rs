@@ -1,14 +1,61 @@
-mod btree_key_values;
+pub mod arena;
+#[cfg(feature = "async")]
+pub mod async_generation;
+pub mod btree_key_values;
+pub mod cave_dungeon;
+pub mod checked;
+pub mod collision;
+#[cfg(feature = "config-serde")]
+pub mod config_file;
+pub mod connectivity;
 pub mod constants;
 pub mod core_expansion_dungeon;
 mod create_start;
+pub mod dead_end;
 pub mod delaunary_3d;
+pub mod density;
 pub mod divided_randomized_dungeon;
+pub mod doorway;
+pub mod encounter;
+#[cfg(feature = "events")]
+pub mod events;
+pub mod furniture;
 pub mod generate_drd;
-mod intersect_line_and_line;
-mod intersect_rect_with_line;
+pub mod generation_report;
+#[cfg(feature = "geometry")]
+pub mod geometry;
+#[cfg(not(feature = "geometry"))]
+#[allow(dead_code)]
+mod geometry;
+pub mod graph_export;
+pub mod junction;
+pub mod lighting;
+pub mod material;
+pub mod nearest_edges;
 pub mod passage;
+pub mod patrol;
+pub mod pipeline;
+pub mod portal;
+pub mod prelude;
+pub mod preset;
+pub mod regenerate;
+pub mod remap;
 pub mod room;
+pub mod room_adjacency;
 pub mod room_candidate_connection;
 pub mod room_connection;
+pub mod room_merge;
+#[cfg(feature = "scripting")]
+pub mod scripting;
+pub mod sound;
+pub mod stair;
+pub mod stitch;
+pub mod tilemap;
+pub mod tools;
+pub mod tower;
+pub mod transform;
+pub mod validation;
 pub mod voxel_map;
+pub mod wfc;
+pub mod world;
+pub mod zoning;
@@ -0,0 +1,135 @@
+use crate::constants::{Direction4, VoxelType, DIRECTIONS};
+use crate::passage::{Passage, PassageId};
+use crate::room::{Room, RoomId};
+use crate::voxel_map::VoxelMap;
+use nalgebra::Vector3;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum LightMount {
+    Wall,    // Hangs on the wall beside the cell
+    Ceiling, // Hangs from the ceiling above the cell
+    Corner,  // Upper corner of a room
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LightSuggestion {
+    pub position: (i32, i32, i32), // Highest free cell under the fixture
+    pub mount: LightMount,
+    pub facing: Option<Direction4>, // Horizontal direction the light shines; None shines downward
+    pub passage_id: Option<PassageId>,
+    pub room_id: Option<RoomId>,
+}
+
+// 通路では spacing 以上離して壁か天井に、部屋では上部の四隅に照明を置く
+// 階段の上や、その真上の空間は天井の高さが揃わないため避ける
+pub fn suggest_lights(
+    rooms: &BTreeMap<RoomId, Room>,
+    voxel_map: &VoxelMap,
+    passages: &BTreeMap<PassageId, Passage>,
+    spacing: u32,
+) -> Vec<LightSuggestion> {
+    let mut lights = Vec::new();
+    for room in rooms.values() {
+        let top = (room.origin.1 + room.height) as i32 - 1;
+        let (x0, z0) = (room.origin.0 as i32, room.origin.2 as i32);
+        let (x1, z1) = (room.end().0 as i32 - 1, room.end().2 as i32 - 1);
+        for (x, z, facing) in [
+            (x0, z0, Direction4::Right),
+            (x1, z0, Direction4::Left),
+            (x0, z1, Direction4::Right),
+            (x1, z1, Direction4::Left),
+        ] {
+            lights.push(LightSuggestion {
+                position: (x, top, z),
+                mount: LightMount::Corner,
+                facing: Some(facing),
+                passage_id: None,
+                room_id: Some(room.id),
+            });
+        }
+    }
+
+    let spacing = spacing.max(1) as i32;
+    let mut lit: Vec<Vector3<i32>> = Vec::new();
+    for (passage_id, passage) in passages.iter() {
+        for ((x, y, z), voxel_type) in passage.cells.iter() {
+            let p = Vector3::new(*x, *y, *z);
+            if *voxel_type != VoxelType::PassageSpace
+                || voxel_map.get(&(p - Vector3::y())) != VoxelType::PassageFloor
+                || lit.iter().any(|q| (q - p).abs().sum() < spacing)
+            {
+                continue;
+            }
+            let top = p + Vector3::new(0, passage.height - 1, 0);
+            if voxel_map.get(&top) != VoxelType::PassageSpace {
+                continue;
+            }
+            let wall = DIRECTIONS
+                .iter()
+                .find(|dir| !voxel_map.get(&(top + dir.to_vec3())).is_passable());
+            let (mount, facing) = match wall {
+                Some(dir) => (LightMount::Wall, Some(dir.opposite())),
+                None if !voxel_map.get(&(top + Vector3::y())).is_passable() => {
+                    (LightMount::Ceiling, None)
+                }
+                None => continue,
+            };
+            lit.push(p);
+            lights.push(LightSuggestion {
+                position: (top.x, top.y, top.z),
+                mount,
+                facing,
+                passage_id: Some(*passage_id),
+                room_id: None,
+            });
+        }
+    }
+    lights
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::VoxelType;
+    use crate::generate_drd::{generate_dungeon_3d, Dungeon3DGeneratorConfig};
+    use crate::lighting::LightMount;
+    use nalgebra::Vector3;
+
+    #[test]
+    fn test_suggest_lights() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        let lights = result.suggest_lights(6);
+        assert_eq!(
+            lights
+                .iter()
+                .filter(|light| light.mount == LightMount::Corner)
+                .count(),
+            result.rooms.len() * 4
+        );
+        let passage_lights = lights
+            .iter()
+            .filter(|light| light.passage_id.is_some())
+            .collect::<Vec<_>>();
+        assert!(!passage_lights.is_empty());
+        for (i, light) in passage_lights.iter().enumerate() {
+            let (x, y, z) = light.position;
+            let p = Vector3::new(x, y, z);
+            assert!(result.voxel_map.get(&p).is_passable());
+            // 階段の上には置かない
+            for dy in 1..=2 {
+                assert!(!matches!(
+                    result.voxel_map.get(&(p - Vector3::new(0, dy, 0))),
+                    VoxelType::PassageStair(_)
+                ));
+            }
+            for other in passage_lights.iter().skip(i + 1) {
+                let (ox, oy, oz) = other.position;
+                assert!((x - ox).abs() + (y - oy).abs() + (z - oz).abs() >= 6);
+            }
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use crate::constants::VoxelType;
+use crate::passage::{passage_owners, Passage, PassageId};
+use crate::room::{Room, RoomId};
+use crate::voxel_map::VoxelMap;
+use nalgebra::Vector3;
+use std::collections::{BTreeMap, HashMap};
+
+pub type MaterialId = u32;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum MaterialRole {
+    RoomFloor,
+    RoomWall,
+    PassageFloor,
+    Stair,
+    CaveFloor,
+    Wall, // Solid voxels without a more specific role, including the unstored rock around passages
+    Custom(u16), // VoxelType::Custom with the same id
+}
+
+impl MaterialRole {
+    // 描画される面を持つボクセルの役割。通れる空間には材質が無い
+    pub fn of(voxel_type: &VoxelType) -> Option<Self> {
+        match voxel_type {
+            VoxelType::RoomFloor(_) => Some(MaterialRole::RoomFloor),
+            VoxelType::RoomWall(_) => Some(MaterialRole::RoomWall),
+            VoxelType::PassageFloor => Some(MaterialRole::PassageFloor),
+            VoxelType::PassageStair(_) => Some(MaterialRole::Stair),
+            VoxelType::CaveFloor => Some(MaterialRole::CaveFloor),
+            VoxelType::Wall => Some(MaterialRole::Wall),
+            VoxelType::Custom(id) => Some(MaterialRole::Custom(*id)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MaterialPalette {
+    pub materials: BTreeMap<MaterialRole, MaterialId>, // Material of each role; roles without an entry use 0
+    pub hierarchy_materials: BTreeMap<(u32, MaterialRole), MaterialId>, // Overrides for rooms and passages of one hierarchy level
+    pub room_materials: BTreeMap<(RoomId, MaterialRole), MaterialId>, // Overrides for themed rooms; take precedence over hierarchy_materials
+}
+
+impl Default for MaterialPalette {
+    fn default() -> Self {
+        MaterialPalette {
+            materials: BTreeMap::from([
+                (MaterialRole::Wall, 0),
+                (MaterialRole::RoomFloor, 1),
+                (MaterialRole::RoomWall, 2),
+                (MaterialRole::PassageFloor, 3),
+                (MaterialRole::Stair, 4),
+                (MaterialRole::CaveFloor, 5),
+            ]),
+            hierarchy_materials: BTreeMap::new(),
+            room_materials: BTreeMap::new(),
+        }
+    }
+}
+
+impl MaterialPalette {
+    // 部屋ごとの指定、階層ごとの指定、既定の順に探す
+    pub fn material(&self, role: MaterialRole, room: Option<&Room>) -> MaterialId {
+        room.and_then(|room| {
+            self.room_materials
+                .get(&(room.id, role))
+                .or_else(|| self.hierarchy_materials.get(&(room.hierarchy, role)))
+        })
+        .or_else(|| self.materials.get(&role))
+        .copied()
+        .unwrap_or(0)
+    }
+}
+
+// 描画される各ボクセルに材質を割り当てる。メッシュの書き出しで材質ごとにサブメッシュを分けるために使う
+// 部屋のボクセルはその部屋、通路のボクセルは通路の始点の部屋の階層やテーマに従う
+// マップに置かれていない岩も、通れる空間に接するものは壁として割り当て、接する空間の部屋に従う
+pub fn assign_materials(
+    voxel_map: &VoxelMap,
+    rooms: &BTreeMap<RoomId, Room>,
+    passages: &BTreeMap<PassageId, Passage>,
+    palette: &MaterialPalette,
+) -> HashMap<Vector3<i32>, MaterialId> {
+    let passage_owners = passage_owners(passages);
+    let owner = |p: &Vector3<i32>, voxel_type: &VoxelType| match voxel_type {
+        VoxelType::Doorway(room_id) => Some(*room_id),
+        _ => voxel_type.room_id().or_else(|| {
+            passage_owners
+                .get(p)
+                .map(|passage_id| passages[passage_id].start_room_id)
+        }),
+    };
+
+    let mut materials = HashMap::new();
+    // 同じ岩が複数の空間に接する場合は、結果が走査順に依らないよう小さい方の部屋を選ぶ
+    let mut rock_owners: HashMap<Vector3<i32>, Option<RoomId>> = HashMap::new();
+    for (p, voxel_type) in voxel_map.map.iter() {
+        let room_id = owner(p, voxel_type);
+        if let Some(role) = MaterialRole::of(voxel_type) {
+            materials.insert(
+                *p,
+                palette.material(role, room_id.and_then(|id| rooms.get(&id))),
+            );
+        }
+        if !voxel_type.is_passable() {
+            continue;
+        }
+        for d in [Vector3::x(), Vector3::y(), Vector3::z()] {
+            for q in [p + d, p - d] {
+                if voxel_map.map.contains_key(&q) {
+                    continue;
+                }
+                rock_owners
+                    .entry(q)
+                    .and_modify(|current| {
+                        *current = match (*current, room_id) {
+                            (Some(a), Some(b)) => Some(a.min(b)),
+                            (a, b) => a.or(b),
+                        }
+                    })
+                    .or_insert(room_id);
+            }
+        }
+    }
+    for (p, room_id) in rock_owners {
+        let room = room_id.and_then(|id| rooms.get(&id));
+        materials.insert(p, palette.material(MaterialRole::Wall, room));
+    }
+    materials
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::VoxelType;
+    use crate::generate_drd::{generate_dungeon_3d, Dungeon3DGeneratorConfig};
+    use crate::material::{assign_materials, MaterialPalette, MaterialRole};
+    use nalgebra::Vector3;
+
+    #[test]
+    fn test_assign_materials() {
+        let mut palette = MaterialPalette::default();
+        palette
+            .hierarchy_materials
+            .insert((1, MaterialRole::RoomFloor), 10);
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            material_palette: palette,
+            ..Default::default()
+        })
+        .unwrap();
+        let palette = MaterialPalette::default();
+        for (p, voxel_type) in result.voxel_map.map.iter() {
+            let material = result.materials.get(p).copied();
+            match voxel_type {
+                VoxelType::RoomFloor(room_id) => {
+                    let expected = if result.rooms[room_id].hierarchy == 1 {
+                        10
+                    } else {
+                        palette.materials[&MaterialRole::RoomFloor]
+                    };
+                    assert_eq!(material, Some(expected));
+                }
+                VoxelType::PassageStair(_) => {
+                    assert_eq!(material, Some(palette.materials[&MaterialRole::Stair]));
+                }
+                _ if voxel_type.is_passable() => assert_eq!(material, None),
+                _ => assert!(material.is_some()),
+            }
+        }
+        // 通路の周りの岩にも壁の材質が付く
+        let walls = result
+            .materials
+            .keys()
+            .filter(|p| !result.voxel_map.map.contains_key(p))
+            .count();
+        assert!(walls > 0);
+        assert!(result.materials.values().any(|material| *material == 10));
+    }
+
+    #[test]
+    fn test_custom_voxels() {
+        let mut result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        // 部屋の床の1つを溶岩にする
+        const LAVA: u16 = 7;
+        let room = result.rooms.values().next().unwrap();
+        let p = Vector3::new(room.origin.0, room.origin.1, room.origin.2).map(|v| v as i32)
+            - Vector3::y();
+        assert_eq!(result.voxel_map.get(&p), VoxelType::RoomFloor(room.id));
+        result.voxel_map.map.insert(p, VoxelType::Custom(LAVA));
+        assert!(!result.voxel_map.get(&p).is_passable());
+        assert_eq!(result.voxel_map.get(&p).room_id(), None);
+
+        let mut palette = MaterialPalette::default();
+        palette.materials.insert(MaterialRole::Custom(LAVA), 42);
+        let materials =
+            assign_materials(&result.voxel_map, &result.rooms, &result.passages, &palette);
+        assert_eq!(materials.get(&p), Some(&42));
+        assert_eq!(
+            MaterialPalette::default().material(MaterialRole::Custom(LAVA), None),
+            0
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use crate::generate_drd::{layout_connections, Dungeon3DGeneratorError};
+use crate::generation_report::GenerationPhase;
+use crate::pipeline::{GenerationState, SelectEdges};
+use crate::room::RoomId;
+use pathfinding::prelude::kruskal;
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Instant;
+
+// 各部屋を近い順に k 個の部屋とつなぐ。Delaunay 分割を使わないため部屋が多い場合に軽い
+// つながらない部屋の組が残る場合は、最も短い辺で補って全体を1つにする
+pub struct NearestNeighborSelectEdges {
+    pub k: u32, // Nearest rooms each room is joined to
+}
+
+impl Default for NearestNeighborSelectEdges {
+    fn default() -> Self {
+        NearestNeighborSelectEdges { k: 3 }
+    }
+}
+
+impl SelectEdges for NearestNeighborSelectEdges {
+    fn select_edges(&self, state: &mut GenerationState<'_>) -> Result<(), Dungeon3DGeneratorError> {
+        let GenerationState {
+            config,
+            report,
+            rooms,
+            room_connections,
+            connections,
+            ..
+        } = state;
+        let phase_start = Instant::now();
+        let mut neighbors: BTreeMap<RoomId, Vec<(u64, RoomId)>> = BTreeMap::new();
+        let mut lengths: BTreeMap<(RoomId, RoomId), u64> = BTreeMap::new();
+        for room_connection in room_connections.iter().filter(|room_connection| {
+            !config.forbids(
+                &rooms[&room_connection.room0_id],
+                &rooms[&room_connection.room1_id],
+            )
+        }) {
+            let (room0_id, room1_id) = (room_connection.room0_id, room_connection.room1_id);
+            let length = room_connection.squared_length as u64;
+            neighbors
+                .entry(room0_id)
+                .or_default()
+                .push((length, room1_id));
+            neighbors
+                .entry(room1_id)
+                .or_default()
+                .push((length, room0_id));
+            lengths.insert((room0_id.min(room1_id), room0_id.max(room1_id)), length);
+        }
+        let mut nearest = BTreeSet::new();
+        for (room_id, candidates) in neighbors.iter_mut() {
+            candidates.sort();
+            for (_, other_id) in candidates.iter().take(self.k as usize) {
+                nearest.insert(((*room_id).min(*other_id), (*room_id).max(*other_id)));
+            }
+        }
+
+        // 近傍の辺を優先した最小全域木を必須の通路にする。近傍の辺以外は部屋の集まりをつなぐ場合にのみ使われる
+        let weighted_edges = lengths
+            .iter()
+            .map(|((room0_id, room1_id), length)| {
+                let penalty = match nearest.contains(&(*room0_id, *room1_id)) {
+                    true => 0,
+                    false => u64::MAX / 2,
+                };
+                (*room0_id, *room1_id, penalty + length)
+            })
+            .collect::<Vec<_>>();
+        let required = kruskal(&weighted_edges)
+            .map(|(room0_id, room1_id, _)| (*room0_id, *room1_id))
+            .collect::<BTreeSet<_>>();
+        let mut selected = required
+            .iter()
+            .map(|(room0_id, room1_id)| (*room0_id, *room1_id, true))
+            .collect::<Vec<_>>();
+        selected.extend(
+            nearest
+                .into_iter()
+                .filter(|pair| !required.contains(pair))
+                .map(|(room0_id, room1_id)| (room0_id, room1_id, false)),
+        );
+        *connections = layout_connections(rooms, selected, config.passage_height);
+        report.record(GenerationPhase::SelectEdges, phase_start);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generate_drd::Dungeon3DGeneratorConfig;
+    use crate::nearest_edges::NearestNeighborSelectEdges;
+    use crate::pipeline::GenerationPipeline;
+    use crate::room::Room;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn test_nearest_neighbor_select_edges() {
+        let pipeline = GenerationPipeline {
+            select_edges: Box::new(NearestNeighborSelectEdges { k: 2 }),
+            ..Default::default()
+        };
+        let layout = pipeline
+            .generate_layout(Dungeon3DGeneratorConfig {
+                seed: Some(0),
+                ..Default::default()
+            })
+            .unwrap();
+        let pairs = layout
+            .connections
+            .values()
+            .map(|connection| {
+                let ids = (connection.start_room.id, connection.end_room.id);
+                (ids.0.min(ids.1), ids.0.max(ids.1))
+            })
+            .collect::<BTreeSet<_>>();
+        assert!(pairs.len() <= layout.rooms.len() * 2);
+        // 必須の辺だけで全ての部屋がつながる木になる
+        let required = layout
+            .connections
+            .values()
+            .filter(|connection| connection.required)
+            .count();
+        assert_eq!(required, layout.rooms.len() - 1);
+        // 各部屋は最も近い部屋とつながる
+        for room in layout.rooms.values() {
+            let center = room.center();
+            let nearest = layout
+                .rooms
+                .values()
+                .filter(|other| other.id != room.id)
+                .min_by(|a, b| {
+                    let distance = |other: &Room| {
+                        let c = other.center();
+                        let d = (c.0 - center.0, c.1 - center.1, c.2 - center.2);
+                        d.0 * d.0 + d.1 * d.1 + d.2 * d.2
+                    };
+                    distance(a).total_cmp(&distance(b))
+                })
+                .unwrap();
+            assert!(pairs.contains(&(room.id.min(nearest.id), room.id.max(nearest.id))));
+        }
+    }
+}
@@ -1,8 +1,34 @@
 use crate::constants::{Direction4, VoxelType};
-use crate::room::RoomId;
-use std::collections::BTreeSet;
+use crate::create_start::{create_start, create_starts};
+use crate::room::{Room, RoomId};
+use nalgebra::Vector3;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
-#[derive(Debug)]
+#[derive(Ord, PartialOrd, PartialEq, Eq, Hash, Copy, Clone, Debug)]
+pub struct PassageId(u64);
+
+impl PassageId {
+    pub fn first() -> Self {
+        PassageId(1)
+    }
+
+    pub fn gen_id(&mut self) -> Self {
+        let ret = *self;
+        self.0 += 1;
+        ret
+    }
+
+    pub fn inner(&self) -> u64 {
+        self.0
+    }
+
+    // 別の生成結果と番号が重ならないようにずらす
+    pub fn shifted(&self, amount: u64) -> Self {
+        PassageId(self.0 + amount)
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Passage {
     pub cells: Vec<((i32, i32, i32), VoxelType)>,
     pub start: (i32, i32, i32),
@@ -10,4 +36,83 @@ pub struct Passage {
     pub start_room_id: RoomId,
     pub end_room_id: RoomId,
     pub height: i32,
+    pub attempt: usize, // Index of the start candidate which reached the end room
+}
+
+impl Passage {
+    pub fn new(room0: &Room, room1: &Room, height: i32) -> Self {
+        let (start_room_id, end_room_id, start, dirs) = create_start(room0, room1);
+        Passage {
+            cells: Vec::new(),
+            start: (start.x, start.y, start.z),
+            start_dirs: dirs,
+            start_room_id,
+            end_room_id,
+            height,
+            attempt: 0,
+        }
+    }
+
+    // 開始地点の候補ごとの通路。先頭は Passage::new と同じ
+    pub fn candidates(room0: &Room, room1: &Room, height: i32) -> Vec<Self> {
+        create_starts(room0, room1)
+            .into_iter()
+            .enumerate()
+            .map(
+                |(attempt, (start_room_id, end_room_id, start, dirs))| Passage {
+                    cells: Vec::new(),
+                    start: (start.x, start.y, start.z),
+                    start_dirs: dirs,
+                    start_room_id,
+                    end_room_id,
+                    height,
+                    attempt,
+                },
+            )
+            .collect()
+    }
+}
+
+// 通路のボクセルごとに、そのボクセルを掘った通路の番号を求める
+// 複数の通路が共有するボクセルは、先に掘られた番号の小さい通路のものとする
+pub fn passage_owners(passages: &BTreeMap<PassageId, Passage>) -> HashMap<Vector3<i32>, PassageId> {
+    let mut owners = HashMap::new();
+    for (passage_id, passage) in passages.iter() {
+        for ((x, y, z), _) in passage.cells.iter() {
+            owners
+                .entry(Vector3::new(*x, *y, *z))
+                .or_insert(*passage_id);
+        }
+    }
+    owners
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generate_drd::{generate_dungeon_3d, Dungeon3DGeneratorConfig};
+
+    #[test]
+    fn test_passage_map() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        let passage_map = result.passage_map();
+        for (p, voxel_type) in result.voxel_map.map.iter() {
+            if voxel_type.room_id().is_none() && voxel_type.is_passable() {
+                assert!(passage_map.contains_key(p));
+            }
+        }
+        // 各通路は少なくとも1つのボクセルの持ち主になり、持ち主の通路はそのボクセルを含む
+        for passage_id in result.passages.keys() {
+            assert!(passage_map.values().any(|owner| owner == passage_id));
+        }
+        for (p, passage_id) in passage_map.iter() {
+            assert!(result.passages[passage_id]
+                .cells
+                .iter()
+                .any(|(q, _)| *q == (p.x, p.y, p.z)));
+        }
+    }
 }
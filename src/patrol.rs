@@ -0,0 +1,223 @@
+use crate::btree_key_values::BTreeKeyValues;
+use crate::constants::{VoxelType, DIRECTIONS};
+use crate::generate_drd::Dungeon3DGeneratorResult;
+use crate::room::RoomId;
+use nalgebra::Vector3;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::RangeInclusive;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PatrolKind {
+    RoomPerimeter(RoomId), // Along the walls of the room
+    Corridor,              // Through passages, crossing rooms where the loop needs to
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct PatrolRoute {
+    pub kind: PatrolKind,
+    pub cells: Vec<(i32, i32, i32)>, // Walkable cells in walking order; the last cell is next to the first
+}
+
+// 部屋の内側を通る1歩の重み。通路と部屋の壁沿いは 1
+const ROOM_INTERIOR_COST: u32 = 3;
+const ATTEMPTS_PER_ROUTE: usize = 8;
+
+// 歩けるセルを巡って元の位置に戻る巡回経路を最大 count 本提案する。長さはセルの数で length_range に収める
+// 部屋の壁沿いを一周する経路と、通路の1歩を含む最も軽い閉路から選ぶ。部屋の内側は重く数えて避ける
+pub fn generate_patrol_routes<R: Rng>(
+    result: &Dungeon3DGeneratorResult,
+    count: usize,
+    length_range: RangeInclusive<usize>,
+    rng: &mut R,
+) -> Vec<PatrolRoute> {
+    let walkable = walkable_cells(result);
+    let mut routes = Vec::new();
+    for room in result.rooms.values() {
+        let (x0, y, z0) = room.origin;
+        let (x1, _, z1) = room.end();
+        if room.width < 2 || room.depth < 2 {
+            continue;
+        }
+        let (x0, y, z0, x1, z1) = (x0 as i32, y as i32, z0 as i32, x1 as i32 - 1, z1 as i32 - 1);
+        let cells = (x0..x1)
+            .map(|x| (x, y, z0))
+            .chain((z0..z1).map(|z| (x1, y, z)))
+            .chain((x0 + 1..=x1).rev().map(|x| (x, y, z1)))
+            .chain((z0 + 1..=z1).rev().map(|z| (x0, y, z)))
+            .collect::<Vec<_>>();
+        if length_range.contains(&cells.len()) && cells.iter().all(|p| walkable.contains_key(p)) {
+            routes.push(PatrolRoute {
+                kind: PatrolKind::RoomPerimeter(room.id),
+                cells,
+            });
+        }
+    }
+
+    let mut edges = Vec::new();
+    for (p, _) in walkable.iter().filter(|(_, cell)| cell.passage) {
+        for q in neighbors(&walkable, p) {
+            if walkable[&q].passage && *p < q {
+                edges.push((*p, q));
+            }
+        }
+    }
+    edges.shuffle(rng);
+    let mut seen = BTreeSet::new();
+    let mut corridors = 0;
+    for (a, b) in edges.into_iter().take(count * ATTEMPTS_PER_ROUTE) {
+        if corridors >= count {
+            break;
+        }
+        let Some(cells) = find_cycle(&walkable, a, b, *length_range.end()) else {
+            continue;
+        };
+        if length_range.contains(&cells.len())
+            && seen.insert(cells.iter().copied().collect::<BTreeSet<_>>())
+        {
+            corridors += 1;
+            routes.push(PatrolRoute {
+                kind: PatrolKind::Corridor,
+                cells,
+            });
+        }
+    }
+    routes.shuffle(rng);
+    routes.truncate(count);
+    routes
+}
+
+struct WalkableCell {
+    passage: bool,
+    cost: u32,
+}
+
+// 通路の床の上のセルと部屋の床の上のセル。部屋の壁沿いでないセルは重くする
+fn walkable_cells(result: &Dungeon3DGeneratorResult) -> BTreeMap<(i32, i32, i32), WalkableCell> {
+    let voxel_map = &result.voxel_map;
+    let mut walkable = BTreeMap::new();
+    for (p, voxel_type) in voxel_map.map.iter() {
+        let passage = match voxel_type {
+            VoxelType::RoomBottomSpace(_) => false,
+            VoxelType::PassageStair(_) => true,
+            VoxelType::PassageSpace | VoxelType::Doorway(_)
+                if voxel_map.get(&(p - Vector3::y())) == VoxelType::PassageFloor =>
+            {
+                true
+            }
+            _ => continue,
+        };
+        let interior = !passage
+            && DIRECTIONS.iter().all(|dir| {
+                matches!(
+                    voxel_map.get(&(p + dir.to_vec3())),
+                    VoxelType::RoomBottomSpace(_)
+                )
+            });
+        walkable.insert(
+            (p.x, p.y, p.z),
+            WalkableCell {
+                passage,
+                cost: if interior { ROOM_INTERIOR_COST } else { 1 },
+            },
+        );
+    }
+    walkable
+}
+
+// 階段で1段上下した先も隣接するセルとして扱う
+fn neighbors(
+    walkable: &BTreeMap<(i32, i32, i32), WalkableCell>,
+    (x, y, z): &(i32, i32, i32),
+) -> Vec<(i32, i32, i32)> {
+    let mut result = Vec::new();
+    for dir in DIRECTIONS.iter() {
+        let v = dir.to_vec3();
+        for dy in -1..=1 {
+            let p = (x + v.x, y + dy, z + v.z);
+            if walkable.contains_key(&p) {
+                result.push(p);
+            }
+        }
+    }
+    result
+}
+
+// a から b への1歩を使わずに b から a へ戻る最も軽い経路を探し、a から始まる閉路にする
+// 重みが max_length の ROOM_INTERIOR_COST 倍を超えたら諦める
+fn find_cycle(
+    walkable: &BTreeMap<(i32, i32, i32), WalkableCell>,
+    a: (i32, i32, i32),
+    b: (i32, i32, i32),
+    max_length: usize,
+) -> Option<Vec<(i32, i32, i32)>> {
+    let max_cost = max_length as u32 * ROOM_INTERIOR_COST;
+    let mut parents = BTreeMap::from([(b, b)]);
+    let mut costs = BTreeMap::from([(b, 0)]);
+    let mut queue = BTreeKeyValues::default();
+    queue.push_back(0, b);
+    while let Some(p) = queue.pop_first() {
+        if p == a {
+            break;
+        }
+        let cost = costs[&p];
+        for q in neighbors(walkable, &p) {
+            if p == b && q == a {
+                continue;
+            }
+            let next_cost = cost + walkable[&q].cost;
+            if next_cost > max_cost || costs.get(&q).is_some_and(|c| *c <= next_cost) {
+                continue;
+            }
+            costs.insert(q, next_cost);
+            parents.insert(q, p);
+            queue.push_back(next_cost, q);
+        }
+    }
+    parents.get(&a)?;
+    let mut cells = vec![a];
+    let mut current = parents[&a];
+    while current != b {
+        cells.push(current);
+        current = parents[&current];
+    }
+    cells.push(b);
+    Some(cells)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generate_drd::{generate_dungeon_3d, Dungeon3DGeneratorConfig};
+    use crate::patrol::{generate_patrol_routes, PatrolKind};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn test_generate_patrol_routes() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            loop_probability: 1.0,
+            ..Default::default()
+        })
+        .unwrap();
+        let routes = generate_patrol_routes(&result, 6, 12..=200, &mut StdRng::seed_from_u64(0));
+        assert_eq!(routes.len(), 6);
+        assert!(routes
+            .iter()
+            .any(|route| route.kind == PatrolKind::Corridor));
+        for route in routes.iter() {
+            assert!((12..=200).contains(&route.cells.len()));
+            let cells = route.cells.iter().collect::<BTreeSet<_>>();
+            assert_eq!(cells.len(), route.cells.len());
+            for (i, (x, y, z)) in route.cells.iter().enumerate() {
+                let (nx, ny, nz) = route.cells[(i + 1) % route.cells.len()];
+                assert_eq!((nx - x).abs() + (nz - z).abs(), 1);
+                assert!((ny - y).abs() <= 1);
+                let voxel_type = result.voxel_map.get(&nalgebra::Vector3::new(*x, *y, *z));
+                assert!(voxel_type.is_passable());
+            }
+        }
+    }
+}
@@ -0,0 +1,420 @@
+use crate::connectivity::ConnectivityRepair;
+use crate::dead_end::DeadEnd;
+use crate::doorway::Doorway;
+#[cfg(feature = "events")]
+use crate::events::{
+    record_edges, record_passages, record_rooms, record_voxel_changes, GenerationEvent,
+};
+use crate::generate_drd::{
+    build_graph, carve_passages, place_rooms, post_process, select_edges, BlockSpan,
+    Dungeon3DGeneratorConfig, Dungeon3DGeneratorError, Dungeon3DGeneratorResult, DungeonLayout,
+    FailedConnection, LayoutConnection, SurfaceEntrance, UnmetHierarchyBoundary,
+};
+use crate::generation_report::GenerationReport;
+use crate::junction::Junction;
+use crate::material::MaterialId;
+use crate::passage::{Passage, PassageId};
+use crate::room::{Room, RoomId};
+use crate::room_connection::{ConnectionId, RoomConnection};
+use crate::stair::Stair;
+use crate::voxel_map::{RoomAvoidance, RouteStats, VoxelMap, VoxelMapError};
+use nalgebra::Vector3;
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+use std::collections::{BTreeMap, HashMap};
+
+// 生成の途中経過。各段階はこれを読み書きして次の段階へ引き継ぐ
+pub struct GenerationState<'a> {
+    pub config: Dungeon3DGeneratorConfig,
+    pub rng: &'a mut dyn RngCore,
+    pub report: GenerationReport,
+    pub rooms: BTreeMap<RoomId, Room>,
+    pub voxel_map: VoxelMap, // Bounds and surface are set up front; rooms are added by PlaceRooms
+    pub room_connections: Vec<RoomConnection>, // Candidate edges between every pair of rooms
+    pub connections: BTreeMap<ConnectionId, LayoutConnection>, // Selected edges; required ones come first
+    pub failed_connections: Vec<(ConnectionId, VoxelMapError)>, // Selected edges CarvePassages could not carve, with the error of the last attempt
+    pub passages: BTreeMap<PassageId, Passage>,
+    pub repairs: Vec<ConnectivityRepair>,
+    pub objective_passages: Vec<(RoomId, RoomId, PassageId)>,
+    pub unmet_hierarchy_boundaries: Vec<UnmetHierarchyBoundary>,
+    pub under_connected_rooms: BTreeMap<RoomId, usize>,
+    pub surface_entrance: Option<SurfaceEntrance>,
+    pub doorways: Vec<Doorway>,
+    pub junctions: Vec<Junction>,
+    pub dead_ends: Vec<DeadEnd>,
+    pub stairs: Vec<Stair>,
+    pub stairwell: Vec<RoomId>,       // Set by TowerPlaceRooms
+    pub central_room: Option<RoomId>, // Set by ArenaPlaceRooms
+    pub waypoint_rooms: Vec<RoomId>,  // Added to rooms by DefaultSelectEdges
+    pub room_extensions: BTreeMap<RoomId, Vec<Room>>, // Set by DefaultPlaceRooms when merge_adjacent_rooms is enabled
+    pub materials: HashMap<Vector3<i32>, MaterialId>,
+    pub route_stats: RouteStats,
+    pub(crate) room_spans: BTreeMap<RoomId, (BlockSpan, BlockSpan)>, // room_id -> (x_span, z_span)
+    #[cfg(feature = "events")]
+    pub events: Vec<GenerationEvent>, // Pushed by the default stages as they run; the pipeline fills in what replaced stages leave out
+}
+
+impl<'a> GenerationState<'a> {
+    // 乱数は呼び出し側から受け取るため、config.seed は使わない
+    pub fn new(config: Dungeon3DGeneratorConfig, rng: &'a mut dyn RngCore) -> Self {
+        let mut voxel_map = VoxelMap::new(
+            -(config.margin_for_bounds as i32),
+            -(config.margin_for_bounds as i32),
+            -(config.margin_for_bounds as i32),
+            (config.width + config.margin_for_bounds) as i32,
+            (config.height + config.margin_for_bounds) as i32,
+            (config.depth + config.margin_for_bounds) as i32,
+        );
+        if let Some(surface_height) = config.surface_height.as_ref() {
+            voxel_map.set_surface(|x, z| surface_height(x, z));
+        }
+        if let Some(passage_step_cost) = config.passage_step_cost.as_ref() {
+            let passage_step_cost = passage_step_cost.clone();
+            voxel_map.set_step_cost(move |x, y, z| passage_step_cost(x, y, z));
+        }
+        voxel_map.set_room_avoidance(RoomAvoidance {
+            forbid_foreign_rooms: config.avoid_foreign_rooms,
+            foreign_room_penalty: i32::try_from(config.foreign_room_penalty).unwrap_or(i32::MAX),
+        });
+        voxel_map.set_stair_run(config.stair_run);
+        voxel_map.set_stair_clearance(config.stair_clearance);
+        voxel_map.set_guided_search(config.guided_route_search);
+        voxel_map.set_max_route_nodes(config.max_route_nodes);
+        GenerationState {
+            config,
+            rng,
+            report: GenerationReport::default(),
+            rooms: BTreeMap::new(),
+            voxel_map,
+            room_connections: Vec::new(),
+            connections: BTreeMap::new(),
+            failed_connections: Vec::new(),
+            passages: BTreeMap::new(),
+            repairs: Vec::new(),
+            objective_passages: Vec::new(),
+            unmet_hierarchy_boundaries: Vec::new(),
+            under_connected_rooms: BTreeMap::new(),
+            surface_entrance: None,
+            doorways: Vec::new(),
+            junctions: Vec::new(),
+            dead_ends: Vec::new(),
+            stairs: Vec::new(),
+            stairwell: Vec::new(),
+            central_room: None,
+            waypoint_rooms: Vec::new(),
+            room_extensions: BTreeMap::new(),
+            materials: HashMap::new(),
+            route_stats: RouteStats::default(),
+            room_spans: BTreeMap::new(),
+            #[cfg(feature = "events")]
+            events: Vec::new(),
+        }
+    }
+
+    pub fn into_layout(self) -> DungeonLayout {
+        DungeonLayout {
+            rooms: self.rooms,
+            voxel_map: self.voxel_map,
+            connections: self.connections,
+        }
+    }
+
+    pub fn into_result(mut self) -> Dungeon3DGeneratorResult {
+        self.report.explored_route_nodes = self.route_stats.explored_nodes;
+        self.report.explored_guide_nodes = self.route_stats.guide_nodes;
+        for connection in self.connections.values() {
+            let (center0, center1) = (connection.start_room.center(), connection.end_room.center());
+            let length = Vector3::new(
+                center1.0 - center0.0,
+                center1.1 - center0.1,
+                center1.2 - center0.2,
+            )
+            .norm();
+            *self
+                .report
+                .connection_lengths
+                .entry(length as u32)
+                .or_default() += 1;
+        }
+        let room_connections = self
+            .connections
+            .values()
+            .map(LayoutConnection::room_connection)
+            .collect();
+        let failed_connections = self
+            .failed_connections
+            .into_iter()
+            .filter_map(|(connection_id, reason)| {
+                let connection = self.connections.get(&connection_id)?;
+                Some(FailedConnection {
+                    connection: connection.room_connection(),
+                    required: connection.required,
+                    reason,
+                })
+            })
+            .collect();
+        Dungeon3DGeneratorResult {
+            rooms: self.rooms,
+            voxel_map: self.voxel_map,
+            passages: self.passages,
+            room_connections,
+            failed_connections,
+            report: self.config.report.then_some(self.report),
+            repairs: self.repairs,
+            objective_passages: self.objective_passages,
+            unmet_hierarchy_boundaries: self.unmet_hierarchy_boundaries,
+            under_connected_rooms: self.under_connected_rooms,
+            surface_entrance: self.surface_entrance,
+            doorways: self.doorways,
+            junctions: self.junctions,
+            dead_ends: self.dead_ends,
+            stairs: self.stairs,
+            stairwell: self.stairwell,
+            central_room: self.central_room,
+            waypoint_rooms: self.waypoint_rooms,
+            room_extensions: self.room_extensions,
+            #[cfg(feature = "events")]
+            events: self.events,
+            materials: self.materials,
+        }
+    }
+}
+
+pub trait PlaceRooms {
+    fn place_rooms(&self, state: &mut GenerationState<'_>) -> Result<(), Dungeon3DGeneratorError>;
+}
+
+pub trait BuildGraph {
+    fn build_graph(&self, state: &mut GenerationState<'_>) -> Result<(), Dungeon3DGeneratorError>;
+}
+
+pub trait SelectEdges {
+    fn select_edges(&self, state: &mut GenerationState<'_>) -> Result<(), Dungeon3DGeneratorError>;
+}
+
+pub trait CarvePassages {
+    fn carve_passages(
+        &self,
+        state: &mut GenerationState<'_>,
+    ) -> Result<(), Dungeon3DGeneratorError>;
+}
+
+pub trait PostProcess {
+    fn post_process(&self, state: &mut GenerationState<'_>) -> Result<(), Dungeon3DGeneratorError>;
+}
+
+// 区画ごとに部屋を配置してボクセルへ変換する
+pub struct DefaultPlaceRooms;
+
+impl PlaceRooms for DefaultPlaceRooms {
+    fn place_rooms(&self, state: &mut GenerationState<'_>) -> Result<(), Dungeon3DGeneratorError> {
+        place_rooms(state)
+    }
+}
+
+// 全ての部屋の組を候補の辺とする
+pub struct DefaultBuildGraph;
+
+impl BuildGraph for DefaultBuildGraph {
+    fn build_graph(&self, state: &mut GenerationState<'_>) -> Result<(), Dungeon3DGeneratorError> {
+        build_graph(state)
+    }
+}
+
+// 最小全域木と Delaunay の辺の一部を選ぶ
+pub struct DefaultSelectEdges;
+
+impl SelectEdges for DefaultSelectEdges {
+    fn select_edges(&self, state: &mut GenerationState<'_>) -> Result<(), Dungeon3DGeneratorError> {
+        select_edges(state)
+    }
+}
+
+// 選ばれた辺の通路を掘り、接続性を修復する
+pub struct DefaultCarvePassages;
+
+impl CarvePassages for DefaultCarvePassages {
+    fn carve_passages(
+        &self,
+        state: &mut GenerationState<'_>,
+    ) -> Result<(), Dungeon3DGeneratorError> {
+        carve_passages(state)
+    }
+}
+
+// 通路の壁の凹凸、行き止まり、出入口、交差点を処理する
+pub struct DefaultPostProcess;
+
+impl PostProcess for DefaultPostProcess {
+    fn post_process(&self, state: &mut GenerationState<'_>) -> Result<(), Dungeon3DGeneratorError> {
+        post_process(state)
+    }
+}
+
+// 1つの段階だけを差し替えられるように、各段階を順に実行する
+pub struct GenerationPipeline {
+    pub place_rooms: Box<dyn PlaceRooms>,
+    pub build_graph: Box<dyn BuildGraph>,
+    pub select_edges: Box<dyn SelectEdges>,
+    pub carve_passages: Box<dyn CarvePassages>,
+    pub post_process: Box<dyn PostProcess>,
+}
+
+impl Default for GenerationPipeline {
+    fn default() -> Self {
+        GenerationPipeline {
+            place_rooms: Box::new(DefaultPlaceRooms),
+            build_graph: Box::new(DefaultBuildGraph),
+            select_edges: Box::new(DefaultSelectEdges),
+            carve_passages: Box::new(DefaultCarvePassages),
+            post_process: Box::new(DefaultPostProcess),
+        }
+    }
+}
+
+impl GenerationPipeline {
+    pub fn generate(
+        &self,
+        config: Dungeon3DGeneratorConfig,
+    ) -> Result<Dungeon3DGeneratorResult, Dungeon3DGeneratorError> {
+        let mut rng = seeded_rng(config.seed);
+        self.generate_with_rng(config, &mut rng)
+    }
+
+    pub fn generate_with_rng(
+        &self,
+        config: Dungeon3DGeneratorConfig,
+        rng: &mut dyn RngCore,
+    ) -> Result<Dungeon3DGeneratorResult, Dungeon3DGeneratorError> {
+        self.run(config, rng).map(GenerationState::into_result)
+    }
+
+    pub fn generate_layout(
+        &self,
+        config: Dungeon3DGeneratorConfig,
+    ) -> Result<DungeonLayout, Dungeon3DGeneratorError> {
+        let mut rng = seeded_rng(config.seed);
+        self.run_layout(config, &mut rng)
+            .map(GenerationState::into_layout)
+    }
+
+    pub fn run<'a>(
+        &self,
+        config: Dungeon3DGeneratorConfig,
+        rng: &'a mut dyn RngCore,
+    ) -> Result<GenerationState<'a>, Dungeon3DGeneratorError> {
+        let state = self.run_layout(config, rng)?;
+        self.run_passages(state)
+    }
+
+    // run_layout の後の、通路を掘って仕上げる段階を実行する
+    pub fn run_passages<'a>(
+        &self,
+        mut state: GenerationState<'a>,
+    ) -> Result<GenerationState<'a>, Dungeon3DGeneratorError> {
+        self.carve_passages.carve_passages(&mut state)?;
+        #[cfg(feature = "events")]
+        record_passages(&mut state);
+        #[cfg(feature = "events")]
+        let before = state.voxel_map.map.clone();
+        self.post_process.post_process(&mut state)?;
+        #[cfg(feature = "events")]
+        record_voxel_changes(&mut state, &before);
+        Ok(state)
+    }
+
+    // 通路を掘る前の段階までを実行する
+    pub fn run_layout<'a>(
+        &self,
+        config: Dungeon3DGeneratorConfig,
+        rng: &'a mut dyn RngCore,
+    ) -> Result<GenerationState<'a>, Dungeon3DGeneratorError> {
+        let mut state = GenerationState::new(config, rng);
+        self.place_rooms.place_rooms(&mut state)?;
+        #[cfg(feature = "events")]
+        record_rooms(&mut state);
+        self.build_graph.build_graph(&mut state)?;
+        self.select_edges.select_edges(&mut state)?;
+        #[cfg(feature = "events")]
+        {
+            record_rooms(&mut state);
+            record_edges(&mut state);
+        }
+        Ok(state)
+    }
+}
+
+pub(crate) fn seeded_rng(seed: Option<u64>) -> StdRng {
+    seed.map(SeedableRng::seed_from_u64)
+        .unwrap_or_else(StdRng::from_entropy)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generate_drd::{
+        generate_dungeon_3d, Dungeon3DGeneratorConfig, Dungeon3DGeneratorError, LayoutConnection,
+    };
+    use crate::passage::Passage;
+    use crate::pipeline::{GenerationPipeline, GenerationState, SelectEdges};
+    use crate::room_connection::ConnectionId;
+
+    #[test]
+    fn test_default_pipeline_matches_generate_dungeon_3d() {
+        let config = || Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        };
+        let result = GenerationPipeline::default().generate(config()).unwrap();
+        let expected = generate_dungeon_3d(config()).unwrap();
+        assert_eq!(result.to_canonical_string(), expected.to_canonical_string());
+    }
+
+    #[test]
+    fn test_replace_select_edges() {
+        // 部屋を番号順に一列につなぐ
+        struct ChainEdges;
+        impl SelectEdges for ChainEdges {
+            fn select_edges(
+                &self,
+                state: &mut GenerationState,
+            ) -> Result<(), Dungeon3DGeneratorError> {
+                let mut connection_id = ConnectionId::first();
+                let rooms = state.rooms.values().collect::<Vec<_>>();
+                for pair in rooms.windows(2) {
+                    let passage =
+                        Passage::new(pair[0], pair[1], state.config.passage_height as i32);
+                    let connection = LayoutConnection {
+                        start_room: state.rooms[&passage.start_room_id].clone(),
+                        end_room: state.rooms[&passage.end_room_id].clone(),
+                        passage,
+                        required: true,
+                    };
+                    state.connections.insert(connection_id.gen_id(), connection);
+                }
+                Ok(())
+            }
+        }
+        let pipeline = GenerationPipeline {
+            select_edges: Box::new(ChainEdges),
+            ..Default::default()
+        };
+        let result = pipeline
+            .generate(Dungeon3DGeneratorConfig {
+                seed: Some(0),
+                ..Default::default()
+            })
+            .unwrap();
+        // 修復で追加された通路以外は全て隣り合う番号の部屋をつなぐ
+        for (passage_id, passage) in result.passages.iter() {
+            if result
+                .repairs
+                .iter()
+                .any(|repair| repair.passage_id == *passage_id)
+            {
+                continue;
+            }
+            let (id0, id1) = (passage.start_room_id.inner(), passage.end_room_id.inner());
+            assert_eq!(id0.abs_diff(id1), 1);
+        }
+    }
+}
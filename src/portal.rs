@@ -0,0 +1,102 @@
+use crate::constants::{Direction4, DIRECTIONS};
+use crate::doorway::Doorway;
+use crate::passage::PassageId;
+use crate::room::RoomId;
+use crate::voxel_map::VoxelMap;
+use nalgebra::Vector3;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Portal {
+    pub room_id: RoomId,
+    pub passage_id: PassageId,
+    pub facing: Direction4,   // Direction from the passage into the room
+    pub min: (i32, i32, i32), // Corner of the opening with the smallest coordinates
+    pub max: (i32, i32, i32), // Opposite corner; equal to min along the facing axis
+}
+
+impl Portal {
+    // 開口部の四隅を、床の2点、天井の2点の順で返す
+    pub fn corners(&self) -> [(i32, i32, i32); 4] {
+        let (x0, y0, z0) = self.min;
+        let (x1, y1, z1) = self.max;
+        match self.facing {
+            Direction4::Left | Direction4::Right => {
+                [(x0, y0, z0), (x0, y0, z1), (x0, y1, z1), (x0, y1, z0)]
+            }
+            Direction4::Far | Direction4::Near => {
+                [(x0, y0, z0), (x1, y0, z0), (x1, y1, z0), (x0, y1, z0)]
+            }
+        }
+    }
+}
+
+// 可視判定のため、出入口ごとに部屋と通路の境界面にある開口部の長方形を求める
+// 開口部の高さは、通路と部屋の両方の空間が続く高さまでとする
+pub fn find_portals(voxel_map: &VoxelMap, doorways: &[Doorway]) -> Vec<Portal> {
+    let mut portals = Vec::new();
+    for doorway in doorways.iter() {
+        let (x, y, z) = doorway.position;
+        let p = Vector3::new(x, y, z);
+        for dir in DIRECTIONS.iter() {
+            let q = p + dir.to_vec3();
+            let opening = (0..)
+                .take_while(|dy| {
+                    let up = Vector3::new(0, *dy, 0);
+                    voxel_map.get(&(p + up)).is_passable()
+                        && voxel_map.get(&(q + up)).room_id() == Some(doorway.room_id)
+                })
+                .count() as i32;
+            if opening == 0 {
+                continue;
+            }
+            // ボクセル p は [p, p + 1) を占めるので、境界面は向きによって p か p + 1 にある
+            let face = p + dir.to_vec3().map(|v| v.max(0));
+            let (min, max) = match dir {
+                Direction4::Left | Direction4::Right => {
+                    ((face.x, y, z), (face.x, y + opening, z + 1))
+                }
+                Direction4::Far | Direction4::Near => {
+                    ((x, y, face.z), (x + 1, y + opening, face.z))
+                }
+            };
+            portals.push(Portal {
+                room_id: doorway.room_id,
+                passage_id: doorway.passage_id,
+                facing: *dir,
+                min,
+                max,
+            });
+        }
+    }
+    portals
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generate_drd::{generate_dungeon_3d, Dungeon3DGeneratorConfig};
+    use crate::portal::find_portals;
+
+    #[test]
+    fn test_find_portals() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        let portals = find_portals(&result.voxel_map, &result.doorways);
+        assert!(portals.len() >= result.doorways.len());
+        for portal in portals.iter() {
+            let room = &result.rooms[&portal.room_id];
+            let corners = portal.corners();
+            // 四隅は全て部屋の外面の上にある
+            for (x, y, z) in corners {
+                assert!(room.origin.0 as i32 <= x && x <= room.end().0 as i32);
+                assert!(room.origin.2 as i32 <= z && z <= room.end().2 as i32);
+                assert!(room.origin.1 as i32 <= y && y <= (room.origin.1 + room.height) as i32);
+            }
+            assert!(portal.max.1 > portal.min.1);
+            assert_eq!(corners[0], portal.min);
+            assert_eq!(corners[2], portal.max);
+        }
+    }
+}
@@ -0,0 +1,19 @@
+// よく使う型と関数をまとめて use できるようにする
+pub use crate::arena::generate_arena;
+#[cfg(feature = "async")]
+pub use crate::async_generation::generate_dungeon_3d_async;
+pub use crate::checked::generate_dungeon_3d_checked;
+pub use crate::constants::{Direction4, VoxelType};
+pub use crate::core_expansion_dungeon::{
+    generate_ced, CEDConfig, CEDError, CEDResult, CEDRoomCandidate, CEDSeedRoom,
+};
+pub use crate::generate_drd::{
+    generate_dungeon_3d, generate_dungeon_3d_with_rng, Dungeon3DGeneratorConfig,
+    Dungeon3DGeneratorError, Dungeon3DGeneratorResult,
+};
+pub use crate::passage::{Passage, PassageId};
+pub use crate::preset::Preset;
+pub use crate::room::{Room, RoomId};
+pub use crate::tower::generate_tower;
+pub use crate::voxel_map::VoxelMap;
+pub use crate::world::{AxisConvention, WorldAabb, WorldTransform};
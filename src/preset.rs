@@ -0,0 +1,103 @@
+use crate::generate_drd::Dungeon3DGeneratorConfig;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Preset {
+    Catacombs, // Many small low rooms joined by narrow, rough and frequently looping corridors
+    Caverns,   // Few large tall rooms with wide, heavily roughened passages
+    Fortress,  // Regular rooms packed close together, joined by doors where walls touch
+    Tower,     // Narrow footprint stacked over many levels; pairs with generate_tower
+}
+
+pub const PRESETS: [Preset; 4] = [
+    Preset::Catacombs,
+    Preset::Caverns,
+    Preset::Fortress,
+    Preset::Tower,
+];
+
+impl Dungeon3DGeneratorConfig {
+    // 調整済みの設定。seed などは返り値を書き換えて指定する
+    pub fn preset(preset: Preset) -> Self {
+        match preset {
+            Preset::Catacombs => Dungeon3DGeneratorConfig {
+                width: 32,
+                height: 8,
+                depth: 32,
+                room_hierarchy: 2,
+                room_width_range: 3..=5,
+                room_height_range: 2..=2,
+                room_depth_range: 3..=5,
+                room_margin_x: 3,
+                room_margin_z: 3,
+                passage_wall_noise: 0.15,
+                loop_probability: 0.5,
+                ..Default::default()
+            },
+            Preset::Caverns => Dungeon3DGeneratorConfig {
+                width: 40,
+                height: 14,
+                depth: 40,
+                room_hierarchy: 2,
+                room_width_range: 8..=14,
+                room_height_range: 3..=5,
+                room_depth_range: 8..=14,
+                room_margin_y: 2,
+                passage_height: 3,
+                passage_wall_noise: 0.4,
+                loop_probability: 0.4,
+                hub_min_degree: 3,
+                ..Default::default()
+            },
+            Preset::Fortress => Dungeon3DGeneratorConfig {
+                width: 40,
+                height: 10,
+                depth: 40,
+                room_hierarchy: 2,
+                room_width_range: 6..=10,
+                room_height_range: 3..=3,
+                room_depth_range: 6..=10,
+                room_margin_x: 2,
+                room_margin_z: 2,
+                direct_doors: true,
+                trim_dead_ends: true,
+                loop_probability: 0.2,
+                ..Default::default()
+            },
+            Preset::Tower => Dungeon3DGeneratorConfig {
+                width: 16,
+                height: 36,
+                depth: 16,
+                room_hierarchy: 6,
+                room_width_range: 4..=7,
+                room_height_range: 2..=3,
+                room_depth_range: 4..=7,
+                room_margin_x: 2,
+                room_margin_z: 2,
+                min_inter_hierarchy_connections: 1,
+                loop_probability: 0.1,
+                trim_dead_ends: true,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generate_drd::{generate_dungeon_3d, Dungeon3DGeneratorConfig};
+    use crate::preset::PRESETS;
+    use crate::validation::validate;
+
+    #[test]
+    fn test_presets() {
+        for preset in PRESETS {
+            let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+                seed: Some(0),
+                ..Dungeon3DGeneratorConfig::preset(preset)
+            })
+            .unwrap_or_else(|e| panic!("{:?}: {}", preset, e));
+            assert!(result.rooms.len() > 1, "{:?}", preset);
+            assert!(validate(&result).is_empty(), "{:?}", preset);
+        }
+    }
+}
@@ -0,0 +1,321 @@
+use crate::checked::panic_message;
+use crate::constants::VoxelType;
+#[cfg(feature = "events")]
+use crate::events::GenerationEvent;
+use crate::generate_drd::{
+    Dungeon3DGeneratorConfig, Dungeon3DGeneratorError, Dungeon3DGeneratorResult, LayoutConnection,
+};
+use crate::generation_report::GenerationReport;
+use crate::pipeline::{seeded_rng, GenerationPipeline, GenerationState};
+use crate::room::{Room, RoomId};
+use crate::room_connection::{ConnectionId, RoomConnection};
+use nalgebra::Vector3;
+use rand::rngs::StdRng;
+use std::collections::{BTreeMap, HashMap};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// 設定を少しずつ変えながら何度も生成し直すビューアやエディタ向けに、直前の生成結果と統計を保持する
+// 通路にだけ関わる設定を変えた場合は、前回の部屋の配置と選んだ辺を使い回して通路を掘る段階からやり直す
+// 極端な設定で panic しても呼び出し側が止まらないよう、panic はエラーとして保持する
+pub struct Regenerator {
+    pipeline: GenerationPipeline,
+    layout: Option<LayoutCache>,
+    result: Option<Result<Dungeon3DGeneratorResult, Dungeon3DGeneratorError>>,
+    elapsed: Duration,
+    generations: u64,
+}
+
+impl Default for Regenerator {
+    fn default() -> Self {
+        Regenerator::new(GenerationPipeline::default())
+    }
+}
+
+impl Regenerator {
+    pub fn new(pipeline: GenerationPipeline) -> Self {
+        Regenerator {
+            pipeline,
+            layout: None,
+            result: None,
+            elapsed: Duration::ZERO,
+            generations: 0,
+        }
+    }
+
+    // 設定で生成し直す。統計を表示できるよう、生成の記録は常に集める
+    // 部屋の配置と辺の選択に使う設定が前回と同じなら、その段階の経過時間は前回のものを報告する
+    pub fn regenerate(
+        &mut self,
+        config: &Dungeon3DGeneratorConfig,
+    ) -> &Result<Dungeon3DGeneratorResult, Dungeon3DGeneratorError> {
+        let config = Dungeon3DGeneratorConfig {
+            report: true,
+            ..config.clone()
+        };
+        let started = Instant::now();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| self.generate(config)))
+            .unwrap_or_else(|payload| {
+                Err(Dungeon3DGeneratorError::Panicked(panic_message(
+                    payload.as_ref(),
+                )))
+            });
+        self.elapsed = started.elapsed();
+        self.generations += 1;
+        self.result.insert(result)
+    }
+
+    fn generate(
+        &mut self,
+        config: Dungeon3DGeneratorConfig,
+    ) -> Result<Dungeon3DGeneratorResult, Dungeon3DGeneratorError> {
+        if !self
+            .layout
+            .as_ref()
+            .is_some_and(|layout| layout.reusable(&config))
+        {
+            // 配置に失敗した場合や panic した場合に古い配置を残さない
+            self.layout = None;
+            self.layout = Some(LayoutCache::new(&self.pipeline, &config)?);
+        }
+        let layout = self.layout.as_ref().unwrap();
+        let mut rng = layout.rng.clone();
+        let state = layout.restore(config, &mut rng);
+        self.pipeline
+            .run_passages(state)
+            .map(GenerationState::into_result)
+    }
+
+    // シードを1つ進めて生成し直す。シードが無い場合は 0 から始める
+    pub fn reseed(
+        &mut self,
+        config: &mut Dungeon3DGeneratorConfig,
+    ) -> &Result<Dungeon3DGeneratorResult, Dungeon3DGeneratorError> {
+        config.seed = Some(config.seed.map_or(0, |seed| seed.wrapping_add(1)));
+        self.regenerate(config)
+    }
+
+    pub fn result(&self) -> Option<&Result<Dungeon3DGeneratorResult, Dungeon3DGeneratorError>> {
+        self.result.as_ref()
+    }
+
+    // 直前の生成にかかった時間
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    // 生成した回数。表示側で結果が変わったかを判断するために使う
+    pub fn generations(&self) -> u64 {
+        self.generations
+    }
+}
+
+// 部屋の配置と辺の選択を終えた時点の途中経過
+struct LayoutCache {
+    config: Dungeon3DGeneratorConfig, // Config the layout was generated with, before the stages adjusted it
+    rng: StdRng, // Left as the layout stages left it, so carving draws the same numbers as a full generation
+    report: GenerationReport,
+    rooms: BTreeMap<RoomId, Room>,
+    map: HashMap<Vector3<i32>, VoxelType>,
+    room_connections: Vec<RoomConnection>,
+    connections: BTreeMap<ConnectionId, LayoutConnection>,
+    stairwell: Vec<RoomId>,
+    central_room: Option<RoomId>,
+    waypoint_rooms: Vec<RoomId>,
+    room_extensions: BTreeMap<RoomId, Vec<Room>>,
+    #[cfg(feature = "events")]
+    events: Vec<GenerationEvent>,
+}
+
+impl LayoutCache {
+    fn new(
+        pipeline: &GenerationPipeline,
+        config: &Dungeon3DGeneratorConfig,
+    ) -> Result<Self, Dungeon3DGeneratorError> {
+        let mut rng = seeded_rng(config.seed);
+        let state = pipeline.run_layout(config.clone(), &mut rng)?;
+        let GenerationState {
+            report,
+            rooms,
+            voxel_map,
+            room_connections,
+            connections,
+            stairwell,
+            central_room,
+            waypoint_rooms,
+            room_extensions,
+            #[cfg(feature = "events")]
+            events,
+            ..
+        } = state;
+        Ok(LayoutCache {
+            config: config.clone(),
+            rng,
+            report,
+            rooms,
+            map: voxel_map.map,
+            room_connections,
+            connections,
+            stairwell,
+            central_room,
+            waypoint_rooms,
+            room_extensions,
+            #[cfg(feature = "events")]
+            events,
+        })
+    }
+
+    // 部屋の配置と辺の選択に使う設定が全て同じか。シードが無い場合は毎回違う配置になるため使い回さない
+    fn reusable(&self, config: &Dungeon3DGeneratorConfig) -> bool {
+        let a = &self.config;
+        config.seed.is_some()
+            && a.seed == config.seed
+            && (a.width, a.height, a.depth) == (config.width, config.height, config.depth)
+            && a.room_hierarchy == config.room_hierarchy
+            && a.room_width_range == config.room_width_range
+            && a.room_height_range == config.room_height_range
+            && a.room_depth_range == config.room_depth_range
+            && (a.room_margin_x, a.room_margin_y, a.room_margin_z)
+                == (
+                    config.room_margin_x,
+                    config.room_margin_y,
+                    config.room_margin_z,
+                )
+            && a.room_density == config.room_density
+            && a.room_aspect_ratio_range == config.room_aspect_ratio_range
+            && a.block_split_jitter == config.block_split_jitter
+            && a.passage_height == config.passage_height
+            && a.margin_for_bounds == config.margin_for_bounds
+            && a.boundary_margin == config.boundary_margin
+            && a.room_anchors == config.room_anchors
+            && same_fn(a.surface_height.as_ref(), config.surface_height.as_ref())
+            && a.merge_adjacent_rooms == config.merge_adjacent_rooms
+            && (a.hub_min_degree, a.hub_growth) == (config.hub_min_degree, config.hub_growth)
+            && a.loop_probability == config.loop_probability
+            && a.max_connection_length == config.max_connection_length
+            && a.split_long_connections == config.split_long_connections
+            && a.max_connection_rise == config.max_connection_rise
+            && a.excluded_room_pairs == config.excluded_room_pairs
+            && a.excluded_hierarchy_pairs == config.excluded_hierarchy_pairs
+            && same_fn(
+                a.room_placement_score.as_ref(),
+                config.room_placement_score.as_ref(),
+            )
+    }
+
+    // 新しい設定で作り直したボクセルの範囲や探索の設定に、配置した部屋と選んだ辺を戻す
+    fn restore<'a>(
+        &self,
+        config: Dungeon3DGeneratorConfig,
+        rng: &'a mut StdRng,
+    ) -> GenerationState<'a> {
+        let mut state = GenerationState::new(config, rng);
+        state.report = self.report.clone();
+        state.rooms = self.rooms.clone();
+        state.voxel_map.map = self.map.clone();
+        state.room_connections = self.room_connections.clone();
+        state.connections = self.connections.clone();
+        state.stairwell = self.stairwell.clone();
+        state.central_room = self.central_room;
+        state.waypoint_rooms = self.waypoint_rooms.clone();
+        state.room_extensions = self.room_extensions.clone();
+        #[cfg(feature = "events")]
+        {
+            state.events = self.events.clone();
+        }
+        state
+    }
+}
+
+// 関数を比べられないため、同じ Arc を指している場合だけ同じとみなす
+fn same_fn<T: ?Sized>(a: Option<&Arc<T>>, b: Option<&Arc<T>>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+        (a, b) => a.is_none() && b.is_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generate_drd::{
+        generate_dungeon_3d, Dungeon3DGeneratorConfig, Dungeon3DGeneratorError,
+    };
+    use crate::regenerate::Regenerator;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_regenerator() {
+        let mut regenerator = Regenerator::default();
+        assert!(regenerator.result().is_none());
+        let mut config = Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        };
+        let expected = generate_dungeon_3d(config.clone()).unwrap();
+        let result = regenerator.regenerate(&config).as_ref().unwrap();
+        assert_eq!(result.to_canonical_string(), expected.to_canonical_string());
+        assert!(result.report.is_some());
+
+        regenerator.reseed(&mut config);
+        assert_eq!(config.seed, Some(1));
+        assert_eq!(regenerator.generations(), 2);
+
+        let result = regenerator.regenerate(&Dungeon3DGeneratorConfig {
+            room_placement_score: Some(Arc::new(|_| panic!("score"))),
+            ..config
+        });
+        assert!(matches!(result, Err(Dungeon3DGeneratorError::Panicked(_))));
+    }
+
+    #[test]
+    fn test_regenerate_reuses_layout() {
+        // 部屋の配置の候補を評価した回数で、配置をやり直したかを調べる
+        let scored = Arc::new(AtomicUsize::new(0));
+        let counter = scored.clone();
+        let config = Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            width: 32,
+            depth: 32,
+            room_placement_score: Some(Arc::new(move |_| {
+                counter.fetch_add(1, Ordering::Relaxed);
+                0.0
+            })),
+            ..Default::default()
+        };
+        let mut regenerator = Regenerator::default();
+        regenerator.regenerate(&config).as_ref().unwrap();
+        let placed = scored.load(Ordering::Relaxed);
+        assert!(placed > 0);
+
+        // 通路にだけ関わる設定の変更では配置を使い回し、全て生成し直した場合と同じ結果になる
+        let carving = Dungeon3DGeneratorConfig {
+            stair_run: 2,
+            trim_dead_ends: true,
+            passage_wall_noise: 0.2,
+            ..config.clone()
+        };
+        let result = regenerator.regenerate(&carving).as_ref().unwrap();
+        assert_eq!(scored.load(Ordering::Relaxed), placed);
+        let text = result.to_canonical_string();
+        assert_eq!(
+            text,
+            generate_dungeon_3d(carving).unwrap().to_canonical_string()
+        );
+
+        // 配置に関わる設定を変えると配置からやり直す
+        let scored_before = scored.load(Ordering::Relaxed);
+        let layout = Dungeon3DGeneratorConfig {
+            loop_probability: 0.5,
+            ..config
+        };
+        let result = regenerator.regenerate(&layout).as_ref().unwrap();
+        assert!(scored.load(Ordering::Relaxed) > scored_before);
+        let text = result.to_canonical_string();
+        assert_eq!(
+            text,
+            generate_dungeon_3d(layout).unwrap().to_canonical_string()
+        );
+    }
+}
@@ -0,0 +1,287 @@
+use crate::constants::Axis;
+use crate::generate_drd::Dungeon3DGeneratorResult;
+use crate::passage::PassageId;
+use crate::room::RoomId;
+use crate::voxel_map::VoxelMapError;
+use nalgebra::Vector3;
+use std::collections::BTreeMap;
+
+impl Dungeon3DGeneratorResult {
+    // 部屋を番号順に start から連番で振り直し、全ての参照を書き換える。戻り値は古い番号から新しい番号への対応
+    // 多数のダンジョンを1つの世界に置く場合に、番号が重ならないよう使う
+    pub fn remap_ids(&mut self, start: RoomId) -> BTreeMap<RoomId, RoomId> {
+        let mut next = start;
+        let mapping = self
+            .rooms
+            .keys()
+            .map(|room_id| (*room_id, next.gen_id()))
+            .collect::<BTreeMap<_, _>>();
+        let map = |room_id: RoomId| mapping.get(&room_id).copied().unwrap_or(room_id);
+
+        self.rooms = std::mem::take(&mut self.rooms)
+            .into_values()
+            .map(|mut room| {
+                room.id = map(room.id);
+                (room.id, room)
+            })
+            .collect();
+        self.room_extensions = std::mem::take(&mut self.room_extensions)
+            .into_iter()
+            .map(|(room_id, mut extensions)| {
+                for room in extensions.iter_mut() {
+                    room.id = map(room.id);
+                }
+                (map(room_id), extensions)
+            })
+            .collect();
+        for voxel_type in self.voxel_map.map.values_mut() {
+            *voxel_type = voxel_type.map_room_id(map);
+        }
+        for passage in self.passages.values_mut() {
+            passage.start_room_id = map(passage.start_room_id);
+            passage.end_room_id = map(passage.end_room_id);
+            for (_, voxel_type) in passage.cells.iter_mut() {
+                *voxel_type = voxel_type.map_room_id(map);
+            }
+        }
+        for room_connection in self.room_connections.iter_mut().chain(
+            self.failed_connections
+                .iter_mut()
+                .map(|failed| &mut failed.connection),
+        ) {
+            room_connection.room0_id = map(room_connection.room0_id);
+            room_connection.room1_id = map(room_connection.room1_id);
+        }
+        for repair in self.repairs.iter_mut() {
+            repair.room0_id = map(repair.room0_id);
+            repair.room1_id = map(repair.room1_id);
+        }
+        for (room0_id, room1_id, _) in self.objective_passages.iter_mut() {
+            *room0_id = map(*room0_id);
+            *room1_id = map(*room1_id);
+        }
+        self.under_connected_rooms = std::mem::take(&mut self.under_connected_rooms)
+            .into_iter()
+            .map(|(room_id, degree)| (map(room_id), degree))
+            .collect();
+        if let Some(surface_entrance) = self.surface_entrance.as_mut() {
+            surface_entrance.room_id = map(surface_entrance.room_id);
+            for (_, voxel_type) in surface_entrance.cells.iter_mut() {
+                *voxel_type = voxel_type.map_room_id(map);
+            }
+        }
+        for doorway in self.doorways.iter_mut() {
+            doorway.room_id = map(doorway.room_id);
+        }
+        for room_id in self
+            .stairwell
+            .iter_mut()
+            .chain(self.central_room.iter_mut())
+            .chain(self.waypoint_rooms.iter_mut())
+        {
+            *room_id = map(*room_id);
+        }
+        mapping
+    }
+
+    // 通路を番号順に start から連番で振り直し、全ての参照を書き換える。戻り値は古い番号から新しい番号への対応
+    pub fn remap_passage_ids(&mut self, start: PassageId) -> BTreeMap<PassageId, PassageId> {
+        let mut next = start;
+        let mapping = self
+            .passages
+            .keys()
+            .map(|passage_id| (*passage_id, next.gen_id()))
+            .collect::<BTreeMap<_, _>>();
+        let map = |passage_id: PassageId| mapping.get(&passage_id).copied().unwrap_or(passage_id);
+
+        self.passages = std::mem::take(&mut self.passages)
+            .into_iter()
+            .map(|(passage_id, passage)| (map(passage_id), passage))
+            .collect();
+        for repair in self.repairs.iter_mut() {
+            repair.passage_id = map(repair.passage_id);
+        }
+        for (_, _, passage_id) in self.objective_passages.iter_mut() {
+            *passage_id = map(*passage_id);
+        }
+        for doorway in self.doorways.iter_mut() {
+            doorway.passage_id = map(doorway.passage_id);
+        }
+        for junction in self.junctions.iter_mut() {
+            for passage_id in junction.connected_passages.iter_mut() {
+                *passage_id = map(*passage_id);
+            }
+        }
+        for dead_end in self.dead_ends.iter_mut() {
+            dead_end.passage_id = map(dead_end.passage_id);
+        }
+        for stair in self.stairs.iter_mut() {
+            stair.passage_id = map(stair.passage_id);
+        }
+        mapping
+    }
+
+    // 部屋、ボクセル、通路など全ての座標を平行移動する。部屋の原点は負にできないため移動量も負にはしない
+    // 座標が i32 の範囲を超える場合は panic する
+    pub fn translate(&mut self, offset: (u32, u32, u32)) {
+        if let Err(error) = self.checked_translate(offset) {
+            panic!("{}", error);
+        }
+    }
+
+    // translate と同じだが、座標が i32 の範囲を超える場合は何も変更せずに VoxelMapError::Overflow を返す
+    pub fn checked_translate(&mut self, offset: (u32, u32, u32)) -> Result<(), VoxelMapError> {
+        let [x, y, z] = [offset.0, offset.1, offset.2].map(i32::try_from);
+        let (Ok(x), Ok(y), Ok(z)) = (x, y, z) else {
+            let axis = match (x, y) {
+                (Err(_), _) => Axis::X,
+                (_, Err(_)) => Axis::Y,
+                _ => Axis::Z,
+            };
+            return Err(VoxelMapError::Overflow(axis));
+        };
+        let vector = Vector3::new(x, y, z);
+        if let Some(axis) = self.voxel_map.translation_overflow(vector) {
+            return Err(VoxelMapError::Overflow(axis));
+        }
+        // 部屋の範囲もボクセルの座標として i32 に収まる必要がある
+        for room in self
+            .rooms
+            .values()
+            .chain(self.room_extensions.values().flatten())
+        {
+            let end = room.end();
+            for (axis, end, offset) in [
+                (Axis::X, end.0, offset.0),
+                (Axis::Y, end.1, offset.1),
+                (Axis::Z, end.2, offset.2),
+            ] {
+                if end as u64 + offset as u64 > i32::MAX as u64 {
+                    return Err(VoxelMapError::Overflow(axis));
+                }
+            }
+        }
+        self.translate_unchecked(offset, vector);
+        Ok(())
+    }
+
+    fn translate_unchecked(&mut self, offset: (u32, u32, u32), vector: Vector3<i32>) {
+        let cell = |(x, y, z): (i32, i32, i32)| (x + vector.x, y + vector.y, z + vector.z);
+
+        for room in self
+            .rooms
+            .values_mut()
+            .chain(self.room_extensions.values_mut().flatten())
+        {
+            room.origin = (
+                room.origin.0 + offset.0,
+                room.origin.1 + offset.1,
+                room.origin.2 + offset.2,
+            );
+        }
+        self.voxel_map.translate_unchecked(vector);
+        for passage in self.passages.values_mut() {
+            passage.start = cell(passage.start);
+            for (p, _) in passage.cells.iter_mut() {
+                *p = cell(*p);
+            }
+        }
+        if let Some(surface_entrance) = self.surface_entrance.as_mut() {
+            surface_entrance.opening = cell(surface_entrance.opening);
+            for (p, _) in surface_entrance.cells.iter_mut() {
+                *p = cell(*p);
+            }
+        }
+        for doorway in self.doorways.iter_mut() {
+            doorway.position = cell(doorway.position);
+        }
+        for junction in self.junctions.iter_mut() {
+            junction.position = cell(junction.position);
+        }
+        for dead_end in self.dead_ends.iter_mut() {
+            for p in dead_end.cells.iter_mut() {
+                *p = cell(*p);
+            }
+        }
+        for stair in self.stairs.iter_mut() {
+            stair.bottom = cell(stair.bottom);
+            stair.top = cell(stair.top);
+        }
+        self.materials = std::mem::take(&mut self.materials)
+            .into_iter()
+            .map(|(p, material)| (p + vector, material))
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::Axis;
+    use crate::generate_drd::{generate_dungeon_3d, Dungeon3DGeneratorConfig};
+    use crate::passage::PassageId;
+    use crate::room::RoomId;
+    use crate::voxel_map::VoxelMapError;
+    use nalgebra::Vector3;
+
+    #[test]
+    fn test_remap_and_translate() {
+        let generate = || {
+            generate_dungeon_3d(Dungeon3DGeneratorConfig {
+                seed: Some(0),
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        let original = generate();
+        let mut result = generate();
+        let start = RoomId::first().shifted(100);
+        let mapping = result.remap_ids(start);
+        assert_eq!(mapping.len(), original.rooms.len());
+        assert_eq!(*result.rooms.keys().next().unwrap(), start);
+        assert!(result
+            .voxel_map
+            .map
+            .values()
+            .filter_map(|voxel_type| voxel_type.room_id())
+            .all(|room_id| result.rooms.contains_key(&room_id)));
+        result.remap_passage_ids(PassageId::first().shifted(50));
+        assert!(result.doorways.iter().all(|doorway| {
+            result.rooms.contains_key(&doorway.room_id)
+                && result.passages.contains_key(&doorway.passage_id)
+        }));
+        // 元の番号へ戻すと同じ結果になる
+        result.remap_ids(RoomId::first());
+        result.remap_passage_ids(PassageId::first());
+        assert_eq!(result.to_canonical_string(), original.to_canonical_string());
+
+        // 桁あふれする移動は何も変更しない
+        let before = result.clone();
+        assert_eq!(
+            result.checked_translate((i32::MAX as u32 - 4, 0, 0)),
+            Err(VoxelMapError::Overflow(Axis::X))
+        );
+        assert_eq!(
+            result.checked_translate((0, 0, u32::MAX)),
+            Err(VoxelMapError::Overflow(Axis::Z))
+        );
+        assert_eq!(result, before);
+
+        result.translate((10, 2, 20));
+        for (room_id, room) in result.rooms.iter() {
+            let before = &original.rooms[room_id];
+            assert_eq!(
+                room.origin,
+                (
+                    before.origin.0 + 10,
+                    before.origin.1 + 2,
+                    before.origin.2 + 20
+                )
+            );
+        }
+        for (p, voxel_type) in original.voxel_map.map.iter() {
+            let q = p + Vector3::new(10, 2, 20);
+            assert_eq!(result.voxel_map.get(&q), *voxel_type);
+            assert_eq!(result.materials.get(&q), original.materials.get(p));
+        }
+    }
+}
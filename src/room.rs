@@ -1,4 +1,7 @@
-#[derive(Debug)]
+use crate::voxel_map::VoxelMap;
+use nalgebra::Vector3;
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Room {
     pub id: RoomId,
     pub width: u32,
@@ -6,6 +9,7 @@ pub struct Room {
     pub depth: u32,
     pub origin: (u32, u32, u32),
     pub center_offset: (f32, f32, f32),
+    pub hierarchy: u32, // Vertical level of the block the room was placed in
 }
 
 impl Room {
@@ -17,6 +21,7 @@ impl Room {
             depth,
             origin,
             center_offset: (width as f32 / 2.0, height as f32 / 2.0, depth as f32 / 2.0),
+            hierarchy: 0,
         }
     }
 
@@ -36,6 +41,63 @@ impl Room {
         )
     }
 
+    pub fn contains_point(&self, x: u32, y: u32, z: u32) -> bool {
+        let end = self.end();
+        self.origin.0 <= x
+            && x < end.0
+            && self.origin.1 <= y
+            && y < end.1
+            && self.origin.2 <= z
+            && z < end.2
+    }
+
+    // 壁、床、天井の6面を返す。各面には部屋の外の通れる空間に面した開口部のセルを添える
+    pub fn faces(&self, voxel_map: &VoxelMap) -> Vec<RoomFace> {
+        let (x0, y0, z0) = (
+            self.origin.0 as i32,
+            self.origin.1 as i32,
+            self.origin.2 as i32,
+        );
+        let end = self.end();
+        let (x1, y1, z1) = (end.0 as i32, end.1 as i32, end.2 as i32);
+        [
+            (RoomFaceSide::Left, (x0, y0, z0), (x0, y1, z1)),
+            (RoomFaceSide::Right, (x1, y0, z0), (x1, y1, z1)),
+            (RoomFaceSide::Far, (x0, y0, z0), (x1, y1, z0)),
+            (RoomFaceSide::Near, (x0, y0, z1), (x1, y1, z1)),
+            (RoomFaceSide::Floor, (x0, y0, z0), (x1, y0, z1)),
+            (RoomFaceSide::Ceiling, (x0, y1, z0), (x1, y1, z1)),
+        ]
+        .into_iter()
+        .map(|(side, min, max)| {
+            let normal = side.normal();
+            // 面に接する部屋の内側のセル
+            let inner = |n: i32, start: i32, end: i32| match n {
+                -1 => start..start + 1,
+                1 => end - 1..end,
+                _ => start..end,
+            };
+            let mut openings = Vec::new();
+            for y in inner(normal.y, y0, y1) {
+                for z in inner(normal.z, z0, z1) {
+                    for x in inner(normal.x, x0, x1) {
+                        let outside = voxel_map.get(&(Vector3::new(x, y, z) + normal));
+                        if outside.is_passable() && outside.room_id() != Some(self.id) {
+                            openings.push((x, y, z));
+                        }
+                    }
+                }
+            }
+            RoomFace {
+                side,
+                min,
+                max,
+                openings,
+            }
+        })
+        .collect()
+    }
+
     pub fn is_contract(&self, other: &Room, margin: u32) -> bool {
         let self_end = self.end();
         let self_end = (
@@ -58,6 +120,50 @@ impl Room {
     }
 }
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum RoomFaceSide {
+    Left,
+    Right,
+    Far,
+    Near,
+    Floor,
+    Ceiling,
+}
+
+impl RoomFaceSide {
+    // 部屋の外へ向かう向き
+    pub fn normal(&self) -> Vector3<i32> {
+        match self {
+            RoomFaceSide::Left => Vector3::new(-1, 0, 0),
+            RoomFaceSide::Right => Vector3::new(1, 0, 0),
+            RoomFaceSide::Far => Vector3::new(0, 0, -1),
+            RoomFaceSide::Near => Vector3::new(0, 0, 1),
+            RoomFaceSide::Floor => Vector3::new(0, -1, 0),
+            RoomFaceSide::Ceiling => Vector3::new(0, 1, 0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RoomFace {
+    pub side: RoomFaceSide,
+    pub min: (i32, i32, i32), // Corner of the face rectangle with the smallest coordinates
+    pub max: (i32, i32, i32), // Opposite corner; equal to min along the normal axis
+    pub openings: Vec<(i32, i32, i32)>, // Cells inside the room whose neighbour across the face is open space of something else
+}
+
+impl RoomFace {
+    // 面を覆うセルの数
+    pub fn area(&self) -> usize {
+        let size = (
+            (self.max.0 - self.min.0).max(1),
+            (self.max.1 - self.min.1).max(1),
+            (self.max.2 - self.min.2).max(1),
+        );
+        (size.0 * size.1 * size.2) as usize
+    }
+}
+
 #[derive(Ord, PartialOrd, PartialEq, Eq, Hash, Copy, Clone, Debug)]
 pub struct RoomId(u64);
 
@@ -75,4 +181,52 @@ impl RoomId {
     pub fn inner(&self) -> u64 {
         self.0
     }
+
+    // inner の逆。ファイルなどに保存した番号から戻す
+    pub fn from_inner(inner: u64) -> Self {
+        RoomId(inner)
+    }
+
+    // 別の生成結果と番号が重ならないようにずらす
+    pub fn shifted(&self, amount: u64) -> Self {
+        RoomId(self.0 + amount)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generate_drd::{generate_dungeon_3d, Dungeon3DGeneratorConfig};
+    use crate::room::RoomFaceSide;
+
+    #[test]
+    fn test_faces() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        for room in result.rooms.values() {
+            let faces = room.faces(&result.voxel_map);
+            assert_eq!(faces.len(), 6);
+            for face in faces.iter() {
+                let area = match face.side {
+                    RoomFaceSide::Left | RoomFaceSide::Right => room.height * room.depth,
+                    RoomFaceSide::Far | RoomFaceSide::Near => room.width * room.height,
+                    RoomFaceSide::Floor | RoomFaceSide::Ceiling => room.width * room.depth,
+                };
+                assert_eq!(face.area(), area as usize);
+                assert!(face.openings.len() <= face.area());
+            }
+            // 出入口に面したセルは壁の開口部に含まれる
+            for doorway in result.doorways.iter().filter(|d| d.room_id == room.id) {
+                let (x, y, z) = doorway.position;
+                assert!(faces
+                    .iter()
+                    .any(|face| face.openings.iter().any(|(ox, oy, oz)| {
+                        let n = face.side.normal();
+                        (ox + n.x, oy + n.y, oz + n.z) == (x, y, z)
+                    })));
+            }
+        }
+    }
 }
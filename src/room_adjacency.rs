@@ -0,0 +1,124 @@
+use crate::constants::{Axis, VoxelType};
+use crate::room::{Room, RoomId};
+use crate::voxel_map::VoxelMap;
+use nalgebra::Vector3;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RoomAdjacency {
+    pub room0_id: RoomId,
+    pub room1_id: RoomId,
+    pub axis: Axis, // Axis along which the two walls face each other
+    pub gap: u32,   // Number of voxels between the walls (0 or 1)
+    pub door_cells: Vec<(i32, i32, i32)>, // Free voxels in the gap at the shared floor level
+}
+
+// 通路の有無に関わらず、壁同士が1ボクセル以内で向かい合っている部屋の組を検出する
+pub fn find_adjacent_rooms(
+    rooms: &BTreeMap<RoomId, Room>,
+    voxel_map: &VoxelMap,
+) -> Vec<RoomAdjacency> {
+    let mut adjacencies = Vec::new();
+    let rooms = rooms.values().collect::<Vec<_>>();
+    for (i, room0) in rooms.iter().enumerate() {
+        for room1 in rooms.iter().skip(i + 1) {
+            if let Some(adjacency) = adjacency(room0, room1, voxel_map) {
+                adjacencies.push(adjacency);
+            }
+        }
+    }
+    adjacencies
+}
+
+fn adjacency(room0: &Room, room1: &Room, voxel_map: &VoxelMap) -> Option<RoomAdjacency> {
+    let (start0, end0) = bounds(room0);
+    let (start1, end1) = bounds(room1);
+    // 床を含めた高さ方向の範囲が重なっていること
+    overlap(start0.y, end0.y, start1.y, end1.y)?;
+    for axis in [Axis::X, Axis::Z] {
+        let (a, b) = match axis {
+            Axis::X => (0, 2),
+            _ => (2, 0),
+        };
+        let Some((from, to)) = overlap(start0[b], end0[b], start1[b], end1[b]) else {
+            continue;
+        };
+        let (wall, gap) = if end0[a] <= start1[a] {
+            (end0[a], start1[a] - end0[a])
+        } else if end1[a] <= start0[a] {
+            (end1[a], start0[a] - end1[a])
+        } else {
+            continue;
+        };
+        if gap > 1 {
+            continue;
+        }
+
+        let mut door_cells = Vec::new();
+        if gap == 1 && room0.origin.1 == room1.origin.1 {
+            let y = room0.origin.1 as i32;
+            for t in from..to {
+                let mut p = Vector3::new(0, y, 0);
+                p[a] = wall;
+                p[b] = t;
+                if voxel_map.get(&p) == VoxelType::Wall {
+                    door_cells.push((p.x, p.y, p.z));
+                }
+            }
+        }
+        return Some(RoomAdjacency {
+            room0_id: room0.id,
+            room1_id: room1.id,
+            axis,
+            gap: gap as u32,
+            door_cells,
+        });
+    }
+    None
+}
+
+fn bounds(room: &Room) -> (Vector3<i32>, Vector3<i32>) {
+    let end = room.end();
+    (
+        Vector3::new(
+            room.origin.0 as i32,
+            room.origin.1 as i32 - 1,
+            room.origin.2 as i32,
+        ),
+        Vector3::new(end.0 as i32, end.1 as i32, end.2 as i32),
+    )
+}
+
+fn overlap(start0: i32, end0: i32, start1: i32, end1: i32) -> Option<(i32, i32)> {
+    let (from, to) = (start0.max(start1), end0.min(end1));
+    (from < to).then_some((from, to))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::Axis;
+    use crate::room::{Room, RoomId};
+    use crate::room_adjacency::find_adjacent_rooms;
+    use crate::voxel_map::VoxelMap;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_find_adjacent_rooms() {
+        let mut room_id = RoomId::first();
+        let mut rooms = BTreeMap::new();
+        let mut voxel_map = VoxelMap::new(0, 0, 0, 32, 8, 32);
+        for (width, origin) in [(4, (1, 1, 1)), (4, (6, 1, 2)), (4, (20, 1, 1))] {
+            let room = Room::new(room_id.gen_id(), width, 2, 4, origin);
+            voxel_map.add_room(&room).unwrap();
+            rooms.insert(room.id, room);
+        }
+        let adjacencies = find_adjacent_rooms(&rooms, &voxel_map);
+        assert_eq!(adjacencies.len(), 1);
+        assert_eq!(adjacencies[0].axis, Axis::X);
+        assert_eq!(adjacencies[0].gap, 1);
+        assert_eq!(
+            adjacencies[0].door_cells,
+            vec![(5, 1, 2), (5, 1, 3), (5, 1, 4)]
+        );
+    }
+}
@@ -1,7 +1,26 @@
 use crate::room::RoomId;
 use std::hash::{Hash, Hasher};
 
-#[derive(Debug)]
+#[derive(Ord, PartialOrd, PartialEq, Eq, Hash, Copy, Clone, Debug)]
+pub struct ConnectionId(u64);
+
+impl ConnectionId {
+    pub fn first() -> Self {
+        ConnectionId(1)
+    }
+
+    pub fn gen_id(&mut self) -> Self {
+        let ret = *self;
+        self.0 += 1;
+        ret
+    }
+
+    pub fn inner(&self) -> u64 {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct RoomConnection {
     pub room0_id: RoomId,
     pub room1_id: RoomId,
@@ -0,0 +1,136 @@
+use crate::constants::VoxelType;
+use crate::room::{Room, RoomId};
+use crate::room_adjacency::find_adjacent_rooms;
+use crate::voxel_map::VoxelMap;
+use nalgebra::Vector3;
+use std::collections::BTreeMap;
+
+// 壁同士が1ボクセル以内で向かい合い、床の高さが同じ部屋の組を1つの部屋にまとめる
+// 番号の小さい部屋を残し、もう一方の部屋の空間は残した部屋の番号に書き換えて extensions に加える
+// 間の壁は低い方の部屋の高さまで取り除く。戻り値はまとめた部屋の番号から残した部屋の番号への対応
+pub fn merge_adjacent_rooms(
+    rooms: &mut BTreeMap<RoomId, Room>,
+    extensions: &mut BTreeMap<RoomId, Vec<Room>>,
+    voxel_map: &mut VoxelMap,
+) -> BTreeMap<RoomId, RoomId> {
+    let original = rooms.clone();
+    let mut merged: BTreeMap<RoomId, RoomId> = BTreeMap::new();
+    let resolve = |merged: &BTreeMap<RoomId, RoomId>, mut room_id: RoomId| {
+        while let Some(next) = merged.get(&room_id) {
+            room_id = *next;
+        }
+        room_id
+    };
+    for adjacency in find_adjacent_rooms(&original, voxel_map) {
+        let (room0, room1) = (
+            &original[&adjacency.room0_id],
+            &original[&adjacency.room1_id],
+        );
+        if room0.origin.1 != room1.origin.1
+            || (adjacency.gap > 0 && adjacency.door_cells.is_empty())
+        {
+            continue;
+        }
+        let (id0, id1) = (resolve(&merged, room0.id), resolve(&merged, room1.id));
+        let (keep, absorb) = (id0.min(id1), id0.max(id1));
+
+        // 間の壁のうち、床から天井まで全て空いているものだけを取り除く
+        let height = room0.height.min(room1.height) as i32;
+        for (x, y, z) in adjacency.door_cells.iter() {
+            let column = (-1..height)
+                .map(|dy| {
+                    let voxel_type = match dy {
+                        -1 => VoxelType::RoomFloor(keep),
+                        0 => VoxelType::RoomBottomSpace(keep),
+                        _ => VoxelType::RoomSpace(keep),
+                    };
+                    (Vector3::new(*x, y + dy, *z), voxel_type)
+                })
+                .collect::<Vec<_>>();
+            if column
+                .iter()
+                .all(|(p, _)| voxel_map.get(p) == VoxelType::Wall)
+            {
+                voxel_map.map.extend(column);
+            }
+        }
+        if keep == absorb {
+            continue;
+        }
+
+        let mut absorbed = extensions.remove(&absorb).unwrap_or_default();
+        if let Some(room) = rooms.remove(&absorb) {
+            absorbed.push(room);
+        }
+        let room_extensions = extensions.entry(keep).or_default();
+        room_extensions.extend(absorbed.into_iter().map(|room| Room { id: keep, ..room }));
+        merged.insert(absorb, keep);
+    }
+
+    let merged = merged
+        .keys()
+        .map(|room_id| (*room_id, resolve(&merged, *room_id)))
+        .collect::<BTreeMap<_, _>>();
+    for voxel_type in voxel_map.map.values_mut() {
+        *voxel_type =
+            voxel_type.map_room_id(|room_id| merged.get(&room_id).copied().unwrap_or(room_id));
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::VoxelType;
+    use crate::room::{Room, RoomId};
+    use crate::room_merge::merge_adjacent_rooms;
+    use crate::voxel_map::VoxelMap;
+    use nalgebra::Vector3;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_merge_adjacent_rooms() {
+        let mut room_id = RoomId::first();
+        let mut rooms = BTreeMap::new();
+        let mut voxel_map = VoxelMap::new(0, 0, 0, 32, 8, 32);
+        for (width, height, origin) in [
+            (4, 3, (1, 1, 1)),
+            (4, 2, (6, 1, 2)),
+            (4, 2, (11, 1, 1)),
+            (4, 2, (20, 1, 1)),
+        ] {
+            let room = Room::new(room_id.gen_id(), width, height, 4, origin);
+            voxel_map.add_room(&room).unwrap();
+            rooms.insert(room.id, room);
+        }
+        let ids = rooms.keys().copied().collect::<Vec<_>>();
+        let mut extensions = BTreeMap::new();
+        let merged = merge_adjacent_rooms(&mut rooms, &mut extensions, &mut voxel_map);
+        assert_eq!(merged, BTreeMap::from([(ids[1], ids[0]), (ids[2], ids[0])]));
+        assert_eq!(
+            rooms.keys().copied().collect::<Vec<_>>(),
+            vec![ids[0], ids[3]]
+        );
+        assert_eq!(extensions[&ids[0]].len(), 2);
+        assert!(extensions[&ids[0]].iter().all(|room| room.id == ids[0]));
+        // 間の壁は低い方の部屋の高さまで開き、その上は壁のまま残る
+        assert_eq!(
+            voxel_map.get(&Vector3::new(5, 1, 3)),
+            VoxelType::RoomBottomSpace(ids[0])
+        );
+        assert_eq!(
+            voxel_map.get(&Vector3::new(5, 2, 3)),
+            VoxelType::RoomSpace(ids[0])
+        );
+        assert_eq!(voxel_map.get(&Vector3::new(5, 3, 3)), VoxelType::Wall);
+        assert_eq!(voxel_map.get(&Vector3::new(5, 1, 1)), VoxelType::Wall);
+        assert_eq!(
+            voxel_map.get(&Vector3::new(12, 1, 2)),
+            VoxelType::RoomBottomSpace(ids[0])
+        );
+        assert!(voxel_map
+            .map
+            .values()
+            .filter_map(|voxel_type| voxel_type.room_id())
+            .all(|room_id| rooms.contains_key(&room_id)));
+    }
+}
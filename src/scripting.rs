@@ -0,0 +1,232 @@
+use crate::generate_drd::{Dungeon3DGeneratorConfig, PassageStepCost, RoomPlacementScore};
+use rhai::{Dynamic, Engine, EvalAltResult, ParseError, Scope, AST};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+// 再コンパイルなしに生成のルールを調整するための Rhai スクリプト
+// 次の関数を定義すると、対応する判断の箇所で評価される
+//   fn score_room(x, y, z, width, height, depth, hierarchy) -> 配置候補の評価値 (大きいほど優先)
+//   fn passage_cost(x, y, z) -> 通路が (x, y, z) を1歩進むときの追加コスト
+// 実行時エラーは生成を止めずに既定値で続け、最初のエラーを take_runtime_error で受け取れるようにする
+#[derive(Clone)]
+pub struct GenerationScript {
+    engine: Arc<Engine>,
+    ast: Arc<AST>,
+    runtime_error: Arc<Mutex<Option<ScriptError>>>, // First runtime error since the last take_runtime_error
+}
+
+#[derive(Debug)]
+pub enum ScriptError {
+    Parse(ParseError),
+    Runtime {
+        function: &'static str,
+        message: String, // Rhai error, or the type of a value the function may not return
+    },
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptError::Parse(_) => write!(f, "failed to compile the generation script"),
+            ScriptError::Runtime { function, message } => {
+                write!(f, "{} failed: {}", function, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ScriptError::Parse(error) => Some(error),
+            ScriptError::Runtime { .. } => None,
+        }
+    }
+}
+
+impl GenerationScript {
+    pub fn compile(source: &str) -> Result<Self, ScriptError> {
+        let engine = Engine::new();
+        let ast = engine.compile(source).map_err(ScriptError::Parse)?;
+        Ok(Self {
+            engine: Arc::new(engine),
+            ast: Arc::new(ast),
+            runtime_error: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    // 生成中に最初に起きた実行時エラーを取り出す。取り出した後は次のエラーを記録する
+    pub fn take_runtime_error(&self) -> Option<ScriptError> {
+        self.runtime_error.lock().unwrap().take()
+    }
+
+    // スクリプトで定義された関数を設定に組み込む
+    pub fn apply(&self, config: &mut Dungeon3DGeneratorConfig) {
+        if let Some(score) = self.room_placement_score() {
+            config.room_placement_score = Some(score);
+        }
+        if let Some(cost) = self.passage_step_cost() {
+            config.passage_step_cost = Some(cost);
+        }
+    }
+
+    // 実行時エラーになった候補や数でない値を返した候補は評価値 0 として扱う
+    pub fn room_placement_score(&self) -> Option<RoomPlacementScore> {
+        if !self.defines("score_room", 7) {
+            return None;
+        }
+        let script = self.clone();
+        Some(Arc::new(move |room| {
+            let args = (
+                room.origin.0 as i64,
+                room.origin.1 as i64,
+                room.origin.2 as i64,
+                room.width as i64,
+                room.height as i64,
+                room.depth as i64,
+                room.hierarchy as i64,
+            );
+            script
+                .call("score_room", args)
+                .and_then(|value| {
+                    value
+                        .as_float()
+                        .or_else(|_| value.as_int().map(|v| v as f64))
+                        .map_err(|type_name| format!("returned {}, expected a number", type_name))
+                })
+                .unwrap_or_else(|message| script.record_error("score_room", message, 0.0))
+        }))
+    }
+
+    // 実行時エラーになった点や整数でない値を返した点の追加コストは 0 として扱う
+    // i32 に収まらない値は i32 の範囲に丸める
+    pub fn passage_step_cost(&self) -> Option<PassageStepCost> {
+        if !self.defines("passage_cost", 3) {
+            return None;
+        }
+        let script = self.clone();
+        Some(Arc::new(move |x, y, z| {
+            script
+                .call("passage_cost", (x as i64, y as i64, z as i64))
+                .and_then(|value| {
+                    value
+                        .as_int()
+                        .map_err(|type_name| format!("returned {}, expected an integer", type_name))
+                })
+                .map(|cost| cost.clamp(i32::MIN as i64, i32::MAX as i64) as i32)
+                .unwrap_or_else(|message| script.record_error("passage_cost", message, 0))
+        }))
+    }
+
+    // 最初のエラーだけを残し、代わりに使う値を返す
+    fn record_error<T>(&self, function: &'static str, message: String, fallback: T) -> T {
+        self.runtime_error
+            .lock()
+            .unwrap()
+            .get_or_insert(ScriptError::Runtime { function, message });
+        fallback
+    }
+
+    fn defines(&self, name: &str, params: usize) -> bool {
+        self.ast
+            .iter_functions()
+            .any(|function| function.name == name && function.params.len() == params)
+    }
+
+    fn call(&self, name: &str, args: impl rhai::FuncArgs) -> Result<Dynamic, String> {
+        self.engine
+            .call_fn::<Dynamic>(&mut Scope::new(), &self.ast, name, args)
+            .map_err(|error: Box<EvalAltResult>| error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generate_drd::{generate_dungeon_3d, Dungeon3DGeneratorConfig};
+    use crate::room::{Room, RoomId};
+    use crate::scripting::{GenerationScript, ScriptError};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_generation_script() {
+        let script = GenerationScript::compile(
+            r#"
+            fn score_room(x, y, z, width, height, depth, hierarchy) {
+                width * depth
+            }
+            fn passage_cost(x, y, z) {
+                if y > 6 { 5 } else { 0 }
+            }
+            "#,
+        )
+        .unwrap();
+        let mut config = Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        };
+        script.apply(&mut config);
+        assert!(config.room_placement_score.is_some());
+        assert!(config.passage_step_cost.is_some());
+
+        let plain = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        let result = generate_dungeon_3d(config).unwrap();
+        let total_area = |rooms: &BTreeMap<RoomId, Room>| {
+            rooms
+                .values()
+                .map(|room| room.width * room.depth)
+                .sum::<u32>()
+        };
+        assert!(total_area(&result.rooms) > total_area(&plain.rooms));
+    }
+
+    #[test]
+    fn test_script_runtime_errors() {
+        let generate = |source: &str| {
+            let script = GenerationScript::compile(source).unwrap();
+            let mut config = Dungeon3DGeneratorConfig {
+                seed: Some(0),
+                foreign_room_penalty: 20,
+                ..Default::default()
+            };
+            script.apply(&mut config);
+            generate_dungeon_3d(config).unwrap();
+            script
+        };
+        // 誤字の変数と数でない値は既定値で続け、最初のエラーを返す
+        let script = generate(
+            r#"
+            fn score_room(x, y, z, width, height, depth, hierarchy) {
+                "large"
+            }
+            fn passage_cost(x, y, z) {
+                hieght
+            }
+            "#,
+        );
+        let error = script.take_runtime_error().unwrap();
+        assert!(matches!(
+            &error,
+            ScriptError::Runtime { function: "score_room", message } if message.contains("string")
+        ));
+        assert!(script.take_runtime_error().is_none());
+
+        // i32 に収まらないコストは丸められ、エラーにならない
+        let script = generate(
+            r#"
+            fn passage_cost(x, y, z) {
+                if x == 10 { 10000000000 } else { 0 }
+            }
+            "#,
+        );
+        assert!(script.take_runtime_error().is_none());
+        let error = generate("fn passage_cost(x, y, z) { hieght }")
+            .take_runtime_error()
+            .unwrap();
+        assert!(error.to_string().starts_with("passage_cost failed:"));
+        assert!(error.to_string().contains("hieght"));
+    }
+}
@@ -0,0 +1,166 @@
+use crate::generate_drd::Dungeon3DGeneratorResult;
+use crate::graph_export::passage_length;
+use crate::passage::PassageId;
+use crate::room::RoomId;
+use std::collections::BTreeMap;
+
+// 減衰の係数。単位は利用側で決める。dB として扱うと足し合わせで経路全体の減衰になる
+#[derive(Debug, Clone, PartialEq)]
+pub struct SoundAttenuation {
+    pub per_cell: f32, // Loss per walkable cell of the passage
+    pub per_step: f32, // Extra loss per stair step
+    pub doorway: f32,  // Loss through a one-voxel doorway; wider doorways divide it by their width
+}
+
+impl Default for SoundAttenuation {
+    fn default() -> Self {
+        SoundAttenuation {
+            per_cell: 0.5,
+            per_step: 1.0,
+            doorway: 3.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SoundEdge {
+    pub passage_id: PassageId,
+    pub room0_id: RoomId,
+    pub room1_id: RoomId,
+    pub length: usize,              // Walkable cells of the passage
+    pub steps: u32,                 // Stair steps along the passage
+    pub doorway_widths: (u32, u32), // Doorway voxels into room0 and room1; 0 where the passage joins another passage
+    pub attenuation: f32,
+}
+
+// 通路ごとに、長さ、階段の段数、出入口の幅から部屋の間の音の減衰を求める
+// 音響ミドルウェアがジオメトリへのレイキャストなしで遮蔽と伝搬を近似できるようにする
+pub fn sound_graph(
+    result: &Dungeon3DGeneratorResult,
+    attenuation: &SoundAttenuation,
+) -> Vec<SoundEdge> {
+    let mut steps: BTreeMap<PassageId, u32> = BTreeMap::new();
+    for stair in result.stairs.iter() {
+        *steps.entry(stair.passage_id).or_default() += stair.steps;
+    }
+    let mut doorway_widths: BTreeMap<(PassageId, RoomId), u32> = BTreeMap::new();
+    for doorway in result.doorways.iter() {
+        *doorway_widths
+            .entry((doorway.passage_id, doorway.room_id))
+            .or_default() += 1;
+    }
+    result
+        .passages
+        .iter()
+        .filter(|(_, passage)| passage.start_room_id != passage.end_room_id)
+        .map(|(passage_id, passage)| {
+            let length = passage_length(&result.voxel_map, passage);
+            let steps = steps.get(passage_id).copied().unwrap_or(0);
+            let width = |room_id: RoomId| {
+                doorway_widths
+                    .get(&(*passage_id, room_id))
+                    .copied()
+                    .unwrap_or(0)
+            };
+            let doorway_widths = (width(passage.start_room_id), width(passage.end_room_id));
+            let doorway_loss = [doorway_widths.0, doorway_widths.1]
+                .into_iter()
+                .filter(|width| *width > 0)
+                .map(|width| attenuation.doorway / width as f32)
+                .sum::<f32>();
+            SoundEdge {
+                passage_id: *passage_id,
+                room0_id: passage.start_room_id,
+                room1_id: passage.end_room_id,
+                length,
+                steps,
+                doorway_widths,
+                attenuation: length as f32 * attenuation.per_cell
+                    + steps as f32 * attenuation.per_step
+                    + doorway_loss,
+            }
+        })
+        .collect()
+}
+
+// export_graph_json と同じ部屋の番号を使い、辺に減衰を持たせて書き出す
+pub fn export_sound_graph_json(
+    result: &Dungeon3DGeneratorResult,
+    attenuation: &SoundAttenuation,
+) -> String {
+    let edges = sound_graph(result, attenuation)
+        .iter()
+        .map(|edge| {
+            format!(
+                "{{\"id\":{},\"source\":{},\"target\":{},\"attenuation\":{},\"length\":{},\"steps\":{},\"doorway_widths\":[{},{}]}}",
+                edge.passage_id.inner(),
+                edge.room0_id.inner(),
+                edge.room1_id.inner(),
+                edge.attenuation,
+                edge.length,
+                edge.steps,
+                edge.doorway_widths.0,
+                edge.doorway_widths.1
+            )
+        })
+        .collect::<Vec<_>>();
+    let nodes = result
+        .rooms
+        .keys()
+        .map(|room_id| format!("{{\"id\":{}}}", room_id.inner()))
+        .collect::<Vec<_>>();
+    format!(
+        "{{\"nodes\":[{}],\"edges\":[{}]}}",
+        nodes.join(","),
+        edges.join(",")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generate_drd::{generate_dungeon_3d, Dungeon3DGeneratorConfig};
+    use crate::sound::{export_sound_graph_json, sound_graph, SoundAttenuation};
+
+    #[test]
+    fn test_sound_graph() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            wide_doorway_room_size: Some(6),
+            ..Default::default()
+        })
+        .unwrap();
+        let attenuation = SoundAttenuation::default();
+        let edges = sound_graph(&result, &attenuation);
+        assert!(!edges.is_empty());
+        assert!(edges.iter().any(|edge| edge.steps > 0));
+        assert!(edges
+            .iter()
+            .any(|edge| edge.doorway_widths.0 > 1 || edge.doorway_widths.1 > 1));
+        for edge in edges.iter() {
+            assert!(edge.length > 0);
+            assert!(edge.attenuation >= edge.length as f32 * attenuation.per_cell);
+        }
+
+        // 出入口だけで減衰させると、広い出入口ほど小さくなる
+        let doorway_only = SoundAttenuation {
+            per_cell: 0.0,
+            per_step: 0.0,
+            doorway: 4.0,
+        };
+        for edge in sound_graph(&result, &doorway_only) {
+            let expected = [edge.doorway_widths.0, edge.doorway_widths.1]
+                .into_iter()
+                .filter(|width| *width > 0)
+                .map(|width| 4.0 / width as f32)
+                .sum::<f32>();
+            assert_eq!(edge.attenuation, expected);
+        }
+
+        let json = export_sound_graph_json(&result, &attenuation);
+        assert_eq!(json.matches("\"attenuation\":").count(), edges.len());
+        assert_eq!(
+            json.matches("{\"id\":").count(),
+            edges.len() + result.rooms.len()
+        );
+    }
+}
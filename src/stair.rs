@@ -0,0 +1,107 @@
+use crate::constants::{Direction4, VoxelType};
+use crate::passage::{Passage, PassageId};
+use nalgebra::Vector3;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Stair {
+    pub passage_id: PassageId,
+    pub bottom: (i32, i32, i32), // Walkable cell in front of the lowest step
+    pub top: (i32, i32, i32),    // Walkable cell beyond the highest step
+    pub direction: Direction4,   // Horizontal direction of walking up
+    pub steps: u32,              // Number of PassageStair voxels
+    pub rise: u32,               // Voxels climbed from bottom to top
+}
+
+// 通路ごとに、同じ向きで続く PassageStair のボクセルを1つの階段にまとめる
+// stair_run が 2 以上の場合は同じ高さで隣り合うボクセルも同じ階段の一部になる
+// 階段のボクセルの上面を歩くため、下の踊り場は段と同じ高さ、上の踊り場は1つ上になる
+pub fn find_stairs(passages: &BTreeMap<PassageId, Passage>) -> Vec<Stair> {
+    let mut stairs = Vec::new();
+    for (passage_id, passage) in passages.iter() {
+        let steps = passage
+            .cells
+            .iter()
+            .filter_map(|((x, y, z), voxel_type)| match voxel_type {
+                VoxelType::PassageStair(direction) => Some(((*x, *y, *z), *direction)),
+                _ => None,
+            })
+            .collect::<BTreeMap<_, _>>();
+        // 同じ向きで続く隣の段。同じ高さのものを優先する
+        let neighbor = |(x, y, z): (i32, i32, i32), direction: Direction4, sign: i32| {
+            let v = direction.to_vec3() * sign;
+            [(x + v.x, y, z + v.z), (x + v.x, y + sign, z + v.z)]
+                .into_iter()
+                .find(|p| steps.get(p) == Some(&direction))
+        };
+        for (position, direction) in steps.iter() {
+            if neighbor(*position, *direction, -1).is_some() {
+                continue;
+            }
+            let mut last = *position;
+            let mut count = 1;
+            while let Some(next) = neighbor(last, *direction, 1) {
+                last = next;
+                count += 1;
+            }
+            let v = direction.to_vec3();
+            let bottom = Vector3::new(position.0, position.1, position.2) - v;
+            let top = Vector3::new(last.0, last.1 + 1, last.2) + v;
+            stairs.push(Stair {
+                passage_id: *passage_id,
+                bottom: (bottom.x, bottom.y, bottom.z),
+                top: (top.x, top.y, top.z),
+                direction: *direction,
+                steps: count,
+                rise: (top.y - bottom.y) as u32,
+            });
+        }
+    }
+    stairs
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::VoxelType;
+    use crate::generate_drd::{generate_dungeon_3d, Dungeon3DGeneratorConfig};
+    use nalgebra::Vector3;
+
+    #[test]
+    fn test_find_stairs() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(!result.stairs.is_empty());
+        let stair_voxels = result
+            .voxel_map
+            .map
+            .values()
+            .filter(|voxel_type| matches!(voxel_type, VoxelType::PassageStair(_)))
+            .count();
+        assert_eq!(
+            result
+                .stairs
+                .iter()
+                .map(|stair| stair.steps as usize)
+                .sum::<usize>(),
+            stair_voxels
+        );
+        for stair in result.stairs.iter() {
+            let (bx, by, bz) = stair.bottom;
+            let (tx, ty, tz) = stair.top;
+            assert_eq!(ty - by, stair.rise as i32);
+            assert_eq!(stair.rise, stair.steps);
+            assert_eq!((tx - bx).abs() + (tz - bz).abs(), stair.steps as i32 + 1);
+            assert!(result
+                .voxel_map
+                .get(&Vector3::new(bx, by, bz))
+                .is_passable());
+            assert!(result
+                .voxel_map
+                .get(&Vector3::new(tx, ty, tz))
+                .is_passable());
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use crate::dead_end::find_dead_ends;
+use crate::doorway::mark_doorways;
+use crate::generate_drd::{carve_between, Dungeon3DGeneratorError, Dungeon3DGeneratorResult};
+use crate::junction::find_junctions;
+use crate::material::{assign_materials, MaterialPalette};
+use crate::passage::PassageId;
+use crate::room::RoomId;
+use crate::stair::find_stairs;
+use crate::voxel_map::{MergeConflict, RouteStats, VoxelMapError};
+use nalgebra::Vector3;
+use std::collections::BTreeMap;
+
+const STITCH_CANDIDATES: usize = 8; // Nearest room pairs tried before giving up
+
+// b を offset だけ平行移動して a に合成し、最も近い部屋の組の間に通路を1本掘る
+// b の部屋と通路は a と重ならない番号に振り直す。b の地表への入口と生成の記録は引き継がない
+// ボクセルが重なる場合や通路が掘れない場合はエラーを返し、a は変更しない
+// つなぐ通路の材質は MaterialPalette の既定値になる
+pub fn stitch(
+    a: &mut Dungeon3DGeneratorResult,
+    mut b: Dungeon3DGeneratorResult,
+    offset: (u32, u32, u32),
+) -> Result<PassageId, Dungeon3DGeneratorError> {
+    let next_room_id = a
+        .rooms
+        .keys()
+        .last()
+        .map_or(RoomId::first(), |room_id| room_id.shifted(1));
+    let next_passage_id = a
+        .passages
+        .keys()
+        .last()
+        .map_or(PassageId::first(), |passage_id| passage_id.shifted(1));
+    b.remap_ids(next_room_id);
+    b.remap_passage_ids(next_passage_id);
+    b.checked_translate(offset)
+        .map_err(Dungeon3DGeneratorError::VoxelMapError)?;
+
+    let mut voxel_map = a.voxel_map.clone();
+    voxel_map
+        .merge(&b.voxel_map, Vector3::zeros(), MergeConflict::Error)
+        .map_err(Dungeon3DGeneratorError::VoxelMapError)?;
+
+    // 中心の近い部屋の組から順に通路を掘る
+    let mut pairs = a
+        .rooms
+        .values()
+        .flat_map(|room0| b.rooms.values().map(move |room1| (room0, room1)))
+        .map(|(room0, room1)| {
+            let (c0, c1) = (room0.center(), room1.center());
+            let distance = (c1.0 - c0.0).powi(2) + (c1.1 - c0.1).powi(2) + (c1.2 - c0.2).powi(2);
+            (distance, room0, room1)
+        })
+        .collect::<Vec<_>>();
+    pairs.sort_by(|x, y| x.0.total_cmp(&y.0));
+    let passage_height = a
+        .passages
+        .values()
+        .next()
+        .map_or(2, |passage| passage.height);
+    let mut error = VoxelMapError::Unreachable;
+    let mut stitched = None;
+    for (_, room0, room1) in pairs.into_iter().take(STITCH_CANDIDATES) {
+        match carve_between(
+            &mut voxel_map,
+            room0,
+            room1,
+            passage_height,
+            &mut RouteStats::default(),
+        ) {
+            Ok(passage) => {
+                stitched = Some(passage);
+                break;
+            }
+            Err(e) => error = e,
+        }
+    }
+    let passage = stitched.ok_or(Dungeon3DGeneratorError::VoxelMapError(error))?;
+
+    a.voxel_map = voxel_map;
+    a.rooms.append(&mut b.rooms);
+    a.passages.append(&mut b.passages);
+    a.room_connections.append(&mut b.room_connections);
+    a.failed_connections.append(&mut b.failed_connections);
+    a.repairs.append(&mut b.repairs);
+    a.objective_passages.append(&mut b.objective_passages);
+    a.unmet_hierarchy_boundaries
+        .append(&mut b.unmet_hierarchy_boundaries);
+    a.under_connected_rooms.append(&mut b.under_connected_rooms);
+    a.doorways.append(&mut b.doorways);
+    a.stairs.append(&mut b.stairs);
+    a.stairwell.append(&mut b.stairwell);
+    a.central_room = a.central_room.or(b.central_room);
+    a.waypoint_rooms.append(&mut b.waypoint_rooms);
+    a.room_extensions.append(&mut b.room_extensions);
+    for (p, material) in b.materials {
+        a.materials.entry(p).or_insert(material);
+    }
+
+    // つなぐ通路の出入口、階段、材質を求め、交差点と行き止まりは全体で求め直す
+    let passage_id = a
+        .passages
+        .keys()
+        .last()
+        .map_or(PassageId::first(), |passage_id| passage_id.shifted(1));
+    let mut stitched = BTreeMap::from([(passage_id, passage)]);
+    a.doorways
+        .extend(mark_doorways(&mut a.voxel_map, &mut stitched));
+    a.stairs.extend(find_stairs(&stitched));
+    for (p, material) in assign_materials(
+        &a.voxel_map,
+        &a.rooms,
+        &stitched,
+        &MaterialPalette::default(),
+    ) {
+        a.materials.entry(p).or_insert(material);
+    }
+    a.passages.extend(stitched);
+    a.junctions = find_junctions(&a.voxel_map, &a.passages);
+    a.dead_ends = find_dead_ends(&a.voxel_map, &a.passages);
+    Ok(passage_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::connectivity::room_components;
+    use crate::generate_drd::{generate_dungeon_3d, Dungeon3DGeneratorConfig};
+    use crate::stitch::stitch;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn test_stitch() {
+        let generate = |seed| {
+            generate_dungeon_3d(Dungeon3DGeneratorConfig {
+                seed: Some(seed),
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        let mut a = generate(0);
+        let b = generate(1);
+        let (a_rooms, b_rooms) = (a.rooms.len(), b.rooms.len());
+        let a_passages = a.passages.len();
+        assert!(stitch(&mut a, generate(1), (0, 0, 0)).is_err());
+        assert_eq!(a.rooms.len(), a_rooms);
+
+        let passage_id = stitch(&mut a, b, (40, 0, 0)).unwrap();
+        assert_eq!(a.rooms.len(), a_rooms + b_rooms);
+        assert!(a.passages.len() > a_passages);
+        let passage = &a.passages[&passage_id];
+        let ends = [passage.start_room_id.inner(), passage.end_room_id.inner()];
+        assert_eq!(ends.iter().filter(|id| **id <= a_rooms as u64).count(), 1);
+        // 部屋の番号は重ならず、全ての部屋が1つにつながる
+        let room_ids = a
+            .voxel_map
+            .map
+            .values()
+            .filter_map(|voxel_type| voxel_type.room_id())
+            .collect::<BTreeSet<_>>();
+        assert_eq!(room_ids, a.rooms.keys().copied().collect());
+        assert_eq!(room_components(&a.voxel_map, &a.rooms).len(), 1);
+    }
+}
@@ -0,0 +1,123 @@
+use crate::constants::{Direction4, VoxelType, DIRECTIONS};
+use crate::voxel_map::VoxelMap;
+use nalgebra::Vector3;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Tile {
+    Empty,
+    Floor,
+    Wall,
+    StairUp(Direction4),   // Climbs one level toward the direction
+    StairDown(Direction4), // Top of a stair which climbs toward the direction
+}
+
+#[derive(Debug, Clone)]
+pub struct TileLayer {
+    pub y: i32,                // Height of the walkable space in voxel coordinates
+    pub origin: (i32, i32),    // (x, z) of tiles[0][0]
+    pub tiles: Vec<Vec<Tile>>, // tiles[z][x]
+}
+
+impl TileLayer {
+    pub fn get(&self, x: i32, z: i32) -> Tile {
+        let (ox, oz) = self.origin;
+        self.tiles
+            .get((z - oz) as usize)
+            .and_then(|row| row.get((x - ox) as usize))
+            .copied()
+            .unwrap_or(Tile::Empty)
+    }
+}
+
+// 2.5D やタイルベースのエンジン向けに、歩ける空間の高さごとに2次元のタイルへ投影する
+pub fn project_to_layers(voxel_map: &VoxelMap) -> Vec<TileLayer> {
+    let (start, end) = voxel_map.bounds();
+    let mut layers = Vec::new();
+    for y in start.y..end.y {
+        let tiles = (start.z..end.z)
+            .map(|z| {
+                (start.x..end.x)
+                    .map(|x| project_tile(voxel_map, Vector3::new(x, y, z)))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        if tiles.iter().flatten().all(|tile| *tile == Tile::Empty) {
+            continue;
+        }
+        layers.push(TileLayer {
+            y,
+            origin: (start.x, start.z),
+            tiles,
+        });
+    }
+    layers
+}
+
+fn project_tile(voxel_map: &VoxelMap, p: Vector3<i32>) -> Tile {
+    if let Some(tile) = walkable_tile(voxel_map, p) {
+        return tile;
+    }
+    let touches_walkable = DIRECTIONS
+        .iter()
+        .any(|dir| walkable_tile(voxel_map, p + dir.to_vec3()).is_some());
+    if touches_walkable && !voxel_map.get(&p).is_passable() {
+        Tile::Wall
+    } else {
+        Tile::Empty
+    }
+}
+
+fn walkable_tile(voxel_map: &VoxelMap, p: Vector3<i32>) -> Option<Tile> {
+    match voxel_map.get(&p) {
+        VoxelType::PassageStair(direction) => Some(Tile::StairUp(direction)),
+        voxel_type if voxel_type.is_passable() => match voxel_map.get(&(p - Vector3::y())) {
+            VoxelType::PassageStair(direction) => Some(Tile::StairDown(direction)),
+            VoxelType::RoomFloor(_) | VoxelType::PassageFloor | VoxelType::CaveFloor => {
+                Some(Tile::Floor)
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::VoxelType;
+    use crate::generate_drd::{generate_dungeon_3d, Dungeon3DGeneratorConfig};
+    use crate::tilemap::{project_to_layers, Tile};
+
+    #[test]
+    fn test_project_to_layers() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        let layers = project_to_layers(&result.voxel_map);
+        assert!(!layers.is_empty());
+        for room in result.rooms.values() {
+            let layer = layers
+                .iter()
+                .find(|layer| layer.y == room.origin.1 as i32)
+                .unwrap();
+            assert_eq!(
+                layer.get(room.origin.0 as i32, room.origin.2 as i32),
+                Tile::Floor
+            );
+        }
+        // 階段の上り口と下り口が隣り合う層に現れること
+        for (p, voxel_type) in result.voxel_map.map.iter() {
+            if let VoxelType::PassageStair(direction) = voxel_type {
+                let get = |y: i32| {
+                    layers
+                        .iter()
+                        .find(|layer| layer.y == y)
+                        .map(|layer| layer.get(p.x, p.z))
+                };
+                assert_eq!(get(p.y), Some(Tile::StairUp(*direction)));
+                assert_eq!(get(p.y + 1), Some(Tile::StairDown(*direction)));
+            }
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use crate::checked::generate_dungeon_3d_checked;
+use crate::generate_drd::{Dungeon3DGeneratorConfig, Dungeon3DGeneratorError};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SeedStats {
+    pub seed: u64,
+    pub rooms: usize,
+    pub passages: usize,
+    pub repairs: usize,         // Connectivity repairs needed after carving
+    pub failed_passages: usize, // Optional passages which could not be carved
+    pub dead_ends: usize,
+    pub stairs: usize,
+    pub duration: Duration, // Wall-clock time of the whole generation
+}
+
+#[derive(Debug)]
+pub struct SeedSweep {
+    pub stats: Vec<SeedStats>, // Successful seeds in the order they were given
+    pub failures: Vec<(u64, Dungeon3DGeneratorError)>, // Seeds whose generation failed or panicked
+}
+
+impl SeedSweep {
+    // 修復と失敗した通路が最も少ないシード。同じ場合は通路が多く、番号が小さいものを選ぶ
+    pub fn best_seed(&self) -> Option<u64> {
+        self.stats
+            .iter()
+            .min_by_key(|stats| {
+                (
+                    stats.repairs + stats.failed_passages,
+                    usize::MAX - stats.passages,
+                    stats.seed,
+                )
+            })
+            .map(|stats| stats.seed)
+    }
+
+    pub fn failure_rate(&self) -> f64 {
+        let total = self.stats.len() + self.failures.len();
+        match total {
+            0 => 0.0,
+            _ => self.failures.len() as f64 / total as f64,
+        }
+    }
+}
+
+// シードを変えて生成を繰り返し、シードごとの統計と失敗を集める
+// 固定のレベルとして出荷するシードを選ぶ場合や、変更による品質の低下を統計的に確かめる場合に使う
+// rayon が有効ならシードごとに並列に生成する。panic も失敗として集める
+pub fn seed_sweep<I: IntoIterator<Item = u64>>(
+    config: &Dungeon3DGeneratorConfig,
+    seeds: I,
+) -> SeedSweep {
+    let seeds = seeds.into_iter().collect::<Vec<_>>();
+    #[cfg(feature = "rayon")]
+    let runs = seeds
+        .par_iter()
+        .map(|seed| run_seed(config, *seed))
+        .collect::<Vec<_>>();
+    #[cfg(not(feature = "rayon"))]
+    let runs = seeds
+        .iter()
+        .map(|seed| run_seed(config, *seed))
+        .collect::<Vec<_>>();
+
+    let mut sweep = SeedSweep {
+        stats: Vec::new(),
+        failures: Vec::new(),
+    };
+    for (seed, run) in seeds.into_iter().zip(runs) {
+        match run {
+            Ok(stats) => sweep.stats.push(stats),
+            Err(error) => sweep.failures.push((seed, error)),
+        }
+    }
+    sweep
+}
+
+fn run_seed(
+    config: &Dungeon3DGeneratorConfig,
+    seed: u64,
+) -> Result<SeedStats, Dungeon3DGeneratorError> {
+    let started = Instant::now();
+    let result = generate_dungeon_3d_checked(Dungeon3DGeneratorConfig {
+        seed: Some(seed),
+        report: true,
+        ..config.clone()
+    })?;
+    Ok(SeedStats {
+        seed,
+        rooms: result.rooms.len(),
+        passages: result.passages.len(),
+        repairs: result.repairs.len(),
+        failed_passages: result
+            .report
+            .as_ref()
+            .map_or(0, |report| report.failed_passages),
+        dead_ends: result.dead_ends.len(),
+        stairs: result.stairs.len(),
+        duration: started.elapsed(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generate_drd::{generate_dungeon_3d, Dungeon3DGeneratorConfig};
+    use crate::tools::seed_sweep;
+
+    #[test]
+    fn test_seed_sweep() {
+        let config = Dungeon3DGeneratorConfig::default();
+        let sweep = seed_sweep(&config, [3, 0, 1]);
+        assert!(sweep.failures.is_empty());
+        assert_eq!(
+            sweep
+                .stats
+                .iter()
+                .map(|stats| stats.seed)
+                .collect::<Vec<_>>(),
+            vec![3, 0, 1]
+        );
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(sweep.stats[1].rooms, result.rooms.len());
+        assert_eq!(sweep.stats[1].passages, result.passages.len());
+        assert!(sweep.best_seed().is_some());
+        assert_eq!(sweep.failure_rate(), 0.0);
+
+        let sweep = seed_sweep(&Dungeon3DGeneratorConfig { width: 4, ..config }, 0..2);
+        assert_eq!(sweep.failures.len(), 2);
+        assert_eq!(sweep.best_seed(), None);
+        assert_eq!(sweep.failure_rate(), 1.0);
+    }
+}
@@ -0,0 +1,284 @@
+use crate::constants::Axis;
+use crate::generate_drd::{
+    layout_connections, sample_room_size, BlockSpan, ConfigViolation, Dungeon3DGeneratorConfig,
+    Dungeon3DGeneratorError, Dungeon3DGeneratorResult,
+};
+use crate::generation_report::GenerationPhase;
+use crate::pipeline::{GenerationPipeline, GenerationState, PlaceRooms, SelectEdges};
+use crate::room::{Room, RoomId};
+use rand::{Rng, RngCore};
+use std::ops::RangeInclusive;
+use std::time::Instant;
+
+// 各階の中央に階段室を重ね、その周りに1つか2つの部屋を置く
+// 幅と奥行きが小さく高さが大きいダンジョンでは区画の分割が細くなりすぎるため、こちらを使う
+// room_anchors と room_placement_score、地表の高さは考慮しない
+pub struct TowerPlaceRooms {
+    pub stairwell_width: u32, // Width of the stairwell room on every floor (x-axis)
+    pub stairwell_depth: u32, // Depth of the stairwell room on every floor (z-axis)
+    pub rooms_per_floor: RangeInclusive<u32>, // Rooms beside the stairwell on each floor; at most 2
+}
+
+impl Default for TowerPlaceRooms {
+    fn default() -> Self {
+        TowerPlaceRooms {
+            stairwell_width: 4,
+            stairwell_depth: 4,
+            rooms_per_floor: 1..=2,
+        }
+    }
+}
+
+impl PlaceRooms for TowerPlaceRooms {
+    fn place_rooms(&self, state: &mut GenerationState<'_>) -> Result<(), Dungeon3DGeneratorError> {
+        let GenerationState {
+            config,
+            rng,
+            report,
+            rooms,
+            voxel_map,
+            stairwell,
+            ..
+        } = state;
+        config.room_margin_x = config.room_margin_x.max(1);
+        config.room_margin_y = config.room_margin_y.max(1);
+        config.room_margin_z = config.room_margin_z.max(1);
+        let mut violations = Vec::new();
+        if config.room_hierarchy == 0 {
+            violations.push(ConfigViolation::new(
+                "room_hierarchy",
+                config.room_hierarchy,
+                ">= 1",
+            ));
+        } else {
+            let min_height = config
+                .room_hierarchy
+                .saturating_mul(config.room_height_range.start() + config.room_margin_y);
+            if config.height < min_height {
+                violations.push(ConfigViolation::new(
+                    "height",
+                    config.height,
+                    format!(">= {}", min_height),
+                ));
+            }
+        }
+        let stairwell_x = config.width.saturating_sub(self.stairwell_width) / 2;
+        let stairwell_z = config.depth.saturating_sub(self.stairwell_depth) / 2;
+        for (field, value, stairwell, stairwell_size) in [
+            ("width", config.width, stairwell_x, self.stairwell_width),
+            ("depth", config.depth, stairwell_z, self.stairwell_depth),
+        ] {
+            if stairwell < config.boundary_margin {
+                let min = stairwell_size + 2 * config.boundary_margin;
+                violations.push(ConfigViolation::new(field, value, format!(">= {}", min)));
+            }
+        }
+        if !violations.is_empty() {
+            return Err(Dungeon3DGeneratorError::ConfigValidation(violations));
+        }
+
+        let phase_start = Instant::now();
+        let mut room_id = RoomId::first();
+        let h_block_size = config.height / config.room_hierarchy;
+        for ry in 0..config.room_hierarchy {
+            let y_span = BlockSpan::new(
+                ry,
+                config.room_hierarchy,
+                h_block_size,
+                config.height,
+                config.room_margin_y,
+                config.boundary_margin,
+            );
+            // 同じ階の部屋は床の高さをそろえ、階段室と平らな通路でつなぐ
+            let mut add_room = |rng: &mut dyn RngCore, width, depth, origin: (u32, u32)| {
+                let height = sample_room_size(
+                    rng,
+                    &config.room_height_range,
+                    y_span.available,
+                    Axis::Y,
+                    (origin.0, ry, origin.1),
+                )?;
+                let id = room_id.gen_id();
+                rooms.insert(
+                    id,
+                    Room {
+                        hierarchy: ry,
+                        ..Room::new(id, width, height, depth, (origin.0, y_span.start, origin.1))
+                    },
+                );
+                Ok::<_, Dungeon3DGeneratorError>(id)
+            };
+            stairwell.push(add_room(
+                &mut **rng,
+                self.stairwell_width,
+                self.stairwell_depth,
+                (stairwell_x, stairwell_z),
+            )?);
+
+            // 階ごとに階段室を挟む軸を選び、その両側か片側に部屋を置く
+            let count = rng
+                .gen_range(*self.rooms_per_floor.start()..=*self.rooms_per_floor.end())
+                .clamp(1, 2);
+            let axis = if rng.gen_bool(0.5) { Axis::X } else { Axis::Z };
+            let sides = match count {
+                1 => vec![rng.gen_range(0..2)],
+                _ => vec![0, 1],
+            };
+            for side in sides {
+                let (along, across) = match axis {
+                    Axis::X => (
+                        side_span(
+                            side,
+                            stairwell_x,
+                            self.stairwell_width,
+                            config.width,
+                            config.room_margin_x,
+                            config.boundary_margin,
+                        ),
+                        (
+                            config.boundary_margin,
+                            config.depth.saturating_sub(2 * config.boundary_margin),
+                        ),
+                    ),
+                    _ => (
+                        side_span(
+                            side,
+                            stairwell_z,
+                            self.stairwell_depth,
+                            config.depth,
+                            config.room_margin_z,
+                            config.boundary_margin,
+                        ),
+                        (
+                            config.boundary_margin,
+                            config.width.saturating_sub(2 * config.boundary_margin),
+                        ),
+                    ),
+                };
+                let ((x_start, x_available), (z_start, z_available)) = match axis {
+                    Axis::X => (along, across),
+                    _ => (across, along),
+                };
+                let block = (side, ry, 0);
+                let width =
+                    sample_room_size(rng, &config.room_width_range, x_available, Axis::X, block)?;
+                let depth =
+                    sample_room_size(rng, &config.room_depth_range, z_available, Axis::Z, block)?;
+                let x = rng.gen_range(x_start..=x_start + x_available - width);
+                let z = rng.gen_range(z_start..=z_start + z_available - depth);
+                add_room(&mut **rng, width, depth, (x, z))?;
+            }
+        }
+        report.record(GenerationPhase::PlaceRooms, phase_start);
+
+        let phase_start = Instant::now();
+        for room in rooms.values() {
+            voxel_map
+                .add_room(room)
+                .map_err(Dungeon3DGeneratorError::VoxelMapError)?;
+        }
+        report.record(GenerationPhase::VoxelizeRooms, phase_start);
+        Ok(())
+    }
+}
+
+// 階段室の手前 (side 0) か奥 (side 1) の (開始位置, 長さ)。部屋同士の余白は階段室の側に取る
+fn side_span(
+    side: u32,
+    stairwell_start: u32,
+    stairwell_size: u32,
+    total: u32,
+    margin: u32,
+    boundary_margin: u32,
+) -> (u32, u32) {
+    match side {
+        0 => (
+            boundary_margin,
+            stairwell_start.saturating_sub(margin + boundary_margin),
+        ),
+        _ => {
+            let start = stairwell_start + stairwell_size + margin;
+            (
+                start,
+                total.saturating_sub(boundary_margin).saturating_sub(start),
+            )
+        }
+    }
+}
+
+// 階段室を上下の階とつなぎ、各階の部屋をその階の階段室とつなぐ
+pub struct TowerSelectEdges;
+
+impl SelectEdges for TowerSelectEdges {
+    fn select_edges(&self, state: &mut GenerationState<'_>) -> Result<(), Dungeon3DGeneratorError> {
+        let phase_start = Instant::now();
+        let mut selected = state
+            .stairwell
+            .windows(2)
+            .map(|pair| (pair[0], pair[1], true))
+            .collect::<Vec<_>>();
+        for room in state.rooms.values() {
+            if !state.stairwell.contains(&room.id) {
+                selected.push((state.stairwell[room.hierarchy as usize], room.id, true));
+            }
+        }
+        state.connections = layout_connections(&state.rooms, selected, state.config.passage_height);
+        state
+            .report
+            .record(GenerationPhase::SelectEdges, phase_start);
+        Ok(())
+    }
+}
+
+impl GenerationPipeline {
+    // 塔型のダンジョンを生成する段階の組み合わせ
+    pub fn tower() -> Self {
+        GenerationPipeline {
+            place_rooms: Box::new(TowerPlaceRooms::default()),
+            select_edges: Box::new(TowerSelectEdges),
+            ..Default::default()
+        }
+    }
+}
+
+pub fn generate_tower(
+    config: Dungeon3DGeneratorConfig,
+) -> Result<Dungeon3DGeneratorResult, Dungeon3DGeneratorError> {
+    GenerationPipeline::tower().generate(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generate_drd::Dungeon3DGeneratorConfig;
+    use crate::preset::Preset;
+    use crate::tower::generate_tower;
+    use crate::validation::validate;
+
+    #[test]
+    fn test_generate_tower() {
+        let config = Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Dungeon3DGeneratorConfig::preset(Preset::Tower)
+        };
+        let room_hierarchy = config.room_hierarchy;
+        let result = generate_tower(config).unwrap();
+        assert_eq!(result.stairwell.len(), room_hierarchy as usize);
+        let bottom = &result.rooms[&result.stairwell[0]];
+        for (hierarchy, room_id) in result.stairwell.iter().enumerate() {
+            let room = &result.rooms[room_id];
+            assert_eq!(room.hierarchy, hierarchy as u32);
+            assert_eq!(
+                (room.origin.0, room.origin.2),
+                (bottom.origin.0, bottom.origin.2)
+            );
+            let floor_rooms = result
+                .rooms
+                .values()
+                .filter(|r| r.hierarchy == room.hierarchy)
+                .collect::<Vec<_>>();
+            assert!((2..=3).contains(&floor_rooms.len()));
+            assert!(floor_rooms.iter().all(|r| r.origin.1 == room.origin.1));
+        }
+        assert!(validate(&result).is_empty());
+    }
+}
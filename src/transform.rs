@@ -0,0 +1,198 @@
+use crate::constants::Direction4;
+use crate::generate_drd::Dungeon3DGeneratorResult;
+use crate::room::Room;
+use nalgebra::Vector3;
+
+impl Dungeon3DGeneratorResult {
+    // ボクセルマップの範囲の中で y 軸回りに 90 度ずつ回す。1回で Left は Far、Far は Right を向く
+    // 回すと範囲の幅と奥行きが入れ替わる。範囲の始点は変わらない
+    pub fn rotate_y(&mut self, quarter_turns: i32) {
+        for _ in 0..quarter_turns.rem_euclid(4) {
+            let (start, end) = self.voxel_map.bounds();
+            let size = end - start;
+            self.transform(
+                |p| {
+                    Vector3::new(
+                        start.x + size.z - 1 - (p.z - start.z),
+                        p.y,
+                        start.z + p.x - start.x,
+                    )
+                },
+                |v| Vector3::new(-v.z, 0, v.x),
+                |room| {
+                    let x = start.x + size.z - (room.origin.2 as i32 - start.z) - room.depth as i32;
+                    let z = start.z + room.origin.0 as i32 - start.x;
+                    Room {
+                        width: room.depth,
+                        depth: room.width,
+                        origin: (x as u32, room.origin.1, z as u32),
+                        center_offset: (
+                            room.center_offset.2,
+                            room.center_offset.1,
+                            room.center_offset.0,
+                        ),
+                        ..room.clone()
+                    }
+                },
+                start + Vector3::new(size.z, size.y, size.x),
+            );
+        }
+    }
+
+    // ボクセルマップの範囲の中で x 軸方向に反転する。Left と Right が入れ替わる
+    pub fn mirror_x(&mut self) {
+        let (start, end) = self.voxel_map.bounds();
+        self.transform(
+            |p| Vector3::new(start.x + end.x - 1 - p.x, p.y, p.z),
+            |v| Vector3::new(-v.x, 0, v.z),
+            |room| {
+                let x = start.x + end.x - room.origin.0 as i32 - room.width as i32;
+                Room {
+                    origin: (x as u32, room.origin.1, room.origin.2),
+                    ..room.clone()
+                }
+            },
+            end,
+        );
+    }
+
+    // ボクセルマップの範囲の中で z 軸方向に反転する。Far と Near が入れ替わる
+    pub fn mirror_z(&mut self) {
+        let (start, end) = self.voxel_map.bounds();
+        self.transform(
+            |p| Vector3::new(p.x, p.y, start.z + end.z - 1 - p.z),
+            |v| Vector3::new(v.x, 0, -v.z),
+            |room| {
+                let z = start.z + end.z - room.origin.2 as i32 - room.depth as i32;
+                Room {
+                    origin: (room.origin.0, room.origin.1, z as u32),
+                    ..room.clone()
+                }
+            },
+            end,
+        );
+    }
+
+    // ボクセルの位置 point、向きのベクトル vector、部屋 room の変換を全ての要素に適用する
+    fn transform<F, G, H>(&mut self, point: F, vector: G, room: H, end: Vector3<i32>)
+    where
+        F: Fn(Vector3<i32>) -> Vector3<i32>,
+        G: Fn(Vector3<i32>) -> Vector3<i32>,
+        H: Fn(&Room) -> Room,
+    {
+        let cell = |(x, y, z): (i32, i32, i32)| {
+            let p = point(Vector3::new(x, y, z));
+            (p.x, p.y, p.z)
+        };
+        let direction =
+            |direction: Direction4| Direction4::from_vec3(&vector(direction.to_vec3())).unwrap();
+
+        for r in self
+            .rooms
+            .values_mut()
+            .chain(self.room_extensions.values_mut().flatten())
+        {
+            *r = room(r);
+        }
+        self.voxel_map.transform_horizontal(&point, direction, end);
+        for passage in self.passages.values_mut() {
+            passage.start = cell(passage.start);
+            passage.start_dirs = passage.start_dirs.iter().map(|d| direction(*d)).collect();
+            for (p, voxel_type) in passage.cells.iter_mut() {
+                *p = cell(*p);
+                *voxel_type = voxel_type.map_direction(direction);
+            }
+        }
+        if let Some(surface_entrance) = self.surface_entrance.as_mut() {
+            surface_entrance.opening = cell(surface_entrance.opening);
+            for (p, voxel_type) in surface_entrance.cells.iter_mut() {
+                *p = cell(*p);
+                *voxel_type = voxel_type.map_direction(direction);
+            }
+        }
+        for doorway in self.doorways.iter_mut() {
+            doorway.position = cell(doorway.position);
+        }
+        for junction in self.junctions.iter_mut() {
+            junction.position = cell(junction.position);
+        }
+        for dead_end in self.dead_ends.iter_mut() {
+            for p in dead_end.cells.iter_mut() {
+                *p = cell(*p);
+            }
+        }
+        for stair in self.stairs.iter_mut() {
+            stair.bottom = cell(stair.bottom);
+            stair.top = cell(stair.top);
+            stair.direction = direction(stair.direction);
+        }
+        self.materials = std::mem::take(&mut self.materials)
+            .into_iter()
+            .map(|(p, material)| (point(p), material))
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::VoxelType;
+    use crate::generate_drd::{
+        generate_dungeon_3d, Dungeon3DGeneratorConfig, Dungeon3DGeneratorResult,
+    };
+    use crate::validation::validate;
+    use nalgebra::Vector3;
+
+    #[test]
+    fn test_rotate_and_mirror() {
+        let generate = || {
+            generate_dungeon_3d(Dungeon3DGeneratorConfig {
+                seed: Some(0),
+                width: 40,
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        let original = generate();
+        let mut result = generate();
+        for transform in [
+            |result: &mut Dungeon3DGeneratorResult| result.rotate_y(1),
+            |result: &mut Dungeon3DGeneratorResult| result.mirror_x(),
+            |result: &mut Dungeon3DGeneratorResult| result.mirror_z(),
+        ] {
+            transform(&mut result);
+            assert_eq!(validate(&result), vec![]);
+            for room in result.rooms.values() {
+                let p = Vector3::new(room.origin.0, room.origin.1, room.origin.2).map(|v| v as i32);
+                let far = p + Vector3::new(room.width as i32 - 1, 0, room.depth as i32 - 1);
+                assert_eq!(
+                    result.voxel_map.get(&p),
+                    VoxelType::RoomBottomSpace(room.id)
+                );
+                assert_eq!(
+                    result.voxel_map.get(&far),
+                    VoxelType::RoomBottomSpace(room.id)
+                );
+            }
+            // 階段は向いている方向へ上る
+            for stair in result.stairs.iter() {
+                let v = stair.direction.to_vec3();
+                let rise =
+                    (stair.top.0 - stair.bottom.0) * v.x + (stair.top.2 - stair.bottom.2) * v.z;
+                assert!(rise > 0);
+                let first = Vector3::new(stair.bottom.0, stair.bottom.1, stair.bottom.2) + v;
+                assert!(
+                    matches!(
+                        result.voxel_map.get(&first),
+                        VoxelType::PassageStair(direction) if direction == stair.direction
+                    ) || stair.steps == 0
+                );
+            }
+        }
+        // 4回回す、2回反転すると元に戻る
+        result.mirror_x();
+        result.mirror_z();
+        result.rotate_y(3);
+        assert_eq!(result.voxel_map.bounds(), original.voxel_map.bounds());
+        assert_eq!(result.to_canonical_string(), original.to_canonical_string());
+    }
+}
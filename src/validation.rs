@@ -0,0 +1,279 @@
+use crate::connectivity::room_components;
+use crate::constants::{VoxelType, DIRECTIONS};
+use crate::generate_drd::Dungeon3DGeneratorResult;
+use crate::passage::PassageId;
+use crate::room::RoomId;
+use nalgebra::Vector3;
+use std::collections::BTreeSet;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ValidationIssue {
+    // Rooms are split into several groups which cannot reach each other
+    Disconnected {
+        components: Vec<BTreeSet<RoomId>>,
+    },
+    // A voxel does not hold the type its room or passage carved
+    VoxelConflict {
+        position: (i32, i32, i32),
+        expected: VoxelType,
+        found: VoxelType,
+    },
+    // A stair does not have enough free space above it or at its landing
+    StairWithoutClearance {
+        position: (i32, i32, i32),
+    },
+    // A passage is not attached to its start or end room
+    PassageNotAttached {
+        passage_id: PassageId,
+        room_id: RoomId,
+    },
+    OutOfBounds {
+        position: (i32, i32, i32),
+    },
+}
+
+pub fn validate(result: &Dungeon3DGeneratorResult) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    validate_connectivity(result, &mut issues);
+    validate_rooms(result, &mut issues);
+    validate_passages(result, &mut issues);
+    validate_bounds(result, &mut issues);
+    issues
+}
+
+fn validate_connectivity(result: &Dungeon3DGeneratorResult, issues: &mut Vec<ValidationIssue>) {
+    let components = room_components(&result.voxel_map, &result.rooms);
+    if components.len() > 1 {
+        issues.push(ValidationIssue::Disconnected { components });
+    }
+}
+
+fn validate_rooms(result: &Dungeon3DGeneratorResult, issues: &mut Vec<ValidationIssue>) {
+    // 他の部屋をまとめた部屋は、まとめた部屋の空間も同じ番号で埋まっている
+    for room in result
+        .rooms
+        .values()
+        .chain(result.room_extensions.values().flatten())
+    {
+        for y in -1..room.height as i32 {
+            for z in 0..room.depth as i32 {
+                for x in 0..room.width as i32 {
+                    let p = Vector3::new(
+                        x + room.origin.0 as i32,
+                        y + room.origin.1 as i32,
+                        z + room.origin.2 as i32,
+                    );
+                    let expected = match y {
+                        -1 => VoxelType::RoomFloor(room.id),
+                        0 => VoxelType::RoomBottomSpace(room.id),
+                        _ => VoxelType::RoomSpace(room.id),
+                    };
+                    let found = result.voxel_map.get(&p);
+                    if found != expected {
+                        issues.push(ValidationIssue::VoxelConflict {
+                            position: (p.x, p.y, p.z),
+                            expected,
+                            found,
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn validate_passages(result: &Dungeon3DGeneratorResult, issues: &mut Vec<ValidationIssue>) {
+    let voxel_map = &result.voxel_map;
+    for (passage_id, passage) in result.passages.iter() {
+        for ((x, y, z), expected) in passage.cells.iter() {
+            let p = Vector3::new(*x, *y, *z);
+            let found = voxel_map.get(&p);
+            if found != *expected {
+                issues.push(ValidationIssue::VoxelConflict {
+                    position: (*x, *y, *z),
+                    expected: *expected,
+                    found,
+                });
+            }
+            if let VoxelType::PassageStair(direction) = expected {
+                let is_space = |p: Vector3<i32>| voxel_map.get(&p) == VoxelType::PassageSpace;
+                let has_head_room =
+                    (1..=passage.height).all(|y| is_space(p + Vector3::new(0, y, 0)));
+                let landing = p + direction.to_vec3() + Vector3::new(0, 1, 0);
+                let has_landing = matches!(
+                    voxel_map.get(&landing),
+                    VoxelType::PassageSpace
+                        | VoxelType::RoomBottomSpace(_)
+                        | VoxelType::PassageStair(_)
+                        | VoxelType::Doorway(_)
+                );
+                if !has_head_room || !has_landing {
+                    issues.push(ValidationIssue::StairWithoutClearance {
+                        position: (*x, *y, *z),
+                    });
+                }
+            }
+        }
+
+        for room_id in [passage.start_room_id, passage.end_room_id] {
+            let attached = passage.cells.iter().any(|((x, y, z), voxel_type)| {
+                matches!(
+                    voxel_type,
+                    VoxelType::PassageSpace | VoxelType::PassageStair(_) | VoxelType::Doorway(_)
+                ) && DIRECTIONS.iter().any(|dir| {
+                    voxel_map.get(&(Vector3::new(*x, *y, *z) + dir.to_vec3()))
+                        == VoxelType::RoomBottomSpace(room_id)
+                })
+            });
+            if !attached {
+                issues.push(ValidationIssue::PassageNotAttached {
+                    passage_id: *passage_id,
+                    room_id,
+                });
+            }
+        }
+    }
+}
+
+fn validate_bounds(result: &Dungeon3DGeneratorResult, issues: &mut Vec<ValidationIssue>) {
+    let mut positions = result
+        .voxel_map
+        .map
+        .keys()
+        .filter(|p| !result.voxel_map.contains(p))
+        .map(|p| (p.x, p.y, p.z))
+        .collect::<Vec<_>>();
+    positions.sort();
+    issues.extend(
+        positions
+            .into_iter()
+            .map(|position| ValidationIssue::OutOfBounds { position }),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::{VoxelType, DIRECTIONS};
+    use crate::generate_drd::{
+        generate_dungeon_3d, Dungeon3DGeneratorConfig, Dungeon3DGeneratorResult,
+    };
+    use crate::validation::{validate, ValidationIssue};
+    use nalgebra::Vector3;
+
+    fn generate() -> Dungeon3DGeneratorResult {
+        generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_validate_generated_dungeons() {
+        for seed in 0..3 {
+            let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+                seed: Some(seed),
+                ..Default::default()
+            })
+            .unwrap();
+            assert_eq!(validate(&result), vec![], "seed: {}", seed);
+        }
+    }
+
+    #[test]
+    fn test_validate_rough_passages() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            passage_wall_noise: 0.5,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(validate(&result), vec![]);
+    }
+
+    #[test]
+    fn test_validate_removed_room_voxel() {
+        let mut result = generate();
+        let room = result.rooms.values().next().unwrap().clone();
+        let (x, y, z) = room.origin;
+        let p = Vector3::new(x as i32, y as i32, z as i32);
+        result.voxel_map.map.remove(&p);
+        assert!(validate(&result).contains(&ValidationIssue::VoxelConflict {
+            position: (p.x, p.y, p.z),
+            expected: VoxelType::RoomBottomSpace(room.id),
+            found: VoxelType::Wall,
+        }));
+    }
+
+    #[test]
+    fn test_validate_out_of_bounds() {
+        let mut result = generate();
+        let (_, end) = result.voxel_map.bounds();
+        result.voxel_map.map.insert(end, VoxelType::Wall);
+        assert_eq!(
+            validate(&result),
+            vec![ValidationIssue::OutOfBounds {
+                position: (end.x, end.y, end.z),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_validate_blocked_stair() {
+        let mut result = generate();
+        let (x, y, z) = result
+            .passages
+            .values()
+            .flat_map(|passage| passage.cells.iter())
+            .find(|(_, voxel_type)| matches!(voxel_type, VoxelType::PassageStair(_)))
+            .map(|(p, _)| *p)
+            .unwrap();
+        result
+            .voxel_map
+            .map
+            .insert(Vector3::new(x, y + 1, z), VoxelType::Wall);
+        assert!(
+            validate(&result).contains(&ValidationIssue::StairWithoutClearance {
+                position: (x, y, z)
+            })
+        );
+    }
+
+    #[test]
+    fn test_validate_detached_passage() {
+        let mut result = generate();
+        let (passage_id, passage) = result
+            .passages
+            .iter()
+            .find(|(_, passage)| passage.start_room_id != passage.end_room_id)
+            .map(|(passage_id, passage)| (*passage_id, passage.clone()))
+            .unwrap();
+        // 通路の開始側の部屋に接するセルを取り除くと、開始側の部屋から離れた通路になる
+        let room_id = passage.start_room_id;
+        let touches_room = |(x, y, z): &(i32, i32, i32)| {
+            DIRECTIONS.iter().any(|dir| {
+                result
+                    .voxel_map
+                    .get(&(Vector3::new(*x, *y, *z) + dir.to_vec3()))
+                    == VoxelType::RoomBottomSpace(room_id)
+            })
+        };
+        let cells = passage
+            .cells
+            .iter()
+            .filter(|(p, _)| !touches_room(p))
+            .copied()
+            .collect::<Vec<_>>();
+        assert!(cells.len() < passage.cells.len());
+        result.passages.get_mut(&passage_id).unwrap().cells = cells;
+        let issues = validate(&result);
+        assert!(issues.contains(&ValidationIssue::PassageNotAttached {
+            passage_id,
+            room_id,
+        }));
+        assert!(!issues.contains(&ValidationIssue::PassageNotAttached {
+            passage_id,
+            room_id: passage.end_room_id,
+        }));
+    }
+}
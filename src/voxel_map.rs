@@ -1,22 +1,98 @@
 use crate::btree_key_values::BTreeKeyValues;
-use crate::constants::{Direction4, VoxelType, DIRECTIONS};
+use crate::constants::{Axis, Direction4, VoxelType, DIRECTIONS};
 use crate::passage::Passage;
 use crate::room::{Room, RoomId};
 use nalgebra::Vector3;
-use std::collections::{BTreeMap, BTreeSet, HashMap};
+use rand::Rng;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::sync::Arc;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum VoxelMapError {
     Conflict,
     NoRoom(RoomId),
     Unreachable,
+    Budget,         // The route search explored more nodes than max_route_nodes allows
+    Overflow(Axis), // A translated coordinate would leave the i32 range along the axis
+}
+
+impl fmt::Display for VoxelMapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VoxelMapError::Conflict => write!(f, "voxel is already occupied"),
+            VoxelMapError::NoRoom(room_id) => {
+                write!(f, "room {} is not in the map", room_id.inner())
+            }
+            VoxelMapError::Unreachable => write!(f, "no passage route between the rooms"),
+            VoxelMapError::Budget => {
+                write!(f, "the passage route search ran out of its node budget")
+            }
+            VoxelMapError::Overflow(axis) => {
+                write!(
+                    f,
+                    "translated coordinates overflow along the {:?} axis",
+                    axis
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for VoxelMapError {}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct RouteStats {
+    pub explored_nodes: usize,
+    pub stored_route_cells: usize, // Voxels kept by the explored steps; each step keeps only the voxels it wrote
+    pub guide_nodes: usize, // Blocks visited while building the coarse distances of guided search
+}
+
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct RoomAvoidance {
+    pub forbid_foreign_rooms: bool, // Passages may not open into rooms other than their start and end rooms
+    pub foreign_room_penalty: i32,  // Extra cost of each step within two voxels of such a room
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MergeConflict {
+    KeepExisting,   // Voxels already in the map win
+    Overwrite,      // Voxels of the merged map win
+    PreferPassable, // Open space wins over solid voxels, so either map can cut through the other
+    Error,          // Fail with VoxelMapError::Conflict and leave the map unchanged
+}
+
+// 通路が1歩進むときの追加コストを返す関数。探索が初めて通った点で評価する
+#[derive(Clone)]
+struct StepCostFn(Arc<dyn Fn(i32, i32, i32) -> i32 + Send + Sync>);
+
+impl fmt::Debug for StepCostFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "StepCostFn")
+    }
+}
+
+impl PartialEq for StepCostFn {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
 }
 
-#[derive(Clone, Debug)]
+impl Eq for StepCostFn {}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct VoxelMap {
     pub map: HashMap<Vector3<i32>, VoxelType>,
     start: Vector3<i32>,
     end: Vector3<i32>,
+    surface: HashMap<(i32, i32), i32>, // (x, z) -> height of the terrain surface
+    room_avoidance: RoomAvoidance,
+    step_cost: Option<StepCostFn>, // Evaluated on demand for points missing from step_costs
+    step_costs: HashMap<Vector3<i32>, i32>, // Extra route cost of a passage step at the point; also caches step_cost
+    stair_run: i32,                         // Horizontal voxels of a stair per one-voxel climb
+    stair_clearance: Option<i32>, // Free voxels above a stair step; None uses the passage height
+    guided_search: bool, // Score passage routes by walkable distance to the end room instead of straight distance
+    max_route_nodes: Option<usize>, // Nodes one route search may explore before giving up
 }
 
 impl VoxelMap {
@@ -25,7 +101,343 @@ impl VoxelMap {
             map: Default::default(),
             start: Vector3::new(x, y, z),
             end: Vector3::new(x + width, y + height, z + depth),
+            surface: HashMap::new(),
+            room_avoidance: RoomAvoidance::default(),
+            step_cost: None,
+            step_costs: HashMap::new(),
+            stair_run: 1,
+            stair_clearance: None,
+            guided_search: false,
+            max_route_nodes: None,
+        }
+    }
+
+    // 階段で1段上がるごとに進む水平方向のボクセル数。1 で 45 度になる
+    pub fn set_stair_run(&mut self, stair_run: u32) {
+        self.stair_run = stair_run.max(1) as i32;
+    }
+
+    // 階段の上に空ける高さ。通路の高さより低くはならない
+    pub fn set_stair_clearance(&mut self, stair_clearance: Option<u32>) {
+        self.stair_clearance = stair_clearance.map(|clearance| clearance as i32);
+    }
+
+    // 部屋を回り込む距離で通路の探索を導く。部屋が間にある長い通路ほど探索する点が減る
+    pub fn set_guided_search(&mut self, guided_search: bool) {
+        self.guided_search = guided_search;
+    }
+
+    // 1回の通路の探索で調べる点の上限。超えた探索は VoxelMapError::Budget で打ち切る
+    pub fn set_max_route_nodes(&mut self, max_route_nodes: Option<u32>) {
+        self.max_route_nodes = max_route_nodes.map(|max| max as usize);
+    }
+
+    // 通路が始点と終点以外の部屋へ開口しないようにする
+    pub fn set_room_avoidance(&mut self, room_avoidance: RoomAvoidance) {
+        self.room_avoidance = room_avoidance;
+    }
+
+    // 地表の高さを設定すると、通路は地表より下にのみ掘られる
+    pub fn set_surface<F>(&mut self, surface_height: F)
+    where
+        F: Fn(i32, i32) -> i32,
+    {
+        self.surface.clear();
+        for x in self.start.x..self.end.x {
+            for z in self.start.z..self.end.z {
+                self.surface.insert((x, z), surface_height(x, z));
+            }
+        }
+    }
+
+    // 範囲内の各点で通路が1歩進むときの追加コストを設定する
+    // 関数は探索が初めて通った点でだけ評価し、結果を覚えておく
+    pub fn set_step_cost<F>(&mut self, step_cost: F)
+    where
+        F: Fn(i32, i32, i32) -> i32 + Send + Sync + 'static,
+    {
+        self.step_costs.clear();
+        self.step_cost = Some(StepCostFn(Arc::new(step_cost)));
+    }
+
+    // 点の追加コスト。範囲外の点は 0
+    fn extra_step_cost(&mut self, point: &Vector3<i32>) -> i32 {
+        if let Some(cost) = self.step_costs.get(point) {
+            return *cost;
+        }
+        let Some(step_cost) = self.step_cost.as_ref().filter(|_| self.contains(point)) else {
+            return 0;
+        };
+        let cost = (step_cost.0)(point.x, point.y, point.z);
+        self.step_costs.insert(*point, cost);
+        cost
+    }
+
+    // 範囲内の全ての点で関数を評価し、0 でない追加コストだけを残す
+    // 点の対応を逆にたどれない回転や合成の前に使う
+    fn evaluate_step_costs(&mut self) {
+        if let Some(step_cost) = self.step_cost.take() {
+            for x in self.start.x..self.end.x {
+                for y in self.start.y..self.end.y {
+                    for z in self.start.z..self.end.z {
+                        self.step_costs
+                            .entry(Vector3::new(x, y, z))
+                            .or_insert_with(|| (step_cost.0)(x, y, z));
+                    }
+                }
+            }
+        }
+        self.step_costs.retain(|_, cost| *cost != 0);
+    }
+
+    // 平行移動した後も同じ点で同じコストになるように、関数の引数を戻す
+    fn translate_step_cost(&mut self, offset: Vector3<i32>) {
+        if let Some(step_cost) = self.step_cost.take() {
+            self.step_cost = Some(StepCostFn(Arc::new(move |x, y, z| {
+                (step_cost.0)(x - offset.x, y - offset.y, z - offset.z)
+            })));
+        }
+    }
+
+    pub fn surface_height(&self, x: i32, z: i32) -> Option<i32> {
+        self.surface.get(&(x, z)).copied()
+    }
+
+    pub fn is_below_surface(&self, point: &Vector3<i32>) -> bool {
+        self.surface_height(point.x, point.z)
+            .is_none_or(|height| point.y < height)
+    }
+
+    pub fn bounds(&self) -> (Vector3<i32>, Vector3<i32>) {
+        (self.start, self.end)
+    }
+
+    // 実際に使われているボクセルだけを囲む最小の範囲 (終点は含まない)
+    pub fn occupied_bounds(&self) -> Option<(Vector3<i32>, Vector3<i32>)> {
+        let mut points = self.map.keys();
+        let first = *points.next()?;
+        Some(
+            points.fold((first, first + Vector3::new(1, 1, 1)), |(start, end), p| {
+                (start.inf(p), end.sup(&(p + Vector3::new(1, 1, 1))))
+            }),
+        )
+    }
+
+    // 通路用の余白を取り除き、使われている範囲の始点が原点になるよう平行移動する
+    // 部屋や通路の座標は occupied_bounds の始点を引いて合わせる
+    pub fn translated_to_origin(&self) -> VoxelMap {
+        let (start, end) = self.occupied_bounds().unwrap_or_default();
+        let size = end - start;
+        let mut voxel_map = VoxelMap::new(0, 0, 0, size.x, size.y, size.z);
+        voxel_map.map = self
+            .map
+            .iter()
+            .map(|(p, voxel_type)| (p - start, *voxel_type))
+            .collect();
+        voxel_map.surface = self
+            .surface
+            .iter()
+            .filter(|((x, z), _)| start.x <= *x && *x < end.x && start.z <= *z && *z < end.z)
+            .map(|((x, z), height)| ((x - start.x, z - start.z), height - start.y))
+            .collect();
+        voxel_map.room_avoidance = self.room_avoidance;
+        voxel_map.stair_run = self.stair_run;
+        voxel_map.stair_clearance = self.stair_clearance;
+        voxel_map.guided_search = self.guided_search;
+        voxel_map.max_route_nodes = self.max_route_nodes;
+        voxel_map.step_cost = self.step_cost.clone();
+        voxel_map.translate_step_cost(-start);
+        voxel_map.step_costs = self
+            .step_costs
+            .iter()
+            .map(|(p, cost)| (p - start, *cost))
+            .collect();
+        voxel_map
+    }
+
+    // 範囲、ボクセル、地表と通路のコストをまとめて平行移動する
+    // 座標が i32 の範囲を超える場合は panic する。ワールドの遠くに置く場合は checked_translate を使う
+    pub fn translate(&mut self, offset: Vector3<i32>) {
+        if let Some(axis) = self.translation_overflow(offset) {
+            panic!("{}", VoxelMapError::Overflow(axis));
+        }
+        self.translate_unchecked(offset);
+    }
+
+    // translate と同じだが、座標が i32 の範囲を超える場合は何も変更せずに VoxelMapError::Overflow を返す
+    pub fn checked_translate(&mut self, offset: Vector3<i32>) -> Result<(), VoxelMapError> {
+        if let Some(axis) = self.translation_overflow(offset) {
+            return Err(VoxelMapError::Overflow(axis));
+        }
+        self.translate_unchecked(offset);
+        Ok(())
+    }
+
+    // offset だけ動かすと i32 の範囲を超える座標がある最初の軸
+    pub(crate) fn translation_overflow(&self, offset: Vector3<i32>) -> Option<Axis> {
+        let (mut min, mut max) = (self.start, self.end);
+        let points = self
+            .map
+            .keys()
+            .chain(self.step_costs.keys())
+            .copied()
+            .chain(
+                self.surface
+                    .iter()
+                    .map(|((x, z), height)| Vector3::new(*x, *height, *z)),
+            );
+        for p in points {
+            min = min.inf(&p);
+            max = max.sup(&p);
+        }
+        [Axis::X, Axis::Y, Axis::Z]
+            .into_iter()
+            .enumerate()
+            .find(|(i, _)| {
+                min[*i].checked_add(offset[*i]).is_none()
+                    || max[*i].checked_add(offset[*i]).is_none()
+            })
+            .map(|(_, axis)| axis)
+    }
+
+    pub(crate) fn translate_unchecked(&mut self, offset: Vector3<i32>) {
+        self.start += offset;
+        self.end += offset;
+        self.map = self
+            .map
+            .drain()
+            .map(|(p, voxel_type)| (p + offset, voxel_type))
+            .collect();
+        self.surface = self
+            .surface
+            .drain()
+            .map(|((x, z), height)| ((x + offset.x, z + offset.z), height + offset.y))
+            .collect();
+        self.translate_step_cost(offset);
+        self.step_costs = self
+            .step_costs
+            .drain()
+            .map(|(p, cost)| (p + offset, cost))
+            .collect();
+    }
+
+    // 各点と階段の向きを水平方向に変換し、範囲の終点を end にする。生成結果の回転と反転に使う
+    pub(crate) fn transform_horizontal<F, G>(&mut self, point: F, direction: G, end: Vector3<i32>)
+    where
+        F: Fn(Vector3<i32>) -> Vector3<i32>,
+        G: Fn(Direction4) -> Direction4,
+    {
+        self.evaluate_step_costs();
+        self.end = end;
+        self.map = self
+            .map
+            .drain()
+            .map(|(p, voxel_type)| (point(p), voxel_type.map_direction(&direction)))
+            .collect();
+        self.surface = self
+            .surface
+            .drain()
+            .map(|((x, z), height)| {
+                let q = point(Vector3::new(x, 0, z));
+                ((q.x, q.z), height)
+            })
+            .collect();
+        self.step_costs = self
+            .step_costs
+            .drain()
+            .map(|(p, cost)| (point(p), cost))
+            .collect();
+    }
+
+    // 別の生成結果と合成する前に、部屋の番号を重ならないようにずらす
+    pub fn shift_room_ids(&mut self, amount: u64) {
+        for voxel_type in self.map.values_mut() {
+            *voxel_type = voxel_type.map_room_id(|room_id| room_id.shifted(amount));
+        }
+    }
+
+    // other を offset だけ平行移動して重ね、範囲を両方を含むように広げる
+    // 両方にあるボクセルは conflict に従う。Error の場合は何も変更しない
+    pub fn merge(
+        &mut self,
+        other: &VoxelMap,
+        offset: Vector3<i32>,
+        conflict: MergeConflict,
+    ) -> Result<(), VoxelMapError> {
+        if conflict == MergeConflict::Error
+            && other
+                .map
+                .keys()
+                .any(|p| self.map.contains_key(&(p + offset)))
+        {
+            return Err(VoxelMapError::Conflict);
+        }
+        for (p, voxel_type) in other.map.iter() {
+            let p = p + offset;
+            let keep = self.map.get(&p).is_some_and(|current| match conflict {
+                MergeConflict::KeepExisting => true,
+                MergeConflict::PreferPassable => current.is_passable() && !voxel_type.is_passable(),
+                MergeConflict::Overwrite | MergeConflict::Error => false,
+            });
+            if !keep {
+                self.map.insert(p, *voxel_type);
+            }
+        }
+        // 地表と通路のコストは既存のものを優先し、Overwrite の場合のみ上書きする
+        let overwrite = conflict == MergeConflict::Overwrite;
+        for ((x, z), height) in other.surface.iter() {
+            let key = (x + offset.x, z + offset.z);
+            if overwrite || !self.surface.contains_key(&key) {
+                self.surface.insert(key, height + offset.y);
+            }
+        }
+        let mut other = other.clone();
+        other.evaluate_step_costs();
+        self.evaluate_step_costs();
+        for (p, cost) in other.step_costs.iter() {
+            let p = p + offset;
+            if overwrite || !self.step_costs.contains_key(&p) {
+                self.step_costs.insert(p, *cost);
+            }
+        }
+        self.start = self.start.inf(&(other.start + offset));
+        self.end = self.end.sup(&(other.end + offset));
+        Ok(())
+    }
+
+    // mask を offset だけ平行移動し、mask で使われている位置のボクセルを取り除く
+    // 取り除いた位置は何も無い壁として扱われる
+    pub fn subtract(&mut self, mask: &VoxelMap, offset: Vector3<i32>) {
+        for p in mask.map.keys() {
+            self.map.remove(&(p + offset));
+        }
+    }
+
+    pub fn contains(&self, point: &Vector3<i32>) -> bool {
+        self.start.x <= point.x
+            && self.start.y <= point.y
+            && self.start.z <= point.z
+            && point.x < self.end.x
+            && point.y < self.end.y
+            && point.z < self.end.z
+    }
+
+    // HashMap の順序に依存しないよう、座標順に並べた1ボクセル1行の文字列にする
+    pub fn to_canonical_string(&self) -> String {
+        let mut voxels = self
+            .map
+            .iter()
+            .map(|(p, voxel_type)| ((p.x, p.y, p.z), *voxel_type))
+            .collect::<Vec<_>>();
+        voxels.sort_by_key(|(p, _)| *p);
+        let mut text = format!(
+            "bounds ({}, {}, {})..({}, {}, {})\n",
+            self.start.x, self.start.y, self.start.z, self.end.x, self.end.y, self.end.z
+        );
+        for ((x, y, z), voxel_type) in voxels {
+            text += &format!("({}, {}, {}) {:?}\n", x, y, z, voxel_type);
         }
+        text
     }
 
     pub fn get(&self, point: &Vector3<i32>) -> VoxelType {
@@ -57,16 +469,180 @@ impl VoxelMap {
         Ok(())
     }
 
+    pub fn remove_room(&mut self, room: &Room) {
+        for y in -1..room.height as i32 {
+            for z in 0..room.depth as i32 {
+                for x in 0..room.width as i32 {
+                    let p = Vector3::new(
+                        x + room.origin.0 as i32,
+                        y + room.origin.1 as i32,
+                        z + room.origin.2 as i32,
+                    );
+                    if self.get(&p).room_id() == Some(room.id) {
+                        self.map.remove(&p);
+                    }
+                }
+            }
+        }
+    }
+
     pub fn add_passage(
         &mut self,
         passage: &Passage,
         rooms: &BTreeMap<RoomId, Room>,
     ) -> Result<(), VoxelMapError> {
+        self.add_passage_with_stats(passage, rooms, &mut RouteStats::default())
+    }
+
+    pub fn add_passage_with_stats(
+        &mut self,
+        passage: &Passage,
+        rooms: &BTreeMap<RoomId, Room>,
+        stats: &mut RouteStats,
+    ) -> Result<(), VoxelMapError> {
+        self.add_passage_recording_cells(&mut passage.clone(), rooms, stats)
+    }
+
+    // add_passage_with_stats と同じく通路を掘り、書いたボクセルを passage.cells に残す
+    pub fn add_passage_recording_cells(
+        &mut self,
+        passage: &mut Passage,
+        rooms: &BTreeMap<RoomId, Room>,
+        stats: &mut RouteStats,
+    ) -> Result<(), VoxelMapError> {
+        let end_room = rooms
+            .get(&passage.end_room_id)
+            .ok_or(VoxelMapError::NoRoom(passage.end_room_id))?;
+        self.add_passage_to_room(passage, end_room, stats)
+    }
+
+    pub fn add_passage_to_room(
+        &mut self,
+        passage: &mut Passage,
+        end_room: &Room,
+        stats: &mut RouteStats,
+    ) -> Result<(), VoxelMapError> {
+        let end_room_id = end_room.id;
+        let distances = self
+            .guided_search
+            .then(|| self.route_distances(end_room_id, stats));
+        self.search_passage(
+            passage,
+            stats,
+            true,
+            |map, point| map.get(point) == Some(&VoxelType::RoomBottomSpace(end_room_id)),
+            |point, cost| match distances
+                .as_ref()
+                .and_then(|distances| distances.get(point))
+            {
+                Some(distance) => (distance * GUIDE_BLOCK * 10).saturating_add(cost),
+                None => calc_score(end_room, point, cost),
+            },
+        )
+        .map(|_| ())
+    }
+
+    // 範囲を GUIDE_BLOCK の辺の区画に分け、部屋の床の上の空間がある区画から各区画までの区画数
+    // 部屋や通路の壁で埋まった区画は通らない。区画の数だけ調べるため、長い通路でも安く回り込みを見積もれる
+    fn route_distances(&self, room_id: RoomId, stats: &mut RouteStats) -> DistanceField {
+        let size =
+            (self.end - self.start).map(|length| (length.max(0) + GUIDE_BLOCK - 1) / GUIDE_BLOCK);
+        let mut distances = DistanceField {
+            start: self.start,
+            size,
+            distances: vec![None; (size.x * size.y * size.z) as usize],
+        };
+        // 区画ごとに範囲内の点の数から通れない点の数を引き、残りがある区画を通れるとする
+        let mut open = vec![0; distances.distances.len()];
+        for y in 0..size.y {
+            for z in 0..size.z {
+                for x in 0..size.x {
+                    let block_start = Vector3::new(x, y, z) * GUIDE_BLOCK;
+                    let block_end = block_start
+                        .add_scalar(GUIDE_BLOCK)
+                        .inf(&(self.end - self.start));
+                    let length = block_end - block_start;
+                    open[((y * size.z + z) * size.x + x) as usize] = length.x * length.y * length.z;
+                }
+            }
+        }
+        let mut queue = VecDeque::new();
+        for (p, voxel_type) in self.map.iter() {
+            let Some(index) = distances.index(p) else {
+                continue;
+            };
+            if *voxel_type == VoxelType::RoomBottomSpace(room_id) {
+                if distances.distances[index].is_none() {
+                    distances.distances[index] = Some(0);
+                    queue.push_back(index);
+                }
+            } else if *voxel_type != VoxelType::PassageSpace {
+                open[index] -= 1;
+            }
+        }
+        while let Some(index) = queue.pop_front() {
+            stats.guide_nodes += 1;
+            let distance = distances.distances[index].unwrap();
+            let block = distances.block(index);
+            for d in [Vector3::x(), Vector3::y(), Vector3::z()] {
+                for next in [block + d, block - d] {
+                    let Some(next) = distances.block_index(&next) else {
+                        continue;
+                    };
+                    if open[next] > 0 && distances.distances[next].is_none() {
+                        distances.distances[next] = Some(distance + 1);
+                        queue.push_back(next);
+                    }
+                }
+            }
+        }
+        distances
+    }
+
+    // 地表に到達する通路を追加し、地表の出口の座標を返す
+    pub fn add_surface_passage(
+        &mut self,
+        passage: &mut Passage,
+        stats: &mut RouteStats,
+    ) -> Result<Vector3<i32>, VoxelMapError> {
+        let surface = self.surface.clone();
+        let surface_height = |point: &Vector3<i32>| surface.get(&(point.x, point.z)).copied();
+        self.search_passage(
+            passage,
+            stats,
+            false,
+            |_, point| surface_height(point).is_some_and(|height| height <= point.y),
+            |point, cost| {
+                (surface_height(point).map_or(0, |height| (height - point.y).max(0)) * 10)
+                    .saturating_add(cost)
+            },
+        )
+    }
+
+    fn search_passage<G, S>(
+        &mut self,
+        passage: &mut Passage,
+        stats: &mut RouteStats,
+        respect_surface: bool,
+        is_goal: G,
+        calc_score: S,
+    ) -> Result<Vector3<i32>, VoxelMapError>
+    where
+        G: Fn(&HashMap<Vector3<i32>, VoxelType>, &Vector3<i32>) -> bool,
+        S: Fn(&Vector3<i32>, i32) -> i32,
+    {
         // key = ParallelShiftAll > ParallelShift > Stair
         #[derive(Eq, PartialEq, Hash, Clone, Debug)]
         enum RouteKey {
-            ParallelShift { movable_dirs: BTreeSet<Direction4> },
+            ParallelShift {
+                movable_dirs: BTreeSet<Direction4>,
+            },
             Stair(Direction4),
+            // 緩やかな階段の途中。remaining はこのボクセルを含めて上がるまでに残るボクセル数
+            StairRun {
+                direction: Direction4,
+                remaining: i32,
+            },
         }
         impl RouteKey {
             // 同じ移動先を持って省略可能か
@@ -78,9 +654,18 @@ impl VoxelMap {
                         } => movable_dirs
                             .iter()
                             .all(|dir| self_movable_dirs.contains(dir)),
-                        RouteKey::Stair(_) => false,
+                        RouteKey::Stair(_) | RouteKey::StairRun { .. } => false,
                     },
-                    RouteKey::Stair(_) => self == other,
+                    RouteKey::Stair(_) | RouteKey::StairRun { .. } => self == other,
+                }
+            }
+
+            // 同じ歩みから分かれる経路の順番。平行移動を階段より先に調べる
+            fn rank(&self) -> u8 {
+                match self {
+                    RouteKey::ParallelShift { .. } => 0,
+                    RouteKey::Stair(_) => 1,
+                    RouteKey::StairRun { .. } => 2,
                 }
             }
         }
@@ -89,21 +674,33 @@ impl VoxelMap {
             key: RouteKey,
             point: Vector3<i32>,
             cost: i32,
-            map: HashMap<Vector3<i32>, VoxelType>,
+            parent: Option<usize>, // Node in the arena holding the voxels written so far
+            direction: Direction4, // Direction of the step which reached point
         }
+        // 同じスコアの経路は追加した順に調べる。同じ歩みから分かれる経路は方向の順、次に RouteKey::rank の順に
+        // 追加して、探索の書き方に関わらず同じシードで同じ通路を掘る
+        let push_routes = |queue: &mut BTreeKeyValues<i32, Route>,
+                           mut routes: Vec<(i32, Route)>| {
+            routes.sort_by_key(|(_, route)| (route.direction, route.key.rank()));
+            for (score, route) in routes {
+                queue.push_back(score, route);
+            }
+        };
 
         let start = Vector3::new(passage.start.0, passage.start.1, passage.start.2);
-        let end_room = rooms
-            .get(&passage.end_room_id)
-            .ok_or(VoxelMapError::NoRoom(passage.end_room_id))?;
+        let own_rooms = [passage.start_room_id, passage.end_room_id];
+        let avoidance = self.room_avoidance;
+        let clearance = self.stair_clearance.unwrap_or(0).max(passage.height);
 
         let mut queue: BTreeKeyValues<i32, Route> = BTreeKeyValues::default(); // score, route
         let mut route_map: HashMap<Vector3<i32>, Vec<(RouteKey, i32)>> = HashMap::new(); // point, route_key, cost
+        let mut arena = RouteArena::default();
 
+        let mut start_routes = Vec::new();
         for start_dir in passage.start_dirs.iter() {
             let next_point = start + start_dir.to_vec3();
-            let next_score = calc_score(end_room, &next_point, 0);
-            queue.push_back(
+            let next_score = calc_score(&next_point, 0);
+            start_routes.push((
                 next_score,
                 Route {
                     key: RouteKey::ParallelShift {
@@ -115,36 +712,51 @@ impl VoxelMap {
                     },
                     point: next_point,
                     cost: 0,
-                    map: Default::default(),
+                    parent: None,
+                    direction: *start_dir,
                 },
-            );
-            queue.push_back(
+            ));
+            start_routes.push((
                 next_score,
                 Route {
                     key: RouteKey::Stair(*start_dir),
                     point: next_point,
                     cost: 0,
-                    map: Default::default(),
+                    parent: None,
+                    direction: *start_dir,
                 },
-            );
+            ));
         }
+        push_routes(&mut queue, start_routes);
 
-        while let Some(mut route) = queue.pop_first_back() {
-            if route.point.x < self.start.x
-                || route.point.y < self.start.y
-                || route.point.z < self.start.z
-                || self.end.x <= route.point.x
-                || self.end.y <= route.point.y
-                || self.end.z <= route.point.z
+        let mut explored_nodes = 0;
+        while let Some(route) = queue.pop_first() {
+            stats.explored_nodes += 1;
+            explored_nodes += 1;
+            if self
+                .max_route_nodes
+                .is_some_and(|max_route_nodes| explored_nodes > max_route_nodes)
             {
+                return Err(VoxelMapError::Budget);
+            }
+            if !self.contains(&route.point) {
                 continue;
             }
 
-            if self.map.get(&route.point) == Some(&VoxelType::RoomBottomSpace(end_room.id)) {
-                for (key, value) in route.map.into_iter() {
-                    self.map.insert(key, value);
+            // 階段の途中では止まらない
+            let in_stair_run = matches!(route.key, RouteKey::StairRun { .. });
+            if !in_stair_run && is_goal(&self.map, &route.point) {
+                let mut cells = arena
+                    .collect(route.parent)
+                    .into_iter()
+                    .map(|(p, voxel_type)| ((p.x, p.y, p.z), voxel_type))
+                    .collect::<Vec<_>>();
+                cells.sort_by_key(|(p, _)| *p);
+                for (p, voxel_type) in cells.iter() {
+                    self.map.insert(Vector3::new(p.0, p.1, p.2), *voxel_type);
                 }
-                return Ok(());
+                passage.cells = cells;
+                return Ok(route.point);
             }
 
             // 既に登録されているルートよりも最短距離があればそちらを利用し処理を省略
@@ -185,19 +797,37 @@ impl VoxelMap {
                     .push((route.key.clone(), route.cost));
             }
 
-            match &route.key {
+            let routes = match &route.key {
                 RouteKey::ParallelShift { movable_dirs } => {
                     // コンフリクトしていないか確認
                     // 通路として塞がれていないか確認
-                    if !add_passage(&route.point, passage.height, &self.map, &mut route.map) {
+                    if respect_surface
+                        && !self.is_below_surface(
+                            &(route.point + Vector3::new(0, passage.height - 1, 0)),
+                        )
+                    {
+                        continue;
+                    }
+                    let mut cells = RouteCells::new(&arena, route.parent);
+                    if !add_passage(&route.point, passage.height, &self.map, &mut cells) {
+                        continue;
+                    }
+                    stats.stored_route_cells += cells.added.len();
+                    let node = arena.push(route.parent, cells.added);
+                    let top = passage.height - 1;
+                    if avoidance.forbid_foreign_rooms
+                        && self.is_near_foreign_room(&own_rooms, &route.point, top, 1)
+                    {
                         continue;
                     }
+                    let step = self.step_cost(&own_rooms, &route.point, top);
 
+                    let mut routes = Vec::new();
                     for movable_dir in movable_dirs {
                         // 平行移動の探索を予約
                         let next_point = route.point + movable_dir.to_vec3();
-                        let next_const = calc_score(end_room, &next_point, route.cost + 1);
-                        queue.push_back(
+                        let next_const = calc_score(&next_point, route.cost.saturating_add(step));
+                        routes.push((
                             next_const,
                             Route {
                                 key: RouteKey::ParallelShift {
@@ -209,75 +839,663 @@ impl VoxelMap {
                                 },
                                 point: next_point,
                                 cost: next_const,
-                                map: route.map.clone(),
+                                parent: Some(node),
+                                direction: *movable_dir,
                             },
-                        );
+                        ));
                         // 階段の探索を予約
-                        queue.push_back(
+                        routes.push((
                             next_const,
                             Route {
                                 key: RouteKey::Stair(*movable_dir),
                                 point: next_point,
                                 cost: next_const,
-                                map: route.map.clone(),
+                                parent: Some(node),
+                                direction: *movable_dir,
                             },
-                        );
+                        ));
                     }
+                    routes
                 }
-                RouteKey::Stair(direction) => {
+                RouteKey::Stair(direction) | RouteKey::StairRun { direction, .. } => {
                     // コンフリクトしていないか確認
                     // 階段として塞がれていないか確認
+                    if respect_surface
+                        && !self.is_below_surface(&(route.point + Vector3::new(0, clearance, 0)))
+                    {
+                        continue;
+                    }
+                    let mut cells = RouteCells::new(&arena, route.parent);
                     if !add_stair(
                         &route.point,
                         passage.height,
+                        clearance,
                         direction,
                         &self.map,
-                        &mut route.map,
+                        &mut cells,
                     ) {
                         continue;
                     }
+                    stats.stored_route_cells += cells.added.len();
+                    let node = arena.push(route.parent, cells.added);
+                    if avoidance.forbid_foreign_rooms
+                        && self.is_near_foreign_room(&own_rooms, &route.point, passage.height, 1)
+                    {
+                        continue;
+                    }
+                    let step = self.step_cost(&own_rooms, &route.point, passage.height);
 
-                    // 平行移動の探索を予約
-                    let next_point = route.point + direction.to_vec3() + Vector3::new(0, 1, 0);
-                    let next_const = calc_score(end_room, &next_point, route.cost + 1);
-                    queue.push_back(
-                        next_const,
-                        Route {
-                            key: RouteKey::ParallelShift {
-                                movable_dirs: DIRECTIONS
-                                    .iter()
-                                    .filter(|d| !direction.is_opposite(d))
-                                    .copied()
-                                    .collect(),
+                    let remaining = match route.key {
+                        RouteKey::StairRun { remaining, .. } => remaining,
+                        _ => self.stair_run,
+                    };
+                    if remaining > 1 {
+                        // 同じ高さのまま階段を続ける
+                        let next_point = route.point + direction.to_vec3();
+                        let next_const = calc_score(&next_point, route.cost.saturating_add(step));
+                        vec![(
+                            next_const,
+                            Route {
+                                key: RouteKey::StairRun {
+                                    direction: *direction,
+                                    remaining: remaining - 1,
+                                },
+                                point: next_point,
+                                cost: next_const,
+                                parent: Some(node),
+                                direction: *direction,
                             },
-                            point: next_point,
-                            cost: next_const,
-                            map: route.map.clone(),
-                        },
-                    );
-                    // 階段の探索を予約
-                    queue.push_back(
-                        next_const,
-                        Route {
-                            key: RouteKey::Stair(*direction),
-                            point: next_point,
-                            cost: next_const,
-                            map: route.map.clone(),
-                        },
-                    );
+                        )]
+                    } else {
+                        // 平行移動と階段の探索を予約
+                        let next_point = route.point + direction.to_vec3() + Vector3::new(0, 1, 0);
+                        let next_const = calc_score(&next_point, route.cost.saturating_add(step));
+                        vec![
+                            (
+                                next_const,
+                                Route {
+                                    key: RouteKey::ParallelShift {
+                                        movable_dirs: DIRECTIONS
+                                            .iter()
+                                            .filter(|d| !direction.is_opposite(d))
+                                            .copied()
+                                            .collect(),
+                                    },
+                                    point: next_point,
+                                    cost: next_const,
+                                    parent: Some(node),
+                                    direction: *direction,
+                                },
+                            ),
+                            (
+                                next_const,
+                                Route {
+                                    key: RouteKey::Stair(*direction),
+                                    point: next_point,
+                                    cost: next_const,
+                                    parent: Some(node),
+                                    direction: *direction,
+                                },
+                            ),
+                        ]
+                    }
                 }
             };
+            push_routes(&mut queue, routes);
         }
 
         Err(VoxelMapError::Unreachable)
     }
+
+    // 通路の列から水平距離 distance 以内に、始点と終点以外の部屋があるか
+    fn is_near_foreign_room(
+        &self,
+        own_rooms: &[RoomId],
+        point: &Vector3<i32>,
+        top: i32,
+        distance: i32,
+    ) -> bool {
+        (-distance..=distance).any(|dx| {
+            (-distance..=distance).any(|dz| {
+                dx.abs() + dz.abs() <= distance
+                    && (0..=top).any(|y| {
+                        self.get(&(point + Vector3::new(dx, y, dz)))
+                            .room_id()
+                            .is_some_and(|room_id| !own_rooms.contains(&room_id))
+                    })
+            })
+        })
+    }
+
+    // 無関係な部屋の近くや追加コストが設定された点を通るほど1歩のコストを高くする
+    // 探索が後戻りしないよう、1歩のコストは1未満にしない
+    // 利用側の関数が大きな値を返してもあふれないように、足し合わせは i32 の上限で止める
+    fn step_cost(&mut self, own_rooms: &[RoomId], point: &Vector3<i32>, top: i32) -> i32 {
+        let extra = self.extra_step_cost(point);
+        let penalty = self.room_avoidance.foreign_room_penalty;
+        let cost = if penalty > 0 && self.is_near_foreign_room(own_rooms, point, top, 2) {
+            1i32.saturating_add(penalty).saturating_add(extra)
+        } else {
+            1i32.saturating_add(extra)
+        };
+        cost.max(1)
+    }
+}
+
+impl VoxelMap {
+    // 通路の壁を確率的に1ボクセル外側へ膨らませる
+    // 床の連続性と天井の高さを保つため、床から天井までの列ごと追加する
+    pub fn roughen_passage<R: Rng>(&mut self, passage: &mut Passage, amplitude: f64, rng: &mut R) {
+        let up = Vector3::new(0, 1, 0);
+        let walking_cells = passage
+            .cells
+            .iter()
+            .map(|((x, y, z), _)| Vector3::new(*x, *y, *z))
+            .filter(|p| {
+                self.map.get(p) == Some(&VoxelType::PassageSpace)
+                    && self.map.get(&(p - up)) == Some(&VoxelType::PassageFloor)
+            })
+            .collect::<Vec<_>>();
+        let mut added = Vec::new();
+        for p in walking_cells {
+            for dir in DIRECTIONS.iter() {
+                if !rng.gen_bool(amplitude) {
+                    continue;
+                }
+                let bulge = p + dir.to_vec3();
+                let column = (-1..passage.height)
+                    .map(|y| bulge + Vector3::new(0, y, 0))
+                    .collect::<Vec<_>>();
+                let is_free = column.iter().all(|c| {
+                    self.contains(c)
+                        && !self.map.contains_key(c)
+                        && DIRECTIONS
+                            .iter()
+                            .all(|d| self.get(&(c + d.to_vec3())).room_id().is_none())
+                });
+                if !is_free || !self.is_below_surface(&(bulge + up * (passage.height - 1))) {
+                    continue;
+                }
+                for (index, c) in column.into_iter().enumerate() {
+                    let voxel_type = if index == 0 {
+                        VoxelType::PassageFloor
+                    } else {
+                        VoxelType::PassageSpace
+                    };
+                    self.map.insert(c, voxel_type);
+                    added.push(((c.x, c.y, c.z), voxel_type));
+                }
+            }
+        }
+        passage.cells.extend(added);
+        passage.cells.sort_by_key(|(p, _)| *p);
+    }
+}
+
+// セーブデータ向けに、座標を i16 に、ボクセルの種類をパレットの番号に詰めた表現
+// 地表の高さや経路探索の設定は含まない
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CompactVoxelMap {
+    pub start: Vector3<i32>,
+    pub end: Vector3<i32>,
+    pub palette: Vec<VoxelType>, // Distinct voxel types in order of first appearance
+    pub voxels: Vec<(Vector3<i16>, u16)>, // Offset from start and index into the palette
+}
+
+#[derive(Debug)]
+pub enum CompactVoxelMapError {
+    CoordinateOutOfRange(Vector3<i32>),
+    TooManyVoxelTypes,
+    InvalidPaletteIndex(u16),
+}
+
+impl fmt::Display for CompactVoxelMapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompactVoxelMapError::CoordinateOutOfRange(p) => {
+                write!(
+                    f,
+                    "voxel ({}, {}, {}) is too far from the start",
+                    p.x, p.y, p.z
+                )
+            }
+            CompactVoxelMapError::TooManyVoxelTypes => write!(f, "too many distinct voxel types"),
+            CompactVoxelMapError::InvalidPaletteIndex(index) => {
+                write!(f, "palette index {} is out of range", index)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CompactVoxelMapError {}
+
+impl CompactVoxelMap {
+    pub fn memory_usage(&self) -> usize {
+        size_of::<Self>()
+            + self.palette.capacity() * size_of::<VoxelType>()
+            + self.voxels.capacity() * size_of::<(Vector3<i16>, u16)>()
+    }
+}
+
+impl VoxelMap {
+    // HashMap の各要素には制御用の1バイトが付くため、それも含めて概算する
+    pub fn memory_usage(&self) -> usize {
+        size_of::<Self>()
+            + self.map.capacity() * (size_of::<(Vector3<i32>, VoxelType)>() + 1)
+            + self.surface.capacity() * (size_of::<((i32, i32), i32)>() + 1)
+            + self.step_costs.capacity() * (size_of::<(Vector3<i32>, i32)>() + 1)
+    }
+
+    pub fn to_compact(&self) -> Result<CompactVoxelMap, CompactVoxelMapError> {
+        let mut palette = Vec::new();
+        let mut indices = HashMap::new();
+        let mut voxels = Vec::with_capacity(self.map.len());
+        for (p, voxel_type) in self.map.iter() {
+            let offset = p - self.start;
+            let to_i16 = |v: i32| i16::try_from(v).ok();
+            let (Some(x), Some(y), Some(z)) =
+                (to_i16(offset.x), to_i16(offset.y), to_i16(offset.z))
+            else {
+                return Err(CompactVoxelMapError::CoordinateOutOfRange(*p));
+            };
+            let index = match indices.get(voxel_type) {
+                Some(index) => *index,
+                None => {
+                    let index = u16::try_from(palette.len())
+                        .map_err(|_| CompactVoxelMapError::TooManyVoxelTypes)?;
+                    palette.push(*voxel_type);
+                    indices.insert(*voxel_type, index);
+                    index
+                }
+            };
+            voxels.push((Vector3::new(x, y, z), index));
+        }
+        Ok(CompactVoxelMap {
+            start: self.start,
+            end: self.end,
+            palette,
+            voxels,
+        })
+    }
+
+    pub fn from_compact(compact: &CompactVoxelMap) -> Result<VoxelMap, CompactVoxelMapError> {
+        let size = compact.end - compact.start;
+        let mut voxel_map = VoxelMap::new(
+            compact.start.x,
+            compact.start.y,
+            compact.start.z,
+            size.x,
+            size.y,
+            size.z,
+        );
+        voxel_map.map.reserve(compact.voxels.len());
+        for (offset, index) in compact.voxels.iter() {
+            let voxel_type = compact
+                .palette
+                .get(*index as usize)
+                .ok_or(CompactVoxelMapError::InvalidPaletteIndex(*index))?;
+            voxel_map
+                .map
+                .insert(compact.start + offset.cast::<i32>(), *voxel_type);
+        }
+        Ok(voxel_map)
+    }
+}
+
+// (x, z) の列ごとに、同じ種類のボクセルが縦に連続する区間をまとめた表現
+// 部屋の床や空間は縦に揃うため、ボクセル単位よりも大幅に小さくなる
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RleColumns {
+    pub start: Vector3<i32>,
+    pub end: Vector3<i32>,
+    pub columns: Vec<RleColumn>, // Sorted by (x, z); columns without voxels are omitted
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RleColumn {
+    pub x: i32,
+    pub z: i32,
+    pub runs: Vec<VoxelRun>, // Sorted by y; gaps between runs are empty
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct VoxelRun {
+    pub y: i32,      // Lowest voxel of the run
+    pub length: u32, // Number of voxels stacked upward from y
+    pub voxel_type: VoxelType,
+}
+
+impl VoxelMap {
+    pub fn to_rle_columns(&self) -> RleColumns {
+        let mut points = self.map.keys().copied().collect::<Vec<_>>();
+        points.sort_by_key(|p| (p.x, p.z, p.y));
+        let mut columns: Vec<RleColumn> = Vec::new();
+        for p in points {
+            let voxel_type = self.map[&p];
+            let column = match columns.last_mut() {
+                Some(column) if column.x == p.x && column.z == p.z => column,
+                _ => {
+                    columns.push(RleColumn {
+                        x: p.x,
+                        z: p.z,
+                        runs: Vec::new(),
+                    });
+                    columns.last_mut().unwrap()
+                }
+            };
+            match column.runs.last_mut() {
+                Some(run) if run.voxel_type == voxel_type && run.y + run.length as i32 == p.y => {
+                    run.length += 1;
+                }
+                _ => column.runs.push(VoxelRun {
+                    y: p.y,
+                    length: 1,
+                    voxel_type,
+                }),
+            }
+        }
+        RleColumns {
+            start: self.start,
+            end: self.end,
+            columns,
+        }
+    }
+
+    pub fn from_rle_columns(rle_columns: &RleColumns) -> VoxelMap {
+        let size = rle_columns.end - rle_columns.start;
+        let mut voxel_map = VoxelMap::new(
+            rle_columns.start.x,
+            rle_columns.start.y,
+            rle_columns.start.z,
+            size.x,
+            size.y,
+            size.z,
+        );
+        for column in rle_columns.columns.iter() {
+            for run in column.runs.iter() {
+                for y in run.y..run.y + run.length as i32 {
+                    voxel_map
+                        .map
+                        .insert(Vector3::new(column.x, y, column.z), run.voxel_type);
+                }
+            }
+        }
+        voxel_map
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Region {
+    pub voxels: Vec<(i32, i32, i32)>, // Passable voxels of the region in coordinate order
+    pub rooms: BTreeSet<RoomId>,      // Rooms whose space belongs to the region
+}
+
+impl VoxelMap {
+    // 通れるボクセルを上下左右前後のつながりで塗り分ける
+    // 歩ける空間が1つにまとまっているかの検証や、残響の区域分けに使う
+    pub fn label_regions(&self) -> Vec<Region> {
+        let mut points = self
+            .map
+            .iter()
+            .filter(|(_, voxel_type)| voxel_type.is_passable())
+            .map(|(p, _)| (p.x, p.y, p.z))
+            .collect::<Vec<_>>();
+        points.sort();
+        let mut visited = BTreeSet::new();
+        let mut regions = Vec::new();
+        for start in points {
+            if visited.contains(&start) {
+                continue;
+            }
+            let mut region = Region {
+                voxels: Vec::new(),
+                rooms: BTreeSet::new(),
+            };
+            let mut stack = vec![start];
+            visited.insert(start);
+            while let Some((x, y, z)) = stack.pop() {
+                let p = Vector3::new(x, y, z);
+                region.voxels.push((x, y, z));
+                if let Some(room_id) = self.get(&p).room_id() {
+                    region.rooms.insert(room_id);
+                }
+                for d in [Vector3::x(), Vector3::y(), Vector3::z()] {
+                    for next in [p + d, p - d] {
+                        if self.get(&next).is_passable() && visited.insert((next.x, next.y, next.z))
+                        {
+                            stack.push((next.x, next.y, next.z));
+                        }
+                    }
+                }
+            }
+            region.voxels.sort();
+            regions.push(region);
+        }
+        regions
+    }
+
+    // 通れるボクセルごとに、指定した部屋のいずれかまでの幅優先探索での距離を求める
+    // 難易度の段階付けや、霧の晴れる順番に使う。たどり着けないボクセルは含まない
+    pub fn room_distances(&self, rooms: &BTreeSet<RoomId>) -> HashMap<Vector3<i32>, u32> {
+        let mut starts = self
+            .map
+            .iter()
+            .filter(|(_, voxel_type)| {
+                voxel_type.is_passable()
+                    && voxel_type
+                        .room_id()
+                        .is_some_and(|room_id| rooms.contains(&room_id))
+            })
+            .map(|(p, _)| *p)
+            .collect::<Vec<_>>();
+        starts.sort_by_key(|p| (p.x, p.y, p.z));
+        let mut distances = starts.iter().map(|p| (*p, 0)).collect::<HashMap<_, _>>();
+        let mut queue = VecDeque::from(starts);
+        while let Some(p) = queue.pop_front() {
+            let distance = distances[&p];
+            for d in [Vector3::x(), Vector3::y(), Vector3::z()] {
+                for next in [p + d, p - d] {
+                    if self.get(&next).is_passable() && !distances.contains_key(&next) {
+                        distances.insert(next, distance + 1);
+                        queue.push_back(next);
+                    }
+                }
+            }
+        }
+        distances
+    }
+
+    // 部屋の空間の列ごとに最も高いボクセル。シャンデリアや鍾乳石など天井から吊るす物を置く位置に使う
+    // 合併した部屋のように列ごとに高さが違っても求められるようボクセルを調べる。真上が通れる列は天井がないので除く
+    pub fn ceiling_cells_of_room(&self, room_id: RoomId) -> Vec<Vector3<i32>> {
+        let mut tops: BTreeMap<(i32, i32), i32> = BTreeMap::new();
+        for (p, voxel_type) in self.map.iter() {
+            if matches!(
+                voxel_type,
+                VoxelType::RoomSpace(id) | VoxelType::RoomBottomSpace(id) if *id == room_id
+            ) {
+                let top = tops.entry((p.x, p.z)).or_insert(p.y);
+                *top = (*top).max(p.y);
+            }
+        }
+        tops.into_iter()
+            .map(|((x, z), y)| Vector3::new(x, y, z))
+            .filter(|p| !self.get(&(p + Vector3::y())).is_passable())
+            .collect()
+    }
 }
 
 // 部屋までの距離コスト計算
 fn calc_score(room: &Room, start: &Vector3<i32>, cost: i32) -> i32 {
     let center = room.center();
     let d = (Vector3::new(center.0 as i32, room.origin.1 as i32, center.2 as i32) - *start).abs();
-    (d.x + d.y + d.z) * 10 + cost
+    ((d.x + d.y + d.z) * 10).saturating_add(cost)
+}
+
+// 誘導する探索で範囲をまとめる区画の辺の長さ
+const GUIDE_BLOCK: i32 = 4;
+
+// 区画ごとの距離。届かない区画や範囲外は None
+struct DistanceField {
+    start: Vector3<i32>,
+    size: Vector3<i32>, // Blocks along each axis
+    distances: Vec<Option<i32>>,
+}
+
+impl DistanceField {
+    fn block_index(&self, block: &Vector3<i32>) -> Option<usize> {
+        ((0..self.size.x).contains(&block.x)
+            && (0..self.size.y).contains(&block.y)
+            && (0..self.size.z).contains(&block.z))
+        .then(|| ((block.y * self.size.z + block.z) * self.size.x + block.x) as usize)
+    }
+
+    fn block(&self, index: usize) -> Vector3<i32> {
+        let index = index as i32;
+        Vector3::new(
+            index % self.size.x,
+            index / (self.size.x * self.size.z),
+            index / self.size.x % self.size.z,
+        )
+    }
+
+    fn index(&self, p: &Vector3<i32>) -> Option<usize> {
+        self.block_index(&(p - self.start).map(|v| v.div_euclid(GUIDE_BLOCK)))
+    }
+
+    fn get(&self, p: &Vector3<i32>) -> Option<i32> {
+        self.index(p).and_then(|index| self.distances[index])
+    }
+}
+
+// 探索した経路の1歩。親をたどると経路がそれまでに書き込んだボクセルがそろう
+#[derive(Debug)]
+struct RouteNode {
+    parent: Option<usize>,
+    depth: usize,                          // Steps from the start of the route
+    jump: Option<usize>, // Ancestor to skip to when looking for an ancestor at a given depth
+    cells: Vec<(Vector3<i32>, VoxelType)>, // Voxels written by this step only
+}
+
+// 経路ごとに書き込んだボクセルの地図を複製せず、共通する手前の歩みを親への番号で共有する
+// ボクセルを集めるのはゴールに着いた経路だけ
+// 点ごとに書き込んだ歩みを索引に持ち、その歩みが経路の祖先かを jump で対数回の手順で確かめる
+#[derive(Debug, Default)]
+struct RouteArena {
+    nodes: Vec<RouteNode>,
+    writers: HashMap<Vector3<i32>, Vec<usize>>, // point -> nodes which wrote it, in push order
+}
+
+impl RouteArena {
+    fn push(&mut self, parent: Option<usize>, cells: Vec<(Vector3<i32>, VoxelType)>) -> usize {
+        let index = self.nodes.len();
+        // 親の jump の幅が、その先の jump の幅と同じなら2つ分をまとめて飛ぶ (skew-binary)
+        let (depth, jump) = match parent {
+            None => (0, None),
+            Some(parent) => {
+                let parent_node = &self.nodes[parent];
+                let jump = match parent_node.jump {
+                    Some(jump) => match self.nodes[jump].jump {
+                        Some(next)
+                            if parent_node.depth - self.nodes[jump].depth
+                                == self.nodes[jump].depth - self.nodes[next].depth =>
+                        {
+                            next
+                        }
+                        _ => parent,
+                    },
+                    None => parent,
+                };
+                (parent_node.depth + 1, Some(jump))
+            }
+        };
+        for (point, _) in cells.iter() {
+            let writers = self.writers.entry(*point).or_default();
+            if writers.last() != Some(&index) {
+                writers.push(index);
+            }
+        }
+        self.nodes.push(RouteNode {
+            parent,
+            depth,
+            jump,
+            cells,
+        });
+        index
+    }
+
+    // ancestor が node 自身か node の祖先か
+    fn is_ancestor(&self, ancestor: usize, mut node: Option<usize>) -> bool {
+        let depth = self.nodes[ancestor].depth;
+        while let Some(index) = node {
+            let route_node = &self.nodes[index];
+            if route_node.depth <= depth {
+                return index == ancestor;
+            }
+            node = match route_node.jump {
+                Some(jump) if self.nodes[jump].depth >= depth => Some(jump),
+                _ => route_node.parent,
+            };
+        }
+        false
+    }
+
+    // 経路で最後にその点へ書き込んだボクセル。祖先は子より先に追加されるため、番号の大きい順に調べる
+    fn get(&self, node: Option<usize>, point: &Vector3<i32>) -> Option<&VoxelType> {
+        let index = node?;
+        let writers = self.writers.get(point)?;
+        let end = writers.partition_point(|writer| *writer <= index);
+        writers[..end]
+            .iter()
+            .rev()
+            .find(|writer| self.is_ancestor(**writer, node))
+            .and_then(|writer| {
+                self.nodes[*writer]
+                    .cells
+                    .iter()
+                    .find(|(p, _)| p == point)
+                    .map(|(_, voxel_type)| voxel_type)
+            })
+    }
+
+    fn collect(&self, mut node: Option<usize>) -> HashMap<Vector3<i32>, VoxelType> {
+        let mut map = HashMap::new();
+        while let Some(index) = node {
+            let route_node = &self.nodes[index];
+            map.extend(route_node.cells.iter().copied());
+            node = route_node.parent;
+        }
+        map
+    }
+}
+
+// 1歩で書き込むボクセル。読むときは経路のそれまでのボクセルも見る
+struct RouteCells<'a> {
+    arena: &'a RouteArena,
+    parent: Option<usize>,
+    added: Vec<(Vector3<i32>, VoxelType)>,
+}
+
+impl<'a> RouteCells<'a> {
+    fn new(arena: &'a RouteArena, parent: Option<usize>) -> Self {
+        RouteCells {
+            arena,
+            parent,
+            added: Vec::new(),
+        }
+    }
+
+    fn get(&self, point: &Vector3<i32>) -> Option<&VoxelType> {
+        self.added
+            .iter()
+            .find(|(p, _)| p == point)
+            .map(|(_, voxel_type)| voxel_type)
+            .or_else(|| self.arena.get(self.parent, point))
+    }
+
+    fn insert(&mut self, point: Vector3<i32>, voxel_type: VoxelType) {
+        self.added.push((point, voxel_type));
+    }
 }
 
 #[inline]
@@ -285,7 +1503,7 @@ fn add_passage(
     point: &Vector3<i32>,
     height: i32,
     readonly_map: &HashMap<Vector3<i32>, VoxelType>,
-    writable_map: &mut HashMap<Vector3<i32>, VoxelType>,
+    writable_map: &mut RouteCells<'_>,
 ) -> bool {
     let ground_point = point + Vector3::new(0, -1, 0);
     let ground = readonly_map
@@ -314,9 +1532,10 @@ fn add_passage(
 fn add_stair(
     point: &Vector3<i32>,
     height: i32,
+    clearance: i32,
     direction: &Direction4,
     readonly_map: &HashMap<Vector3<i32>, VoxelType>,
-    writable_map: &mut HashMap<Vector3<i32>, VoxelType>,
+    writable_map: &mut RouteCells<'_>,
 ) -> bool {
     let ground = readonly_map.get(point).or_else(|| writable_map.get(point));
     if ground.is_some() {
@@ -324,8 +1543,12 @@ fn add_stair(
     }
     writable_map.insert(point.clone_owned(), VoxelType::PassageStair(*direction));
 
-    for y in 0..height {
-        let space_point = point + Vector3::new(0, y + 1, 0);
+    // 段の真上に加えて、斜めに上るときに頭が通る一つ先の列も通路の高さより上を空ける
+    let ahead = point + direction.to_vec3();
+    let space_points = (0..clearance)
+        .map(|y| point + Vector3::new(0, y + 1, 0))
+        .chain((height..clearance).map(|y| ahead + Vector3::new(0, y + 1, 0)));
+    for space_point in space_points {
         let space = readonly_map
             .get(&space_point)
             .or_else(|| writable_map.get(&space_point));
@@ -337,3 +1560,453 @@ fn add_stair(
     }
     true
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::{Direction4, VoxelType, DIRECTIONS};
+    use crate::generate_drd::{generate_dungeon_3d, Dungeon3DGeneratorConfig};
+    use crate::passage::Passage;
+    use crate::room::{Room, RoomId};
+    use crate::voxel_map::{
+        MergeConflict, RoomAvoidance, RouteArena, RouteStats, VoxelMap, VoxelMapError,
+    };
+    use nalgebra::Vector3;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::collections::{BTreeMap, BTreeSet, HashSet};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_lazy_step_cost() {
+        let mut room_id = RoomId::first();
+        let room0 = Room::new(room_id.gen_id(), 5, 2, 5, (1, 1, 1));
+        let room1 = Room::new(room_id.gen_id(), 5, 2, 5, (16, 4, 16));
+        let mut voxel_map = VoxelMap::new(0, 0, 0, 24, 24, 24);
+        voxel_map.add_room(&room0).unwrap();
+        voxel_map.add_room(&room1).unwrap();
+        voxel_map.set_room_avoidance(RoomAvoidance {
+            forbid_foreign_rooms: false,
+            foreign_room_penalty: i32::MAX,
+        });
+        // 大きな追加コストでもあふれずに掘れる
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        voxel_map.set_step_cost(move |x, _, _| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            if x == 10 {
+                i32::MAX
+            } else {
+                0
+            }
+        });
+        let passage = Passage::new(&room0, &room1, 2);
+        let rooms = BTreeMap::from([(room0.id, room0), (room1.id, room1)]);
+        voxel_map.add_passage(&passage, &rooms).unwrap();
+
+        // 探索が通った点でだけ、1度ずつ評価する
+        let calls = calls.load(Ordering::Relaxed);
+        assert!(0 < calls && calls < 24 * 24 * 24);
+        assert_eq!(calls, voxel_map.step_costs.len());
+
+        // 平行移動した後も同じ点で同じコストになる
+        let offset = Vector3::new(5, -3, 7);
+        voxel_map.translate(offset);
+        for p in [Vector3::new(10, 0, 0), Vector3::new(11, 23, 2)] {
+            let expected = if p.x == 10 { i32::MAX } else { 0 };
+            assert_eq!(voxel_map.extra_step_cost(&(p + offset)), expected);
+        }
+    }
+
+    #[test]
+    fn test_long_corridor() {
+        let mut room_id = RoomId::first();
+        let room0 = Room::new(room_id.gen_id(), 5, 2, 5, (1, 1, 1));
+        let room1 = Room::new(room_id.gen_id(), 5, 2, 5, (90, 1, 1));
+        let mut voxel_map = VoxelMap::new(0, 0, 0, 96, 8, 8);
+        voxel_map.add_room(&room0).unwrap();
+        voxel_map.add_room(&room1).unwrap();
+        voxel_map.set_max_route_nodes(Some(100_000));
+        let mut passage = Passage::new(&room0, &room1, 2);
+        let mut stats = RouteStats::default();
+        voxel_map
+            .add_passage_to_room(&mut passage, &room1, &mut stats)
+            .unwrap();
+
+        // 経路の歩みを共有する前と同じ、部屋の間をまっすぐ結ぶ通路になる
+        let expected = (6..90)
+            .flat_map(|x| {
+                [
+                    ((x, 0, 3), VoxelType::PassageFloor),
+                    ((x, 1, 3), VoxelType::PassageSpace),
+                    ((x, 2, 3), VoxelType::PassageSpace),
+                ]
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(passage.cells, expected);
+        let cells = passage
+            .cells
+            .iter()
+            .map(|(p, _)| Vector3::new(p.0, p.1, p.2))
+            .collect::<HashSet<_>>();
+        let mut stack = vec![*cells.iter().next().unwrap()];
+        let mut visited = HashSet::new();
+        while let Some(p) = stack.pop() {
+            if visited.insert(p) {
+                stack.extend(
+                    DIRECTIONS
+                        .iter()
+                        .map(|dir| p + dir.to_vec3())
+                        .chain([p + Vector3::new(0, 1, 0), p - Vector3::new(0, 1, 0)])
+                        .filter(|next| cells.contains(next)),
+                );
+            }
+        }
+        assert_eq!(visited, cells);
+
+        // 歩みごとにその歩みで書いたボクセルだけを持つため、経路の長さによらず1歩あたり数ボクセルで収まる
+        // 経路ごとに地図を複製すると、1歩ごとにそれまでの経路の全てのボクセルを持つことになる
+        assert!(stats.stored_route_cells <= stats.explored_nodes * 4);
+        assert!(stats.stored_route_cells < stats.explored_nodes * passage.cells.len() / 10);
+    }
+
+    #[test]
+    fn test_route_arena_lookup() {
+        // 枝分かれした経路で、親を1つずつたどった場合と同じボクセルを返す
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut arena = RouteArena::default();
+        for index in 0..2000 {
+            let parent =
+                (index > 0 && rng.gen_bool(0.95)).then(|| rng.gen_range(index.max(8) - 8..index));
+            let cells = (0..3)
+                .map(|y| {
+                    let p = Vector3::new(rng.gen_range(0..8), y, rng.gen_range(0..8));
+                    (p, VoxelType::Custom(index as u16))
+                })
+                .collect();
+            arena.push(parent, cells);
+        }
+        for _ in 0..2000 {
+            let node = Some(rng.gen_range(0..arena.nodes.len()));
+            let p = Vector3::new(
+                rng.gen_range(0..8),
+                rng.gen_range(0..3),
+                rng.gen_range(0..8),
+            );
+            let mut expected = None;
+            let mut ancestor = node;
+            while let Some(index) = ancestor {
+                let route_node = &arena.nodes[index];
+                if let Some((_, voxel_type)) = route_node.cells.iter().find(|(q, _)| *q == p) {
+                    expected = Some(voxel_type);
+                    break;
+                }
+                ancestor = route_node.parent;
+            }
+            assert_eq!(arena.get(node, &p), expected);
+        }
+    }
+
+    #[test]
+    fn test_equal_cost_route_order() {
+        // 部屋の角から斜め向かいの部屋へ掘る。x と z のどちらへ進んでも同じコストになる
+        // 鏡写しにしても方向の順で x 方向を先に進み、向かいの部屋の列で z 方向へ曲がる
+        let carve = |mirror_x: bool, mirror_z: bool, reversed: bool| {
+            let m = |v: i32, mirrored: bool| if mirrored { 23 - v } else { v };
+            let origin = |v: i32, mirrored: bool| m(v, mirrored).min(m(v + 4, mirrored)) as u32;
+            let mut room_id = RoomId::first();
+            let room0 = Room::new(
+                room_id.gen_id(),
+                5,
+                2,
+                5,
+                (origin(2, mirror_x), 1, origin(2, mirror_z)),
+            );
+            let room1 = Room::new(
+                room_id.gen_id(),
+                5,
+                2,
+                5,
+                (origin(16, mirror_x), 1, origin(16, mirror_z)),
+            );
+            let mut voxel_map = VoxelMap::new(0, 0, 0, 24, 8, 24);
+            let mut dirs = vec![
+                if mirror_x {
+                    Direction4::Left
+                } else {
+                    Direction4::Right
+                },
+                if mirror_z {
+                    Direction4::Far
+                } else {
+                    Direction4::Near
+                },
+            ];
+            let mut rooms = vec![&room0, &room1];
+            if reversed {
+                dirs.reverse();
+                rooms.reverse();
+            }
+            for room in rooms {
+                voxel_map.add_room(room).unwrap();
+            }
+            let mut passage = Passage::new(&room0, &room1, 2);
+            passage.start = (m(6, mirror_x), 1, m(6, mirror_z));
+            passage.start_dirs = dirs.into_iter().collect();
+            voxel_map
+                .add_passage_to_room(&mut passage, &room1, &mut RouteStats::default())
+                .unwrap();
+            passage
+                .cells
+                .into_iter()
+                .filter(|(_, voxel_type)| *voxel_type == VoxelType::PassageFloor)
+                .map(|((x, _, z), _)| (m(x, mirror_x), m(z, mirror_z)))
+                .collect::<BTreeSet<_>>()
+        };
+        let expected = (7..=16)
+            .map(|x| (x, 6))
+            .chain((7..=15).map(|z| (16, z)))
+            .collect::<BTreeSet<_>>();
+        for (mirror_x, mirror_z) in [(false, false), (true, false), (false, true), (true, true)] {
+            for reversed in [false, true] {
+                assert_eq!(
+                    carve(mirror_x, mirror_z, reversed),
+                    expected,
+                    "mirror: {:?}, reversed: {}",
+                    (mirror_x, mirror_z),
+                    reversed
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_merge_and_subtract() {
+        let generate = |seed| {
+            generate_dungeon_3d(Dungeon3DGeneratorConfig {
+                seed: Some(seed),
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        let (a, b) = (generate(0), generate(1));
+        let mut other = b.voxel_map.clone();
+        other.shift_room_ids(a.rooms.keys().last().unwrap().inner());
+        let offset = Vector3::new(a.voxel_map.bounds().1.x - b.voxel_map.bounds().0.x, 0, 0);
+        let mut merged = a.voxel_map.clone();
+        merged.merge(&other, offset, MergeConflict::Error).unwrap();
+        assert_eq!(
+            merged.map.len(),
+            a.voxel_map.map.len() + b.voxel_map.map.len()
+        );
+        assert_eq!(merged.bounds().0, a.voxel_map.bounds().0);
+        assert_eq!(merged.bounds().1.x, b.voxel_map.bounds().1.x + offset.x);
+        // 合成後の部屋の番号は重ならない
+        let room_ids = |voxel_map: &VoxelMap| {
+            voxel_map
+                .map
+                .values()
+                .filter_map(|voxel_type| voxel_type.room_id())
+                .collect::<BTreeSet<_>>()
+        };
+        assert!(room_ids(&a.voxel_map).is_disjoint(&room_ids(&other)));
+
+        let before = merged.to_canonical_string();
+        assert!(matches!(
+            merged.merge(&other, offset, MergeConflict::Error),
+            Err(VoxelMapError::Conflict)
+        ));
+        assert_eq!(merged.to_canonical_string(), before);
+
+        merged.subtract(&other, offset);
+        assert_eq!(merged.map, a.voxel_map.map);
+
+        // 重なる位置では通れる空間が残る
+        let mut overlapped = a.voxel_map.clone();
+        overlapped
+            .merge(&other, Vector3::zeros(), MergeConflict::PreferPassable)
+            .unwrap();
+        for (p, voxel_type) in a.voxel_map.map.iter().chain(other.map.iter()) {
+            if voxel_type.is_passable() {
+                assert!(overlapped.get(p).is_passable());
+            }
+        }
+    }
+
+    #[test]
+    fn test_compact_round_trip() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        let compact = result.voxel_map.to_compact().unwrap();
+        assert!(compact.memory_usage() < result.voxel_map.memory_usage());
+        let restored = VoxelMap::from_compact(&compact).unwrap();
+        assert_eq!(
+            restored.to_canonical_string(),
+            result.voxel_map.to_canonical_string()
+        );
+    }
+
+    #[test]
+    fn test_rle_columns_round_trip() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        let rle_columns = result.voxel_map.to_rle_columns();
+        let runs = rle_columns
+            .columns
+            .iter()
+            .map(|column| column.runs.len())
+            .sum::<usize>();
+        assert!(runs < result.voxel_map.map.len());
+        let restored = VoxelMap::from_rle_columns(&rle_columns);
+        assert_eq!(
+            restored.to_canonical_string(),
+            result.voxel_map.to_canonical_string()
+        );
+    }
+
+    #[test]
+    fn test_label_regions() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        let regions = result.voxel_map.label_regions();
+        assert_eq!(regions.len(), 1);
+        assert_eq!(
+            regions[0].rooms,
+            result.rooms.keys().copied().collect::<BTreeSet<_>>()
+        );
+        let passable = result
+            .voxel_map
+            .map
+            .values()
+            .filter(|voxel_type| voxel_type.is_passable())
+            .count();
+        assert_eq!(regions[0].voxels.len(), passable);
+    }
+
+    #[test]
+    fn test_room_distances() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        let spawn = *result.rooms.keys().next().unwrap();
+        let distances = result.voxel_map.room_distances(&BTreeSet::from([spawn]));
+        let passable = result
+            .voxel_map
+            .map
+            .values()
+            .filter(|voxel_type| voxel_type.is_passable())
+            .count();
+        assert_eq!(distances.len(), passable);
+        for (p, distance) in distances.iter() {
+            let voxel_type = result.voxel_map.get(p);
+            assert_eq!(*distance == 0, voxel_type.room_id() == Some(spawn));
+            // 距離が 1 以上のボクセルは、1つ近い隣のボクセルを持つ
+            if *distance > 0 {
+                assert!([Vector3::x(), Vector3::y(), Vector3::z()]
+                    .iter()
+                    .flat_map(|d| [p + d, p - d])
+                    .any(|q| distances.get(&q) == Some(&(distance - 1))));
+            }
+        }
+    }
+
+    #[test]
+    fn test_route_distances() {
+        let mut room_id = RoomId::first();
+        let end_room = Room::new(room_id.gen_id(), 4, 2, 4, (0, 1, 0));
+        // x 8..12, z 0..20 を床から天井まで埋め、区画 x = 2 を z = 5 の列以外で塞ぐ
+        let wall_room = Room::new(room_id.gen_id(), 4, 3, 20, (8, 1, 0));
+        let mut voxel_map = VoxelMap::new(0, 0, 0, 24, 4, 24);
+        voxel_map.add_room(&end_room).unwrap();
+        voxel_map.add_room(&wall_room).unwrap();
+        let mut stats = RouteStats::default();
+        let distances = voxel_map.route_distances(end_room.id, &mut stats);
+        // 6 x 1 x 6 の区画のうち塞いだ 5 つ以外を1度ずつ調べる
+        assert_eq!(stats.guide_nodes, 31);
+        assert_eq!(distances.get(&Vector3::new(1, 1, 1)), Some(0));
+        assert_eq!(distances.get(&Vector3::new(9, 1, 1)), None);
+        assert_eq!(distances.get(&Vector3::new(6, 2, 3)), Some(1));
+        // 壁の向こうは z = 5 の列まで回り込む
+        assert_eq!(distances.get(&Vector3::new(13, 1, 1)), Some(13));
+        assert_eq!(distances.get(&Vector3::new(13, 1, 21)), Some(8));
+        assert_eq!(distances.get(&Vector3::new(24, 1, 1)), None);
+    }
+
+    #[test]
+    fn test_ceiling_cells_of_room() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        for room in result.rooms.values() {
+            let cells = result.voxel_map.ceiling_cells_of_room(room.id);
+            assert_eq!(cells.len(), (room.width * room.depth) as usize);
+            assert!(cells
+                .iter()
+                .all(|p| p.y == (room.origin.1 + room.height) as i32 - 1));
+        }
+
+        let merged = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            room_margin_x: 1,
+            room_margin_z: 1,
+            room_hierarchy: 1,
+            room_width_range: 5..=12,
+            room_depth_range: 5..=12,
+            merge_adjacent_rooms: true,
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(!merged.room_extensions.is_empty());
+        for (room_id, extensions) in merged.room_extensions.iter() {
+            let cells = merged.voxel_map.ceiling_cells_of_room(*room_id);
+            let room = &merged.rooms[room_id];
+            let area = extensions
+                .iter()
+                .chain([room])
+                .map(|room| room.width * room.depth)
+                .sum::<u32>();
+            // 取り除いた壁の分だけ部屋を合わせた面積より広くなる
+            let columns = merged
+                .voxel_map
+                .map
+                .iter()
+                .filter(|(_, voxel_type)| {
+                    **voxel_type == crate::constants::VoxelType::RoomBottomSpace(*room_id)
+                })
+                .map(|(p, _)| (p.x, p.z))
+                .collect::<BTreeSet<_>>();
+            assert!(cells.len() >= area as usize);
+            assert_eq!(cells.len(), columns.len());
+        }
+    }
+
+    #[test]
+    fn test_translated_to_origin() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        let (start, end) = result.voxel_map.occupied_bounds().unwrap();
+        let translated = result.voxel_map.translated_to_origin();
+        assert_eq!(translated.bounds(), (Vector3::zeros(), end - start));
+        assert_eq!(translated.occupied_bounds(), Some(translated.bounds()));
+        assert_eq!(translated.map.len(), result.voxel_map.map.len());
+        for (p, voxel_type) in result.voxel_map.map.iter() {
+            assert_eq!(translated.get(&(p - start)), *voxel_type);
+        }
+    }
+}
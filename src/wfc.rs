@@ -0,0 +1,351 @@
+use crate::constants::{Direction4, VoxelType, DIRECTIONS};
+use crate::voxel_map::VoxelMap;
+use nalgebra::Vector3;
+use rand::{Rng, SeedableRng};
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct WFCConfig {
+    pub tiles: Vec<WFCTile>,
+    pub tile_size: (u32, u32, u32),   // Size of every tile in voxels
+    pub grid_size: (u32, u32, u32),   // Number of tiles on each axis
+    pub boundary_socket: Option<u32>, // Socket every face on the grid boundary must have
+    pub seed: Option<u64>,            // Seed value for random dungeon generation
+    pub max_attempts: u32,            // Restarts allowed after a contradiction
+}
+
+impl Default for WFCConfig {
+    fn default() -> Self {
+        // 0: 壁, 1: 通路の開口部
+        let mut tiles = vec![WFCTile {
+            voxels: vec![],
+            sockets: WFCSockets::default(),
+            weight: 4,
+        }];
+        for mask in 1..16 {
+            let openings = DIRECTIONS
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| mask & (1 << i) != 0)
+                .map(|(_, dir)| *dir)
+                .collect::<Vec<_>>();
+            tiles.push(WFCTile::corridor(&openings, (3, 3, 3)));
+        }
+        WFCConfig {
+            tiles,
+            tile_size: (3, 3, 3),
+            grid_size: (8, 1, 8),
+            boundary_socket: Some(0),
+            seed: None,
+            max_attempts: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct WFCTile {
+    pub voxels: Vec<((i32, i32, i32), VoxelType)>, // x, y, z within the tile
+    pub sockets: WFCSockets,
+    pub weight: u32,
+}
+
+impl WFCTile {
+    // 中央から開口部へ伸びる幅1の通路タイル
+    pub fn corridor(openings: &[Direction4], tile_size: (u32, u32, u32)) -> Self {
+        let center = (tile_size.0 as i32 / 2, tile_size.2 as i32 / 2);
+        let mut columns = vec![center];
+        let mut sockets = WFCSockets::default();
+        for dir in openings {
+            let v = dir.to_vec3();
+            let (mut x, mut z) = center;
+            while (0..tile_size.0 as i32).contains(&(x + v.x))
+                && (0..tile_size.2 as i32).contains(&(z + v.z))
+            {
+                x += v.x;
+                z += v.z;
+                columns.push((x, z));
+            }
+            *sockets.horizontal_mut(*dir) = 1;
+        }
+        let mut voxels = Vec::new();
+        for (x, z) in columns {
+            voxels.push(((x, 0, z), VoxelType::PassageFloor));
+            for y in 1..tile_size.1 as i32 {
+                voxels.push(((x, y, z), VoxelType::PassageSpace));
+            }
+        }
+        WFCTile {
+            voxels,
+            sockets,
+            weight: 1,
+        }
+    }
+}
+
+// 隣接するタイル同士は向かい合う面のソケットが一致する必要がある
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct WFCSockets {
+    pub left: u32,
+    pub right: u32,
+    pub far: u32,
+    pub near: u32,
+    pub down: u32,
+    pub up: u32,
+}
+
+impl WFCSockets {
+    pub fn horizontal_mut(&mut self, dir: Direction4) -> &mut u32 {
+        match dir {
+            Direction4::Left => &mut self.left,
+            Direction4::Right => &mut self.right,
+            Direction4::Far => &mut self.far,
+            Direction4::Near => &mut self.near,
+        }
+    }
+
+    fn face(&self, face: usize) -> u32 {
+        [
+            self.left, self.right, self.far, self.near, self.down, self.up,
+        ][face]
+    }
+}
+
+// left, right, far, near, down, up の順で、反対の面は index ^ 1
+const FACES: [(i32, i32, i32); 6] = [
+    (-1, 0, 0),
+    (1, 0, 0),
+    (0, 0, -1),
+    (0, 0, 1),
+    (0, -1, 0),
+    (0, 1, 0),
+];
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct WFCResult {
+    pub voxel_map: VoxelMap,
+    pub tile_indices: BTreeMap<(u32, u32, u32), usize>, // grid position -> tile index
+}
+
+#[derive(Debug)]
+pub enum WFCError {
+    NoTiles,
+    InvalidTileVoxel { index: usize },
+    Contradiction,
+}
+
+impl fmt::Display for WFCError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WFCError::NoTiles => write!(f, "no tiles were given"),
+            WFCError::InvalidTileVoxel { index } => {
+                write!(f, "tile {} has a voxel outside the tile size", index)
+            }
+            WFCError::Contradiction => write!(f, "no tile satisfies the neighbouring constraints"),
+        }
+    }
+}
+
+impl std::error::Error for WFCError {}
+
+pub fn generate_wfc(config: WFCConfig) -> Result<WFCResult, WFCError> {
+    if config.tiles.is_empty() {
+        return Err(WFCError::NoTiles);
+    }
+    let (tw, th, td) = (
+        config.tile_size.0 as i32,
+        config.tile_size.1 as i32,
+        config.tile_size.2 as i32,
+    );
+    if let Some((index, _)) = config.tiles.iter().enumerate().find(|(_, tile)| {
+        tile.voxels.iter().any(|((x, y, z), _)| {
+            !(0..tw).contains(x) || !(0..th).contains(y) || !(0..td).contains(z)
+        })
+    }) {
+        return Err(WFCError::InvalidTileVoxel { index });
+    }
+
+    let mut rng: rand::rngs::StdRng = config
+        .seed
+        .map(SeedableRng::seed_from_u64)
+        .unwrap_or_else(rand::rngs::StdRng::from_entropy);
+
+    let grid = Grid {
+        size: Vector3::new(
+            config.grid_size.0 as i32,
+            config.grid_size.1 as i32,
+            config.grid_size.2 as i32,
+        ),
+    };
+    let mut collapsed = None;
+    for _ in 0..config.max_attempts.max(1) {
+        if let Some(result) = solve(&config, &grid, &mut rng) {
+            collapsed = Some(result);
+            break;
+        }
+    }
+    let collapsed = collapsed.ok_or(WFCError::Contradiction)?;
+
+    let mut voxel_map = VoxelMap::new(
+        0,
+        0,
+        0,
+        grid.size.x * tw,
+        grid.size.y * th,
+        grid.size.z * td,
+    );
+    let mut tile_indices = BTreeMap::new();
+    for (index, tile_index) in collapsed.into_iter().enumerate() {
+        let cell = grid.position(index);
+        tile_indices.insert((cell.x as u32, cell.y as u32, cell.z as u32), tile_index);
+        for ((x, y, z), voxel_type) in config.tiles[tile_index].voxels.iter() {
+            voxel_map.map.insert(
+                Vector3::new(cell.x * tw + x, cell.y * th + y, cell.z * td + z),
+                *voxel_type,
+            );
+        }
+    }
+
+    Ok(WFCResult {
+        voxel_map,
+        tile_indices,
+    })
+}
+
+struct Grid {
+    size: Vector3<i32>,
+}
+
+impl Grid {
+    fn len(&self) -> usize {
+        (self.size.x * self.size.y * self.size.z) as usize
+    }
+
+    fn position(&self, index: usize) -> Vector3<i32> {
+        let index = index as i32;
+        Vector3::new(
+            index % self.size.x,
+            index / (self.size.x * self.size.z),
+            (index / self.size.x) % self.size.z,
+        )
+    }
+
+    fn index(&self, p: &Vector3<i32>) -> Option<usize> {
+        if (0..self.size.x).contains(&p.x)
+            && (0..self.size.y).contains(&p.y)
+            && (0..self.size.z).contains(&p.z)
+        {
+            Some((p.x + p.z * self.size.x + p.y * self.size.x * self.size.z) as usize)
+        } else {
+            None
+        }
+    }
+}
+
+// 候補が最も少ないセルから確定させ、制約を伝播する
+fn solve<R: Rng>(config: &WFCConfig, grid: &Grid, rng: &mut R) -> Option<Vec<usize>> {
+    let all = (0..config.tiles.len()).collect::<Vec<_>>();
+    let mut candidates = vec![all; grid.len()];
+
+    if let Some(socket) = config.boundary_socket {
+        let mut stack = Vec::new();
+        for (index, options) in candidates.iter_mut().enumerate() {
+            let p = grid.position(index);
+            for (face, (x, y, z)) in FACES.iter().enumerate() {
+                if grid.index(&(p + Vector3::new(*x, *y, *z))).is_none() {
+                    options.retain(|tile| config.tiles[*tile].sockets.face(face) == socket);
+                    stack.push(index);
+                }
+            }
+            if options.is_empty() {
+                return None;
+            }
+        }
+        propagate(config, grid, &mut candidates, stack)?;
+    }
+
+    loop {
+        let Some((index, _)) = candidates
+            .iter()
+            .enumerate()
+            .filter(|(_, options)| options.len() > 1)
+            .min_by_key(|(_, options)| options.len())
+        else {
+            return Some(candidates.into_iter().map(|options| options[0]).collect());
+        };
+
+        let options = &candidates[index];
+        let total = options
+            .iter()
+            .map(|tile| config.tiles[*tile].weight.max(1))
+            .sum::<u32>();
+        let mut pick = rng.gen_range(0..total);
+        let mut chosen = options[0];
+        for tile in options {
+            let weight = config.tiles[*tile].weight.max(1);
+            if pick < weight {
+                chosen = *tile;
+                break;
+            }
+            pick -= weight;
+        }
+        candidates[index] = vec![chosen];
+        propagate(config, grid, &mut candidates, vec![index])?;
+    }
+}
+
+fn propagate(
+    config: &WFCConfig,
+    grid: &Grid,
+    candidates: &mut [Vec<usize>],
+    mut stack: Vec<usize>,
+) -> Option<()> {
+    while let Some(index) = stack.pop() {
+        let p = grid.position(index);
+        for (face, (x, y, z)) in FACES.iter().enumerate() {
+            let Some(next_index) = grid.index(&(p + Vector3::new(*x, *y, *z))) else {
+                continue;
+            };
+            let sockets = candidates[index]
+                .iter()
+                .map(|tile| config.tiles[*tile].sockets.face(face))
+                .collect::<Vec<_>>();
+            let before = candidates[next_index].len();
+            candidates[next_index]
+                .retain(|tile| sockets.contains(&config.tiles[*tile].sockets.face(face ^ 1)));
+            if candidates[next_index].is_empty() {
+                return None;
+            }
+            if candidates[next_index].len() != before {
+                stack.push(next_index);
+            }
+        }
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::VoxelType;
+    use crate::wfc::{generate_wfc, WFCConfig};
+
+    #[test]
+    fn test_generate_wfc() {
+        let result = generate_wfc(WFCConfig {
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(result.tile_indices.len(), 64);
+        // 境界の外へ通路が伸びていないこと
+        let (start, end) = result.voxel_map.bounds();
+        assert!(result
+            .voxel_map
+            .map
+            .iter()
+            .filter(|(_, voxel_type)| **voxel_type == VoxelType::PassageFloor)
+            .all(|(p, _)| {
+                p.x > start.x && p.z > start.z && p.x < end.x - 1 && p.z < end.z - 1
+            }));
+    }
+}
@@ -0,0 +1,262 @@
+use crate::constants::Direction4;
+use crate::generate_drd::Dungeon3DGeneratorResult;
+use crate::passage::{Passage, PassageId};
+use crate::room::{Room, RoomId};
+use nalgebra::Vector3;
+use std::collections::BTreeMap;
+
+// 出力する座標の上方向。生成結果は Y-up で、ZUp は右手系のまま (x, y, z) を (x, -z, y) に移す
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum AxisConvention {
+    #[default]
+    YUp, // Same axes as the generator; Far is -z
+    ZUp, // Blender style; Far becomes +y
+}
+
+impl AxisConvention {
+    pub fn convert_point(&self, p: &Vector3<f32>) -> Vector3<f32> {
+        match self {
+            AxisConvention::YUp => *p,
+            AxisConvention::ZUp => Vector3::new(p.x, -p.z, p.y),
+        }
+    }
+
+    pub fn convert_vector(&self, v: &Vector3<i32>) -> Vector3<i32> {
+        match self {
+            AxisConvention::YUp => *v,
+            AxisConvention::ZUp => Vector3::new(v.x, -v.z, v.y),
+        }
+    }
+
+    // ボクセルの番号を変換する。反転する軸は 1 ずらし、変換後もセルが [p, p + 1) の範囲を占めるようにする
+    pub fn convert_voxel(&self, p: &Vector3<i32>) -> Vector3<i32> {
+        match self {
+            AxisConvention::YUp => *p,
+            AxisConvention::ZUp => Vector3::new(p.x, -p.z - 1, p.y),
+        }
+    }
+
+    // convert_voxel の逆
+    pub fn restore_voxel(&self, p: &Vector3<i32>) -> Vector3<i32> {
+        match self {
+            AxisConvention::YUp => *p,
+            AxisConvention::ZUp => Vector3::new(p.x, p.z, -p.y - 1),
+        }
+    }
+}
+
+// ボクセルの位置をワールド座標に変換する。1ボクセルの一辺が voxel_scale、ボクセル (0, 0, 0) の角が origin になる
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WorldTransform {
+    pub voxel_scale: f32,     // Length of a voxel edge in world units
+    pub origin: Vector3<f32>, // World position of the min corner of voxel (0, 0, 0), in the axes of `axis`
+    pub axis: AxisConvention, // Up axis of every position and direction this transform returns
+}
+
+impl Default for WorldTransform {
+    fn default() -> Self {
+        WorldTransform {
+            voxel_scale: 1.0,
+            origin: Vector3::zeros(),
+            axis: AxisConvention::YUp,
+        }
+    }
+}
+
+// ワールド座標の軸に沿った箱。max は箱の外側の角
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WorldAabb {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+
+impl WorldAabb {
+    pub fn center(&self) -> Vector3<f32> {
+        (self.min + self.max) / 2.0
+    }
+
+    pub fn size(&self) -> Vector3<f32> {
+        self.max - self.min
+    }
+
+    // 両方の箱を含む最小の箱
+    pub fn union(&self, other: &WorldAabb) -> WorldAabb {
+        WorldAabb {
+            min: self.min.inf(&other.min),
+            max: self.max.sup(&other.max),
+        }
+    }
+
+    pub fn contains(&self, p: &Vector3<f32>) -> bool {
+        (0..3).all(|i| self.min[i] <= p[i] && p[i] < self.max[i])
+    }
+}
+
+impl WorldTransform {
+    pub fn new(voxel_scale: f32) -> Self {
+        WorldTransform {
+            voxel_scale,
+            ..Default::default()
+        }
+    }
+
+    // ボクセルの最小の角のワールド座標
+    pub fn voxel_position(&self, p: &Vector3<i32>) -> Vector3<f32> {
+        self.origin + self.axis.convert_voxel(p).map(|v| v as f32) * self.voxel_scale
+    }
+
+    // ボクセルの中心のワールド座標
+    pub fn voxel_center(&self, p: &Vector3<i32>) -> Vector3<f32> {
+        self.voxel_position(p) + Vector3::repeat(self.voxel_scale / 2.0)
+    }
+
+    // ワールド座標を含むボクセル
+    pub fn to_voxel(&self, p: &Vector3<f32>) -> Vector3<i32> {
+        let p = ((p - self.origin) / self.voxel_scale).map(|v| v.floor() as i32);
+        self.axis.restore_voxel(&p)
+    }
+
+    // 階段の上る向きなど、水平の向きのワールド座標での単位ベクトル
+    pub fn direction(&self, direction: Direction4) -> Vector3<f32> {
+        self.axis
+            .convert_vector(&direction.to_vec3())
+            .map(|v| v as f32)
+    }
+
+    // 部屋の空間を囲む箱。床と壁は含まない
+    pub fn room_aabb(&self, room: &Room) -> WorldAabb {
+        let origin = Vector3::new(room.origin.0, room.origin.1, room.origin.2).map(|v| v as f32);
+        let size = Vector3::new(room.width, room.height, room.depth).map(|v| v as f32);
+        let corner =
+            |p: Vector3<f32>| self.origin + self.axis.convert_point(&(p * self.voxel_scale));
+        let (a, b) = (corner(origin), corner(origin + size));
+        WorldAabb {
+            min: a.inf(&b),
+            max: a.sup(&b),
+        }
+    }
+
+    // 通路のボクセルの中心を掘った順に並べる
+    pub fn passage_path(&self, passage: &Passage) -> Vec<Vector3<f32>> {
+        passage
+            .cells
+            .iter()
+            .map(|((x, y, z), _)| self.voxel_center(&Vector3::new(*x, *y, *z)))
+            .collect()
+    }
+}
+
+// ワールドの遠くに置いたダンジョンのボクセル座標を i64 で求める。結果を平行移動すると i32 を超える位置で使う
+pub fn world_voxel(p: &Vector3<i32>, offset: &Vector3<i64>) -> Vector3<i64> {
+    offset + p.map(i64::from)
+}
+
+impl Dungeon3DGeneratorResult {
+    // 部屋ごとの箱。まとめた部屋は room_extensions の箱も含む
+    pub fn room_aabbs(&self, transform: &WorldTransform) -> BTreeMap<RoomId, WorldAabb> {
+        self.rooms
+            .iter()
+            .map(|(room_id, room)| {
+                let aabb = self
+                    .room_extensions
+                    .get(room_id)
+                    .into_iter()
+                    .flatten()
+                    .fold(transform.room_aabb(room), |aabb, extension| {
+                        aabb.union(&transform.room_aabb(extension))
+                    });
+                (*room_id, aabb)
+            })
+            .collect()
+    }
+
+    pub fn passage_paths(
+        &self,
+        transform: &WorldTransform,
+    ) -> BTreeMap<PassageId, Vec<Vector3<f32>>> {
+        self.passages
+            .iter()
+            .map(|(passage_id, passage)| (*passage_id, transform.passage_path(passage)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generate_drd::{generate_dungeon_3d, Dungeon3DGeneratorConfig};
+    use crate::world::{world_voxel, AxisConvention, WorldTransform};
+    use nalgebra::Vector3;
+
+    #[test]
+    fn test_world_transform() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        let transform = WorldTransform {
+            voxel_scale: 2.0,
+            origin: Vector3::new(10.0, 0.0, -4.0),
+            ..Default::default()
+        };
+        let aabbs = result.room_aabbs(&transform);
+        for room in result.rooms.values() {
+            let aabb = &aabbs[&room.id];
+            assert_eq!(
+                aabb.size(),
+                Vector3::new(room.width, room.height, room.depth).map(|v| v as f32 * 2.0)
+            );
+            let origin =
+                Vector3::new(room.origin.0, room.origin.1, room.origin.2).map(|v| v as i32);
+            assert_eq!(aabb.min, transform.voxel_position(&origin));
+            assert!(aabb.contains(&transform.voxel_center(&origin)));
+            assert_eq!(transform.to_voxel(&aabb.min), origin);
+            let far = Vector3::new(i32::MAX as i64, 0, i64::from(i32::MIN) * 4);
+            assert_eq!(world_voxel(&origin, &far) - far, origin.map(i64::from));
+        }
+        for (passage_id, path) in result.passage_paths(&transform) {
+            let passage = &result.passages[&passage_id];
+            assert_eq!(path.len(), passage.cells.len());
+            for (p, ((x, y, z), _)) in path.iter().zip(passage.cells.iter()) {
+                assert_eq!(transform.to_voxel(p), Vector3::new(*x, *y, *z));
+            }
+        }
+    }
+
+    #[test]
+    fn test_z_up() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        let y_up = WorldTransform::new(2.0);
+        let z_up = WorldTransform {
+            axis: AxisConvention::ZUp,
+            ..y_up
+        };
+        let (y_aabbs, z_aabbs) = (result.room_aabbs(&y_up), result.room_aabbs(&z_up));
+        for room in result.rooms.values() {
+            let (y_aabb, z_aabb) = (&y_aabbs[&room.id], &z_aabbs[&room.id]);
+            let (y_size, z_size) = (y_aabb.size(), z_aabb.size());
+            assert_eq!(z_size, Vector3::new(y_size.x, y_size.z, y_size.y));
+            assert_eq!(z_aabb.min.z, y_aabb.min.y);
+            assert_eq!(z_aabb.max.y, -y_aabb.min.z);
+
+            let origin =
+                Vector3::new(room.origin.0, room.origin.1, room.origin.2).map(|v| v as i32);
+            assert!(z_aabb.contains(&z_up.voxel_center(&origin)));
+            assert_eq!(z_up.to_voxel(&z_up.voxel_center(&origin)), origin);
+        }
+        // 階段は Z-up でも水平に進む
+        for stair in result.stairs.iter() {
+            let v = z_up.direction(stair.direction);
+            assert_eq!(v.z, 0.0);
+            let bottom = Vector3::new(stair.bottom.0, stair.bottom.1, stair.bottom.2);
+            let top = Vector3::new(stair.top.0, stair.top.1, stair.top.2);
+            let rise = z_up.voxel_position(&top) - z_up.voxel_position(&bottom);
+            assert!(rise.z > 0.0);
+            assert!(rise.dot(&v) > 0.0);
+        }
+    }
+}
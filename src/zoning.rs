@@ -0,0 +1,169 @@
+use crate::generate_drd::Dungeon3DGeneratorResult;
+use crate::room::RoomId;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum RoomZone {
+    Public, // On many shortest routes or passed by other corridors; suits shops and hubs
+    Neutral,
+    Private, // Off every route between other rooms and away from other corridors; suits secrets
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoomTraffic {
+    pub nearby_passages: usize, // Passages of other rooms running within NEAR_DISTANCE voxels of the room
+    pub betweenness: f32, // Share of shortest routes between other rooms which pass through the room (0 to 1)
+    pub zone: RoomZone,
+}
+
+// 通路が部屋の近くを通るとみなす距離
+pub const NEAR_DISTANCE: i32 = 2;
+const PUBLIC_BETWEENNESS: f32 = 0.3;
+const PUBLIC_NEARBY_PASSAGES: usize = 2;
+
+// 近くを通る他の部屋の通路の数と、部屋のグラフでの媒介中心性から部屋の人通りを分類する
+// 人通りの多い部屋に店を、少ない部屋に隠し部屋を置くといった使い方を想定する
+pub fn classify_rooms(result: &Dungeon3DGeneratorResult) -> BTreeMap<RoomId, RoomTraffic> {
+    let betweenness = betweenness_centrality(result);
+    result
+        .rooms
+        .values()
+        .map(|room| {
+            let (x0, y0, z0) = room.origin;
+            let (x1, y1, z1) = room.end();
+            let distance =
+                |v: i32, start: u32, end: u32| (start as i32 - v).max(v - (end as i32 - 1)).max(0);
+            let nearby_passages = result
+                .passages
+                .values()
+                .filter(|passage| {
+                    passage.start_room_id != room.id && passage.end_room_id != room.id
+                })
+                .filter(|passage| {
+                    passage.cells.iter().any(|((x, y, z), _)| {
+                        distance(*x, x0, x1)
+                            .max(distance(*y, y0, y1))
+                            .max(distance(*z, z0, z1))
+                            <= NEAR_DISTANCE
+                    })
+                })
+                .count();
+            let betweenness = betweenness.get(&room.id).copied().unwrap_or(0.0);
+            let zone =
+                if betweenness >= PUBLIC_BETWEENNESS || nearby_passages >= PUBLIC_NEARBY_PASSAGES {
+                    RoomZone::Public
+                } else if betweenness == 0.0 && nearby_passages == 0 {
+                    RoomZone::Private
+                } else {
+                    RoomZone::Neutral
+                };
+            (
+                room.id,
+                RoomTraffic {
+                    nearby_passages,
+                    betweenness,
+                    zone,
+                },
+            )
+        })
+        .collect()
+}
+
+// 通路の本数で数えた最短経路に対する Brandes の方法。部屋の組の数で割って 0 から 1 にする
+fn betweenness_centrality(result: &Dungeon3DGeneratorResult) -> BTreeMap<RoomId, f32> {
+    let mut neighbors: BTreeMap<RoomId, BTreeSet<RoomId>> = result
+        .rooms
+        .keys()
+        .map(|room_id| (*room_id, BTreeSet::new()))
+        .collect();
+    for passage in result.passages.values() {
+        let (room0_id, room1_id) = (passage.start_room_id, passage.end_room_id);
+        if room0_id != room1_id {
+            neighbors.entry(room0_id).or_default().insert(room1_id);
+            neighbors.entry(room1_id).or_default().insert(room0_id);
+        }
+    }
+
+    let mut centrality: BTreeMap<RoomId, f64> =
+        neighbors.keys().map(|room_id| (*room_id, 0.0)).collect();
+    for source in neighbors.keys() {
+        let mut order = Vec::new();
+        let mut predecessors: BTreeMap<RoomId, Vec<RoomId>> = BTreeMap::new();
+        let mut paths: BTreeMap<RoomId, f64> = BTreeMap::from([(*source, 1.0)]);
+        let mut distances: BTreeMap<RoomId, usize> = BTreeMap::from([(*source, 0)]);
+        let mut queue = VecDeque::from([*source]);
+        while let Some(room_id) = queue.pop_front() {
+            order.push(room_id);
+            for next in neighbors[&room_id].iter() {
+                if !distances.contains_key(next) {
+                    distances.insert(*next, distances[&room_id] + 1);
+                    queue.push_back(*next);
+                }
+                if distances[next] == distances[&room_id] + 1 {
+                    *paths.entry(*next).or_default() += paths[&room_id];
+                    predecessors.entry(*next).or_default().push(room_id);
+                }
+            }
+        }
+        let mut dependencies: BTreeMap<RoomId, f64> = BTreeMap::new();
+        for room_id in order.iter().rev() {
+            let dependency = dependencies.get(room_id).copied().unwrap_or(0.0);
+            for predecessor in predecessors.get(room_id).into_iter().flatten() {
+                *dependencies.entry(*predecessor).or_default() +=
+                    paths[predecessor] / paths[room_id] * (1.0 + dependency);
+            }
+            if room_id != source {
+                *centrality.get_mut(room_id).unwrap() += dependency;
+            }
+        }
+    }
+
+    // 無向グラフでは各組を両方向から数えている
+    let n = centrality.len() as f64;
+    let pairs = ((n - 1.0) * (n - 2.0)).max(1.0);
+    centrality
+        .into_iter()
+        .map(|(room_id, value)| (room_id, (value / pairs) as f32))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generate_drd::{generate_dungeon_3d, Dungeon3DGeneratorConfig};
+    use crate::zoning::{classify_rooms, RoomZone};
+
+    #[test]
+    fn test_classify_rooms() {
+        let result = generate_dungeon_3d(Dungeon3DGeneratorConfig {
+            seed: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+        let traffic = classify_rooms(&result);
+        assert_eq!(traffic.len(), result.rooms.len());
+        assert!(traffic
+            .values()
+            .all(|traffic| (0.0..=1.0).contains(&traffic.betweenness)));
+        assert!(traffic
+            .values()
+            .any(|traffic| traffic.zone == RoomZone::Public));
+
+        // 通路が1本だけの部屋は他の部屋の間の経路に含まれない
+        for (room_id, traffic) in traffic.iter() {
+            let degree = result
+                .passages
+                .values()
+                .filter(|passage| {
+                    (passage.start_room_id == *room_id) != (passage.end_room_id == *room_id)
+                })
+                .count();
+            if degree == 1 {
+                assert_eq!(traffic.betweenness, 0.0);
+                assert_eq!(
+                    traffic.zone == RoomZone::Private,
+                    traffic.nearby_passages == 0
+                );
+            }
+        }
+    }
+}